@@ -45,6 +45,8 @@ impl Arch for ArchII {
             "ldr" => false,
             "sdl" => false,
             "sdr" => false,
+            "lld" => false,
+            "scd" => false,
             _ => true,
         }
     }
@@ -66,6 +68,9 @@ impl Arch for ArchI {
             "blezl" => false,
             "btlzall" => false,
             "bgezall" => false,
+            "tge" | "tgeu" | "tlt" | "tltu" | "teq" | "tne" => false,
+            "tgei" | "tgeiu" | "tlti" | "tltiu" | "teqi" | "tnei" => false,
+            "ll" | "sc" => false,
             _ => true,
         }
     }
@@ -0,0 +1,1262 @@
+//! A simple two-pass MIPS assembler, covering the R4300 ISA (including
+//! COP0 and FPU instructions) and the RSP vector unit.
+//!
+//! The syntax follows the subset of bass used by the test suites:
+//!
+//!   * One statement per line, optionally preceded by a `label:`.
+//!     Comments start with `//`.
+//!   * GPRs are specified by name (`t0`, `ra`, ...), as `rN`, or as a bare
+//!     register number. FPU registers are `fN`, RSP vector registers are `vN`.
+//!   * Numbers can be decimal, hexadecimal (`$1F` or `0x1F`) or binary
+//!     (`%101` or `0b101`). Immediates are expressions, and can refer to
+//!     labels and constants.
+//!   * RSP vector elements are specified with the `v1[e3]` syntax. Offsets
+//!     of vector loads/stores are in bytes, and are scaled by the access size.
+//!   * Most three-operand instructions can be written with two operands,
+//!     in which case the destination is also the first source
+//!     (eg: `addi a0,4` or `vxor v0,v1[e0]`).
+//!
+//! Supported directives are `constant NAME(value)`, `base addr`,
+//! `align(n)`, `fill n`, and `db`/`dh`/`dw`/`dd` to emit data.
+//! Pseudo-instructions are `nop`, `li`, `la`, `move`, `neg`, `not`, `b`,
+//! `bal`, `beqz`/`bnez` (and their likely variants), `subi`/`subiu`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use super::cp0::COP0_REG_NAMES;
+
+/// An error found while assembling, with the (1-based) source line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Error for AsmError {}
+
+type Result<T> = std::result::Result<T, String>;
+
+const GPR_NAMES: [&'static str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3", "t0", "t1", "t2", "t3", "t4", "t5", "t6",
+    "t7", "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "t8", "t9", "k0", "k1", "gp", "sp", "fp",
+    "ra",
+];
+
+const FPU_CONDS: [&'static str; 16] = [
+    "f", "un", "eq", "ueq", "olt", "ult", "ole", "ule", "sf", "ngle", "seq", "ngl", "lt", "nge",
+    "le", "ngt",
+];
+
+// RSP vector unit computational opcodes, indexed by function field.
+const VU_OPS: [&'static str; 64] = [
+    "vmulf", "vmulu", "vrndp", "vmulq", "vmudl", "vmudm", "vmudn", "vmudh", "vmacf", "vmacu",
+    "vrndn", "vmacq", "vmadl", "vmadm", "vmadn", "vmadh", "vadd", "vsub", "vsut", "vabs", "vaddc",
+    "vsubc", "vaddb", "vsubb", "vaccb", "vsucb", "vsad", "vsac", "vsum", "vsar", "", "", "vlt",
+    "veq", "vne", "vge", "vcl", "vch", "vcr", "vmrg", "vand", "vnand", "vor", "vnor", "vxor",
+    "vnxor", "", "", "vrcp", "vrcpl", "vrcph", "vmov", "vrsq", "vrsql", "vrsqh", "vnop", "", "",
+    "", "", "", "", "", "",
+];
+
+// RSP vector loads/stores: (suffix, opcode, offset scale as shift).
+const VU_MEMOPS: [(&'static str, u32, u32); 12] = [
+    ("b", 0x00, 0),
+    ("s", 0x01, 1),
+    ("l", 0x02, 2),
+    ("d", 0x03, 3),
+    ("q", 0x04, 4),
+    ("r", 0x05, 4),
+    ("p", 0x06, 3),
+    ("u", 0x07, 3),
+    ("h", 0x08, 4),
+    ("f", 0x09, 4),
+    ("w", 0x0A, 4),
+    ("t", 0x0B, 4),
+];
+
+/// Assemble the specified source code, as if it was loaded at the
+/// specified base address. Returns the binary code (big-endian).
+pub fn assemble(src: &str, base: u64) -> std::result::Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler {
+        syms: HashMap::new(),
+        pc: base,
+        pass: 1,
+        out: Vec::new(),
+    };
+
+    let lines = src
+        .lines()
+        .enumerate()
+        .map(|(n, l)| (n + 1, l))
+        .collect::<Vec<_>>();
+
+    for pass in 1..=2 {
+        asm.pass = pass;
+        asm.pc = base;
+        asm.out.clear();
+        for (num, line) in lines.iter() {
+            asm.line(line).map_err(|msg| AsmError { line: *num, msg })?;
+        }
+    }
+    Ok(asm.out)
+}
+
+struct Assembler {
+    syms: HashMap<String, i64>,
+    pc: u64,
+    pass: usize,
+    out: Vec<u8>,
+}
+
+impl Assembler {
+    fn line(&mut self, line: &str) -> Result<()> {
+        let mut line = match line.find("//") {
+            Some(idx) => &line[..idx],
+            None => line,
+        }
+        .trim();
+
+        // Labels
+        if let Some(idx) = line.find(':') {
+            let label = line[..idx].trim();
+            if is_ident(label) {
+                if self.pass == 1 {
+                    self.define(label, self.pc as i64)?;
+                }
+                line = line[idx + 1..].trim();
+            }
+        }
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, args) = match line.find(|c: char| c.is_whitespace() || c == '(') {
+            Some(idx) => (&line[..idx], line[idx..].trim()),
+            None => (line, ""),
+        };
+        let mnemonic = mnemonic.to_lowercase();
+        let args = split_args(args);
+
+        if self.directive(&mnemonic, &args)? {
+            return Ok(());
+        }
+        self.insn(&mnemonic, &args)
+    }
+
+    fn define(&mut self, name: &str, val: i64) -> Result<()> {
+        if self.syms.insert(name.to_owned(), val).is_some() {
+            return Err(format!("duplicate symbol: {}", name));
+        }
+        Ok(())
+    }
+
+    fn emit32(&mut self, val: u32) {
+        self.emit(val as u64, 4);
+    }
+
+    fn emit(&mut self, val: u64, size: usize) {
+        for i in (0..size).rev() {
+            self.out.push((val >> (i * 8)) as u8);
+        }
+        self.pc += size as u64;
+    }
+
+    // Handle a directive. Returns false if the mnemonic is not a directive.
+    fn directive(&mut self, mnemonic: &str, args: &[&str]) -> Result<bool> {
+        match mnemonic {
+            "constant" => {
+                // constant NAME(value)
+                let arg = args.join(",");
+                let open = arg.find('(').ok_or("invalid constant syntax")?;
+                if !arg.ends_with(')') {
+                    return Err("invalid constant syntax".into());
+                }
+                let name = arg[..open].trim();
+                if !is_ident(name) {
+                    return Err(format!("invalid constant name: {}", name));
+                }
+                let val = self.eval(&arg[open + 1..arg.len() - 1])?;
+                if self.pass == 1 {
+                    self.define(name, val)?;
+                }
+            }
+            "base" => {
+                self.pc = self.eval(one(args)?)? as u64;
+            }
+            "align" => {
+                let n = self.eval(one(args)?)?;
+                if n <= 0 {
+                    return Err(format!("invalid alignment: {}", n));
+                }
+                while self.pc % n as u64 != 0 {
+                    self.emit(0, 1);
+                }
+            }
+            "fill" => {
+                let n = self.eval(one(args)?)?;
+                for _ in 0..n {
+                    self.emit(0, 1);
+                }
+            }
+            "db" | "dh" | "dw" | "dd" => {
+                let size = match mnemonic {
+                    "db" => 1,
+                    "dh" => 2,
+                    "dw" => 4,
+                    _ => 8,
+                };
+                for arg in args {
+                    if arg.starts_with('"') {
+                        if size != 1 || arg.len() < 2 || !arg.ends_with('"') {
+                            return Err(format!("invalid string: {}", arg));
+                        }
+                        for b in arg[1..arg.len() - 1].bytes() {
+                            self.emit(b as u64, 1);
+                        }
+                    } else {
+                        let val = self.eval_late(arg)?;
+                        self.emit(val as u64, size);
+                    }
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Evaluate an expression that can refer to labels defined later in the
+    // source. During the first pass, it evaluates to zero.
+    fn eval_late(&self, expr: &str) -> Result<i64> {
+        match self.eval(expr) {
+            Err(_) if self.pass == 1 => Ok(0),
+            res => res,
+        }
+    }
+
+    fn eval(&self, expr: &str) -> Result<i64> {
+        let mut p = ExprParser {
+            syms: &self.syms,
+            src: expr.as_bytes(),
+            pos: 0,
+        };
+        let val = p.expr(0)?;
+        p.skip_ws();
+        if p.pos != p.src.len() {
+            return Err(format!("invalid expression: {}", expr));
+        }
+        Ok(val)
+    }
+
+    // Signed 16-bit immediate. Values up to 0xFFFF are also accepted, and
+    // are truncated to 16 bits.
+    fn simm16(&self, arg: &str) -> Result<u32> {
+        let val = self.eval_late(arg)?;
+        if val < -0x8000 || val > 0xFFFF {
+            return Err(format!("immediate out of range: {}", arg));
+        }
+        Ok(val as u32 & 0xFFFF)
+    }
+
+    // Unsigned 16-bit immediate.
+    fn uimm16(&self, arg: &str) -> Result<u32> {
+        let val = self.eval_late(arg)?;
+        if val < 0 || val > 0xFFFF {
+            return Err(format!("immediate out of range: {}", arg));
+        }
+        Ok(val as u32)
+    }
+
+    fn uimm(&self, arg: &str, bits: u32) -> Result<u32> {
+        let val = self.eval(arg)?;
+        if val < 0 || val >= 1 << bits {
+            return Err(format!("immediate out of range: {}", arg));
+        }
+        Ok(val as u32)
+    }
+
+    // Branch offset (relative to the delay slot, in words).
+    fn branch(&self, arg: &str) -> Result<u32> {
+        if self.pass == 1 {
+            return Ok(0);
+        }
+        let tgt = self.eval(arg)?;
+        let off = tgt.wrapping_sub(self.pc as i64 + 4);
+        if off & 3 != 0 {
+            return Err(format!("misaligned branch target: {}", arg));
+        }
+        let off = off >> 2;
+        if off < -0x8000 || off > 0x7FFF {
+            return Err(format!("branch target out of range: {}", arg));
+        }
+        Ok(off as u32 & 0xFFFF)
+    }
+
+    // Jump target (within the current 256 MiB region, in words).
+    fn jump(&self, arg: &str) -> Result<u32> {
+        if self.pass == 1 {
+            return Ok(0);
+        }
+        let tgt = self.eval(arg)? as u64;
+        if tgt & 3 != 0 {
+            return Err(format!("misaligned jump target: {}", arg));
+        }
+        if tgt & !0x0FFF_FFFF != (self.pc + 4) & !0x0FFF_FFFF {
+            return Err(format!("jump target out of range: {}", arg));
+        }
+        Ok((tgt >> 2) as u32 & 0x03FF_FFFF)
+    }
+
+    // Memory operand: "offset(base)". Returns the base register and the
+    // offset, unscaled.
+    fn memop(&self, arg: &str) -> Result<(u32, i64)> {
+        let open = arg
+            .rfind('(')
+            .ok_or_else(|| format!("invalid memory operand: {}", arg))?;
+        if !arg.ends_with(')') {
+            return Err(format!("invalid memory operand: {}", arg));
+        }
+        let base = gpr(&arg[open + 1..arg.len() - 1])?;
+        let off = arg[..open].trim();
+        let off = if off.is_empty() {
+            0
+        } else {
+            self.eval_late(off)?
+        };
+        Ok((base, off))
+    }
+
+    fn memop16(&self, arg: &str) -> Result<(u32, u32)> {
+        let (base, off) = self.memop(arg)?;
+        if off < -0x8000 || off > 0x7FFF {
+            return Err(format!("offset out of range: {}", arg));
+        }
+        Ok((base, off as u32 & 0xFFFF))
+    }
+
+    fn insn(&mut self, mnemonic: &str, args: &[&str]) -> Result<()> {
+        let m = mnemonic;
+        let insn = match m {
+            // Pseudo-instructions
+            "nop" => {
+                nargs(args, 0)?;
+                0
+            }
+            "li" | "la" => {
+                // Always expanded into LUI+ORI, so that the size of the
+                // instruction does not depend on the value.
+                nargs(args, 2)?;
+                let rt = gpr(args[0])?;
+                let val = self.eval_late(args[1])?;
+                if val < -0x8000_0000 || val > 0xFFFF_FFFF {
+                    return Err(format!("immediate out of range: {}", args[1]));
+                }
+                let val = val as u32;
+                self.emit32(0x3C00_0000 | rt << 16 | val >> 16);
+                0x3400_0000 | rt << 21 | rt << 16 | (val & 0xFFFF)
+            }
+            "move" => {
+                nargs(args, 2)?;
+                special(0x21, gpr(args[1])?, 0, gpr(args[0])?, 0)
+            }
+            "neg" | "negu" => {
+                nargs(args, 2)?;
+                let func = if m == "neg" { 0x22 } else { 0x23 };
+                special(func, 0, gpr(args[1])?, gpr(args[0])?, 0)
+            }
+            "not" => {
+                nargs(args, 2)?;
+                special(0x27, gpr(args[1])?, 0, gpr(args[0])?, 0)
+            }
+            "b" => {
+                nargs(args, 1)?;
+                0x1000_0000 | self.branch(args[0])?
+            }
+            "bal" => {
+                nargs(args, 1)?;
+                0x0411_0000 | self.branch(args[0])?
+            }
+            "beqz" | "bnez" | "beqzl" | "bnezl" => {
+                nargs(args, 2)?;
+                let op = match m {
+                    "beqz" => 0x04,
+                    "bnez" => 0x05,
+                    "beqzl" => 0x14,
+                    _ => 0x15,
+                };
+                op << 26 | gpr(args[0])? << 21 | self.branch(args[1])?
+            }
+            "subi" | "subiu" | "dsubi" | "dsubiu" => {
+                let (rt, rs, imm) = match args.len() {
+                    2 => (args[0], args[0], args[1]),
+                    3 => (args[0], args[1], args[2]),
+                    _ => return Err("invalid number of operands".into()),
+                };
+                let op = match m {
+                    "subi" => 0x08,
+                    "subiu" => 0x09,
+                    "dsubi" => 0x18,
+                    _ => 0x19,
+                };
+                let val = self.eval_late(imm)?;
+                if val < -0x7FFF || val > 0x8000 {
+                    return Err(format!("immediate out of range: {}", imm));
+                }
+                op << 26 | gpr(rs)? << 21 | gpr(rt)? << 16 | (val.wrapping_neg() as u32 & 0xFFFF)
+            }
+
+            // SPECIAL: three-register ALU ops (rd, rs, rt)
+            "add" | "addu" | "sub" | "subu" | "and" | "or" | "xor" | "nor" | "slt" | "sltu"
+            | "dadd" | "daddu" | "dsub" | "dsubu" => {
+                let func = match m {
+                    "add" => 0x20,
+                    "addu" => 0x21,
+                    "sub" => 0x22,
+                    "subu" => 0x23,
+                    "and" => 0x24,
+                    "or" => 0x25,
+                    "xor" => 0x26,
+                    "nor" => 0x27,
+                    "slt" => 0x2A,
+                    "sltu" => 0x2B,
+                    "dadd" => 0x2C,
+                    "daddu" => 0x2D,
+                    "dsub" => 0x2E,
+                    _ => 0x2F,
+                };
+                let (rd, rs, rt) = three(args)?;
+                special(func, gpr(rs)?, gpr(rt)?, gpr(rd)?, 0)
+            }
+            // Variable shifts (rd, rt, rs)
+            "sllv" | "srlv" | "srav" | "dsllv" | "dsrlv" | "dsrav" => {
+                let func = match m {
+                    "sllv" => 0x04,
+                    "srlv" => 0x06,
+                    "srav" => 0x07,
+                    "dsllv" => 0x14,
+                    "dsrlv" => 0x16,
+                    _ => 0x17,
+                };
+                let (rd, rt, rs) = three(args)?;
+                special(func, gpr(rs)?, gpr(rt)?, gpr(rd)?, 0)
+            }
+            // Immediate shifts (rd, rt, sa)
+            "sll" | "srl" | "sra" | "dsll" | "dsrl" | "dsra" | "dsll32" | "dsrl32" | "dsra32" => {
+                let func = match m {
+                    "sll" => 0x00,
+                    "srl" => 0x02,
+                    "sra" => 0x03,
+                    "dsll" => 0x38,
+                    "dsrl" => 0x3A,
+                    "dsra" => 0x3B,
+                    "dsll32" => 0x3C,
+                    "dsrl32" => 0x3E,
+                    _ => 0x3F,
+                };
+                let (rd, rt, sa) = three(args)?;
+                special(func, 0, gpr(rt)?, gpr(rd)?, self.uimm(sa, 5)?)
+            }
+            "mult" | "multu" | "div" | "divu" | "dmult" | "dmultu" | "ddiv" | "ddivu" | "tge"
+            | "tgeu" | "tlt" | "tltu" | "teq" | "tne" => {
+                let func = match m {
+                    "mult" => 0x18,
+                    "multu" => 0x19,
+                    "div" => 0x1A,
+                    "divu" => 0x1B,
+                    "dmult" => 0x1C,
+                    "dmultu" => 0x1D,
+                    "ddiv" => 0x1E,
+                    "ddivu" => 0x1F,
+                    "tge" => 0x30,
+                    "tgeu" => 0x31,
+                    "tlt" => 0x32,
+                    "tltu" => 0x33,
+                    "teq" => 0x34,
+                    _ => 0x36,
+                };
+                nargs(args, 2)?;
+                special(func, gpr(args[0])?, gpr(args[1])?, 0, 0)
+            }
+            "mfhi" | "mflo" => {
+                nargs(args, 1)?;
+                let func = if m == "mfhi" { 0x10 } else { 0x12 };
+                special(func, 0, 0, gpr(args[0])?, 0)
+            }
+            "mthi" | "mtlo" => {
+                nargs(args, 1)?;
+                let func = if m == "mthi" { 0x11 } else { 0x13 };
+                special(func, gpr(args[0])?, 0, 0, 0)
+            }
+            "jr" => {
+                nargs(args, 1)?;
+                special(0x08, gpr(args[0])?, 0, 0, 0)
+            }
+            "jalr" => match args.len() {
+                1 => special(0x09, gpr(args[0])?, 0, 31, 0),
+                2 => special(0x09, gpr(args[1])?, 0, gpr(args[0])?, 0),
+                _ => return Err("invalid number of operands".into()),
+            },
+            "syscall" | "break" => {
+                let code = match args.len() {
+                    0 => 0,
+                    1 => self.uimm(args[0], 20)?,
+                    _ => return Err("invalid number of operands".into()),
+                };
+                special(if m == "syscall" { 0x0C } else { 0x0D }, 0, 0, 0, 0) | code << 6
+            }
+            "sync" => {
+                nargs(args, 0)?;
+                0x0F
+            }
+
+            // REGIMM
+            "bltz" | "bgez" | "bltzl" | "bgezl" | "bltzal" | "bgezal" | "bltzall" | "bgezall" => {
+                let rt = match m {
+                    "bltz" => 0x00,
+                    "bgez" => 0x01,
+                    "bltzl" => 0x02,
+                    "bgezl" => 0x03,
+                    "bltzal" => 0x10,
+                    "bgezal" => 0x11,
+                    "bltzall" => 0x12,
+                    _ => 0x13,
+                };
+                nargs(args, 2)?;
+                0x0400_0000 | gpr(args[0])? << 21 | rt << 16 | self.branch(args[1])?
+            }
+            "tgei" | "tgeiu" | "tlti" | "tltiu" | "teqi" | "tnei" => {
+                let rt = match m {
+                    "tgei" => 0x08,
+                    "tgeiu" => 0x09,
+                    "tlti" => 0x0A,
+                    "tltiu" => 0x0B,
+                    "teqi" => 0x0C,
+                    _ => 0x0E,
+                };
+                nargs(args, 2)?;
+                0x0400_0000 | gpr(args[0])? << 21 | rt << 16 | self.simm16(args[1])?
+            }
+
+            // Jumps and branches
+            "j" | "jal" => {
+                nargs(args, 1)?;
+                (if m == "j" { 0x02 } else { 0x03 }) << 26 | self.jump(args[0])?
+            }
+            "beq" | "bne" | "beql" | "bnel" => {
+                let op = match m {
+                    "beq" => 0x04,
+                    "bne" => 0x05,
+                    "beql" => 0x14,
+                    _ => 0x15,
+                };
+                nargs(args, 3)?;
+                op << 26 | gpr(args[0])? << 21 | gpr(args[1])? << 16 | self.branch(args[2])?
+            }
+            "blez" | "bgtz" | "blezl" | "bgtzl" => {
+                let op = match m {
+                    "blez" => 0x06,
+                    "bgtz" => 0x07,
+                    "blezl" => 0x16,
+                    _ => 0x17,
+                };
+                nargs(args, 2)?;
+                op << 26 | gpr(args[0])? << 21 | self.branch(args[1])?
+            }
+
+            // Immediate ALU ops (rt, rs, imm)
+            "addi" | "addiu" | "slti" | "sltiu" | "daddi" | "daddiu" | "andi" | "ori" | "xori" => {
+                let op = match m {
+                    "addi" => 0x08,
+                    "addiu" => 0x09,
+                    "slti" => 0x0A,
+                    "sltiu" => 0x0B,
+                    "andi" => 0x0C,
+                    "ori" => 0x0D,
+                    "xori" => 0x0E,
+                    "daddi" => 0x18,
+                    _ => 0x19,
+                };
+                let (rt, rs, imm) = three(args)?;
+                let imm = match op {
+                    0x0C..=0x0E => self.uimm16(imm)?,
+                    _ => self.simm16(imm)?,
+                };
+                op << 26 | gpr(rs)? << 21 | gpr(rt)? << 16 | imm
+            }
+            "lui" => {
+                nargs(args, 2)?;
+                0x3C00_0000 | gpr(args[0])? << 16 | self.uimm16(args[1])?
+            }
+
+            // Loads and stores
+            "lb" | "lh" | "lwl" | "lw" | "lbu" | "lhu" | "lwr" | "lwu" | "sb" | "sh" | "swl"
+            | "sw" | "sdl" | "sdr" | "swr" | "ll" | "lld" | "ld" | "sc" | "scd" | "sd" | "ldl"
+            | "ldr" => {
+                let op = match m {
+                    "ldl" => 0x1A,
+                    "ldr" => 0x1B,
+                    "lb" => 0x20,
+                    "lh" => 0x21,
+                    "lwl" => 0x22,
+                    "lw" => 0x23,
+                    "lbu" => 0x24,
+                    "lhu" => 0x25,
+                    "lwr" => 0x26,
+                    "lwu" => 0x27,
+                    "sb" => 0x28,
+                    "sh" => 0x29,
+                    "swl" => 0x2A,
+                    "sw" => 0x2B,
+                    "sdl" => 0x2C,
+                    "sdr" => 0x2D,
+                    "swr" => 0x2E,
+                    "ll" => 0x30,
+                    "lld" => 0x34,
+                    "ld" => 0x37,
+                    "sc" => 0x38,
+                    "scd" => 0x3C,
+                    _ => 0x3F,
+                };
+                nargs(args, 2)?;
+                let (base, off) = self.memop16(args[1])?;
+                op << 26 | base << 21 | gpr(args[0])? << 16 | off
+            }
+            "lwc1" | "ldc1" | "swc1" | "sdc1" => {
+                let op = match m {
+                    "lwc1" => 0x31,
+                    "ldc1" => 0x35,
+                    "swc1" => 0x39,
+                    _ => 0x3D,
+                };
+                nargs(args, 2)?;
+                let (base, off) = self.memop16(args[1])?;
+                op << 26 | base << 21 | fpr(args[0])? << 16 | off
+            }
+            "cache" => {
+                nargs(args, 2)?;
+                let (base, off) = self.memop16(args[1])?;
+                0xBC00_0000 | base << 21 | self.uimm(args[0], 5)? << 16 | off
+            }
+
+            // COP0
+            "mfc0" | "dmfc0" | "mtc0" | "dmtc0" => {
+                let rs = match m {
+                    "mfc0" => 0x00,
+                    "dmfc0" => 0x01,
+                    "mtc0" => 0x04,
+                    _ => 0x05,
+                };
+                nargs(args, 2)?;
+                0x4000_0000 | rs << 21 | gpr(args[0])? << 16 | cop0reg(args[1])? << 11
+            }
+            "tlbr" | "tlbwi" | "tlbwr" | "tlbp" | "eret" => {
+                nargs(args, 0)?;
+                0x4200_0000
+                    | match m {
+                        "tlbr" => 0x01,
+                        "tlbwi" => 0x02,
+                        "tlbwr" => 0x06,
+                        "tlbp" => 0x08,
+                        _ => 0x18,
+                    }
+            }
+
+            // COP1
+            "mfc1" | "dmfc1" | "mtc1" | "dmtc1" => {
+                let rs = match m {
+                    "mfc1" => 0x00,
+                    "dmfc1" => 0x01,
+                    "mtc1" => 0x04,
+                    _ => 0x05,
+                };
+                nargs(args, 2)?;
+                0x4400_0000 | rs << 21 | gpr(args[0])? << 16 | fpr(args[1])? << 11
+            }
+            "cfc1" | "ctc1" => {
+                nargs(args, 2)?;
+                let fs = match args[1].to_lowercase().as_str() {
+                    "fcsr" => 31,
+                    "fir" => 0,
+                    _ => fpr(args[1]).or_else(|_| self.uimm(args[1], 5))?,
+                };
+                let rs = if m == "cfc1" { 0x02 } else { 0x06 };
+                0x4400_0000 | rs << 21 | gpr(args[0])? << 16 | fs << 11
+            }
+            "bc1f" | "bc1t" | "bc1fl" | "bc1tl" => {
+                let tf = match m {
+                    "bc1f" => 0,
+                    "bc1t" => 1,
+                    "bc1fl" => 2,
+                    _ => 3,
+                };
+                nargs(args, 1)?;
+                0x4500_0000 | tf << 16 | self.branch(args[0])?
+            }
+
+            // COP2 (RSP)
+            "mfc2" | "mtc2" => {
+                nargs(args, 2)?;
+                let (vs, e) = velem(args[1])?;
+                let rs = if m == "mfc2" { 0x00 } else { 0x04 };
+                0x4800_0000 | rs << 21 | gpr(args[0])? << 16 | vs << 11 | e << 7
+            }
+            "cfc2" | "ctc2" => {
+                nargs(args, 2)?;
+                let rd = match args[1].to_lowercase().as_str() {
+                    "vco" => 0,
+                    "vcc" => 1,
+                    "vce" => 2,
+                    _ => self.uimm(args[1], 5)?,
+                };
+                let rs = if m == "cfc2" { 0x02 } else { 0x06 };
+                0x4800_0000 | rs << 21 | gpr(args[0])? << 16 | rd << 11
+            }
+
+            _ => {
+                if let Some(insn) = self.fpu_op(m, args)? {
+                    insn
+                } else if let Some(insn) = self.vu_op(m, args)? {
+                    insn
+                } else if let Some(insn) = self.vu_memop(m, args)? {
+                    insn
+                } else {
+                    return Err(format!("unknown instruction: {}", m));
+                }
+            }
+        };
+        self.emit32(insn);
+        Ok(())
+    }
+
+    // FPU computational instructions (OP.FMT).
+    fn fpu_op(&self, m: &str, args: &[&str]) -> Result<Option<u32>> {
+        let dot = match m.rfind('.') {
+            Some(dot) => dot,
+            None => return Ok(None),
+        };
+        let fmt = match &m[dot + 1..] {
+            "s" => 0x10,
+            "d" => 0x11,
+            "w" => 0x14,
+            "l" => 0x15,
+            _ => return Ok(None),
+        };
+        let op = &m[..dot];
+
+        if op.starts_with("c.") {
+            let cond = match FPU_CONDS.iter().position(|c| *c == &op[2..]) {
+                Some(cond) => cond as u32,
+                None => return Ok(None),
+            };
+            nargs(args, 2)?;
+            return Ok(Some(fpu(fmt, 0x30 | cond, fpr(args[1])?, fpr(args[0])?, 0)));
+        }
+
+        let func = match op {
+            "add" => 0x00,
+            "sub" => 0x01,
+            "mul" => 0x02,
+            "div" => 0x03,
+            "sqrt" => 0x04,
+            "abs" => 0x05,
+            "mov" => 0x06,
+            "neg" => 0x07,
+            "round.l" => 0x08,
+            "trunc.l" => 0x09,
+            "ceil.l" => 0x0A,
+            "floor.l" => 0x0B,
+            "round.w" => 0x0C,
+            "trunc.w" => 0x0D,
+            "ceil.w" => 0x0E,
+            "floor.w" => 0x0F,
+            "cvt.s" => 0x20,
+            "cvt.d" => 0x21,
+            "cvt.w" => 0x24,
+            "cvt.l" => 0x25,
+            _ => return Ok(None),
+        };
+        Ok(Some(if func <= 0x03 {
+            let (fd, fs, ft) = three(args)?;
+            fpu(fmt, func, fpr(ft)?, fpr(fs)?, fpr(fd)?)
+        } else {
+            nargs(args, 2)?;
+            fpu(fmt, func, 0, fpr(args[1])?, fpr(args[0])?)
+        }))
+    }
+
+    // RSP vector computational instructions.
+    fn vu_op(&self, m: &str, args: &[&str]) -> Result<Option<u32>> {
+        let func = match VU_OPS.iter().position(|op| !op.is_empty() && *op == m) {
+            Some(func) => func as u32,
+            None => return Ok(None),
+        };
+        let (vd, vs, vt, e) = match func {
+            // vnop
+            0x37 => {
+                nargs(args, 0)?;
+                (0, 0, 0, 0)
+            }
+            // Single-lane ops: vd[de],vt[e]. The destination element is
+            // encoded in the vs field.
+            0x30..=0x36 => {
+                nargs(args, 2)?;
+                let (vd, de) = velem(args[0])?;
+                let (vt, e) = velem(args[1])?;
+                (vd, de, vt, e)
+            }
+            _ => {
+                let (vd, vs, vt) = three(args)?;
+                let (vt, e) = velem(vt)?;
+                (vreg(vd)?, vreg(vs)?, vt, e)
+            }
+        };
+        Ok(Some(
+            0x4A00_0000 | e << 21 | vt << 16 | vs << 11 | vd << 6 | func,
+        ))
+    }
+
+    // RSP vector loads and stores: vt[e],offset(base).
+    fn vu_memop(&self, m: &str, args: &[&str]) -> Result<Option<u32>> {
+        if m.len() != 3 || !m.ends_with('v') {
+            return Ok(None);
+        }
+        let op = match &m[..1] {
+            "l" => 0x32,
+            "s" => 0x3A,
+            _ => return Ok(None),
+        };
+        let (opc, shift) = match VU_MEMOPS.iter().find(|(suffix, _, _)| *suffix == &m[1..2]) {
+            Some((_, opc, shift)) => (*opc, *shift),
+            None => return Ok(None),
+        };
+        nargs(args, 2)?;
+        let (vt, e) = velem(args[0])?;
+        let (base, off) = self.memop(args[1])?;
+        // The offset is scaled by the access size; as in bass, the lower
+        // bits are simply dropped.
+        let off = off >> shift;
+        if off < -64 || off > 63 {
+            return Err(format!("offset out of range: {}", args[1]));
+        }
+        Ok(Some(
+            op << 26 | base << 21 | vt << 16 | opc << 11 | e << 7 | (off as u32 & 0x7F),
+        ))
+    }
+}
+
+fn special(func: u32, rs: u32, rt: u32, rd: u32, sa: u32) -> u32 {
+    rs << 21 | rt << 16 | rd << 11 | sa << 6 | func
+}
+
+fn fpu(fmt: u32, func: u32, ft: u32, fs: u32, fd: u32) -> u32 {
+    0x4400_0000 | fmt << 21 | ft << 16 | fs << 11 | fd << 6 | func
+}
+
+fn nargs(args: &[&str], n: usize) -> Result<()> {
+    if args.len() != n {
+        return Err(format!(
+            "invalid number of operands (expected {}, found {})",
+            n,
+            args.len()
+        ));
+    }
+    Ok(())
+}
+
+fn one<'a>(args: &[&'a str]) -> Result<&'a str> {
+    nargs(args, 1)?;
+    Ok(args[0])
+}
+
+// Operands of a three-operand instruction. With two operands, the first
+// one is both the destination and the first source.
+fn three<'a>(args: &[&'a str]) -> Result<(&'a str, &'a str, &'a str)> {
+    match args.len() {
+        2 => Ok((args[0], args[0], args[1])),
+        3 => Ok((args[0], args[1], args[2])),
+        n => Err(format!(
+            "invalid number of operands (expected 3, found {})",
+            n
+        )),
+    }
+}
+
+fn is_ident(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+// Split operands on commas, ignoring those within parentheses, brackets
+// or strings.
+fn split_args(args: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    if args.is_empty() {
+        return res;
+    }
+    let mut depth = 0;
+    let mut quote = false;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '"' => quote = !quote,
+            '(' | '[' if !quote => depth += 1,
+            ')' | ']' if !quote => depth -= 1,
+            ',' if !quote && depth == 0 => {
+                res.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    res.push(args[start..].trim());
+    res
+}
+
+// Parse a numbered register with the specified prefix (eg: "f12").
+fn numbered_reg(arg: &str, prefix: char) -> Option<u32> {
+    let arg = arg.to_lowercase();
+    if !arg.starts_with(prefix) {
+        return None;
+    }
+    match arg[1..].parse::<u32>() {
+        Ok(n) if n < 32 => Some(n),
+        _ => None,
+    }
+}
+
+fn gpr(arg: &str) -> Result<u32> {
+    let name = arg.trim().to_lowercase();
+    if let Some(idx) = GPR_NAMES.iter().position(|r| *r == name) {
+        return Ok(idx as u32);
+    }
+    match name.as_str() {
+        "zr" => return Ok(0),
+        "s8" => return Ok(30),
+        _ => {}
+    }
+    if let Some(n) = numbered_reg(&name, 'r') {
+        return Ok(n);
+    }
+    match name.parse::<u32>() {
+        Ok(n) if n < 32 => Ok(n),
+        _ => Err(format!("invalid register: {}", arg)),
+    }
+}
+
+fn fpr(arg: &str) -> Result<u32> {
+    numbered_reg(arg.trim(), 'f').ok_or_else(|| format!("invalid FPU register: {}", arg))
+}
+
+fn vreg(arg: &str) -> Result<u32> {
+    numbered_reg(arg.trim(), 'v').ok_or_else(|| format!("invalid vector register: {}", arg))
+}
+
+fn cop0reg(arg: &str) -> Result<u32> {
+    let arg = arg.trim();
+    if let Some(idx) = COP0_REG_NAMES
+        .iter()
+        .position(|r| r.eq_ignore_ascii_case(arg))
+    {
+        return Ok(idx as u32);
+    }
+    match arg.parse::<u32>() {
+        Ok(n) if n < 32 => Ok(n),
+        _ => Err(format!("invalid COP0 register: {}", arg)),
+    }
+}
+
+// Vector register with element: "v3[e5]". The element is optional, and
+// defaults to zero.
+fn velem(arg: &str) -> Result<(u32, u32)> {
+    let arg = arg.trim();
+    let open = match arg.find('[') {
+        Some(open) => open,
+        None => return Ok((vreg(arg)?, 0)),
+    };
+    if !arg.ends_with(']') {
+        return Err(format!("invalid vector element: {}", arg));
+    }
+    let e = arg[open + 1..arg.len() - 1].trim().to_lowercase();
+    let e = if e.starts_with('e') { &e[1..] } else { &e[..] };
+    match e.parse::<u32>() {
+        Ok(e) if e < 16 => Ok((vreg(&arg[..open])?, e)),
+        _ => Err(format!("invalid vector element: {}", arg)),
+    }
+}
+
+struct ExprParser<'a> {
+    syms: &'a HashMap<String, i64>,
+    src: &'a [u8],
+    pos: usize,
+}
+
+// Binary operators, with their precedence (higher binds tighter).
+const BINOPS: [(&'static str, usize); 10] = [
+    ("<<", 4),
+    (">>", 4),
+    ("|", 1),
+    ("^", 2),
+    ("&", 3),
+    ("+", 5),
+    ("-", 5),
+    ("*", 6),
+    ("/", 6),
+    ("%", 6),
+];
+
+impl<'a> ExprParser<'a> {
+    fn skip_ws(&mut self) {
+        while self.pos < self.src.len() && (self.src[self.pos] as char).is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_ws();
+        self.src.get(self.pos).map(|c| *c as char)
+    }
+
+    // Precedence climbing
+    fn expr(&mut self, min_prec: usize) -> Result<i64> {
+        let mut lhs = self.unary()?;
+        loop {
+            self.skip_ws();
+            let rest = &self.src[self.pos..];
+            let (op, prec) = match BINOPS
+                .iter()
+                .find(|(op, _)| rest.starts_with(op.as_bytes()))
+            {
+                Some(&(op, prec)) if prec > min_prec => (op, prec),
+                _ => return Ok(lhs),
+            };
+            self.pos += op.len();
+            let rhs = self.expr(prec)?;
+            lhs = match op {
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                _ if rhs == 0 => return Err("division by zero".into()),
+                "/" => lhs / rhs,
+                _ => lhs % rhs,
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<i64> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(self.unary()?.wrapping_neg())
+            }
+            Some('~') => {
+                self.pos += 1;
+                Ok(!self.unary()?)
+            }
+            Some('(') => {
+                self.pos += 1;
+                let val = self.expr(0)?;
+                if self.peek() != Some(')') {
+                    return Err("missing closing parenthesis".into());
+                }
+                self.pos += 1;
+                Ok(val)
+            }
+            Some(_) => self.primary(),
+            None => Err("missing operand".into()),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64> {
+        let start = self.pos;
+        while self.pos < self.src.len() {
+            let c = self.src[self.pos] as char;
+            if !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$' || c == '%') {
+                break;
+            }
+            // '%' is a prefix for binary numbers, but also the modulo operator.
+            if c == '%' && self.pos != start {
+                break;
+            }
+            self.pos += 1;
+        }
+        let tok = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+        if tok.is_empty() {
+            return Err(format!("unexpected character: {}", self.src[start] as char));
+        }
+
+        let lower = tok.to_lowercase();
+        let num = if lower.starts_with('$') {
+            i64::from_str_radix(&lower[1..], 16)
+        } else if lower.starts_with("0x") {
+            i64::from_str_radix(&lower[2..], 16)
+        } else if lower.starts_with('%') {
+            i64::from_str_radix(&lower[1..], 2)
+        } else if lower.starts_with("0b") {
+            i64::from_str_radix(&lower[2..], 2)
+        } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+            lower.parse::<i64>()
+        } else {
+            return self
+                .syms
+                .get(tok)
+                .cloned()
+                .ok_or_else(|| format!("undefined symbol: {}", tok));
+        };
+        num.map_err(|_| format!("invalid number: {}", tok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(src: &str) -> Vec<u32> {
+        assemble(src, 0)
+            .unwrap()
+            .chunks(4)
+            .map(|w| u32::from_be_bytes([w[0], w[1], w[2], w[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn test_cpu() {
+        assert_eq!(
+            words(
+                "
+                li a0,$800
+                lw t0,$20(a0)
+                add a2,a1,0
+                addi a2,$1F0   // two-operand form
+                subi a2,$10
+                sll t1,t0,2
+                jr ra
+                nop
+                "
+            ),
+            vec![
+                0x3C04_0000,
+                0x3484_0800,
+                0x8C88_0020,
+                0x00A0_3020,
+                0x20C6_01F0,
+                0x20C6_FFF0,
+                0x0008_4880,
+                0x03E0_0008,
+                0x0000_0000,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(
+            words(
+                "
+                constant SIZE(4*4)
+                Loop:
+                  bnez t0,Loop
+                  subi t0,SIZE
+                  jal Func
+                  nop
+                Func: jr ra
+                  nop
+                  dw Func, SIZE
+                "
+            ),
+            vec![
+                0x1500_FFFF,
+                0x2108_FFF0,
+                0x0C00_0004,
+                0x0000_0000,
+                0x03E0_0008,
+                0x0000_0000,
+                0x0000_0010,
+                0x0000_0010,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fpu() {
+        assert_eq!(
+            words(
+                "
+                add.s f0,f2,f4
+                cvt.d.w f6,f8
+                c.lt.d f2,f4
+                mtc1 t0,f1
+                ctc1 t0,fcsr
+                "
+            ),
+            vec![
+                0x4604_1000,
+                0x4680_41A1,
+                0x4624_103C,
+                0x4488_0800,
+                0x44C8_F800
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rsp() {
+        assert_eq!(
+            words(
+                "
+                vadd v2,v0,v1[e0]
+                vmulf v0,v1[e0]
+                vxor v1,v1
+                vsar v0,v0[e10]
+                vrcph v1[e1],v2[e3]
+                lqv v1[e0],$10(a0)
+                sqv v0[e15],$1F0(a1)
+                ltv v0[e1],$10(a0)
+                mfc2 t0,v0[e2]
+                cfc2 t0,vcc
+                "
+            ),
+            vec![
+                0x4A01_0090,
+                0x4A01_0000,
+                0x4A01_086C,
+                0x4B40_001D,
+                0x4A62_0872,
+                0xC881_2001,
+                0xE8A0_279F,
+                0xC880_5881,
+                0x4808_0100,
+                0x4848_0800,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            assemble("nop\n  foo t0\n", 0).unwrap_err(),
+            AsmError {
+                line: 2,
+                msg: "unknown instruction: foo".into()
+            }
+        );
+        assert!(assemble("addi t0,t1,$10000", 0).is_err());
+        assert!(assemble("b Missing", 0).is_err());
+        assert!(assemble("lqv v0[e16],0(a0)", 0).is_err());
+        assert!(assemble("L: nop\nL: nop", 0).is_err());
+    }
+}
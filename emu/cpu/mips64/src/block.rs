@@ -0,0 +1,194 @@
+use emu::bus::{WriteWatch, WATCH_PAGE_SHIFT};
+use emu::state::current_state_id;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Maximum number of instructions in a block.
+pub(crate) const MAX_BLOCK_LEN: usize = 64;
+
+/// A Block is a sequence of pre-decoded instructions, starting at a specific
+/// physical address. Each opcode is stored together with its handler (F),
+/// so that executing the block does not require decoding it again.
+///
+/// Blocks never cross a page boundary, so that they can be invalidated one
+/// page at a time.
+pub(crate) struct Block<F> {
+    pub ops: Vec<(F, u32)>,
+}
+
+/// BlockCache holds the blocks of a CPU (either pre-decoded or compiled),
+/// indexed by the physical address of their first instruction.
+///
+/// Pages containing blocks are registered into a WriteWatch, which must be
+/// attached to all the buses that can write to the code (the CPU bus, and
+/// the buses used for DMA). Blocks in a page are dropped as soon as the page
+/// is reported as written (see invalidate_page), and all blocks are dropped
+/// when a different state is loaded.
+pub(crate) struct BlockCache<B> {
+    blocks: HashMap<u32, Rc<B>>,
+    pages: HashMap<u32, Vec<u32>>, // Start addresses of the blocks in each page
+    state_id: u32,
+}
+
+impl<B> BlockCache<B> {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::new(),
+            pages: HashMap::new(),
+            state_id: current_state_id(),
+        }
+    }
+
+    pub fn get(&mut self, paddr: u32) -> Option<Rc<B>> {
+        if self.state_id != current_state_id() {
+            self.clear();
+            self.state_id = current_state_id();
+            return None;
+        }
+        self.blocks.get(&paddr).cloned()
+    }
+
+    pub fn insert(&mut self, paddr: u32, block: B, watch: &WriteWatch) -> Rc<B> {
+        let block = Rc::new(block);
+        self.blocks.insert(paddr, block.clone());
+        self.pages
+            .entry(paddr >> WATCH_PAGE_SHIFT)
+            .or_insert_with(Vec::new)
+            .push(paddr);
+        watch.watch(paddr);
+        block
+    }
+
+    /// Drop all the blocks within the specified page (as reported by
+    /// WriteWatch::take_dirty).
+    pub fn invalidate_page(&mut self, page: u32) {
+        if let Some(addrs) = self.pages.remove(&page) {
+            for paddr in addrs {
+                self.blocks.remove(&paddr);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.pages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{asm, ArchIII, Config, CopNull, Cp0, Cpu, CpuContext, Fpu};
+    use emu::bus::be::{Bus, Mem};
+    use emu::bus::{BusFill, MemFlags};
+    use emu::dbg::Tracer;
+    use slog::Discard;
+
+    struct TestConfig;
+
+    impl Config for TestConfig {
+        type Arch = ArchIII;
+        type Cop0 = Cp0;
+        type Cop1 = Fpu;
+        type Cop2 = CopNull;
+        type Cop3 = CopNull;
+    }
+
+    const CODE_ADDR: u64 = 0xFFFF_FFFF_BFC0_1000;
+
+    // A loop that patches its own body after the first iteration, and then
+    // spins forever in a two-instruction block.
+    const PROGRAM: &str = "
+        li v0,0
+        li v1,0
+        li t2,20
+        la t0,patch
+        li t1,$24420010     // addiu v0,v0,$10
+        la s0,$BFC08000
+    loop:
+    patch:
+        addiu v0,v0,1
+        sll v1,v1,1
+        xor v1,v1,v0
+        sw v1,0(s0)
+        lw a0,0(s0)
+        addu v1,v1,a0
+        addiu s0,s0,4
+        addiu t2,t2,-1
+        bnel t2,zero,loop
+        sw t1,0(t0)         // executed only if the branch is taken
+    spin:
+        beq zero,zero,spin
+        nop
+    ";
+
+    // Create a CPU running PROGRAM. The memory is also mapped on a second
+    // bus (as seen by a DMA engine), whose writes are reported to the CPU.
+    fn make_cpu(name: &'static str, cached: bool) -> (Cpu<TestConfig>, Box<Bus>) {
+        let logger = slog::Logger::root(Discard, o!());
+        let mut bus = Bus::new(logger.new(o!()));
+        let mut dma = Bus::new(logger.new(o!()));
+        let mut mem = Mem::new(name, 0x10000, MemFlags::default(), None);
+        let bin = asm::assemble(PROGRAM, CODE_ADDR).unwrap();
+        mem[0x1000..0x1000 + bin.len()].copy_from_slice(&bin);
+        bus.map_mem(0x1FC0_0000, 0x1FC0_FFFF, &mem, BusFill::None)
+            .unwrap();
+        dma.map_mem(0x0000_0000, 0x0000_FFFF, &mem, BusFill::None)
+            .unwrap();
+        let mut cpu = Cpu::new(
+            name,
+            logger.new(o!()),
+            bus,
+            (
+                Cp0::new(name, logger.new(o!())),
+                Fpu::new(name, logger.new(o!())),
+                CopNull {},
+                CopNull {},
+            ),
+        );
+        cpu.ctx_mut().set_pc(CODE_ADDR);
+        cpu.enable_block_cache(cached);
+        dma.watch_writes(0x0000_0000, 0x0000_FFFF, 0x1FC0_0000, cpu.code_watch());
+        (cpu, dma)
+    }
+
+    fn assert_ctx_eq(a: &CpuContext, b: &CpuContext) {
+        assert_eq!(a.regs, b.regs, "regs mismatch at pc {:x}", a.pc);
+        assert_eq!((a.hi, a.lo), (b.hi, b.lo), "hi/lo mismatch");
+        assert_eq!((a.pc, a.next_pc), (b.pc, b.next_pc), "pc mismatch");
+        assert_eq!(a.clock, b.clock, "clock mismatch at pc {:x}", a.pc);
+        assert_eq!(
+            (a.delay_slot, a.tight_exit, a.llbit),
+            (b.delay_slot, b.tight_exit, b.llbit),
+            "flags mismatch at pc {:x}",
+            a.pc
+        );
+    }
+
+    #[test]
+    fn lockstep() {
+        let (mut interp, mut interp_dma) = make_cpu("block-lockstep-interp", false);
+        let (mut cached, mut cached_dma) = make_cpu("block-lockstep-cached", true);
+
+        // Address of the delay slot of the final spin loop
+        let bin = asm::assemble(PROGRAM, CODE_ADDR).unwrap();
+        let spin = bin.len() as u32 - 4 + 0x1000;
+
+        // Run both CPUs for slices of varying length, and compare the
+        // contexts after each of them. Halfway, the delay slot of the spin
+        // loop is patched through DMA.
+        let t = Tracer::null();
+        let mut until = interp.ctx().clock;
+        for i in 0..200 {
+            if i == 100 {
+                assert_eq!(interp.ctx().regs[2], 1 + 19 * 0x10, "self-modifying code");
+                interp_dma.write::<u32>(spin, 0x2463_0001); // addiu v1,v1,1
+                cached_dma.write::<u32>(spin, 0x2463_0001);
+            }
+            until += 1 + (i * 7) % 23;
+            interp.run(until, &t).unwrap();
+            cached.run(until, &t).unwrap();
+            assert_ctx_eq(interp.ctx(), cached.ctx());
+        }
+    }
+}
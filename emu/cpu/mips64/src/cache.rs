@@ -0,0 +1,162 @@
+use emu::bus::be::Bus;
+use emu::memint::MemInt;
+use emu::state::ArrayField;
+
+use byteorder::BigEndian;
+use serde_derive::{Deserialize, Serialize};
+
+/// Geometry of a direct-mapped cache.
+#[derive(Copy, Clone, Debug)]
+pub struct CacheConfig {
+    /// Number of lines
+    pub lines: usize,
+    /// Size of each line in bytes (must be a power of two, multiple of 4)
+    pub line_size: usize,
+}
+
+impl CacheConfig {
+    /// R4300 instruction cache: 16 KiB, 32-byte lines.
+    pub const R4300_ICACHE: CacheConfig = CacheConfig {
+        lines: 512,
+        line_size: 32,
+    };
+
+    /// R4300 data cache: 8 KiB, 16-byte lines.
+    pub const R4300_DCACHE: CacheConfig = CacheConfig {
+        lines: 512,
+        line_size: 16,
+    };
+}
+
+/// Tag and state of a single cache line.
+#[derive(Copy, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct CacheLine {
+    /// Physical tag (physical address >> 12)
+    pub ptag: u32,
+    /// Line contains valid data
+    pub valid: bool,
+    /// Line has been modified and must be written back to memory
+    pub dirty: bool,
+}
+
+/// A direct-mapped, virtually-indexed, physically-tagged cache, like the
+/// ones found in the R4300. Lines are write-back and write-allocate.
+///
+/// The cache does not decide which accesses are cacheable: it is up to the
+/// CPU core to route only cached accesses through it.
+pub struct Cache {
+    line_size: usize,
+    lines: ArrayField<CacheLine>,
+    data: ArrayField<u8>,
+}
+
+impl Cache {
+    pub fn new(name: &str, cfg: CacheConfig) -> Cache {
+        assert!(cfg.line_size.is_power_of_two() && cfg.line_size >= 4);
+        Cache {
+            line_size: cfg.line_size,
+            lines: ArrayField::new(
+                &(name.to_owned() + "::lines"),
+                CacheLine::default(),
+                cfg.lines,
+            ),
+            data: ArrayField::new(
+                &(name.to_owned() + "::data"),
+                0u8,
+                cfg.lines * cfg.line_size,
+            ),
+        }
+    }
+
+    /// Index of the line that can hold the specified virtual address.
+    #[inline]
+    pub fn index(&self, vaddr: u64) -> usize {
+        (vaddr as usize / self.line_size) % self.lines.len()
+    }
+
+    /// Access the tag and state of the line at the specified index.
+    #[inline]
+    pub fn line(&self, idx: usize) -> &CacheLine {
+        &self.lines[idx]
+    }
+
+    #[inline]
+    pub fn line_mut(&mut self, idx: usize) -> &mut CacheLine {
+        &mut self.lines[idx]
+    }
+
+    /// Returns the index of the line containing the specified address, if
+    /// it is currently cached.
+    pub fn hit(&self, vaddr: u64, paddr: u32) -> Option<usize> {
+        let idx = self.index(vaddr);
+        let line = &self.lines[idx];
+        if line.valid && line.ptag == paddr >> 12 {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    /// Physical address of the first byte of the line at the specified index.
+    fn line_paddr(&self, idx: usize) -> u32 {
+        (self.lines[idx].ptag << 12) | ((idx * self.line_size) as u32 & 0xFFF)
+    }
+
+    /// Write the line at the specified index back to memory, if it is
+    /// valid and dirty. The line stays valid.
+    pub fn writeback(&mut self, idx: usize, bus: &mut Bus) {
+        let line = self.lines[idx];
+        if !line.valid || !line.dirty {
+            return;
+        }
+        let paddr = self.line_paddr(idx);
+        let off = idx * self.line_size;
+        for i in (0..self.line_size).step_by(4) {
+            let val = u32::endian_read_from::<BigEndian>(&self.data[off + i..off + i + 4]);
+            bus.write::<u32>((paddr + i as u32) & 0x1FFF_FFFF, val);
+        }
+        self.lines[idx].dirty = false;
+    }
+
+    /// Load the line containing the specified address from memory, writing
+    /// back the line that is being evicted if needed.
+    pub fn fill(&mut self, vaddr: u64, paddr: u32, bus: &mut Bus) -> usize {
+        let idx = self.index(vaddr);
+        self.writeback(idx, bus);
+
+        let base = paddr & !(self.line_size as u32 - 1);
+        let off = idx * self.line_size;
+        for i in (0..self.line_size).step_by(4) {
+            let val = bus.read::<u32>((base + i as u32) & 0x1FFF_FFFF);
+            u32::endian_write_to::<BigEndian>(&mut self.data[off + i..off + i + 4], val);
+        }
+        self.lines[idx] = CacheLine {
+            ptag: paddr >> 12,
+            valid: true,
+            dirty: false,
+        };
+        idx
+    }
+
+    /// Returns the index of the line containing the specified address,
+    /// filling it from memory in case of miss.
+    pub fn lookup(&mut self, vaddr: u64, paddr: u32, bus: &mut Bus) -> usize {
+        match self.hit(vaddr, paddr) {
+            Some(idx) => idx,
+            None => self.fill(vaddr, paddr, bus),
+        }
+    }
+
+    /// Read a value from the line at the specified index.
+    pub fn read<U: MemInt>(&self, idx: usize, vaddr: u64) -> U {
+        let off = idx * self.line_size + (vaddr as usize & (self.line_size - U::SIZE));
+        U::endian_read_from::<BigEndian>(&self.data[off..off + U::SIZE])
+    }
+
+    /// Write a value into the line at the specified index, marking it dirty.
+    pub fn write<U: MemInt>(&mut self, idx: usize, vaddr: u64, val: U) {
+        let off = idx * self.line_size + (vaddr as usize & (self.line_size - U::SIZE));
+        U::endian_write_to::<BigEndian>(&mut self.data[off..off + U::SIZE], val);
+        self.lines[idx].dirty = true;
+    }
+}
@@ -1,14 +1,15 @@
 use bitfield::bitfield;
 
 use super::decode::REG_NAMES;
-use super::{Cop, Cop0, CpuContext, Exception};
+use super::mmu::TlbResult;
+use super::{Cop, Cop0, CpuContext, Exception, MemAccess};
 use emu::dbg::{DebuggerRenderer, DecodedInsn, Operand, RegisterSize, RegisterView, Result, Tracer};
 use emu::int::Numerics;
 use emu::state::Field;
 use serde_derive::{Deserialize, Serialize};
 use slog;
 
-const COP0_REG_NAMES: [&'static str; 32] = [
+pub const COP0_REG_NAMES: [&'static str; 32] = [
     "Index",
     "Random",
     "EntryLo0",
@@ -50,6 +51,10 @@ bitfield! {
     #[inline] pub ie, set_ie: 0;    // Interrupt enable
     #[inline] pub exl, set_exl: 1;  // Is within standard exception
     #[inline] pub erl, set_erl: 2;  // Is within special exception (reset/nmi)
+    #[inline] pub ksu, set_ksu: 4,3; // Privilege level (0=kernel, 1=supervisor, 2=user)
+    #[inline] pub ux, set_ux: 5;    // 64-bit addressing in user mode
+    #[inline] pub sx, set_sx: 6;    // 64-bit addressing in supervisor mode
+    #[inline] pub kx, set_kx: 7;    // 64-bit addressing in kernel mode
     #[inline] pub im, set_im: 15,8; // Interrupt mask (8 lines)
     #[inline] pub nmi, set_nmi: 19; // Are we under NMI?
     #[inline] pub sr, set_sr: 20;   // Is this a soft reset?
@@ -75,6 +80,47 @@ bitfield! {
     #[inline] pub bd, set_bd: 31;    // Exception taken from delay slot
 }
 
+bitfield! {
+    #[derive(Default, Copy, Clone, Serialize, Deserialize)]
+    struct RegConfig(u32);
+    impl Debug;
+    #[inline] pub k0, set_k0: 2,0;  // KSEG0 cache algorithm (2=uncached)
+    #[inline] pub cu, set_cu: 3;    // Update on store conditional (unused)
+    #[inline] pub be, set_be: 15;   // Big endian
+    #[inline] pub ep, set_ep: 27,24; // Transmit data pattern
+    #[inline] pub ec, set_ec: 30,28; // System clock ratio (read-only)
+}
+
+// Config value at reset, with read-only fields set as on the VR4300.
+const CONFIG_RESET: u32 = 0x7006_E460;
+// Writable bits of Config (K0, CU, BE, EP)
+const CONFIG_MASK: u32 = 0x0F00_800F;
+
+// Processor revision identifier (VR4300, revision 2.2)
+const PRID: u32 = 0x0000_0B22;
+
+// WatchLo fields
+const WATCHLO_W: u32 = 1 << 0; // Trap on stores
+const WATCHLO_R: u32 = 1 << 1; // Trap on loads
+const WATCHLO_PADDR: u32 = 0xFFFF_FFF8; // Physical address (bits 31:3)
+
+// Privilege level of the CPU
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mode {
+    Kernel,
+    Supervisor,
+    User,
+}
+
+// Kind of segment a virtual address belongs to
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Segment {
+    // Mapped through the TLB
+    Mapped,
+    // Unmapped view of the physical memory
+    Unmapped { paddr: u32, cached: bool },
+}
+
 #[derive(Default, Copy, Clone, Serialize, Deserialize)]
 struct Cp0Context {
     reg_status: RegStatus,
@@ -82,14 +128,26 @@ struct Cp0Context {
     reg_errorepc: u64,
     reg_epc: u64,
     reg_index: u32,
+    reg_wired: u32,
     reg_pagemask: u32,
     reg_entryhi: u64,
     reg_entrylo0: u64,
     reg_entrylo1: u64,
+    reg_badvaddr: u64,
+    reg_context: u64,
+    reg_xcontext: u64,
+    reg_lladdr: u32,
+    reg_config: RegConfig,
+    reg_watchlo: u32,
+    reg_watchhi: u32,
+    reg_taglo: u32,
+    reg_taghi: u32,
     reg_compare: u32,
     last_count: u32,
     last_count_clock: i64,
     next_timer_interrupt: i64,
+    // Clock at which Random was last reset to its upper bound
+    random_clock: i64,
 }
 
 pub struct Cp0 {
@@ -120,6 +178,22 @@ impl Cp0 {
         self.update_timer_interrupt(cpu);
     }
 
+    // Random decrements on every cycle from 31 down to Wired, and then
+    // wraps around. It is computed lazily from the clock, like Count.
+    // If Wired is greater than 31, Random spans the whole TLB.
+    fn get_random(&self, cpu: &CpuContext) -> u32 {
+        let wired = self.ctx.reg_wired;
+        let range = if wired <= 31 { 32 - wired } else { 32 };
+        let elapsed = (cpu.clock - self.ctx.random_clock) as u64;
+        31 - (elapsed % range as u64) as u32
+    }
+
+    fn set_wired(&mut self, cpu: &CpuContext, val: u32) {
+        // Writing Wired also resets Random to its upper bound.
+        self.ctx.reg_wired = val & 0x3F;
+        self.ctx.random_clock = cpu.clock;
+    }
+
     fn set_compare(&mut self, cpu: &CpuContext, val: u32) {
         info!(self.logger, "COP0 write compare"; "val" => val);
 
@@ -142,6 +216,144 @@ impl Cp0 {
             "count" => self.get_count(cpu),
             "compare" => self.ctx.reg_compare);
     }
+
+    fn tlb_write(&mut self, cpu: &mut CpuContext, idx: usize) {
+        let ctx = &self.ctx;
+        cpu.mmu.write(
+            idx,
+            ctx.reg_pagemask,
+            ctx.reg_entryhi,
+            ctx.reg_entrylo0,
+            ctx.reg_entrylo1,
+        );
+
+        info!(self.logger, "wrote TLB entry";
+            "idx" => idx,
+            "tlb" => ?cpu.mmu.read(idx));
+    }
+
+    // Current privilege level. EXL and ERL force kernel mode, regardless
+    // of KSU.
+    fn mode(&self) -> Mode {
+        let st = &self.ctx.reg_status;
+        if st.exl() || st.erl() {
+            return Mode::Kernel;
+        }
+        match st.ksu() {
+            0 => Mode::Kernel,
+            1 => Mode::Supervisor,
+            _ => Mode::User,
+        }
+    }
+
+    // Whether 64-bit addressing is enabled in the specified mode.
+    fn is_64bit(&self, mode: Mode) -> bool {
+        let st = &self.ctx.reg_status;
+        match mode {
+            Mode::Kernel => st.kx(),
+            Mode::Supervisor => st.sx(),
+            Mode::User => st.ux(),
+        }
+    }
+
+    // Decode the segment of a virtual address for the current privilege
+    // level and addressing mode. Returns the (possibly sign-extended)
+    // virtual address, or Err if the access must raise an address error.
+    fn segment(&self, vaddr: u64) -> std::result::Result<(u64, Segment), u64> {
+        let mode = self.mode();
+        if !self.is_64bit(mode) {
+            // 32-bit addressing: the virtual address is the sign-extension
+            // of the lower 32 bits.
+            let vaddr = vaddr as u32 as i32 as i64 as u64;
+            return match self.segment32(mode, vaddr as u32) {
+                Some(seg) => Ok((vaddr, seg)),
+                None => Err(vaddr),
+            };
+        }
+
+        // Size of the user, supervisor and kernel mapped segments
+        const SEG_SIZE: u64 = 1 << 40;
+        let offset = vaddr & 0x3FFF_FFFF_FFFF_FFFF;
+        let seg = match (mode, vaddr >> 62) {
+            // XUSEG / XSUSEG / XKUSEG: mapped, unless ERL is set (in
+            // which case the lower 2 GiB are an unmapped, uncached view
+            // of the physical memory).
+            (Mode::Kernel, 0) if self.ctx.reg_status.erl() && vaddr < 0x8000_0000 => {
+                Some(Segment::Unmapped {
+                    paddr: vaddr as u32,
+                    cached: false,
+                })
+            }
+            (_, 0) if vaddr < SEG_SIZE => Some(Segment::Mapped),
+            // XSSEG / XKSSEG: mapped
+            (Mode::Supervisor, 1) | (Mode::Kernel, 1) if offset < SEG_SIZE => Some(Segment::Mapped),
+            // XKPHYS: unmapped, with the cache algorithm in bits 61:59.
+            // Physical addresses are limited to 32 bits.
+            (Mode::Kernel, 2) if offset & 0x07FF_FFFF_0000_0000 == 0 => Some(Segment::Unmapped {
+                paddr: vaddr as u32,
+                cached: (vaddr >> 59) & 7 != 2,
+            }),
+            // CSSEG / CKSEG0 / CKSEG1 / CKSSEG / CKSEG3: compatibility
+            // segments, same as the 32-bit ones.
+            (_, 3) if vaddr >= 0xFFFF_FFFF_8000_0000 => self.segment32(mode, vaddr as u32),
+            // XKSEG: mapped
+            (Mode::Kernel, 3) if offset < SEG_SIZE - 0x8000_0000 => Some(Segment::Mapped),
+            _ => None,
+        };
+        seg.map(|seg| (vaddr, seg)).ok_or(vaddr)
+    }
+
+    // Decode the segment of a 32-bit virtual address.
+    fn segment32(&self, mode: Mode, vaddr: u32) -> Option<Segment> {
+        match (mode, vaddr >> 29) {
+            // KUSEG: mapped, unless ERL is set (in which case it is
+            // an unmapped, uncached view of the physical memory).
+            (Mode::Kernel, 0..=3) if self.ctx.reg_status.erl() => Some(Segment::Unmapped {
+                paddr: vaddr,
+                cached: false,
+            }),
+            // KUSEG / SUSEG / USEG: mapped
+            (_, 0..=3) => Some(Segment::Mapped),
+            // KSEG0: unmapped, cached according to Config.K0
+            (Mode::Kernel, 4) => Some(Segment::Unmapped {
+                paddr: vaddr - 0x8000_0000,
+                cached: self.ctx.reg_config.k0() != 2,
+            }),
+            // KSEG1: unmapped, uncached
+            (Mode::Kernel, 5) => Some(Segment::Unmapped {
+                paddr: vaddr - 0xA000_0000,
+                cached: false,
+            }),
+            // KSSEG / SSEG: mapped
+            (Mode::Kernel, 6) | (Mode::Supervisor, 6) => Some(Segment::Mapped),
+            // KSEG3: mapped
+            (Mode::Kernel, 7) => Some(Segment::Mapped),
+            _ => None,
+        }
+    }
+
+    // Translate an address within a TLB-mapped segment.
+    fn tlb_translate(
+        &self,
+        cpu: &CpuContext,
+        vaddr: u64,
+        acc: MemAccess,
+    ) -> std::result::Result<u32, Exception> {
+        match cpu.mmu.lookup(vaddr, self.ctx.reg_entryhi as u8) {
+            TlbResult::Hit { paddr, dirty, .. } => {
+                if acc == MemAccess::Write && !dirty {
+                    Err(Exception::TlbModified(vaddr))
+                } else {
+                    Ok(paddr as u32)
+                }
+            }
+            TlbResult::Invalid => Err(Exception::TlbInvalid(acc, vaddr)),
+            // Refills use the XTLB vector when the current mode has
+            // 64-bit addressing enabled.
+            TlbResult::Miss if self.is_64bit(self.mode()) => Err(Exception::XTlbRefill(acc, vaddr)),
+            TlbResult::Miss => Err(Exception::TlbRefill(acc, vaddr)),
+        }
+    }
 }
 
 impl Cop0 for Cp0 {
@@ -172,59 +384,136 @@ impl Cop0 for Cp0 {
         }
     }
 
+    fn translate(
+        &self,
+        cpu: &CpuContext,
+        vaddr: u64,
+        acc: MemAccess,
+    ) -> std::result::Result<u32, Exception> {
+        match self.segment(vaddr) {
+            Ok((_, Segment::Unmapped { paddr, .. })) => Ok(paddr),
+            Ok((vaddr, Segment::Mapped)) => self.tlb_translate(cpu, vaddr, acc),
+            Err(vaddr) => Err(Exception::AddressError(acc, vaddr)),
+        }
+    }
+
+    fn is_cached(&self, cpu: &CpuContext, vaddr: u64) -> bool {
+        match self.segment(vaddr) {
+            Ok((_, Segment::Unmapped { cached, .. })) => cached,
+            Ok((vaddr, Segment::Mapped)) => match cpu.mmu.lookup(vaddr, self.ctx.reg_entryhi as u8)
+            {
+                TlbResult::Hit { cached, .. } => cached,
+                _ => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn watch(&self, _cpu: &CpuContext, paddr: u32, acc: MemAccess) -> bool {
+        let ctx = &self.ctx;
+        let enabled = match acc {
+            MemAccess::Read => ctx.reg_watchlo & WATCHLO_R != 0,
+            MemAccess::Write => ctx.reg_watchlo & WATCHLO_W != 0,
+            MemAccess::Fetch => false,
+        };
+        // Watch exceptions are not taken while handling another exception.
+        // Physical addresses are 32-bit, so WatchHi must be zero to match.
+        enabled
+            && !ctx.reg_status.exl()
+            && !ctx.reg_status.erl()
+            && ctx.reg_watchhi & 0xF == 0
+            && paddr & WATCHLO_PADDR == ctx.reg_watchlo & WATCHLO_PADDR
+    }
+
+    fn load_linked(&mut self, _cpu: &mut CpuContext, paddr: u32) {
+        // LLAddr holds bits 35:4 of the physical address
+        self.ctx.reg_lladdr = paddr >> 4;
+    }
+
+    fn cop_usable(&self, _cpu: &CpuContext, cop: u32) -> bool {
+        let st = &self.ctx.reg_status;
+        match cop {
+            // COP0 is always usable in kernel mode
+            0 => st.cu0() || st.exl() || st.erl() || st.ksu() == 0,
+            1 => st.cu1(),
+            2 => st.cu2(),
+            3 => st.cu3(),
+            _ => false,
+        }
+    }
+
     fn exception(&mut self, cpu: &mut CpuContext, exc: Exception) {
         use self::Exception::*;
 
         info!(self.logger, "exception"; "exc" => ?exc);
         let ctx = unsafe { self.ctx.as_mut() };
 
+        if let Some(vaddr) = exc.bad_vaddr() {
+            ctx.reg_badvaddr = vaddr;
+        }
+        if exc.is_tlb() {
+            let vaddr = ctx.reg_badvaddr;
+            ctx.reg_context = (ctx.reg_context & !0x7F_FFFF) | ((vaddr >> 9) & 0x7F_FFF0);
+            ctx.reg_xcontext = (ctx.reg_xcontext & !0x1_FFFF_FFFF)
+                | ((vaddr >> 62) << 31)
+                | ((vaddr >> 9) & 0x7FFF_FFF0);
+            ctx.reg_entryhi = (vaddr & 0xC000_00FF_FFFF_E000) | (ctx.reg_entryhi & 0xFF);
+        }
+
         match exc {
             ColdReset => {
-                // ctx.reg_random = 31;
-                // ctx.reg_wired = 0;
-                // ctx.reg_config.set_k0(2);
-                // ctx.reg_config[0..3] should be configured as specified in MipsConfig
+                ctx.random_clock = cpu.clock;
+                ctx.reg_wired = 0;
+                ctx.reg_config.0 = CONFIG_RESET;
+                ctx.reg_config.set_k0(2);
                 ctx.reg_status.set_rp(false);
                 ctx.reg_status.set_bev(true);
                 ctx.reg_status.set_ts(false);
                 ctx.reg_status.set_sr(false);
                 ctx.reg_status.set_nmi(false);
                 ctx.reg_status.set_erl(true);
-                // self.watch_lo[..] = 0;
-                // ctx.reg_perfcnt[..].set_ie(0);
+                ctx.reg_watchlo = 0;
+                ctx.reg_watchhi = 0;
                 ctx.reg_epc = cpu.pc;
                 cpu.set_pc(0xFFFF_FFFF_BFC0_0000);
             }
             SoftReset => {
-                // self.ref_config.set_k0(2);
+                ctx.random_clock = cpu.clock;
+                ctx.reg_wired = 0;
+                ctx.reg_config.set_k0(2);
                 ctx.reg_status.set_rp(false);
                 ctx.reg_status.set_bev(true);
                 ctx.reg_status.set_ts(false);
                 ctx.reg_status.set_sr(true);
                 ctx.reg_status.set_nmi(false);
                 ctx.reg_status.set_erl(true);
-                // self.watch_lo[..] = 0;
-                // ctx.reg_perfcnt[..].set_ie(0);
-                ctx.reg_epc = cpu.pc;
+                ctx.reg_watchlo = 0;
+                ctx.reg_watchhi = 0;
+                ctx.reg_errorepc = cpu.pc;
                 cpu.set_pc(0xFFFF_FFFF_BFC0_0000);
             }
             Nmi => {
-                error!(self.logger, "unimplemented exception type"; "exc" => ?exc);
+                // NMI goes through the reset vector like a soft reset, but
+                // the rest of the CPU state (including Config and the TLB)
+                // is preserved.
+                ctx.reg_status.set_bev(true);
+                ctx.reg_status.set_ts(false);
+                ctx.reg_status.set_sr(true);
+                ctx.reg_status.set_nmi(true);
+                ctx.reg_status.set_erl(true);
+                ctx.reg_errorepc = cpu.pc;
+                cpu.set_pc(0xFFFF_FFFF_BFC0_0000);
             }
             _ => {
                 // Standard exception
                 let vector = if !ctx.reg_status.exl() {
-                    if !cpu.delay_slot {
-                        ctx.reg_epc = cpu.pc;
-                        ctx.reg_cause.set_bd(false);
-                    } else {
-                        ctx.reg_epc = cpu.pc - 4;
-                        ctx.reg_cause.set_bd(true);
-                    }
+                    let (epc, bd) = cpu.exception_pc();
+                    ctx.reg_epc = epc;
+                    ctx.reg_cause.set_bd(bd);
 
                     match exc {
-                        TlbRefill => 0x0,
-                        XTlbRefill => 0x80,
+                        TlbRefill(..) => 0x0,
+                        XTlbRefill(..) => 0x80,
                         Interrupt if ctx.reg_cause.iv() => 0x200,
                         _ => 0x180,
                     }
@@ -233,7 +522,10 @@ impl Cop0 for Cp0 {
                 };
 
                 // Coprocessor unit number
-                ctx.reg_cause.set_ce(0);
+                ctx.reg_cause.set_ce(match exc {
+                    CopUnusable(cop) => cop,
+                    _ => 0,
+                });
                 ctx.reg_cause.set_exc(exc.exc_code().unwrap_or(0));
                 ctx.reg_status.set_exl(true);
                 if ctx.reg_status.bev() {
@@ -250,15 +542,27 @@ impl Cop for Cp0 {
     fn reg(&self, cpu: &CpuContext, idx: usize) -> u128 {
         match idx {
             0 => self.ctx.reg_index as u128,
+            1 => self.get_random(cpu) as u128,
             2 => self.ctx.reg_entrylo0 as u128,
             3 => self.ctx.reg_entrylo1 as u128,
+            4 => self.ctx.reg_context as u128,
             5 => self.ctx.reg_pagemask as u128,
+            6 => self.ctx.reg_wired as u128,
+            8 => self.ctx.reg_badvaddr as u128,
             9 => self.get_count(cpu) as u128,
             10 => self.ctx.reg_entryhi as u128,
             11 => self.ctx.reg_compare as u128,
             12 => self.ctx.reg_status.0 as u128,
             13 => self.ctx.reg_cause.0 as u128,
             14 => self.ctx.reg_epc as u128,
+            15 => PRID as u128,
+            16 => self.ctx.reg_config.0 as u128,
+            17 => self.ctx.reg_lladdr as u128,
+            18 => self.ctx.reg_watchlo as u128,
+            19 => self.ctx.reg_watchhi as u128,
+            20 => self.ctx.reg_xcontext as u128,
+            28 => self.ctx.reg_taglo as u128,
+            29 => self.ctx.reg_taghi as u128,
             30 => self.ctx.reg_errorepc as u128,
             _ => {
                 error!(
@@ -274,11 +578,19 @@ impl Cop for Cp0 {
     fn set_reg(&mut self, cpu: &mut CpuContext, idx: usize, val: u128) {
         match idx {
             0 => self.ctx.reg_index = val as u32 & 0x3F,
+            1 => {} // Random is read-only
             2 => self.ctx.reg_entrylo0 = val as u64,
             3 => self.ctx.reg_entrylo1 = val as u64,
+            4 => {
+                // Only PTEBase is writable, BadVPN2 is read-only.
+                self.ctx.reg_context =
+                    (self.ctx.reg_context & 0x7F_FFFF) | (val as u64 & !0x7F_FFFF);
+            }
             5 => self.ctx.reg_pagemask = val as u32,
+            6 => self.set_wired(cpu, val as u32),
+            8 => {} // BadVAddr is read-only
             9 => self.set_count(cpu, val as u32),
-            10 => self.ctx.reg_entryhi = val as u64,
+            10 => self.ctx.reg_entryhi = val as u64 & 0xC000_00FF_FFFF_E0FF,
             11 => self.set_compare(cpu, val as u32),
             12 => {
                 self.ctx.reg_status.0 = val as u32;
@@ -290,6 +602,21 @@ impl Cop for Cp0 {
                 cpu.tight_exit = true;
             }
             14 => self.ctx.reg_epc = val as u64,
+            15 => {} // PRId is read-only
+            16 => {
+                self.ctx.reg_config.0 =
+                    (self.ctx.reg_config.0 & !CONFIG_MASK) | (val as u32 & CONFIG_MASK);
+            }
+            17 => self.ctx.reg_lladdr = val as u32,
+            18 => self.ctx.reg_watchlo = val as u32 & (WATCHLO_PADDR | WATCHLO_R | WATCHLO_W),
+            19 => self.ctx.reg_watchhi = val as u32 & 0xF,
+            20 => {
+                // Only PTEBase is writable, R and BadVPN2 are read-only.
+                self.ctx.reg_xcontext =
+                    (self.ctx.reg_xcontext & 0x1_FFFF_FFFF) | (val as u64 & !0x1_FFFF_FFFF);
+            }
+            28 => self.ctx.reg_taglo = val as u32,
+            29 => self.ctx.reg_taghi = val as u32,
             30 => self.ctx.reg_errorepc = val as u64,
             _ => {
                 error!(
@@ -312,11 +639,19 @@ impl Cop for Cp0 {
             0x00 => {
                 // MFC0
                 let _sel = opcode & 7;
+                cpu.regs[rt] = (self.reg(cpu, rd) as u32).sx64();
+            }
+            0x01 => {
+                // DMFC0
                 cpu.regs[rt] = self.reg(cpu, rd) as u64;
             }
             0x04 => {
                 // MTC0
                 let _sel = opcode & 7;
+                self.set_reg(cpu, rd, (cpu.regs[rt] as u32).sx64() as u128);
+            }
+            0x05 => {
+                // DMTC0
                 self.set_reg(cpu, rd, cpu.regs[rt] as u128);
             }
             0x10..=0x1F => match func {
@@ -333,17 +668,12 @@ impl Cop for Cp0 {
                 }
                 0x02 => {
                     // TLBWI
-                    cpu.mmu.write(
-                        (ctx.reg_index & 0x1F) as usize,
-                        ctx.reg_pagemask,
-                        ctx.reg_entryhi,
-                        ctx.reg_entrylo0,
-                        ctx.reg_entrylo1,
-                    );
-
-                    info!(self.logger, "wrote TLB entry";
-                        "idx" => ctx.reg_index,
-                        "tlb" => ?cpu.mmu.read((ctx.reg_index & 0x1F) as usize));
+                    self.tlb_write(cpu, (ctx.reg_index & 0x1F) as usize);
+                }
+                0x06 => {
+                    // TLBWR
+                    let idx = self.get_random(cpu) as usize;
+                    self.tlb_write(cpu, idx);
                 }
                 0x08 => {
                     // TLBP
@@ -365,6 +695,7 @@ impl Cop for Cp0 {
                 0x18 => {
                     // ERET
                     // FIXME: verify that it's a NOP when ERL/EXL are 0
+                    cpu.llbit = false;
                     if ctx.reg_status.erl() {
                         ctx.reg_status.set_erl(false);
                         cpu.set_pc(ctx.reg_errorepc);
@@ -400,10 +731,13 @@ impl Cop for Cp0 {
 
         match rs {
             0x00 => DecodedInsn::new2("mfc0", OReg(rt), IReg(c0rd)),
+            0x01 => DecodedInsn::new2("dmfc0", OReg(rt), IReg(c0rd)),
             0x04 => DecodedInsn::new2("mtc0", IReg(rt), OReg(c0rd)),
+            0x05 => DecodedInsn::new2("dmtc0", IReg(rt), OReg(c0rd)),
             0x10..=0x1F => match func {
                 0x1 => DecodedInsn::new0("tlbr"),
                 0x2 => DecodedInsn::new0("tlbwi"),
+                0x6 => DecodedInsn::new0("tlbwr"),
                 0x8 => DecodedInsn::new0("tlbp"),
                 0x18 => DecodedInsn::new0("eret"),
                 _ => DecodedInsn::new1("cop0op?", Imm32(func)),
@@ -457,10 +791,20 @@ impl RegisterView for Cp0 {
                 visit("ErrorEPC", Reg64(&mut ctx.reg_errorepc), None);
 
                 visit("Index", Reg32(&mut ctx.reg_index), None);
+                visit("Wired", Reg32(&mut ctx.reg_wired), None);
                 visit("PageMask", Reg32(&mut ctx.reg_pagemask), None);
                 visit("EntryHi", Reg64(&mut ctx.reg_entryhi), None);
                 visit("EntryLo0", Reg64(&mut ctx.reg_entrylo0), None);
                 visit("EntryLo1", Reg64(&mut ctx.reg_entrylo1), None);
+                visit("BadVAddr", Reg64(&mut ctx.reg_badvaddr), None);
+                visit("Context", Reg64(&mut ctx.reg_context), None);
+                visit("XContext", Reg64(&mut ctx.reg_xcontext), None);
+                visit("LLAddr", Reg32(&mut ctx.reg_lladdr), None);
+                visit("Config", Reg32(&mut ctx.reg_config.0), None);
+                visit("WatchLo", Reg32(&mut ctx.reg_watchlo), None);
+                visit("WatchHi", Reg32(&mut ctx.reg_watchhi), None);
+                visit("TagLo", Reg32(&mut ctx.reg_taglo), None);
+                visit("TagHi", Reg32(&mut ctx.reg_taghi), None);
 
                 visit("Compare", Reg32(&mut ctx.reg_compare), None);
             }
@@ -468,3 +812,204 @@ impl RegisterView for Cp0 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::Discard;
+
+    fn make_cp0(name: &'static str) -> (Cp0, CpuContext) {
+        let logger = slog::Logger::root(Discard, o!());
+        (Cp0::new(name, logger), CpuContext::default())
+    }
+
+    #[test]
+    fn reset_values() {
+        // Each CPU needs its own state fields, hence a different name
+        for &(exc, soft, name) in [
+            (Exception::ColdReset, false, "cp0-coldreset"),
+            (Exception::SoftReset, true, "cp0-softreset"),
+        ]
+        .iter()
+        {
+            let (mut cp0, mut cpu) = make_cp0(name);
+            cp0.exception(&mut cpu, Exception::ColdReset);
+            cp0.set_reg(&mut cpu, 6, 10);
+            cp0.set_reg(&mut cpu, 16, 0x7006_E463);
+            cpu.clock += 1000;
+
+            cp0.exception(&mut cpu, exc);
+            assert_eq!(cp0.reg(&cpu, 1), 31, "{:?}: Random", exc);
+            assert_eq!(cp0.reg(&cpu, 6), 0, "{:?}: Wired", exc);
+            assert_eq!(cp0.reg(&cpu, 16), 0x7006_E462, "{:?}: Config", exc);
+            assert!(cp0.ctx.reg_status.erl(), "{:?}: ERL", exc);
+            assert!(cp0.ctx.reg_status.bev(), "{:?}: BEV", exc);
+            assert_eq!(cp0.ctx.reg_status.sr(), soft, "{:?}: SR", exc);
+            assert_eq!(cpu.pc, 0xFFFF_FFFF_BFC0_0000);
+
+            // Random counts down from 31 to Wired (now 0)
+            cpu.clock += 31;
+            assert_eq!(cp0.reg(&cpu, 1), 0, "{:?}: Random", exc);
+            cpu.clock += 1;
+            assert_eq!(cp0.reg(&cpu, 1), 31, "{:?}: Random", exc);
+        }
+    }
+
+    #[test]
+    fn tlbwr_random() {
+        const TLBWR: u32 = 0x4200_0006;
+        let (mut cp0, mut cpu) = make_cp0("cp0-tlbwr");
+        let t = Tracer::null();
+        cp0.exception(&mut cpu, Exception::ColdReset);
+        cp0.set_reg(&mut cpu, 6, 28);
+
+        let mut written = [false; 32];
+        for n in 1..=20u64 {
+            let idx = cp0.reg(&cpu, 1) as usize;
+            assert!(idx >= 28 && idx <= 31, "Random out of range: {}", idx);
+            cp0.set_reg(&mut cpu, 10, (n << 13) as u128);
+            cp0.op(&mut cpu, TLBWR, &t).unwrap();
+            assert_eq!(cpu.mmu.read(idx).hi(), n << 13);
+            written[idx] = true;
+            cpu.clock += 1;
+        }
+        assert_eq!(written[..28], [false; 28][..], "wired entries overwritten");
+        assert_eq!(written[28..], [true; 4][..]);
+    }
+
+    const KSU_SUPERVISOR: u128 = 1 << 3;
+    const KSU_USER: u128 = 2 << 3;
+    const UX: u128 = 1 << 5;
+    const SX: u128 = 1 << 6;
+    const KX: u128 = 1 << 7;
+
+    fn mapped(vaddr: u64) -> std::result::Result<(u64, Segment), u64> {
+        Ok((vaddr, Segment::Mapped))
+    }
+
+    fn unmapped(paddr: u32, cached: bool) -> Segment {
+        Segment::Unmapped { paddr, cached }
+    }
+
+    #[test]
+    fn segment_address_errors() {
+        let (mut cp0, mut cpu) = make_cp0("cp0-segment");
+
+        // 32-bit user mode: only USEG is accessible
+        cp0.set_reg(&mut cpu, 12, KSU_USER);
+        assert_eq!(cp0.segment(0x7FFF_FFFF), mapped(0x7FFF_FFFF));
+        assert_eq!(cp0.segment(0x8000_0000), Err(0xFFFF_FFFF_8000_0000));
+        assert_eq!(cp0.segment(0xC000_0000), Err(0xFFFF_FFFF_C000_0000));
+
+        // 32-bit supervisor mode: SUSEG and SSEG
+        cp0.set_reg(&mut cpu, 12, KSU_SUPERVISOR);
+        assert_eq!(cp0.segment(0x1000), mapped(0x1000));
+        assert_eq!(cp0.segment(0xC000_0000), mapped(0xFFFF_FFFF_C000_0000));
+        assert_eq!(cp0.segment(0xA000_0000), Err(0xFFFF_FFFF_A000_0000));
+        assert_eq!(cp0.segment(0xE000_0000), Err(0xFFFF_FFFF_E000_0000));
+
+        // 64-bit user mode: XUSEG (1 TiB)
+        cp0.set_reg(&mut cpu, 12, KSU_USER | UX);
+        assert_eq!(cp0.segment(0xFF_FFFF_FFFF), mapped(0xFF_FFFF_FFFF));
+        assert_eq!(cp0.segment(0x100_0000_0000), Err(0x100_0000_0000));
+        assert_eq!(
+            cp0.segment(0x4000_0000_0000_0000),
+            Err(0x4000_0000_0000_0000)
+        );
+        assert_eq!(
+            cp0.segment(0xFFFF_FFFF_8000_0000),
+            Err(0xFFFF_FFFF_8000_0000)
+        );
+
+        // 64-bit supervisor mode: XSUSEG, XSSEG and CSSEG
+        cp0.set_reg(&mut cpu, 12, KSU_SUPERVISOR | SX);
+        let xsseg = 0x4000_00FF_FFFF_FFFF;
+        assert_eq!(cp0.segment(xsseg), mapped(xsseg));
+        assert_eq!(cp0.segment(xsseg + 1), Err(xsseg + 1));
+        assert_eq!(
+            cp0.segment(0x9000_0000_0000_0000),
+            Err(0x9000_0000_0000_0000)
+        );
+        assert_eq!(
+            cp0.segment(0xFFFF_FFFF_C000_0000),
+            mapped(0xFFFF_FFFF_C000_0000)
+        );
+        assert_eq!(
+            cp0.segment(0xFFFF_FFFF_8000_0000),
+            Err(0xFFFF_FFFF_8000_0000)
+        );
+
+        // The processor is in kernel mode while EXL is set
+        cp0.set_reg(&mut cpu, 12, KSU_USER | 0x2);
+        assert_eq!(
+            cp0.segment(0xA000_0000),
+            Ok((0xFFFF_FFFF_A000_0000, unmapped(0, false)))
+        );
+    }
+
+    #[test]
+    fn segment_xkphys() {
+        let (mut cp0, mut cpu) = make_cp0("cp0-xkphys");
+        cp0.set_reg(&mut cpu, 12, KX);
+
+        // The cache algorithm is in bits 61:59 (2 = uncached)
+        for &(vaddr, cached) in [
+            (0x8800_0000_0000_1000, true),
+            (0x9000_0000_0000_1000, false),
+            (0x9800_0000_0000_1000, true),
+            (0xB800_0000_0000_1000, true),
+        ]
+        .iter()
+        {
+            assert_eq!(cp0.segment(vaddr), Ok((vaddr, unmapped(0x1000, cached))));
+        }
+        assert_eq!(
+            cp0.segment(0x9000_0000_FFFF_FFFC),
+            Ok((0x9000_0000_FFFF_FFFC, unmapped(0xFFFF_FFFC, false)))
+        );
+
+        // Physical addresses are limited to 32 bits
+        assert_eq!(
+            cp0.segment(0x9000_0001_0000_0000),
+            Err(0x9000_0001_0000_0000)
+        );
+
+        // XKPHYS is not accessible in 32-bit kernel mode
+        cp0.set_reg(&mut cpu, 12, 0);
+        assert_eq!(
+            cp0.segment(0x9000_0000_0000_1000),
+            Ok((0x1000, Segment::Mapped))
+        );
+    }
+
+    #[test]
+    fn tlb_refill_vector() {
+        let (mut cp0, mut cpu) = make_cp0("cp0-refill");
+
+        // TLB misses use the XTLB refill vector (offset 0x80) when the
+        // current mode has 64-bit addressing enabled.
+        for &(status, vaddr, pc) in [
+            (0, 0x1000_0000, 0xFFFF_FFFF_8000_0000),
+            (KX, 0x1000_0000, 0xFFFF_FFFF_8000_0080),
+            (KX, 0xC000_0000_1000_0000, 0xFFFF_FFFF_8000_0080),
+            (KSU_USER, 0x1000_0000, 0xFFFF_FFFF_8000_0000),
+            (KSU_USER | UX, 0x1000_0000, 0xFFFF_FFFF_8000_0080),
+            (KSU_USER | KX, 0x1000_0000, 0xFFFF_FFFF_8000_0000),
+            (
+                KSU_SUPERVISOR | SX,
+                0x4000_0000_1000_0000,
+                0xFFFF_FFFF_8000_0080,
+            ),
+        ]
+        .iter()
+        {
+            cp0.set_reg(&mut cpu, 12, status);
+            let exc = cp0
+                .translate(&cpu, vaddr, MemAccess::Read)
+                .expect_err("TLB miss expected");
+            cp0.exception(&mut cpu, exc);
+            assert_eq!(cpu.pc, pc, "status: {:x}, vaddr: {:x}", status, vaddr);
+            assert_eq!(cp0.reg(&cpu, 8) as u64, vaddr, "BadVAddr");
+        }
+    }
+}
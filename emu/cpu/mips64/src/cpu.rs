@@ -1,10 +1,15 @@
+use super::block::{Block, BlockCache, MAX_BLOCK_LEN};
+use super::cache::{Cache, CacheConfig, CacheLine};
 use super::decode::{decode, REG_NAMES};
+#[cfg(all(target_arch = "x86_64", unix))]
+use super::jit::Jit;
 use super::mmu::Mmu;
-use super::{Arch, Config, Cop, Cop0};
+use super::{Arch, Config, Cop, Cop0, Timing};
 
 use emu::bus::be::{Bus, MemIoR};
+use emu::bus::WriteWatch;
 use emu::dbg::{
-    BusMemoryView, DebuggerRenderer, DecodedInsn, DisasmView, MemoryBank, RegisterSize,
+    BusMemoryView, DebuggerRenderer, DecodedInsn, DisasmView, MemoryBank, Operand, RegisterSize,
     RegisterView, Result, Tracer,
 };
 use emu::int::Numerics;
@@ -15,6 +20,16 @@ use emu::sync;
 use byteorder::ByteOrder;
 use serde_derive::{Deserialize, Serialize};
 use slog;
+use std::rc::Rc;
+
+/// Kind of memory access performed by the CPU, used for address translation
+/// and to select the correct exception code.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MemAccess {
+    Fetch, // Instruction fetch
+    Read,  // Data load
+    Write, // Data store
+}
 
 #[derive(Copy, Clone, Debug)]
 pub enum Exception {
@@ -23,9 +38,18 @@ pub enum Exception {
     ColdReset,
     SoftReset,
     Nmi,
-    TlbRefill,
-    XTlbRefill,
+    TlbRefill(MemAccess, u64),  // TLB miss (32-bit addressing)
+    XTlbRefill(MemAccess, u64), // TLB miss (64-bit addressing)
+    TlbInvalid(MemAccess, u64), // TLB entry found, but page is not valid
+    TlbModified(u64),           // Write to a TLB page which is not dirty
+    AddressError(MemAccess, u64), // Unaligned or illegal address (AdEL/AdES)
+    Syscall,                      // SYSCALL opcode
+    ReservedInstruction,          // Opcode not implemented by the architecture
+    CopUnusable(u32),             // Coprocessor disabled in Status (Cause.CE)
+    Overflow,                     // Signed arithmetic overflow
     Trap,
+    FloatingPoint,                // FPU exception (see FCSR Cause field)
+    Watch,                        // Data access matching WatchLo/WatchHi
 }
 
 impl Exception {
@@ -36,9 +60,48 @@ impl Exception {
             Exception::ColdReset => None,
             Exception::Nmi => None,
             Exception::SoftReset => None,
-            Exception::TlbRefill => None,
-            Exception::XTlbRefill => None,
+            Exception::TlbModified(_) => Some(0x01),
+            Exception::TlbRefill(acc, _)
+            | Exception::XTlbRefill(acc, _)
+            | Exception::TlbInvalid(acc, _) => match acc {
+                MemAccess::Write => Some(0x03),
+                _ => Some(0x02),
+            },
+            Exception::AddressError(acc, _) => match acc {
+                MemAccess::Write => Some(0x05),
+                _ => Some(0x04),
+            },
+            Exception::Syscall => Some(0x08),
+            Exception::ReservedInstruction => Some(0x0A),
+            Exception::CopUnusable(_) => Some(0x0B),
+            Exception::Overflow => Some(0x0C),
             Exception::Trap => Some(0x0D),
+            Exception::FloatingPoint => Some(0x0F),
+            Exception::Watch => Some(0x17),
+        }
+    }
+
+    /// Return the virtual address that caused the exception (to be stored
+    /// into BadVAddr), if any.
+    pub fn bad_vaddr(&self) -> Option<u64> {
+        match *self {
+            Exception::TlbRefill(_, vaddr)
+            | Exception::XTlbRefill(_, vaddr)
+            | Exception::TlbInvalid(_, vaddr)
+            | Exception::TlbModified(vaddr)
+            | Exception::AddressError(_, vaddr) => Some(vaddr),
+            _ => None,
+        }
+    }
+
+    /// Return true if this is an exception raised by the TLB.
+    pub fn is_tlb(&self) -> bool {
+        match self {
+            Exception::TlbRefill(..)
+            | Exception::XTlbRefill(..)
+            | Exception::TlbInvalid(..)
+            | Exception::TlbModified(..) => true,
+            _ => false,
         }
     }
 }
@@ -60,7 +123,22 @@ pub struct CpuContext {
     pub delay_slot: bool, // True if the current insn is a delay slot
     pub mmu: Mmu,         // The MMU
     pub fpu64: bool,      // True if the FPU (if any) is in 64-bit mode
+    pub llbit: bool,      // True if a load-linked sequence is in progress
     lines: Lines,
+
+    // Timing model state: clock at which HI/LO become available, and
+    // register written by the last load (0 if none).
+    hilo_ready: i64,
+    load_reg: usize,
+
+    // Address of the instruction being executed, and whether it is being
+    // executed within a delay slot. Used to compute EPC on exceptions.
+    pub(crate) exc_pc: u64,
+    pub(crate) exc_delay_slot: bool,
+
+    // Exception requested by a coprocessor (see raise_exception)
+    #[serde(skip)]
+    pub(crate) cop_exception: Option<Exception>,
 }
 
 pub struct Cpu<C: Config> {
@@ -72,6 +150,26 @@ pub struct Cpu<C: Config> {
 
     ctx: Field<CpuContext>,
 
+    // Optional cache model (see enable_caches)
+    icache: Option<Cache>,
+    dcache: Option<Cache>,
+
+    // True if the timing model is enabled (see enable_timing), and the
+    // private state of the model
+    timing: bool,
+    timing_state: <C::Timing as Timing>::State,
+
+    // Pre-decoded blocks, if the cached interpreter is enabled
+    // (see enable_block_cache)
+    blocks: Option<BlockCache<Block<OpFn<C>>>>,
+
+    // Pages holding pre-decoded or compiled blocks (see code_watch)
+    pub(crate) code_watch: Rc<WriteWatch>,
+
+    // Dynamic recompiler, if enabled (see enable_jit)
+    #[cfg(all(target_arch = "x86_64", unix))]
+    jit: Option<Jit<C>>,
+
     name: String,
     logger: slog::Logger,
     until: i64,
@@ -79,7 +177,10 @@ pub struct Cpu<C: Config> {
     last_busy_check: u64,
 }
 
-struct Mipsop<'a, C: Config> {
+// Handler of a single opcode, as returned by Cpu::decode_op.
+pub(crate) type OpFn<C> = fn(&mut Mipsop<C>, &Tracer) -> Result<()>;
+
+pub(crate) struct Mipsop<'a, C: Config> {
     ctx: &'a mut CpuContext,
     opcode: u32,
     cpu: &'a mut Cpu<C>,
@@ -92,8 +193,8 @@ impl<'a, C: Config> Mipsop<'a, C> {
     fn special(&self) -> u32 {
         self.opcode & 0x3f
     }
-    fn ea(&self) -> u32 {
-        self.rs32() + self.sximm32() as u32
+    fn ea(&self) -> u64 {
+        self.rs64().wrapping_add(self.sximm64() as u64)
     }
     fn sa(&self) -> usize {
         ((self.opcode >> 6) & 0x1f) as usize
@@ -146,11 +247,11 @@ impl<'a, C: Config> Mipsop<'a, C> {
     fn irt32(&self) -> i32 {
         self.rt64() as i32
     }
-    fn mrt64(&'a mut self) -> &'a mut u64 {
+    fn mrt64(&mut self) -> &mut u64 {
         let rt = self.rt();
         &mut self.ctx.regs[rt]
     }
-    fn mrd64(&'a mut self) -> &'a mut u64 {
+    fn mrd64(&mut self) -> &mut u64 {
         let rd = self.rd();
         &mut self.ctx.regs[rd]
     }
@@ -181,12 +282,31 @@ impl CpuContext {
     pub fn set_pc(&mut self, pc: u64) {
         self.pc = pc;
         self.next_pc = pc + 4;
+        self.delay_slot = false;
         self.tight_exit = true;
     }
 
     pub fn get_pc(&self) -> u64 {
         self.pc
     }
+
+    /// Request an exception from within a coprocessor opcode. Coprocessors
+    /// do not have access to the core, so the exception is raised by the
+    /// core as soon as the opcode returns.
+    pub fn raise_exception(&mut self, exc: Exception) {
+        self.cop_exception = Some(exc);
+    }
+
+    /// Return the address that must be saved into EPC for an exception raised
+    /// at the current point of execution, and whether the faulting instruction
+    /// is within a delay slot (in which case, EPC points to the branch).
+    pub fn exception_pc(&self) -> (u64, bool) {
+        if self.exc_delay_slot {
+            (self.exc_pc - 4, true)
+        } else {
+            (self.exc_pc, false)
+        }
+    }
 }
 
 macro_rules! branch {
@@ -205,6 +325,9 @@ macro_rules! branch {
         }
         let (cond, tgt) = ($cond, $tgt);
         $op.ctx.branch(cond, tgt, $lkl);
+        if $lkl && !cond && $op.cpu.timing {
+            $op.ctx.clock += C::Timing::branch_likely_penalty();
+        }
 
         // See if this is a short loop (less than 5 instructions). Short loops
         // go through the busy-wait detector.
@@ -219,29 +342,54 @@ macro_rules! branch {
     }};
 }
 
+// A branch-and-link. Subroutine calls (taken branches) are reported to
+// the tracer, which follows the call depth while stepping out.
+macro_rules! call {
+    ($op:ident, $t:ident, $cond:expr, $tgt:expr,likely($lkl:expr)) => {{
+        branch!($op, $cond, $tgt, link(true), likely($lkl));
+        if $op.ctx.delay_slot {
+            $t.trace_call(&$op.cpu.name)?;
+        }
+    }};
+}
+
 macro_rules! check_overflow_add {
     ($op:ident, $dest:expr, $reg1:expr, $reg2:expr) => {{
-        match $reg1.checked_add($reg2) {
-            Some(res) => $dest = res.sx64(),
-            None => $op.cpu.trap_overflow(),
+        match $reg1.overflowing_add($reg2) {
+            (_, true) if C::overflow_traps() => $op.cpu.exception(Exception::Overflow),
+            (res, _) => $dest = res.sx64(),
         }
     }};
 }
 
 macro_rules! check_overflow_sub {
     ($op:ident, $dest:expr, $reg1:expr, $reg2:expr) => {{
-        match $reg1.checked_sub($reg2) {
-            Some(res) => $dest = res.sx64(),
-            None => $op.cpu.trap_overflow(),
+        match $reg1.overflowing_sub($reg2) {
+            (_, true) if C::overflow_traps() => $op.cpu.exception(Exception::Overflow),
+            (res, _) => $dest = res.sx64(),
+        }
+    }};
+}
+
+macro_rules! trap {
+    ($op:ident, $cond:expr) => {{
+        if $cond {
+            $op.cpu.exception(Exception::Trap);
         }
     }};
 }
 
 macro_rules! if_cop {
-    ($op:ident, $cop:ident, $do:expr) => {{
-        if !$op.cpu.$cop.is_null_obj() {
+    ($op:ident, $cop:ident, $idx:expr, $do:expr) => {{
+        if !$op.cpu.cop0.cop_usable(&$op.ctx, $idx) {
+            $op.cpu.exception(Exception::CopUnusable($idx));
+        } else if !$op.cpu.$cop.is_null_obj() {
             let $cop = &mut $op.cpu.$cop;
-            $do
+            let res = $do;
+            if let Some(exc) = $op.ctx.cop_exception.take() {
+                $op.cpu.exception(exc);
+            }
+            res?
         } else {
             let pc = $op.ctx.pc;
             let opcode = $op.opcode;
@@ -252,10 +400,56 @@ macro_rules! if_cop {
 }
 
 macro_rules! if_cop_loadstore {
-    ($op:ident, $cop:ident, $loadstore:ident, $t:ident) => {{
-        if_cop!($op, $cop, {
-            return $cop.$loadstore($op.opcode, &mut $op.ctx, &mut $op.cpu.bus, $t);
-        })
+    ($op:ident, $cop:ident, $idx:expr, $loadstore:ident, $size:ty, $acc:expr, $t:ident) => {{
+        if !$op.cpu.cop0.cop_usable(&$op.ctx, $idx) {
+            $op.cpu.exception(Exception::CopUnusable($idx));
+        } else if !$op.cpu.$cop.is_null_obj() {
+            let vaddr = $op.ea();
+            let paddr = match $op.cpu.translate_aligned::<$size>(vaddr, $acc) {
+                Some(paddr) => C::addr_mask::<$size>(paddr),
+                None => return Ok(()),
+            };
+            if $op.cpu.dcache.is_some() && $op.cpu.cop0.is_cached(&$op.ctx, vaddr) {
+                // Cached access: the core moves the data between the D-cache
+                // and the coprocessor register.
+                let rt = $op.rt();
+                if $acc == MemAccess::Read {
+                    let val = $op.cpu.load::<$size>(vaddr, paddr, $t)?;
+                    $op.cpu.$cop.set_reg(&mut $op.ctx, rt, val as u128);
+                    return Ok(());
+                }
+                let val = $op.cpu.$cop.reg(&$op.ctx, rt) as $size;
+                return $op.cpu.store::<$size>(vaddr, paddr, val, $t);
+            }
+            let $cop = &mut $op.cpu.$cop;
+            return $cop.$loadstore($op.opcode, &mut $op.ctx, &mut $op.cpu.bus, paddr, $t);
+        } else {
+            let pc = $op.ctx.pc;
+            let opcode = $op.opcode;
+            warn!($op.cpu.logger, "COP opcode without COP";
+                "pc" => pc.hex(), "op" => opcode.hex());
+        }
+    }};
+}
+
+// Define the handler of an opcode, as a function that can be stored in
+// a pre-decoded block.
+macro_rules! op {
+    (|$op:ident, $t:ident| $body:expr) => {{
+        #[allow(unused_variables, unreachable_code)]
+        fn handler<C: Config>($op: &mut Mipsop<C>, $t: &Tracer) -> Result<()> {
+            $body;
+            Ok(())
+        }
+        handler::<C> as OpFn<C>
+    }};
+}
+
+macro_rules! load {
+    ($op:ident, $val:expr, $conv:expr) => {{
+        if let Some(val) = $val {
+            *$op.mrt64() = $conv(val);
+        }
     }};
 }
 
@@ -274,6 +468,14 @@ impl<C: Config> Cpu<C> {
             cop1: cops.1,
             cop2: cops.2,
             cop3: cops.3,
+            icache: None,
+            dcache: None,
+            timing: false,
+            timing_state: Default::default(),
+            blocks: None,
+            code_watch: WriteWatch::new(),
+            #[cfg(all(target_arch = "x86_64", unix))]
+            jit: None,
             logger: logger,
             until: 0,
             last_busy_check: 0,
@@ -290,274 +492,452 @@ impl<C: Config> Cpu<C> {
         &mut self.ctx
     }
 
+    /// Disassemble a single opcode, as if it was fetched at the specified
+    /// address. Unlike DisasmView, this does not access the bus, so it can
+    /// be used on code which is not mapped in memory.
+    pub fn disasm_opcode(&self, opcode: u32, pc: u64) -> DecodedInsn {
+        decode(self, opcode, pc)
+    }
+
+    /// Enable the instruction and data cache model. By default, the CPU
+    /// accesses memory directly, as if all accesses were uncached. With the
+    /// cache model enabled, accesses to cached segments go through the
+    /// caches, and the CACHE opcode is used to manage them.
+    pub fn enable_caches(&mut self, icache: CacheConfig, dcache: CacheConfig) {
+        self.icache = Some(Cache::new(&(self.name.clone() + "::icache"), icache));
+        self.dcache = Some(Cache::new(&(self.name.clone() + "::dcache"), dcache));
+    }
+
+    /// Returns the WriteWatch that tracks the pages holding pre-decoded or
+    /// compiled blocks. It is attached to the CPU bus by enable_block_cache
+    /// and enable_jit; if the code can also be written through other buses
+    /// (eg: by DMA), it must be attached to them as well, so that stale
+    /// blocks are discarded.
+    pub fn code_watch(&self) -> &Rc<WriteWatch> {
+        &self.code_watch
+    }
+
+    fn watch_code_writes(&mut self) {
+        self.bus.watch_writes(0, 0xFFFF_FFFF, 0, &self.code_watch);
+    }
+
+    // Drop the blocks in pages that were written since the last check.
+    fn invalidate_blocks(&mut self) {
+        for page in self.code_watch.take_dirty() {
+            if let Some(blocks) = self.blocks.as_mut() {
+                blocks.invalidate_page(page);
+            }
+            #[cfg(all(target_arch = "x86_64", unix))]
+            {
+                if let Some(jit) = self.jit.as_mut() {
+                    jit.invalidate_page(page);
+                }
+            }
+        }
+    }
+
+    /// Enable or disable the cycle timing model defined by Config::Timing.
+    /// When disabled (the default), all instructions take a single cycle.
+    pub fn enable_timing(&mut self, enable: bool) {
+        self.timing = enable;
+        self.timing_state = Default::default();
+    }
+
+    /// Enable or disable the cached interpreter. When enabled, code is
+    /// decoded once into blocks of pre-decoded instructions, which are then
+    /// executed without going through the decoder again.
+    pub fn enable_block_cache(&mut self, enable: bool) {
+        self.blocks = if enable {
+            self.watch_code_writes();
+            Some(BlockCache::new())
+        } else {
+            None
+        };
+    }
+
+    /// Enable or disable the dynamic recompiler. Blocks are translated into
+    /// host code, falling back to the interpreter for opcodes that are not
+    /// compiled natively. The interpreter is still used when the timing
+    /// model is enabled, or while a debugger is tracing the CPU.
+    ///
+    /// The recompiler is available only on x86-64 hosts; elsewhere, this
+    /// function does nothing.
+    pub fn enable_jit(&mut self, enable: bool) {
+        #[cfg(all(target_arch = "x86_64", unix))]
+        {
+            self.jit = if enable {
+                self.watch_code_writes();
+                Some(Jit::new())
+            } else {
+                None
+            };
+        }
+        #[cfg(not(all(target_arch = "x86_64", unix)))]
+        {
+            if enable {
+                warn!(self.logger, "JIT not supported on this host, using the interpreter");
+            }
+        }
+    }
+
     pub fn reset(&mut self) {
         self.exception(Exception::SoftReset);
     }
 
+    /// Trigger a non-maskable interrupt (eg: from the reset button).
+    pub fn nmi(&mut self) {
+        self.exception(Exception::Nmi);
+    }
+
     fn exception(&mut self, exc: Exception) {
+        // Any exception breaks a load-linked sequence
+        self.ctx.llbit = false;
         self.cop0.exception(&mut self.ctx, exc);
     }
 
-    fn trap_overflow(&mut self) {
-        unimplemented!();
+    // Returns whether the opcode reads the GPRs encoded in its rs and rt
+    // fields (which, depending on the opcode, might not be GPRs at all, or
+    // might be the destination).
+    fn reads_rs_rt(opcode: u32) -> (bool, bool) {
+        let rs = (opcode >> 21) & 0x1f;
+        match opcode >> 26 {
+            0x00 => match opcode & 0x3F {
+                0x0C | 0x0D => (false, false), // SYSCALL, BREAK: code field
+                _ => (true, true),
+            },
+            0x02 | 0x03 => (false, false),             // J, JAL
+            0x04 | 0x05 | 0x14 | 0x15 => (true, true), // BEQ, BNE, BEQL, BNEL
+            0x10..=0x13 => (false, rs == 0x04 || rs == 0x05 || rs == 0x06), // MTCz, DMTCz, CTCz
+            0x1A | 0x1B | 0x22 | 0x26 => (true, true), // LDL, LDR, LWL, LWR: merge into rt
+            0x28..=0x2E | 0x38 | 0x3C | 0x3F => (true, true), // GPR stores
+            _ => (true, false),
+        }
+    }
+
+    // Account for the extra cycles taken by an opcode in the timing model:
+    // pipeline interlocks, and multi-cycle operations.
+    fn op_timing(ctx: &mut CpuContext, opcode: u32) {
+        let rs = ((opcode >> 21) & 0x1f) as usize;
+        let rt = ((opcode >> 16) & 0x1f) as usize;
+
+        // Load-use interlock
+        if ctx.load_reg != 0 {
+            let (reads_rs, reads_rt) = Self::reads_rs_rt(opcode);
+            if (reads_rs && ctx.load_reg == rs) || (reads_rt && ctx.load_reg == rt) {
+                ctx.clock += C::Timing::load_use_stall();
+            }
+        }
+        ctx.load_reg = 0;
+
+        match opcode >> 26 {
+            0x00 => match opcode & 0x3F {
+                // MFHI/MFLO: wait for the multiplier
+                0x10 | 0x12 => ctx.clock = ctx.clock.max(ctx.hilo_ready),
+                0x18 | 0x19 => ctx.hilo_ready = ctx.clock + C::Timing::mul_latency(false),
+                0x1A | 0x1B => ctx.hilo_ready = ctx.clock + C::Timing::div_latency(false),
+                0x1C | 0x1D => ctx.hilo_ready = ctx.clock + C::Timing::mul_latency(true),
+                0x1E | 0x1F => ctx.hilo_ready = ctx.clock + C::Timing::div_latency(true),
+                _ => {}
+            },
+            // COP1 computational instructions
+            0x11 if rs >= 0x10 => ctx.clock += C::Timing::fpu_latency(opcode & 0x3F, rs as u32),
+            // GPR loads
+            0x1A | 0x1B | 0x20..=0x27 | 0x30 | 0x34 | 0x37 => ctx.load_reg = rt,
+            _ => {}
+        }
+    }
+
+    // Execute an opcode, given its (pre-decoded) handler.
+    #[inline(always)]
+    pub(crate) fn exec(&mut self, ctx: &mut CpuContext, opcode: u32, f: OpFn<C>, t: &Tracer) -> Result<()> {
+        ctx.clock += 1;
+        if self.timing {
+            Self::op_timing(ctx, opcode);
+            ctx.clock += C::Timing::issue(&mut self.timing_state, opcode, ctx.clock);
+        }
+        f(
+            &mut Mipsop {
+                ctx,
+                opcode,
+                cpu: self,
+            },
+            t,
+        )
     }
 
     #[inline(never)]
     fn op(&mut self, ctx: &mut CpuContext, opcode: u32, t: &Tracer) -> Result<()> {
-        ctx.clock += 1;
-        let mut op = Mipsop {
-            ctx,
-            opcode,
-            cpu: self,
-        };
+        self.exec(ctx, opcode, Self::decode_op(opcode), t)
+    }
+
+    // Decode an opcode into the handler that implements it.
+    fn decode_op(opcode: u32) -> OpFn<C> {
         let h = |s| C::Arch::has_op(s);
-        match op.op() {
+        match opcode >> 26 {
             // SPECIAL
-            0x00 => match op.special() {
-                0x00 if h("sll") => *op.mrd64() = (op.rt32() << op.sa()).sx64(), // SLL
-                0x02 if h("srl") => *op.mrd64() = (op.rt32() >> op.sa()).sx64(), // SRL
-                0x03 if h("sra") => *op.mrd64() = (op.irt32() >> op.sa()).sx64(), // SRA
-                0x04 if h("sllv") => *op.mrd64() = (op.rt32() << (op.rs32() & 0x1F)).sx64(), // SLLV
-                0x06 if h("srll") => *op.mrd64() = (op.rt32() >> (op.rs32() & 0x1F)).sx64(), // SRLV
-                0x07 if h("srav") => *op.mrd64() = (op.irt32() >> (op.rs32() & 0x1F)).sx64(), // SRAV
-                0x08 if h("jr") => branch!(op, true, op.rs64(), link(false)),                 // JR
-                0x09 if h("jalr") => branch!(op, true, op.rs64(), link(true)), // JALR
-                0x0D if h("break") => op.cpu.exception(Exception::Breakpoint), // BREAK
-                0x0F if h("sync") => {}                                        // SYNC
-
-                0x10 if h("mfhi") => *op.mrd64() = op.ctx.hi, // MFHI
-                0x11 if h("mthi") => op.ctx.hi = op.rs64(),   // MTHI
-                0x12 if h("mflo") => *op.mrd64() = op.ctx.lo, // MFLO
-                0x13 if h("mtlo") => op.ctx.lo = op.rs64(),   // MTLO
-                0x14 if h("dsllv") => *op.mrd64() = op.rt64() << (op.rs32() & 0x3F), // DSLLV
-                0x16 if h("dsrlv") => *op.mrd64() = op.rt64() >> (op.rs32() & 0x3F), // DSRLV
-                0x17 if h("dsrav") => *op.mrd64() = (op.irt64() >> (op.rs32() & 0x3F)) as u64, // DSRAV
-                0x18 if h("mult") => {
+            0x00 => match opcode & 0x3F {
+                0x00 if h("sll") => op!(|op, t| *op.mrd64() = (op.rt32() << op.sa()).sx64()), // SLL
+                0x02 if h("srl") => op!(|op, t| *op.mrd64() = (op.rt32() >> op.sa()).sx64()), // SRL
+                0x03 if h("sra") => op!(|op, t| *op.mrd64() = (op.irt32() >> op.sa()).sx64()), // SRA
+                0x04 if h("sllv") => op!(|op, t| *op.mrd64() = (op.rt32() << (op.rs32() & 0x1F)).sx64()), // SLLV
+                0x06 if h("srll") => op!(|op, t| *op.mrd64() = (op.rt32() >> (op.rs32() & 0x1F)).sx64()), // SRLV
+                0x07 if h("srav") => op!(|op, t| *op.mrd64() = (op.irt32() >> (op.rs32() & 0x1F)).sx64()), // SRAV
+                0x08 if h("jr") => op!(|op, t| {
+                    branch!(op, true, op.rs64(), link(false));
+                    if op.rs() == 31 {
+                        // JR RA: return from subroutine
+                        let ret = C::pc_mask(op.ctx.next_pc as u32) as u64;
+                        t.trace_return(&op.cpu.name, ret, op.ctx.regs[29])?;
+                    }
+                }), // JR
+                0x09 if h("jalr") => op!(|op, t| call!(op, t, true, op.rs64(), likely(true))), // JALR
+                0x0C if h("syscall") => op!(|op, t| op.cpu.exception(Exception::Syscall)), // SYSCALL
+                0x0D if h("break") => op!(|op, t| op.cpu.exception(Exception::Breakpoint)), // BREAK
+                0x0F if h("sync") => op!(|op, t| {}), // SYNC
+
+                0x10 if h("mfhi") => op!(|op, t| *op.mrd64() = op.ctx.hi), // MFHI
+                0x11 if h("mthi") => op!(|op, t| op.ctx.hi = op.rs64()), // MTHI
+                0x12 if h("mflo") => op!(|op, t| *op.mrd64() = op.ctx.lo), // MFLO
+                0x13 if h("mtlo") => op!(|op, t| op.ctx.lo = op.rs64()), // MTLO
+                0x14 if h("dsllv") => op!(|op, t| *op.mrd64() = op.rt64() << (op.rs32() & 0x3F)), // DSLLV
+                0x16 if h("dsrlv") => op!(|op, t| *op.mrd64() = op.rt64() >> (op.rs32() & 0x3F)), // DSRLV
+                0x17 if h("dsrav") => op!(|op, t| *op.mrd64() = (op.irt64() >> (op.rs32() & 0x3F)) as u64), // DSRAV
+                0x18 if h("mult") => op!(|op, t| {
                     // MULT
                     let (hi, lo) =
                         (i64::wrapping_mul(op.rt32().isx64(), op.rs32().isx64()) as u64).hi_lo();
-                    op.ctx.lo = lo;
-                    op.ctx.hi = hi;
-                }
-                0x19 if h("multu") => {
+                    op.ctx.lo = (lo as u32).sx64();
+                    op.ctx.hi = (hi as u32).sx64();
+                }),
+                0x19 if h("multu") => op!(|op, t| {
                     // MULTU
                     let (hi, lo) = u64::wrapping_mul(op.rt32() as u64, op.rs32() as u64).hi_lo();
-                    op.ctx.lo = lo;
-                    op.ctx.hi = hi;
-                }
-                0x1A if h("div") => {
+                    op.ctx.lo = (lo as u32).sx64();
+                    op.ctx.hi = (hi as u32).sx64();
+                }),
+                0x1A if h("div") => op!(|op, t| {
                     // DIV
                     op.ctx.lo = op.irs32().wrapping_div(op.irt32()).sx64();
                     op.ctx.hi = op.irs32().wrapping_rem(op.irt32()).sx64();
-                }
-                0x1B if h("divu") => {
+                }),
+                0x1B if h("divu") => op!(|op, t| {
                     // DIVU
                     op.ctx.lo = op.rs32().wrapping_div(op.rt32()).sx64();
                     op.ctx.hi = op.rs32().wrapping_rem(op.rt32()).sx64();
-                }
-                0x1C if h("dmult") => {
+                }),
+                0x1C if h("dmult") => op!(|op, t| {
                     // DMULT
                     let (hi, lo) =
                         i128::wrapping_mul(op.irt64() as i128, op.irs64() as i128).hi_lo();
                     op.ctx.lo = lo as u64;
                     op.ctx.hi = hi as u64;
-                }
-                0x1D if h("dmultu") => {
+                }),
+                0x1D if h("dmultu") => op!(|op, t| {
                     // DMULTU
                     let (hi, lo) = u128::wrapping_mul(op.rt64() as u128, op.rs64() as u128).hi_lo();
                     op.ctx.lo = lo as u64;
                     op.ctx.hi = hi as u64;
-                }
-                0x1E if h("ddiv") => {
+                }),
+                0x1E if h("ddiv") => op!(|op, t| {
                     // DDIV
                     op.ctx.lo = op.irs64().wrapping_div(op.irt64()) as u64;
                     op.ctx.hi = op.irs64().wrapping_rem(op.irt64()) as u64;
-                }
-                0x1F if h("ddivu") => {
+                }),
+                0x1F if h("ddivu") => op!(|op, t| {
                     // DDIVU
                     op.ctx.lo = op.rs64().wrapping_div(op.rt64());
                     op.ctx.hi = op.rs64().wrapping_rem(op.rt64());
-                }
-
-                0x20 if h("add") => check_overflow_add!(op, *op.mrd64(), op.irs32(), op.irt32()), // ADD
-                0x21 if h("addu") => *op.mrd64() = (op.rs32() + op.rt32()).sx64(), // ADDU
-                0x22 if h("sub") => check_overflow_sub!(op, *op.mrd64(), op.irs32(), op.irt32()), // SUB
-                0x23 if h("subu") => *op.mrd64() = (op.rs32() - op.rt32()).sx64(), // SUBU
-                0x24 if h("and") => *op.mrd64() = op.rs64() & op.rt64(),           // AND
-                0x25 if h("or") => *op.mrd64() = op.rs64() | op.rt64(),            // OR
-                0x26 if h("xor") => *op.mrd64() = op.rs64() ^ op.rt64(),           // XOR
-                0x27 if h("nor") => *op.mrd64() = !(op.rs64() | op.rt64()),        // NOR
-                0x2A if h("slt") => *op.mrd64() = (op.irs32() < op.irt32()) as u64, // SLT
-                0x2B if h("sltu") => *op.mrd64() = (op.rs32() < op.rt32()) as u64, // SLTU
-                0x2C if h("dadd") => check_overflow_add!(op, *op.mrd64(), op.irs64(), op.irt64()), // DADD
-                0x2D if h("daddu") => *op.mrd64() = op.rs64() + op.rt64(), // DADDU
-                0x2E if h("dsub") => check_overflow_sub!(op, *op.mrd64(), op.irs64(), op.irt64()), // DSUB
-                0x2F if h("dsubu") => *op.mrd64() = op.rs64() - op.rt64(), // DSUBU
-
-                0x34 if h("teq") => {
-                    // TEQ
-                    if op.rs64() == op.rt64() {
-                        op.cpu.exception(Exception::Trap)
-                    }
-                }
-
-                0x38 if h("dsll") => *op.mrd64() = op.rt64() << op.sa(), // DSLL
-                0x3A if h("dsrl") => *op.mrd64() = op.rt64() >> op.sa(), // DSRL
-                0x3B if h("dsra") => *op.mrd64() = (op.irt64() >> op.sa()) as u64, // DSRA
-                0x3C if h("dsll32") => *op.mrd64() = op.rt64() << (op.sa() + 32), // DSLL32
-                0x3E if h("dsrl32") => *op.mrd64() = op.rt64() >> (op.sa() + 32), // DSRL32
-                0x3F if h("dsra32") => *op.mrd64() = (op.irt64() >> (op.sa() + 32)) as u64, // DSRA32
-
-                _ => {
-                    return t.panic(&format!(
-                        "unimplemented special opcode: func=0x{:x?}",
-                        op.special()
-                    ));
-                }
+                }),
+
+                0x20 if h("add") => op!(|op, t| check_overflow_add!(op, *op.mrd64(), op.irs32(), op.irt32())), // ADD
+                0x21 if h("addu") => op!(|op, t| *op.mrd64() = (op.rs32() + op.rt32()).sx64()), // ADDU
+                0x22 if h("sub") => op!(|op, t| check_overflow_sub!(op, *op.mrd64(), op.irs32(), op.irt32())), // SUB
+                0x23 if h("subu") => op!(|op, t| *op.mrd64() = (op.rs32() - op.rt32()).sx64()), // SUBU
+                0x24 if h("and") => op!(|op, t| *op.mrd64() = op.rs64() & op.rt64()), // AND
+                0x25 if h("or") => op!(|op, t| *op.mrd64() = op.rs64() | op.rt64()), // OR
+                0x26 if h("xor") => op!(|op, t| *op.mrd64() = op.rs64() ^ op.rt64()), // XOR
+                0x27 if h("nor") => op!(|op, t| *op.mrd64() = !(op.rs64() | op.rt64())), // NOR
+                0x2A if h("slt") => op!(|op, t| *op.mrd64() = (op.irs32() < op.irt32()) as u64), // SLT
+                0x2B if h("sltu") => op!(|op, t| *op.mrd64() = (op.rs32() < op.rt32()) as u64), // SLTU
+                0x2C if h("dadd") => op!(|op, t| check_overflow_add!(op, *op.mrd64(), op.irs64(), op.irt64())), // DADD
+                0x2D if h("daddu") => op!(|op, t| *op.mrd64() = op.rs64() + op.rt64()), // DADDU
+                0x2E if h("dsub") => op!(|op, t| check_overflow_sub!(op, *op.mrd64(), op.irs64(), op.irt64())), // DSUB
+                0x2F if h("dsubu") => op!(|op, t| *op.mrd64() = op.rs64() - op.rt64()), // DSUBU
+
+                0x30 if h("tge") => op!(|op, t| trap!(op, op.irs64() >= op.irt64())), // TGE
+                0x31 if h("tgeu") => op!(|op, t| trap!(op, op.rs64() >= op.rt64())), // TGEU
+                0x32 if h("tlt") => op!(|op, t| trap!(op, op.irs64() < op.irt64())), // TLT
+                0x33 if h("tltu") => op!(|op, t| trap!(op, op.rs64() < op.rt64())), // TLTU
+                0x34 if h("teq") => op!(|op, t| trap!(op, op.rs64() == op.rt64())), // TEQ
+                0x36 if h("tne") => op!(|op, t| trap!(op, op.rs64() != op.rt64())), // TNE
+
+                0x38 if h("dsll") => op!(|op, t| *op.mrd64() = op.rt64() << op.sa()), // DSLL
+                0x3A if h("dsrl") => op!(|op, t| *op.mrd64() = op.rt64() >> op.sa()), // DSRL
+                0x3B if h("dsra") => op!(|op, t| *op.mrd64() = (op.irt64() >> op.sa()) as u64), // DSRA
+                0x3C if h("dsll32") => op!(|op, t| *op.mrd64() = op.rt64() << (op.sa() + 32)), // DSLL32
+                0x3E if h("dsrl32") => op!(|op, t| *op.mrd64() = op.rt64() >> (op.sa() + 32)), // DSRL32
+                0x3F if h("dsra32") => op!(|op, t| *op.mrd64() = (op.irt64() >> (op.sa() + 32)) as u64), // DSRA32
+
+                _ => op!(|op, t| {
+                    warn!(op.cpu.logger, "reserved special opcode";
+                        "func" => op.special().hex(), "pc" => op.ctx.pc.hex());
+                    op.cpu.exception(Exception::ReservedInstruction);
+                }),
             },
 
             // REGIMM
-            0x01 => match op.rt() {
-                0x00 if h("bltz") => {
+            0x01 => match (opcode >> 16) & 0x1F {
+                0x00 if h("bltz") => op!(|op, t| {
                     branch!(op, op.irs64() < 0, op.btgt(), link(false), likely(false))
-                }
-                0x01 if h("bgez") => {
+                }),
+                0x01 if h("bgez") => op!(|op, t| {
                     branch!(op, op.irs64() >= 0, op.btgt(), link(false), likely(false))
-                }
-                0x02 if h("btlzl") => {
+                }),
+                0x02 if h("btlzl") => op!(|op, t| {
                     branch!(op, op.irs64() < 0, op.btgt(), link(false), likely(true))
-                }
-                0x03 if h("bgezl") => {
+                }),
+                0x03 if h("bgezl") => op!(|op, t| {
                     branch!(op, op.irs64() >= 0, op.btgt(), link(false), likely(true))
-                }
-                0x10 if h("bltzal") => {
-                    branch!(op, op.irs64() < 0, op.btgt(), link(true), likely(false))
-                }
-                0x11 if h("bgezal") => {
-                    branch!(op, op.irs64() >= 0, op.btgt(), link(true), likely(false))
-                }
-                0x12 if h("bltzall") => {
-                    branch!(op, op.irs64() < 0, op.btgt(), link(true), likely(true))
-                }
-                0x13 if h("bgezall") => {
-                    branch!(op, op.irs64() >= 0, op.btgt(), link(true), likely(true))
-                }
-                _ => panic!(
-                    "unimplemented regimm opcode: func=0x{:x?} pc=0x{:x?}",
-                    op.rt(),
-                    op.ctx.pc - 4
-                ),
+                }),
+                0x08 if h("tgei") => op!(|op, t| trap!(op, op.irs64() >= op.sximm64())), // TGEI
+                0x09 if h("tgeiu") => op!(|op, t| trap!(op, op.rs64() >= op.sximm64() as u64)), // TGEIU
+                0x0A if h("tlti") => op!(|op, t| trap!(op, op.irs64() < op.sximm64())), // TLTI
+                0x0B if h("tltiu") => op!(|op, t| trap!(op, op.rs64() < op.sximm64() as u64)), // TLTIU
+                0x0C if h("teqi") => op!(|op, t| trap!(op, op.irs64() == op.sximm64())), // TEQI
+                0x0E if h("tnei") => op!(|op, t| trap!(op, op.irs64() != op.sximm64())), // TNEI
+                0x10 if h("bltzal") => op!(|op, t| {
+                    call!(op, t, op.irs64() < 0, op.btgt(), likely(false));
+                }),
+                0x11 if h("bgezal") => op!(|op, t| {
+                    call!(op, t, op.irs64() >= 0, op.btgt(), likely(false));
+                }),
+                0x12 if h("bltzall") => op!(|op, t| {
+                    call!(op, t, op.irs64() < 0, op.btgt(), likely(true));
+                }),
+                0x13 if h("bgezall") => op!(|op, t| {
+                    call!(op, t, op.irs64() >= 0, op.btgt(), likely(true));
+                }),
+                _ => op!(|op, t| {
+                    warn!(op.cpu.logger, "reserved regimm opcode";
+                        "func" => op.rt().hex(), "pc" => op.ctx.pc.hex());
+                    op.cpu.exception(Exception::ReservedInstruction);
+                }),
             },
 
-            0x02 if h("j") => branch!(op, true, op.jtgt(), link(false)), // J
-            0x03 if h("jal") => branch!(op, true, op.jtgt(), link(true)), // JAL
-            0x04 if h("beq") => branch!(op, op.rs64() == op.rt64(), op.btgt()), // BEQ
-            0x05 if h("bne") => branch!(op, op.rs64() != op.rt64(), op.btgt()), // BNE
-            0x06 if h("blez") => branch!(op, op.irs64() <= 0, op.btgt()), // BLEZ
-            0x07 if h("bgtz") => branch!(op, op.irs64() > 0, op.btgt()), // BGTZ
-            0x08 if h("addi") => check_overflow_add!(op, *op.mrt64(), op.irs32(), op.sximm32()), // ADDI
-            0x09 if h("addiu") => *op.mrt64() = (op.irs32() + op.sximm32()).sx64(), // ADDIU
-            0x0A if h("slti") => *op.mrt64() = (op.irs32() < op.sximm32()) as u64,  // SLTI
-            0x0B if h("sltiu") => *op.mrt64() = (op.rs32() < op.sximm32() as u32) as u64, // SLTIU
-            0x0C if h("andi") => *op.mrt64() = op.rs64() & op.imm64(),              // ANDI
-            0x0D if h("ori") => *op.mrt64() = op.rs64() | op.imm64(),               // ORI
-            0x0E if h("xori") => *op.mrt64() = op.rs64() ^ op.imm64(),              // XORI
-            0x0F if h("lui") => *op.mrt64() = (op.sximm32() << 16).sx64(),          // LUI
-
-            0x10 => if_cop!(op, cop0, { return cop0.op(&mut op.ctx, opcode, t) }), // COP0
-            0x11 => if_cop!(op, cop1, { return cop1.op(&mut op.ctx, opcode, t) }), // COP1
-            0x12 => if_cop!(op, cop2, { return cop2.op(&mut op.ctx, opcode, t) }), // COP2
-            0x13 => if_cop!(op, cop3, { return cop3.op(&mut op.ctx, opcode, t) }), // COP3
-            0x14 if h("beql") => branch!(op, op.rs64() == op.rt64(), op.btgt(), likely(true)), // BEQL
-            0x15 if h("bnel") => branch!(op, op.rs64() != op.rt64(), op.btgt(), likely(true)), // BNEL
-            0x16 if h("blezl") => branch!(op, op.irs64() <= 0, op.btgt(), likely(true)), // BLEZL
-            0x17 if h("bgtzl") => branch!(op, op.irs64() > 0, op.btgt(), likely(true)),  // BGTZL
-            0x18 if h("daddi") => check_overflow_add!(op, *op.mrt64(), op.irs64(), op.sximm64()), // DADDI
-            0x19 if h("daddiu") => *op.mrt64() = (op.irs64() + op.sximm64()) as u64, // DADDIU
-            0x1a if h("ldl") => *op.mrt64() = op.cpu.lwl::<u64>(op.ea(), op.rt64(), t)?, // LDL
-            0x1b if h("ldr") => *op.mrt64() = op.cpu.lwr::<u64>(op.ea(), op.rt64(), t)?, // LDR
-
-            0x20 if h("lb") => *op.mrt64() = op.cpu.read::<u8>(op.ea(), t)?.sx64(), // LB
-            0x21 if h("lh") => *op.mrt64() = op.cpu.read::<u16>(op.ea(), t)?.sx64(), // LH
-            0x22 if h("lwl") => *op.mrt64() = op.cpu.lwl::<u32>(op.ea(), op.rt32(), t)?.sx64(), // LWL
-            0x23 if h("lw") => *op.mrt64() = op.cpu.read::<u32>(op.ea(), t)?.sx64(), // LW
-            0x24 if h("lbu") => *op.mrt64() = op.cpu.read::<u8>(op.ea(), t)? as u64, // LBU
-            0x25 if h("lhu") => *op.mrt64() = op.cpu.read::<u16>(op.ea(), t)? as u64, // LHU
-            0x26 if h("lwr") => *op.mrt64() = op.cpu.lwr::<u32>(op.ea(), op.rt32(), t)?.sx64(), // LWR
-            0x27 if h("lwu") => *op.mrt64() = op.cpu.read::<u32>(op.ea(), t)? as u64, // LWU
-            0x28 if h("sb") => op.cpu.write::<u8>(op.ea(), op.rt32() as u8, t)?,      // SB
-            0x29 if h("sh") => op.cpu.write::<u16>(op.ea(), op.rt32() as u16, t)?,    // SH
-            0x2A if h("swl") => {
-                // SWL
-                op.cpu
-                    .write::<u32>(op.ea(), op.cpu.swl(op.ea(), op.rt32(), t)?, t)?
-            }
-            0x2B if h("sw") => op.cpu.write::<u32>(op.ea(), op.rt32(), t)?, // SW
-            0x2C if h("sdl") => {
-                // SDL
-                op.cpu
-                    .write::<u64>(op.ea(), op.cpu.swl(op.ea(), op.rt64(), t)?, t)?
-            }
-            0x2D if h("sdr") => {
-                // SDR
-                op.cpu
-                    .write::<u64>(op.ea(), op.cpu.swr(op.ea(), op.rt64(), t)?, t)?
-            }
-            0x2E if h("swr") => {
-                // SWR
-                op.cpu
-                    .write::<u32>(op.ea(), op.cpu.swr(op.ea(), op.rt32(), t)?, t)?
-            }
-            0x2F => {} // CACHE
-
-            0x31 if h("lwc1") => if_cop_loadstore!(op, cop1, lwc, t), // LWC1
-            0x32 if h("lwc2") => if_cop_loadstore!(op, cop2, lwc, t), // LWC2
-            0x35 if h("ldc1") => if_cop_loadstore!(op, cop1, ldc, t), // LDC1
-            0x36 if h("ldc2") => if_cop_loadstore!(op, cop2, ldc, t), // LDC2
-            0x37 if h("ld") => *op.mrt64() = op.cpu.read::<u64>(op.ea(), t)?, // LD
-            0x39 if h("swc1") => if_cop_loadstore!(op, cop1, swc, t), // SWC1
-            0x3A if h("swc2") => if_cop_loadstore!(op, cop2, swc, t), // SWC2
-            0x3D if h("sdc1") => if_cop_loadstore!(op, cop1, sdc, t), // SDC1
-            0x3E if h("sdc2") => if_cop_loadstore!(op, cop2, sdc, t), // SDC2
-            0x3F if h("sd") => op.cpu.write::<u64>(op.ea(), op.rt64(), t)?, // SD
-
-            _ => {
-                panic!(
-                    "unimplemented opcode: func=0x{:x?}, pc={}",
-                    op.op(),
-                    op.ctx.pc.hex()
-                );
-            }
-        };
-        Ok(())
+            0x02 if h("j") => op!(|op, t| branch!(op, true, op.jtgt(), link(false))), // J
+            0x03 if h("jal") => op!(|op, t| call!(op, t, true, op.jtgt(), likely(true))), // JAL
+            0x04 if h("beq") => op!(|op, t| branch!(op, op.rs64() == op.rt64(), op.btgt())), // BEQ
+            0x05 if h("bne") => op!(|op, t| branch!(op, op.rs64() != op.rt64(), op.btgt())), // BNE
+            0x06 if h("blez") => op!(|op, t| branch!(op, op.irs64() <= 0, op.btgt())), // BLEZ
+            0x07 if h("bgtz") => op!(|op, t| branch!(op, op.irs64() > 0, op.btgt())), // BGTZ
+            0x08 if h("addi") => op!(|op, t| check_overflow_add!(op, *op.mrt64(), op.irs32(), op.sximm32())), // ADDI
+            0x09 if h("addiu") => op!(|op, t| *op.mrt64() = (op.irs32() + op.sximm32()).sx64()), // ADDIU
+            0x0A if h("slti") => op!(|op, t| *op.mrt64() = (op.irs32() < op.sximm32()) as u64), // SLTI
+            0x0B if h("sltiu") => op!(|op, t| *op.mrt64() = (op.rs32() < op.sximm32() as u32) as u64), // SLTIU
+            0x0C if h("andi") => op!(|op, t| *op.mrt64() = op.rs64() & op.imm64()), // ANDI
+            0x0D if h("ori") => op!(|op, t| *op.mrt64() = op.rs64() | op.imm64()), // ORI
+            0x0E if h("xori") => op!(|op, t| *op.mrt64() = op.rs64() ^ op.imm64()), // XORI
+            0x0F if h("lui") => op!(|op, t| *op.mrt64() = (op.sximm32() << 16).sx64()), // LUI
+
+            0x10 => op!(|op, t| if_cop!(op, cop0, 0, { cop0.op(&mut op.ctx, op.opcode, t) })), // COP0
+            0x11 => op!(|op, t| if_cop!(op, cop1, 1, { cop1.op(&mut op.ctx, op.opcode, t) })), // COP1
+            0x12 => op!(|op, t| if_cop!(op, cop2, 2, { cop2.op(&mut op.ctx, op.opcode, t) })), // COP2
+            0x13 => op!(|op, t| if_cop!(op, cop3, 3, { cop3.op(&mut op.ctx, op.opcode, t) })), // COP3
+            0x14 if h("beql") => op!(|op, t| branch!(op, op.rs64() == op.rt64(), op.btgt(), likely(true))), // BEQL
+            0x15 if h("bnel") => op!(|op, t| branch!(op, op.rs64() != op.rt64(), op.btgt(), likely(true))), // BNEL
+            0x16 if h("blezl") => op!(|op, t| branch!(op, op.irs64() <= 0, op.btgt(), likely(true))), // BLEZL
+            0x17 if h("bgtzl") => op!(|op, t| branch!(op, op.irs64() > 0, op.btgt(), likely(true))), // BGTZL
+            0x18 if h("daddi") => op!(|op, t| check_overflow_add!(op, *op.mrt64(), op.irs64(), op.sximm64())), // DADDI
+            0x19 if h("daddiu") => op!(|op, t| *op.mrt64() = (op.irs64() + op.sximm64()) as u64), // DADDIU
+            0x1a if h("ldl") => op!(|op, t| load!(op, op.cpu.lwl::<u64>(op.ea(), op.rt64(), t)?, |v: u64| v)), // LDL
+            0x1b if h("ldr") => op!(|op, t| load!(op, op.cpu.lwr::<u64>(op.ea(), op.rt64(), t)?, |v: u64| v)), // LDR
+
+            0x20 if h("lb") => op!(|op, t| load!(op, op.cpu.read::<u8>(op.ea(), t)?, |v: u8| v.sx64())), // LB
+            0x21 if h("lh") => op!(|op, t| load!(op, op.cpu.read::<u16>(op.ea(), t)?, |v: u16| v.sx64())), // LH
+            0x22 if h("lwl") => op!(|op, t| {
+                // LWL
+                load!(op, op.cpu.lwl::<u32>(op.ea(), op.rt32(), t)?, |v: u32| v.sx64())
+            }),
+            0x23 if h("lw") => op!(|op, t| load!(op, op.cpu.read::<u32>(op.ea(), t)?, |v: u32| v.sx64())), // LW
+            0x24 if h("lbu") => op!(|op, t| load!(op, op.cpu.read::<u8>(op.ea(), t)?, |v: u8| v as u64)), // LBU
+            0x25 if h("lhu") => op!(|op, t| load!(op, op.cpu.read::<u16>(op.ea(), t)?, |v: u16| v as u64)), // LHU
+            0x26 if h("lwr") => op!(|op, t| {
+                // LWR
+                load!(op, op.cpu.lwr::<u32>(op.ea(), op.rt32(), t)?, |v: u32| v.sx64())
+            }),
+            0x27 if h("lwu") => op!(|op, t| load!(op, op.cpu.read::<u32>(op.ea(), t)?, |v: u32| v as u64)), // LWU
+            0x28 if h("sb") => op!(|op, t| op.cpu.write::<u8>(op.ea(), op.rt32() as u8, t)?), // SB
+            0x29 if h("sh") => op!(|op, t| op.cpu.write::<u16>(op.ea(), op.rt32() as u16, t)?), // SH
+            0x2A if h("swl") => op!(|op, t| op.cpu.swl::<u32>(op.ea(), op.rt32(), t)?), // SWL
+            0x2B if h("sw") => op!(|op, t| op.cpu.write::<u32>(op.ea(), op.rt32(), t)?), // SW
+            0x2C if h("sdl") => op!(|op, t| op.cpu.swl::<u64>(op.ea(), op.rt64(), t)?), // SDL
+            0x2D if h("sdr") => op!(|op, t| op.cpu.swr::<u64>(op.ea(), op.rt64(), t)?), // SDR
+            0x2E if h("swr") => op!(|op, t| op.cpu.swr::<u32>(op.ea(), op.rt32(), t)?), // SWR
+            0x2F if h("cache") => op!(|op, t| op.cpu.cache_op(op.rt() as u32, op.ea())), // CACHE
+
+            0x30 if h("ll") => op!(|op, t| load!(op, op.cpu.ll::<u32>(op.ea(), t)?, |v: u32| v.sx64())), // LL
+            0x31 if h("lwc1") => op!(|op, t| if_cop_loadstore!(op, cop1, 1, lwc, u32, MemAccess::Read, t)), // LWC1
+            0x32 if h("lwc2") => op!(|op, t| if_cop_loadstore!(op, cop2, 2, lwc, u32, MemAccess::Read, t)), // LWC2
+            0x34 if h("lld") => op!(|op, t| load!(op, op.cpu.ll::<u64>(op.ea(), t)?, |v: u64| v)), // LLD
+            0x35 if h("ldc1") => op!(|op, t| if_cop_loadstore!(op, cop1, 1, ldc, u64, MemAccess::Read, t)), // LDC1
+            0x36 if h("ldc2") => op!(|op, t| if_cop_loadstore!(op, cop2, 2, ldc, u64, MemAccess::Read, t)), // LDC2
+            0x37 if h("ld") => op!(|op, t| load!(op, op.cpu.read::<u64>(op.ea(), t)?, |v: u64| v)), // LD
+            0x38 if h("sc") => op!(|op, t| load!(op, op.cpu.sc::<u32>(op.ea(), op.rt32(), t)?, |v: bool| v as u64)), // SC
+            0x39 if h("swc1") => op!(|op, t| if_cop_loadstore!(op, cop1, 1, swc, u32, MemAccess::Write, t)), // SWC1
+            0x3A if h("swc2") => op!(|op, t| if_cop_loadstore!(op, cop2, 2, swc, u32, MemAccess::Write, t)), // SWC2
+            0x3C if h("scd") => op!(|op, t| load!(op, op.cpu.sc::<u64>(op.ea(), op.rt64(), t)?, |v: bool| v as u64)), // SCD
+            0x3D if h("sdc1") => op!(|op, t| if_cop_loadstore!(op, cop1, 1, sdc, u64, MemAccess::Write, t)), // SDC1
+            0x3E if h("sdc2") => op!(|op, t| if_cop_loadstore!(op, cop2, 2, sdc, u64, MemAccess::Write, t)), // SDC2
+            0x3F if h("sd") => op!(|op, t| op.cpu.write::<u64>(op.ea(), op.rt64(), t)?), // SD
+
+            _ => op!(|op, t| {
+                warn!(op.cpu.logger, "reserved opcode";
+                    "op" => op.op().hex(), "pc" => op.ctx.pc.hex());
+                op.cpu.exception(Exception::ReservedInstruction);
+            }),
+        }
     }
 
-    fn lwl<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
-        let mem = self.read::<S>(addr, t)?;
+    fn lwl<S: MemInt>(&mut self, addr: u64, reg: S, t: &Tracer) -> Result<Option<S>> {
+        let mem = match self.translate(addr, MemAccess::Read) {
+            Some(paddr) => self.load::<S>(addr, paddr, t)?,
+            None => return Ok(None),
+        };
         let shift = (addr as usize & (S::SIZE - 1)) * 8;
         let mask = S::truncate_from((1u64 << shift) - 1u64);
-        Ok((reg & mask) | ((mem << shift) & !mask))
+        Ok(Some((reg & mask) | ((mem << shift) & !mask)))
     }
 
-    fn lwr<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
-        let mem = self.read::<S>(addr, t)?;
+    fn lwr<S: MemInt>(&mut self, addr: u64, reg: S, t: &Tracer) -> Result<Option<S>> {
+        let mem = match self.translate(addr, MemAccess::Read) {
+            Some(paddr) => self.load::<S>(addr, paddr, t)?,
+            None => return Ok(None),
+        };
         let shift = (!addr as usize & (S::SIZE - 1)) * 8;
         let mask = S::max_value() >> shift;
-        Ok((reg & !mask) | ((mem >> shift) & mask))
+        Ok(Some((reg & !mask) | ((mem >> shift) & mask)))
     }
 
-    fn swl<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
-        let mem = self.read::<S>(addr, t)?;
+    fn swl<S: MemInt>(&mut self, addr: u64, reg: S, t: &Tracer) -> Result<()> {
+        let paddr = match self.translate(addr, MemAccess::Write) {
+            Some(paddr) => paddr,
+            None => return Ok(()),
+        };
+        let mem = self.load::<S>(addr, paddr, t)?;
         let shift = (addr as usize & (S::SIZE - 1)) * 8;
         let mask = S::max_value() >> shift;
-        Ok((mem & !mask) | ((reg >> shift) & mask))
+        self.store::<S>(addr, paddr, (mem & !mask) | ((reg >> shift) & mask), t)
     }
 
-    fn swr<S: MemInt>(&self, addr: u32, reg: S, t: &Tracer) -> Result<S> {
-        let mem = self.read::<S>(addr, t)?;
+    fn swr<S: MemInt>(&mut self, addr: u64, reg: S, t: &Tracer) -> Result<()> {
+        let paddr = match self.translate(addr, MemAccess::Write) {
+            Some(paddr) => paddr,
+            None => return Ok(()),
+        };
+        let mem = self.load::<S>(addr, paddr, t)?;
         let shift = (!addr as usize & (S::SIZE - 1)) * 8;
         let mask = S::truncate_from((1 << shift) - 1);
-        Ok((mem & mask) | ((reg << shift) & !mask))
+        self.store::<S>(addr, paddr, (mem & mask) | ((reg << shift) & !mask), t)
     }
 
     // Check if an opcode, when used as part of a loop, can produce different
@@ -583,20 +963,30 @@ impl<C: Config> Cpu<C> {
             0x20 | 0x21 | 0x22 | 0x23 | 0x24 | 0x25 | 0x26 | 0x27 => {
                 // Load opcode. Check if the address is raw memory, in which
                 // case we consider it stable.
-                let sximm32 = (opcode & 0xffff) as i16 as i32;
+                let sximm64 = (opcode & 0xffff) as i16 as i64;
                 let rs = ((opcode >> 21) & 0x1f) as usize;
-                let ea = self.ctx.regs[rs] as u32 + sximm32 as u32;
-                let mem = self.bus.fetch_read_nolog::<u32>(C::addr_mask::<u32>(ea));
-                return mem.is_mem();
+                let ea = self.ctx.regs[rs].wrapping_add(sximm64 as u64);
+                return match self.cop0.translate(&self.ctx, ea, MemAccess::Read) {
+                    Ok(paddr) => {
+                        let mem = self.bus.fetch_read_nolog::<u32>(C::addr_mask::<u32>(paddr));
+                        mem.is_mem()
+                    }
+                    Err(_) => false,
+                };
             }
             0x28 | 0x29 | 0x2A | 0x2B | 0x2E => {
                 // Store opcode. Check if the address is raw memory, in which
                 // case we consider it stable.
-                let sximm32 = (opcode & 0xffff) as i16 as i32;
+                let sximm64 = (opcode & 0xffff) as i16 as i64;
                 let rs = ((opcode >> 21) & 0x1f) as usize;
-                let ea = self.ctx.regs[rs] as u32 + sximm32 as u32;
-                let mem = self.bus.fetch_write_nolog::<u32>(C::addr_mask::<u32>(ea));
-                return mem.is_mem();
+                let ea = self.ctx.regs[rs].wrapping_add(sximm64 as u64);
+                return match self.cop0.translate(&self.ctx, ea, MemAccess::Write) {
+                    Ok(paddr) => {
+                        let mem = self.bus.fetch_write_nolog::<u32>(C::addr_mask::<u32>(paddr));
+                        mem.is_mem()
+                    }
+                    Err(_) => false,
+                };
             }
             // All other opcodes by default are unstable
             _ => return false,
@@ -604,7 +994,10 @@ impl<C: Config> Cpu<C> {
     }
 
     fn detect_busy_wait(&mut self, pc: u64, loop_len: usize) -> bool {
-        let mem = self.fetch(pc);
+        let mem = match self.cop0.translate(&self.ctx, pc, MemAccess::Fetch) {
+            Ok(paddr) => self.bus.fetch_read::<u32>(C::pc_mask(paddr)),
+            Err(_) => return false,
+        };
         let iter = mem.iter().unwrap();
 
         // FIXME: this is buggy if the memory area is shorter than the loop
@@ -617,29 +1010,326 @@ impl<C: Config> Cpu<C> {
         return true;
     }
 
-    fn fetch(&mut self, addr: u64) -> MemIoR<u32> {
-        self.bus.fetch_read::<u32>(C::pc_mask(addr as u32))
+    // Translate a virtual address through COP0. If the translation fails,
+    // or the access hits a watchpoint, the corresponding exception is raised
+    // and None is returned.
+    fn translate(&mut self, vaddr: u64, acc: MemAccess) -> Option<u32> {
+        match self.cop0.translate(&self.ctx, vaddr, acc) {
+            Ok(paddr) if self.cop0.watch(&self.ctx, paddr, acc) => {
+                self.exception(Exception::Watch);
+                None
+            }
+            Ok(paddr) => Some(paddr),
+            Err(exc) => {
+                self.exception(exc);
+                None
+            }
+        }
     }
 
-    fn read<U: MemInt>(&self, addr: u32, t: &Tracer) -> Result<U> {
-        let addr = C::addr_mask::<U>(addr);
+    // Like translate(), but also raise an address error exception if the
+    // access is not aligned to its size (unless the architecture allows it).
+    fn translate_aligned<U: MemInt>(&mut self, vaddr: u64, acc: MemAccess) -> Option<u32> {
+        if !C::unaligned_access() && vaddr & (U::SIZE as u64 - 1) != 0 {
+            self.exception(Exception::AddressError(acc, vaddr));
+            return None;
+        }
+        self.translate(vaddr, acc)
+    }
+
+    // Fetch the memory area starting at the specified PC. Returns the
+    // physical address together with the memory accessor.
+    fn fetch(&mut self, pc: u64) -> Option<(u32, MemIoR<u32>)> {
+        let paddr = C::pc_mask(self.translate_aligned::<u32>(pc, MemAccess::Fetch)?);
+        Some((paddr, self.bus.fetch_read::<u32>(paddr)))
+    }
+
+    // Returns true if the opcode is a branch or jump, that is, if it is
+    // followed by a delay slot.
+    fn is_branch(opcode: u32) -> bool {
+        match opcode >> 26 {
+            0x00 => match opcode & 0x3F {
+                0x08 | 0x09 => true, // JR, JALR
+                _ => false,
+            },
+            0x01 => match (opcode >> 16) & 0x1F {
+                0x00..=0x03 | 0x10..=0x13 => true,
+                _ => false,
+            },
+            0x02..=0x07 | 0x14..=0x17 => true,
+            // BCzF/BCzT and their likely variants
+            0x10..=0x13 => (opcode >> 21) & 0x1F == 0x08,
+            _ => false,
+        }
+    }
+
+    // Decode a block of instructions starting at the specified physical
+    // address. The block ends after the delay slot of the first branch,
+    // or at the end of the page.
+    pub(crate) fn build_block<I: Iterator<Item = u32>>(paddr: u32, mem: I) -> Block<OpFn<C>> {
+        let mut ops = Vec::new();
+        let mut addr = paddr;
+        let mut delay_slot = false;
+        for opcode in mem {
+            ops.push((Self::decode_op(opcode), opcode));
+            addr = addr.wrapping_add(4);
+            if delay_slot || addr & 0xFFF == 0 || ops.len() == MAX_BLOCK_LEN {
+                break;
+            }
+            delay_slot = Self::is_branch(opcode);
+        }
+        Block { ops }
+    }
+
+    // Get the pre-decoded block starting at the specified physical address,
+    // decoding it if it is not cached (or if it was invalidated because the
+    // code has changed).
+    fn get_block(&mut self, paddr: u32, mem: &MemIoR<u32>) -> Rc<Block<OpFn<C>>> {
+        let blocks = self.blocks.as_mut().unwrap();
+        if let Some(block) = blocks.get(paddr) {
+            return block;
+        }
+        let iter = mem
+            .iter()
+            .unwrap_or_else(|| panic!("jumped to non-linear memory: {}", paddr.hex()));
+        blocks.insert(paddr, Self::build_block(paddr, iter), &self.code_watch)
+    }
+
+    fn read_phys<U: MemInt>(&self, paddr: u32, t: &Tracer) -> Result<U> {
+        let addr = C::addr_mask::<U>(paddr);
         let val = self.bus.read::<U>(addr);
         t.trace_mem_read(&self.name, addr.into(), U::ACCESS_SIZE, val.into())?;
         Ok(val)
     }
 
-    fn write<U: MemInt>(&mut self, addr: u32, val: U, t: &Tracer) -> Result<()> {
-        let addr = C::addr_mask::<U>(addr);
+    fn write_phys<U: MemInt>(&mut self, paddr: u32, val: U, t: &Tracer) -> Result<()> {
+        let addr = C::addr_mask::<U>(paddr);
         self.bus.write::<U>(addr, val);
         t.trace_mem_write(&self.name, addr.into(), U::ACCESS_SIZE, val.into())
     }
 
+    // Read from an already translated address, going through the D-cache
+    // if the cache model is enabled and the address is cached.
+    fn load<U: MemInt>(&mut self, vaddr: u64, paddr: u32, t: &Tracer) -> Result<U> {
+        if self.dcache.is_some() && self.cop0.is_cached(&self.ctx, vaddr) {
+            let paddr = C::addr_mask::<U>(paddr);
+            let dcache = self.dcache.as_mut().unwrap();
+            let idx = dcache.lookup(vaddr, paddr, &mut self.bus);
+            let val = dcache.read::<U>(idx, vaddr);
+            t.trace_mem_read(&self.name, paddr.into(), U::ACCESS_SIZE, val.into())?;
+            return Ok(val);
+        }
+        self.uncached_penalty(vaddr, paddr, MemAccess::Read);
+        self.read_phys::<U>(paddr, t)
+    }
+
+    // Write to an already translated address, going through the D-cache
+    // if the cache model is enabled and the address is cached.
+    fn store<U: MemInt>(&mut self, vaddr: u64, paddr: u32, val: U, t: &Tracer) -> Result<()> {
+        if self.dcache.is_some() && self.cop0.is_cached(&self.ctx, vaddr) {
+            let paddr = C::addr_mask::<U>(paddr);
+            let dcache = self.dcache.as_mut().unwrap();
+            let idx = dcache.lookup(vaddr, paddr, &mut self.bus);
+            dcache.write::<U>(idx, vaddr, val);
+            return t.trace_mem_write(&self.name, paddr.into(), U::ACCESS_SIZE, val.into());
+        }
+        self.uncached_penalty(vaddr, paddr, MemAccess::Write);
+        self.write_phys::<U>(paddr, val, t)
+    }
+
+    // Penalty of fetching each instruction starting at the specified PC,
+    // in case the code is being executed uncached.
+    fn fetch_penalty(&self, pc: u64) -> i64 {
+        if !self.timing || self.cop0.is_cached(&self.ctx, pc) {
+            return 0;
+        }
+        match self.cop0.translate(&self.ctx, pc, MemAccess::Fetch) {
+            Ok(paddr) => C::Timing::uncached_penalty(paddr, MemAccess::Fetch),
+            Err(_) => 0,
+        }
+    }
+
+    // Account for the penalty of an access that bypasses the caches.
+    fn uncached_penalty(&mut self, vaddr: u64, paddr: u32, acc: MemAccess) {
+        if self.timing && !self.cop0.is_cached(&self.ctx, vaddr) {
+            self.ctx.clock += C::Timing::uncached_penalty(paddr, acc);
+        }
+    }
+
+    // Fetch an opcode through the I-cache. Returns None if an exception
+    // was raised.
+    fn fetch_icache(&mut self, pc: u64) -> Option<u32> {
+        let paddr = C::pc_mask(self.translate_aligned::<u32>(pc, MemAccess::Fetch)?);
+        let icache = self.icache.as_mut().unwrap();
+        let idx = icache.lookup(pc, paddr, &mut self.bus);
+        Some(icache.read::<u32>(idx, pc))
+    }
+
+    // Execute a CACHE opcode. "op" is the 5-bit field encoding both the
+    // target cache (bits 0-1) and the operation (bits 2-4).
+    fn cache_op(&mut self, op: u32, vaddr: u64) {
+        if !self.cop0.cop_usable(&self.ctx, 0) {
+            self.exception(Exception::CopUnusable(0));
+            return;
+        }
+
+        let icache = match op & 3 {
+            0 => true,
+            1 => false,
+            _ => {
+                warn!(self.logger, "CACHE on unknown cache"; "op" => op.hex());
+                return;
+            }
+        };
+        if (icache && self.icache.is_none()) || (!icache && self.dcache.is_none()) {
+            // No cache model: nothing to do
+            return;
+        }
+
+        // Hit operations (and Create Dirty Exclusive) need the physical
+        // address, and can trigger TLB exceptions.
+        let paddr = match op >> 2 {
+            3..=6 => match self.translate(vaddr, MemAccess::Read) {
+                Some(paddr) => paddr,
+                None => return,
+            },
+            _ => 0,
+        };
+
+        let cache = if icache {
+            self.icache.as_mut().unwrap()
+        } else {
+            self.dcache.as_mut().unwrap()
+        };
+        let bus = &mut self.bus;
+        let idx = cache.index(vaddr);
+
+        match (op >> 2, icache) {
+            // Index Invalidate (I) / Index Writeback Invalidate (D)
+            (0, _) => {
+                cache.writeback(idx, bus);
+                cache.line_mut(idx).valid = false;
+            }
+            // Index Load Tag. PState (TagLo bits 7:6) encodes the line
+            // state: bit 7 is the valid bit, bit 6 the dirty bit.
+            (1, _) => {
+                let line = *cache.line(idx);
+                let pstate = match (line.valid, line.dirty) {
+                    (false, _) => 0,
+                    (true, false) => 0x80,
+                    (true, true) => 0xC0,
+                };
+                let taglo = (line.ptag & 0xF_FFFF) << 8 | pstate;
+                self.cop0.set_reg(&mut self.ctx, 28, taglo as u128);
+                self.cop0.set_reg(&mut self.ctx, 29, 0);
+            }
+            // Index Store Tag
+            (2, _) => {
+                let taglo = self.cop0.reg(&self.ctx, 28) as u32;
+                let line = cache.line_mut(idx);
+                line.ptag = (taglo >> 8) & 0xF_FFFF;
+                line.valid = taglo & 0x80 != 0;
+                line.dirty = line.valid && taglo & 0x40 != 0;
+            }
+            // Create Dirty Exclusive (D only): take ownership of the line
+            // without loading it from memory.
+            (3, false) => {
+                if cache.hit(vaddr, paddr).is_none() {
+                    cache.writeback(idx, bus);
+                }
+                *cache.line_mut(idx) = CacheLine {
+                    ptag: paddr >> 12,
+                    valid: true,
+                    dirty: true,
+                };
+            }
+            // Hit Invalidate
+            (4, _) => {
+                if let Some(idx) = cache.hit(vaddr, paddr) {
+                    cache.line_mut(idx).valid = false;
+                }
+            }
+            // Fill (I)
+            (5, true) => {
+                cache.fill(vaddr, paddr, bus);
+            }
+            // Hit Writeback Invalidate (D)
+            (5, false) => {
+                if let Some(idx) = cache.hit(vaddr, paddr) {
+                    cache.writeback(idx, bus);
+                    cache.line_mut(idx).valid = false;
+                }
+            }
+            // Hit Writeback
+            (6, _) => {
+                if let Some(idx) = cache.hit(vaddr, paddr) {
+                    if icache {
+                        // The I-cache has no dirty bit: always write back
+                        cache.line_mut(idx).dirty = true;
+                    }
+                    cache.writeback(idx, bus);
+                }
+            }
+            _ => {
+                warn!(self.logger, "reserved CACHE operation"; "op" => op.hex());
+            }
+        }
+    }
+
+    // Read from a virtual address. Returns None if an exception was raised
+    // while checking or translating the address.
+    fn read<U: MemInt>(&mut self, vaddr: u64, t: &Tracer) -> Result<Option<U>> {
+        match self.translate_aligned::<U>(vaddr, MemAccess::Read) {
+            Some(paddr) => Ok(Some(self.load::<U>(vaddr, paddr, t)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Write to a virtual address. Nothing is written if an exception was
+    // raised while checking or translating the address.
+    fn write<U: MemInt>(&mut self, vaddr: u64, val: U, t: &Tracer) -> Result<()> {
+        match self.translate_aligned::<U>(vaddr, MemAccess::Write) {
+            Some(paddr) => self.store::<U>(vaddr, paddr, val, t),
+            None => Ok(()),
+        }
+    }
+
+    // Load-linked: read from a virtual address and start a load-linked
+    // sequence, recording the physical address into COP0.
+    fn ll<U: MemInt>(&mut self, vaddr: u64, t: &Tracer) -> Result<Option<U>> {
+        let paddr = match self.translate_aligned::<U>(vaddr, MemAccess::Read) {
+            Some(paddr) => paddr,
+            None => return Ok(None),
+        };
+        let val = self.load::<U>(vaddr, paddr, t)?;
+        self.ctx.llbit = true;
+        self.cop0.load_linked(&mut self.ctx, paddr);
+        Ok(Some(val))
+    }
+
+    // Store-conditional: write to a virtual address only if the load-linked
+    // sequence was not broken. Returns whether the store was performed, or
+    // None if an exception was raised.
+    fn sc<U: MemInt>(&mut self, vaddr: u64, val: U, t: &Tracer) -> Result<Option<bool>> {
+        let paddr = match self.translate_aligned::<U>(vaddr, MemAccess::Write) {
+            Some(paddr) => paddr,
+            None => return Ok(None),
+        };
+        if !self.ctx.llbit {
+            return Ok(Some(false));
+        }
+        self.store::<U>(vaddr, paddr, val, t)?;
+        Ok(Some(true))
+    }
+
     pub fn run(&mut self, until: i64, t: &Tracer) -> Result<()> {
         self.until = until;
 
         let ctx = unsafe { self.ctx.as_mut() };
-        let mut mem = self.fetch(ctx.pc);
+        let mut mem = None;
+        let mut mem_paddr = 0;
         let mut last_mem_pc = ctx.pc;
+        let mut fetch_penalty = 0;
 
         while ctx.clock < self.until {
             if ctx.lines.halt {
@@ -647,31 +1337,114 @@ impl<C: Config> Cpu<C> {
                 return Ok(());
             }
 
+            // Exceptions raised here (interrupts, instruction fetch) refer to
+            // the instruction that is about to be executed.
+            ctx.exc_pc = ctx.pc;
+            ctx.exc_delay_slot = ctx.delay_slot;
+
             // See if there are pending interrupts that COP0 can generate.
             self.cop0.poll_interrupts(ctx);
 
+            // With the cache model enabled, cached code is fetched one
+            // instruction at a time through the I-cache.
+            if self.icache.is_some() && self.cop0.is_cached(ctx, ctx.pc) {
+                mem = None;
+                let op = match self.fetch_icache(ctx.pc) {
+                    Some(op) => op,
+                    None => continue,
+                };
+                ctx.delay_slot = false;
+                ctx.pc = ctx.next_pc;
+                ctx.next_pc += 4;
+                self.op(ctx, op, t)?;
+                t.trace_insn(&self.name, C::pc_mask(ctx.pc as u32) as u64)?;
+                continue;
+            }
+
             // Fetch the next memory area (unless we're looping, in which case
             // we already have the memory pointer).
-            if ctx.pc != last_mem_pc {
-                mem = self.fetch(ctx.pc);
+            if ctx.pc != last_mem_pc || mem.is_none() {
                 last_mem_pc = ctx.pc;
+                match self.fetch(ctx.pc) {
+                    Some((paddr, m)) => {
+                        mem = Some(m);
+                        mem_paddr = paddr;
+                    }
+                    None => {
+                        // Instruction fetch raised an exception: restart
+                        // from the exception vector.
+                        mem = None;
+                        continue;
+                    }
+                }
+                fetch_penalty = self.fetch_penalty(ctx.pc);
+            }
+
+            // Before running a pre-decoded or compiled block, drop those
+            // whose code was written.
+            self.invalidate_blocks();
+
+            // Dynamic recompiler: run the compiled block. The generated code
+            // does not call the tracer, so step aside while a debugger is
+            // attached.
+            #[cfg(all(target_arch = "x86_64", unix))]
+            {
+                if self.jit.is_some() && !self.timing && !t.is_active() {
+                    let mut jit = self.jit.take().unwrap();
+                    let until = self.until;
+                    let res = jit.run_block(self, ctx, mem_paddr, mem.as_ref().unwrap(), until, t);
+                    self.jit = Some(jit);
+                    res?;
+                    continue;
+                }
+            }
+
+            // Cached interpreter: run through the pre-decoded block, with
+            // the same logic of the tight loop below.
+            if self.blocks.is_some() {
+                let block = self.get_block(mem_paddr, mem.as_ref().unwrap());
+                for &(f, op) in block.ops.iter() {
+                    ctx.tight_exit = ctx.delay_slot;
+                    ctx.exc_pc = ctx.pc;
+                    ctx.exc_delay_slot = ctx.delay_slot;
+                    ctx.delay_slot = false;
+                    ctx.pc = ctx.next_pc;
+                    ctx.next_pc += 4;
+                    ctx.clock += fetch_penalty;
+                    self.exec(ctx, op, f, t)?;
+                    t.trace_insn(&self.name, C::pc_mask(ctx.pc as u32) as u64)?;
+                    if ctx.clock >= self.until || ctx.tight_exit {
+                        break;
+                    }
+                }
+                continue;
             }
 
             let mut iter = mem
+                .as_ref()
+                .unwrap()
                 .iter()
                 .unwrap_or_else(|| panic!("jumped to non-linear memory: {}", ctx.pc.hex()));
 
             // Tight loop: go through continuous memory, no branches, no IRQs
             while let Some(op) = iter.next() {
                 ctx.tight_exit = ctx.delay_slot;
+                ctx.exc_pc = ctx.pc;
+                ctx.exc_delay_slot = ctx.delay_slot;
                 ctx.delay_slot = false;
                 ctx.pc = ctx.next_pc;
                 ctx.next_pc += 4;
+                ctx.clock += fetch_penalty;
                 self.op(ctx, op, t)?;
                 t.trace_insn(&self.name, C::pc_mask(ctx.pc as u32) as u64)?;
                 if ctx.clock >= self.until || ctx.tight_exit {
                     break;
                 }
+                // Page boundary: the next virtual page might be mapped
+                // elsewhere, so go through address translation again.
+                if ctx.pc & 0xFFF == 0 {
+                    break;
+                }
             }
         }
         Ok(())
@@ -777,6 +1550,24 @@ impl<C: Config> DisasmView for Cpu<C> {
         C::pc_mask(v as u32) as u64
     }
 
+    fn call_return_pc(&self, pc: u64, insn: &DecodedInsn) -> Option<u64> {
+        match insn.op {
+            // The subroutine returns after the delay slot
+            "jal" | "jalr" | "bltzal" | "bgezal" | "bltzall" | "bgezall" => {
+                Some(self.pc_mask(pc + 8))
+            }
+            _ => None,
+        }
+    }
+
+    fn is_return(&self, insn: &DecodedInsn) -> bool {
+        insn.op == "jr" && insn.args[0] == Operand::IReg("ra")
+    }
+
+    fn stack_pointer(&self) -> Option<u64> {
+        Some(self.ctx.regs[29])
+    }
+
     fn disasm_block<Func: FnMut(u64, &[u8], &DecodedInsn)>(
         &self,
         pc_range: (u64, u64),
@@ -829,3 +1620,168 @@ impl<C: Config> BusMemoryView for Cpu<C> {
         &mut self.bus
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{asm, ArchIII, CopNull, Cp0, Fpu};
+    use super::*;
+    use emu::bus::be::Mem;
+    use emu::bus::{BusFill, MemFlags};
+    use emu::dbg::{Debugger, TraceEvent};
+    use slog::Discard;
+
+    struct TestConfig;
+
+    impl Config for TestConfig {
+        type Arch = ArchIII;
+        type Cop0 = Cp0;
+        type Cop1 = Fpu;
+        type Cop2 = CopNull;
+        type Cop3 = CopNull;
+    }
+
+    #[test]
+    fn disasm_calls() {
+        let logger = slog::Logger::root(Discard, o!());
+        let mut bus = Bus::new(logger.new(o!()));
+        let mut mem = Mem::new("disasm-calls", 0x10000, MemFlags::default(), None);
+        let bin = asm::assemble(
+            "
+            start:
+                jal func            // $00
+                nop
+                jalr t0             // $08
+                nop
+                bgezal a0,start     // $10
+                nop
+                bltzall a1,start    // $18
+                nop
+                j start             // $20
+                nop
+                beq zero,zero,start // $28
+                nop
+                jr t0               // $30
+                nop
+            func:
+                jr ra               // $38
+                nop
+            ",
+            0xFFFF_FFFF_BFC0_1000,
+        )
+        .unwrap();
+        mem[0x1000..0x1000 + bin.len()].copy_from_slice(&bin);
+        bus.map_mem(0x1FC0_0000, 0x1FC0_FFFF, &mem, BusFill::None)
+            .unwrap();
+        let cpu = Cpu::<TestConfig>::new(
+            "disasm-calls",
+            logger.new(o!()),
+            bus,
+            (
+                Cp0::new("disasm-calls", logger.new(o!())),
+                Fpu::new("disasm-calls", logger.new(o!())),
+                CopNull {},
+                CopNull {},
+            ),
+        );
+
+        // Calls return after their delay slot
+        let base = 0x1FC0_1000;
+        let mut calls = Vec::new();
+        let mut rets = Vec::new();
+        cpu.disasm_block((base, base + bin.len() as u64), |pc, _, insn| {
+            if let Some(ret) = cpu.call_return_pc(pc, insn) {
+                calls.push((pc - base, ret - base));
+            }
+            if cpu.is_return(insn) {
+                rets.push(pc - base);
+            }
+        });
+        assert_eq!(
+            calls,
+            vec![(0x00, 0x08), (0x08, 0x10), (0x10, 0x18), (0x18, 0x20)]
+        );
+        assert_eq!(rets, vec![0x38]);
+    }
+
+    fn run_to_event(cpu: &mut Cpu<TestConfig>, dbg: &Debugger) -> TraceEvent {
+        match cpu.run(cpu.ctx().clock + 100_000, &dbg.new_tracer()) {
+            Err(evt) => *evt,
+            Ok(()) => panic!("no trace event"),
+        }
+    }
+
+    #[test]
+    fn step_out_nested() {
+        let logger = slog::Logger::root(Discard, o!());
+        let mut bus = Bus::new(logger.new(o!()));
+        let mut mem = Mem::new("step-out", 0x10000, MemFlags::default(), None);
+        let bin = asm::assemble(
+            "
+            start:
+                jal outer           // $00
+                nop
+            done:
+                j done              // $08
+                nop
+            outer:
+                addiu sp,sp,-16     // $10
+                sw ra,0(sp)
+                jal leaf            // $18
+                nop
+                jal leaf            // $20
+                nop
+                lw ra,0(sp)         // $28
+                jr ra
+                addiu sp,sp,16
+            leaf:
+                jr ra               // $34
+                nop
+            ",
+            0xFFFF_FFFF_BFC0_1000,
+        )
+        .unwrap();
+        mem[0x1000..0x1000 + bin.len()].copy_from_slice(&bin);
+        bus.map_mem(0x1FC0_0000, 0x1FC0_FFFF, &mem, BusFill::None)
+            .unwrap();
+        let mut cpu = Cpu::<TestConfig>::new(
+            "step-out",
+            logger.new(o!()),
+            bus,
+            (
+                Cp0::new("step-out", logger.new(o!())),
+                Fpu::new("step-out", logger.new(o!())),
+                CopNull {},
+                CopNull {},
+            ),
+        );
+        cpu.ctx_mut().set_pc(0xFFFF_FFFF_BFC0_1000);
+        cpu.ctx_mut().regs[29] = 0xFFFF_FFFF_BFC0_8000;
+
+        // Stop within outer(), after its prologue
+        let mut dbg = Debugger::new(&vec!["step-out".to_owned()]);
+        dbg.set_breakpoint_oneshot("step-out", Some(0x1FC0_1018));
+        match run_to_event(&mut cpu, &dbg) {
+            TraceEvent::BreakpointOneShot(_, pc) => assert_eq!(pc, 0x1FC0_1018),
+            evt => panic!("unexpected trace event: {:?}", evt),
+        }
+        dbg.disable_breakpoint_oneshot();
+
+        // Step out: the returns of the nested calls to leaf() happen at the
+        // same stack depth, but must be skipped. The return address is
+        // resolved only when outer() executes its own return, after $ra was
+        // reloaded from the stack.
+        dbg.set_step_out("step-out", Some(cpu.ctx().regs[29]));
+        match run_to_event(&mut cpu, &dbg) {
+            TraceEvent::StepOut(_, pc) => assert_eq!(pc, 0x1FC0_1008),
+            evt => panic!("unexpected trace event: {:?}", evt),
+        }
+
+        // Run through the delay slot up to the return address
+        dbg.set_breakpoint_oneshot("step-out", Some(0x1FC0_1008));
+        match run_to_event(&mut cpu, &dbg) {
+            TraceEvent::BreakpointOneShot(_, pc) => assert_eq!(pc, 0x1FC0_1008),
+            evt => panic!("unexpected trace event: {:?}", evt),
+        }
+        assert_eq!(cpu.ctx().regs[29], 0xFFFF_FFFF_BFC0_8000);
+    }
+}
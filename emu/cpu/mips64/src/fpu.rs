@@ -1,5 +1,5 @@
 use super::decode::{MEMOP_FMT, REG_NAMES};
-use super::{Cop, CpuContext};
+use super::{Cop, CpuContext, Exception};
 
 use emu::dbg::{
     DebuggerRenderer, DecodedInsn, Operand, RegisterSize, RegisterView, Result, Tracer,
@@ -12,6 +12,7 @@ use serde_derive::{Deserialize, Serialize};
 use slog;
 use slog::*;
 use std::marker::PhantomData;
+use std::num::FpCategory;
 
 const FPU_REG_NAMES: [&'static str; 32] = [
     "f0", "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9", "f10", "f11", "f12", "f13", "f14",
@@ -25,6 +26,31 @@ const FPU_CREG_NAMES: [&'static str; 32] = [
     "?25?", "?26?", "?27?", "?28?", "?29?", "?30?", "FCSR",
 ];
 
+// FCSR fields
+const FCSR_FLAGS_SHIFT: u32 = 2;
+const FCSR_ENABLES_SHIFT: u32 = 7;
+const FCSR_CAUSE_SHIFT: u32 = 12;
+const FCSR_FS: u64 = 1 << 24; // Flush denormalized results to zero
+const FCSR_MASK: u64 = 0x0183_FFFF; // Writable bits
+
+// Floating-point exceptions, in the bit order used by the Flags, Enables and
+// Cause fields of FCSR. Unimplemented Operation only exists in Cause, and
+// cannot be masked.
+const FPE_INEXACT: u64 = 1 << 0;
+const FPE_UNDERFLOW: u64 = 1 << 1;
+const FPE_OVERFLOW: u64 = 1 << 2;
+const FPE_DIVBYZERO: u64 = 1 << 3;
+const FPE_INVALID: u64 = 1 << 4;
+const FPE_UNIMPLEMENTED: u64 = 1 << 5;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum RoundingMode {
+    Nearest,  // RN
+    Zero,     // RZ
+    PlusInf,  // RP
+    MinusInf, // RM
+}
+
 #[derive(Default, Copy, Clone, Serialize, Deserialize)]
 struct FpuContext {
     regs: [u64; 32],
@@ -58,6 +84,119 @@ impl FpuContext {
     fn set_fpr<F: FloatRawConvert>(&mut self, idx: usize, val: F) {
         self.set_fgr(idx, val.to_u64bits());
     }
+
+    fn rounding_mode(&self) -> RoundingMode {
+        match self.fcsr & 3 {
+            0 => RoundingMode::Nearest,
+            1 => RoundingMode::Zero,
+            2 => RoundingMode::PlusInf,
+            _ => RoundingMode::MinusInf,
+        }
+    }
+
+    // Computational instructions clear the Cause field before executing.
+    fn clear_cause(&mut self) {
+        self.fcsr &= !(0x3F << FCSR_CAUSE_SHIFT);
+    }
+
+    // Returns true if a Cause bit is set together with its Enable bit.
+    fn trap_pending(&self) -> bool {
+        let cause = (self.fcsr >> FCSR_CAUSE_SHIFT) & 0x3F;
+        let enables = ((self.fcsr >> FCSR_ENABLES_SHIFT) & 0x1F) | FPE_UNIMPLEMENTED;
+        cause & enables != 0
+    }
+
+    // Signal the specified floating-point exceptions. If any of them is
+    // enabled, a Floating Point exception is raised and false is returned:
+    // in this case, the destination register must not be modified.
+    // Otherwise, the exceptions are accumulated into the Flags field.
+    fn signal(&mut self, cpu: &mut CpuContext, exc: u64) -> bool {
+        self.fcsr |= exc << FCSR_CAUSE_SHIFT;
+        if self.trap_pending() {
+            cpu.raise_exception(Exception::FloatingPoint);
+            return false;
+        }
+        self.fcsr |= (exc & 0x1F) << FCSR_FLAGS_SHIFT;
+        true
+    }
+
+    // Finalize the result of an operation: `res` is the result rounded to
+    // nearest, and `err` has the sign of the rounding error (exact result
+    // minus res). The result is rounded as per the current rounding mode,
+    // and the relevant exceptions are signaled. `finite` is true if all the
+    // operands were finite. Returns None if the operation trapped.
+    fn round<F: Float + FloatRawConvert>(
+        &mut self,
+        cpu: &mut CpuContext,
+        res: F,
+        err: f64,
+        finite: bool,
+    ) -> Option<F> {
+        let rm = self.rounding_mode();
+        let neg = res.is_sign_negative();
+        let mut res = res;
+        let mut exc = 0;
+
+        if res.is_nan() {
+            exc = FPE_INVALID;
+            res = F::default_nan();
+        } else if res.is_infinite() {
+            if finite {
+                exc = FPE_OVERFLOW | FPE_INEXACT;
+                res = match (rm, neg) {
+                    (RoundingMode::Nearest, _)
+                    | (RoundingMode::PlusInf, false)
+                    | (RoundingMode::MinusInf, true) => res,
+                    _ if neg => -F::max_value(),
+                    _ => F::max_value(),
+                };
+            }
+        } else {
+            let tiny = if res.is_zero() {
+                err != 0.0
+            } else {
+                res.abs() < F::min_positive_value()
+            };
+            if tiny {
+                if self.fcsr & FCSR_FS == 0 {
+                    // Denormalized results are not supported by the hardware.
+                    exc = FPE_UNIMPLEMENTED;
+                } else {
+                    exc = FPE_UNDERFLOW | FPE_INEXACT;
+                    res = match (rm, neg) {
+                        (RoundingMode::PlusInf, false) => F::min_positive_value(),
+                        (RoundingMode::MinusInf, true) => -F::min_positive_value(),
+                        _ if neg => -F::zero(),
+                        _ => F::zero(),
+                    };
+                }
+            } else if err != 0.0 {
+                exc = FPE_INEXACT;
+                res = match rm {
+                    RoundingMode::Nearest => res,
+                    RoundingMode::Zero if (err < 0.0) != neg => {
+                        if neg {
+                            res.next_up()
+                        } else {
+                            res.next_down()
+                        }
+                    }
+                    RoundingMode::PlusInf if err > 0.0 => res.next_up(),
+                    RoundingMode::MinusInf if err < 0.0 => res.next_down(),
+                    _ => res,
+                };
+                if res.is_infinite() {
+                    exc |= FPE_OVERFLOW;
+                }
+            }
+        }
+
+        if self.signal(cpu, exc) {
+            Some(res)
+        } else {
+            None
+        }
+    }
 }
 
 pub struct Fpu {
@@ -72,7 +211,10 @@ trait FloatRawConvert {
     fn bankers_round(self) -> Self;
     fn to_f32(self) -> f32;
     fn to_f64(self) -> f64;
-    fn to_u64(self) -> u64;
+    fn default_nan() -> Self;
+    fn is_snan(self) -> bool;
+    fn next_up(self) -> Self;
+    fn next_down(self) -> Self;
 }
 
 impl FloatRawConvert for f32 {
@@ -96,8 +238,23 @@ impl FloatRawConvert for f32 {
     fn to_f64(self) -> f64 {
         self as f64
     }
-    fn to_u64(self) -> u64 {
-        self as u64
+    fn default_nan() -> Self {
+        f32::from_bits(0x7FBF_FFFF)
+    }
+    fn is_snan(self) -> bool {
+        // MIPS legacy NaN encoding: the MSB of the fraction is set for
+        // signaling NaNs.
+        self.is_nan() && self.to_bits() & 0x0040_0000 != 0
+    }
+    fn next_up(self) -> Self {
+        match self.to_bits() {
+            0x8000_0000 | 0 => f32::from_bits(1),
+            b if self > 0.0 => f32::from_bits(b + 1),
+            b => f32::from_bits(b - 1),
+        }
+    }
+    fn next_down(self) -> Self {
+        -(-self).next_up()
     }
 }
 
@@ -122,8 +279,21 @@ impl FloatRawConvert for f64 {
     fn to_f64(self) -> f64 {
         self as f64
     }
-    fn to_u64(self) -> u64 {
-        self as u64
+    fn default_nan() -> Self {
+        f64::from_bits(0x7FF7_FFFF_FFFF_FFFF)
+    }
+    fn is_snan(self) -> bool {
+        self.is_nan() && self.to_bits() & 0x0008_0000_0000_0000 != 0
+    }
+    fn next_up(self) -> Self {
+        match self.to_bits() {
+            0x8000_0000_0000_0000 | 0 => f64::from_bits(1),
+            b if self > 0.0 => f64::from_bits(b + 1),
+            b => f64::from_bits(b - 1),
+        }
+    }
+    fn next_down(self) -> Self {
+        -(-self).next_up()
     }
 }
 
@@ -139,6 +309,9 @@ impl<'a, F: Float + FloatRawConvert> Fop<'a, F> {
     fn func(&self) -> u32 {
         self.opcode & 0x3f
     }
+    fn fmt(&self) -> u32 {
+        (self.opcode >> 21) & 0x1f
+    }
     fn cc(&self) -> usize {
         ((self.opcode >> 8) & 7) as usize
     }
@@ -169,15 +342,146 @@ impl<'a, F: Float + FloatRawConvert> Fop<'a, F> {
     fn set_fgd(&mut self, v: u64) {
         self.ctx.set_fgr(self.rd(), v);
     }
-}
 
-macro_rules! approx {
-    ($op:ident, $t:expr, $round:ident, $size:ident, $fallback:expr) => {{
-        match $op.fs().$round().$size() {
-            Some(v) => $op.set_fgd(v as u64),
-            None => $op.set_fgd($fallback as u64),
+    // NaNs and denormalized operands are not supported by the hardware,
+    // and raise an Unimplemented Operation exception. Returns false in
+    // that case.
+    fn check_inputs(&mut self, vals: &[F]) -> bool {
+        if vals
+            .iter()
+            .any(|v| v.is_nan() || v.classify() == FpCategory::Subnormal)
+        {
+            self.ctx.signal(self.cpu, FPE_UNIMPLEMENTED);
+            return false;
         }
-    }};
+        true
+    }
+
+    // Round the result of an arithmetic operation and store it into fd,
+    // unless the operation traps. See FpuContext::round.
+    fn set_fd_rounded(&mut self, res: F, err: F, finite: bool) {
+        let err = if finite { err.to_f64() } else { 0.0 };
+        if let Some(v) = self.ctx.round(self.cpu, res, err, finite) {
+            self.set_fd(v);
+        }
+    }
+
+    fn add(&mut self, a: F, b: F) {
+        if !self.check_inputs(&[a, b]) {
+            return;
+        }
+        // Compute the exact rounding error (2Sum)
+        let s = a + b;
+        let bb = s - a;
+        let err = (a - (s - bb)) + (b - bb);
+
+        // An exact zero sum of operands of opposite signs is -0 only when
+        // rounding towards minus infinity.
+        let mut res = s;
+        if s.is_zero() && a.is_sign_negative() != b.is_sign_negative() {
+            res = if self.ctx.rounding_mode() == RoundingMode::MinusInf {
+                -F::zero()
+            } else {
+                F::zero()
+            };
+        }
+        self.set_fd_rounded(res, err, a.is_finite() && b.is_finite());
+    }
+
+    fn mul(&mut self, a: F, b: F) {
+        if !self.check_inputs(&[a, b]) {
+            return;
+        }
+        let p = a * b;
+        let err = a.mul_add(b, -p);
+        self.set_fd_rounded(p, err, a.is_finite() && b.is_finite());
+    }
+
+    fn div(&mut self, a: F, b: F) {
+        if !self.check_inputs(&[a, b]) {
+            return;
+        }
+        if b.is_zero() && a.is_finite() && !a.is_zero() {
+            if self.ctx.signal(self.cpu, FPE_DIVBYZERO) {
+                self.set_fd(a / b);
+            }
+            return;
+        }
+        let q = a / b;
+        let rem = (-q).mul_add(b, a);
+        let err = if b.is_sign_negative() { -rem } else { rem };
+        self.set_fd_rounded(q, err, a.is_finite() && b.is_finite());
+    }
+
+    fn sqrt(&mut self, a: F) {
+        if !self.check_inputs(&[a]) {
+            return;
+        }
+        let s = a.sqrt();
+        let err = (-s).mul_add(s, a);
+        self.set_fd_rounded(s, err, a.is_finite());
+    }
+
+    // CVT.S.fmt / CVT.D.fmt
+    fn cvt_float(&mut self, double: bool) {
+        let fs = self.fs();
+        if double == (self.fmt() == 0x11) {
+            // Conversion to the same format
+            self.ctx.signal(self.cpu, FPE_UNIMPLEMENTED);
+            return;
+        }
+        if !self.check_inputs(&[fs]) {
+            return;
+        }
+        let v = fs.to_f64();
+        if double {
+            self.set_fgd(v.to_u64bits());
+        } else {
+            let res = v as f32;
+            let err = v - res as f64;
+            if let Some(v) = self.ctx.round(self.cpu, res, err, v.is_finite()) {
+                self.set_fgd(v.to_u64bits());
+            }
+        }
+    }
+
+    // CVT.W.fmt, CVT.L.fmt, and ROUND/TRUNC/CEIL/FLOOR: convert to a 32-bit
+    // or 64-bit fixed-point value with the specified rounding mode.
+    fn cvt_fixed(&mut self, rm: RoundingMode, long: bool) {
+        let fs = self.fs();
+        if !self.check_inputs(&[fs]) {
+            return;
+        }
+        let v = fs.to_f64();
+        let res = match rm {
+            RoundingMode::Nearest => v.bankers_round(),
+            RoundingMode::Zero => v.trunc(),
+            RoundingMode::PlusInf => v.ceil(),
+            RoundingMode::MinusInf => v.floor(),
+        };
+
+        // Values that do not fit the destination (including infinities)
+        // are not handled by the hardware. For 64-bit conversions, the
+        // supported range is limited to 53 bits.
+        let limit = if long {
+            (1u64 << 53) as f64
+        } else {
+            (1u64 << 31) as f64
+        };
+        if !(res >= -limit && res < limit) {
+            self.ctx.signal(self.cpu, FPE_UNIMPLEMENTED);
+            return;
+        }
+
+        let exc = if res != v { FPE_INEXACT } else { 0 };
+        if self.ctx.signal(self.cpu, exc) {
+            if long {
+                self.set_fgd(res as i64 as u64);
+            } else {
+                self.set_fgd(res as i32 as u32 as u64);
+            }
+        }
+    }
 }
 
 macro_rules! cond {
@@ -187,8 +491,13 @@ macro_rules! cond {
         let nan = fs.is_nan() || ft.is_nan();
         let less = if !nan { fs < ft } else { false };
         let equal = if !nan { fs == ft } else { false };
-        if nan && $func & 8 != 0 {
-            panic!("signal FPU NaN in comparison");
+
+        // Signaling comparisons raise Invalid on any NaN; the others only
+        // on signaling NaNs.
+        if nan && ($func & 8 != 0 || fs.is_snan() || ft.is_snan()) {
+            if !$op.ctx.signal($op.cpu, FPE_INVALID) {
+                return Ok(());
+            }
         }
 
         let cond =
@@ -222,6 +531,18 @@ impl Fpu {
         }
     }
 
+    /// Return the current value of FCSR (the control/status register).
+    pub fn fcsr(&self) -> u64 {
+        self.ctx.fcsr
+    }
+
+    /// Directly set FCSR, without going through CTC1 (so no exception is
+    /// raised). The condition bit is propagated to the branch condition.
+    pub fn set_fcsr(&mut self, val: u64) {
+        self.ctx.fcsr = val & FCSR_MASK;
+        self.ctx.fccr = (self.ctx.fccr & !1) | ((val >> 23) & 1);
+    }
+
     fn set_cc(&mut self, cc: usize, val: bool) {
         if cc > 8 {
             panic!("invalid cc code");
@@ -245,7 +566,7 @@ impl Fpu {
         &mut self,
         cpu: &mut CpuContext,
         opcode: u32,
-        t: &Tracer,
+        _t: &Tracer,
     ) -> Result<()> {
         let mut op = Fop::<M> {
             opcode,
@@ -254,36 +575,22 @@ impl Fpu {
             cpu: cpu,
             phantom: PhantomData,
         };
+        if op.func() != 0x06 {
+            op.ctx.clear_cause();
+        }
+        let rm = op.ctx.rounding_mode();
         match op.func() {
-            0x00 => {
-                // ADD.fmt
-                let v = op.fs() + op.ft();
-                op.set_fd(v)
-            }
-            0x01 => {
-                // SUB.fmt
-                let v = op.fs() - op.ft();
-                op.set_fd(v)
-            }
-            0x02 => {
-                // MUL.with_fmtt
-                let v = op.fs() * op.ft();
-                op.set_fd(v)
-            }
-            0x03 => {
-                // DIV.fmt
-                let v = op.fs() / op.ft();
-                op.set_fd(v)
-            }
-            0x04 => {
-                // SQRT.fmt
-                let v = op.fs().sqrt();
-                op.set_fd(v)
-            }
+            0x00 => op.add(op.fs(), op.ft()),  // ADD.fmt
+            0x01 => op.add(op.fs(), -op.ft()), // SUB.fmt
+            0x02 => op.mul(op.fs(), op.ft()),  // MUL.fmt
+            0x03 => op.div(op.fs(), op.ft()),  // DIV.fmt
+            0x04 => op.sqrt(op.fs()),          // SQRT.fmt
             0x05 => {
                 // ABS.fmt
-                let v = op.fs().abs();
-                op.set_fd(v)
+                let v = op.fs();
+                if op.check_inputs(&[v]) {
+                    op.set_fd(v.abs());
+                }
             }
             0x06 => {
                 // MOV.fmt
@@ -292,22 +599,24 @@ impl Fpu {
             }
             0x07 => {
                 // NEG.fmt
-                let v = op.fs().neg();
-                op.set_fd(v)
+                let v = op.fs();
+                if op.check_inputs(&[v]) {
+                    op.set_fd(v.neg());
+                }
             }
-            0x08 => approx!(op, t, bankers_round, to_i64, i64::max_value()), // ROUND.L.fmt
-            0x09 => approx!(op, t, trunc, to_i64, i64::max_value()),         // TRUNC.L.fmt
-            0x0A => approx!(op, t, ceil, to_i64, i64::max_value()),          // CEIL.L.fmt
-            0x0B => approx!(op, t, floor, to_i64, i64::max_value()),         // FLOOR.L.fmt
-            0x0C => approx!(op, t, bankers_round, to_i32, i32::max_value()), // ROUND.W.fmt
-            0x0D => approx!(op, t, trunc, to_i32, i32::max_value()),         // TRUNC.W.fmt
-            0x0E => approx!(op, t, ceil, to_i32, i32::max_value()),          // CEIL.W.fmt
-            0x0F => approx!(op, t, floor, to_i32, i32::max_value()),         // FLOOR.W.fmt
-
-            0x20 => op.set_fgd(op.fs().to_f32().to_u64bits()), // CVT.S.fmt
-            0x21 => op.set_fgd(op.fs().to_f64().to_u64bits()), // CVT.D.fmt
-            0x24 => op.set_fgd(op.fs().to_u64() as u32 as u64), // CVT.W.fmt
-            0x25 => op.set_fgd(op.fs().to_u64()),              // CVT.L.fmt
+            0x08 => op.cvt_fixed(RoundingMode::Nearest, true), // ROUND.L.fmt
+            0x09 => op.cvt_fixed(RoundingMode::Zero, true),    // TRUNC.L.fmt
+            0x0A => op.cvt_fixed(RoundingMode::PlusInf, true), // CEIL.L.fmt
+            0x0B => op.cvt_fixed(RoundingMode::MinusInf, true), // FLOOR.L.fmt
+            0x0C => op.cvt_fixed(RoundingMode::Nearest, false), // ROUND.W.fmt
+            0x0D => op.cvt_fixed(RoundingMode::Zero, false),   // TRUNC.W.fmt
+            0x0E => op.cvt_fixed(RoundingMode::PlusInf, false), // CEIL.W.fmt
+            0x0F => op.cvt_fixed(RoundingMode::MinusInf, false), // FLOOR.W.fmt
+
+            0x20 => op.cvt_float(false),     // CVT.S.fmt
+            0x21 => op.cvt_float(true),      // CVT.D.fmt
+            0x24 => op.cvt_fixed(rm, false), // CVT.W.fmt
+            0x25 => op.cvt_fixed(rm, true),  // CVT.L.fmt
 
             0x30 => cond!(op, 0x30), // C.T.fmt
             0x31 => cond!(op, 0x31), // C.UN.fmt
@@ -327,16 +636,50 @@ impl Fpu {
             0x3F => cond!(op, 0x3F), // C.NGT.fmt
 
             _ => {
-                error!(
-                    op.fpu.logger,
-                    "unimplemented COP1 opcode: func={:x?}",
-                    op.func()
-                );
-                return t.break_here("unimplemented COP1 opcode");
+                // Reserved function: signal Unimplemented Operation.
+                warn!(op.fpu.logger, "reserved COP1 opcode"; "func" => op.func().hex());
+                op.ctx.signal(op.cpu, FPE_UNIMPLEMENTED);
             }
         }
         Ok(())
     }
+
+    // CVT.S.W, CVT.D.W, CVT.S.L, CVT.D.L
+    fn cvt_from_fixed(&mut self, cpu: &mut CpuContext, fmt: u32, func: u32, rs: usize, rd: usize) {
+        let ctx = &mut self.ctx;
+        ctx.clear_cause();
+        let fgs = ctx.get_fgr(rs);
+        let v = if fmt == 0x14 {
+            fgs as i32 as i64
+        } else {
+            fgs as i64
+        };
+        // 64-bit sources are supported only up to 55 bits.
+        if fmt == 0x15 && (v >= 1 << 55 || v < -(1 << 55)) {
+            ctx.signal(cpu, FPE_UNIMPLEMENTED);
+            return;
+        }
+        match func {
+            0x20 => {
+                let res = v as f32;
+                let err = (v as i128 - res as i128) as f64;
+                if let Some(res) = ctx.round(cpu, res, err, true) {
+                    ctx.set_fpr(rd, res);
+                }
+            }
+            0x21 => {
+                let res = v as f64;
+                let err = (v as i128 - res as i128) as f64;
+                if let Some(res) = ctx.round(cpu, res, err, true) {
+                    ctx.set_fpr(rd, res);
+                }
+            }
+            _ => {
+                warn!(self.logger, "reserved COP1 fixed-point opcode"; "func" => func.hex());
+                ctx.signal(cpu, FPE_UNIMPLEMENTED);
+            }
+        }
+    }
 }
 
 impl Cop for Fpu {
@@ -369,7 +712,14 @@ impl Cop for Fpu {
             0x5 => self.ctx.set_fgr(rs, cpu.regs[rt]),               // DMTC1
             0x6 => match rs {
                 // CTC1
-                31 => self.ctx.fcsr = cpu.regs[rt],
+                31 => {
+                    self.ctx.fcsr = cpu.regs[rt] & FCSR_MASK;
+                    // Setting a Cause bit together with its Enable bit
+                    // raises the exception immediately.
+                    if self.ctx.trap_pending() {
+                        cpu.raise_exception(Exception::FloatingPoint);
+                    }
+                }
                 _ => {
                     error!(self.logger, "CTC1 to unknown register: {:x}", rs);
                     return t.break_here("CTC1 to unknown register");
@@ -386,38 +736,7 @@ impl Cop for Fpu {
             0x10 => return self.fop::<f32>(cpu, opcode, t),
             0x11 => return self.fop::<f64>(cpu, opcode, t),
 
-            0x14 => match func {
-                0x20 => {
-                    // CVT.S.W
-                    let fgs = self.ctx.get_fgr(rs);
-                    self.ctx.set_fpr(rd, fgs as i32 as f32);
-                }
-                0x21 => {
-                    // CVT.D.W
-                    let fgs = self.ctx.get_fgr(rs);
-                    self.ctx.set_fpr(rd, fgs as i32 as f64);
-                }
-                _ => {
-                    error!(self.logger, "unimplemented COP1 W: func={:x?}", func);
-                    return t.break_here("unimplemented COP1 W opcode");
-                }
-            },
-            0x15 => match func {
-                0x20 => {
-                    // CVT.S.L
-                    let fgs = self.ctx.get_fgr(rs);
-                    self.ctx.set_fpr(rd, fgs as i64 as f32);
-                }
-                0x21 => {
-                    // CVT.D.L
-                    let fgs = self.ctx.get_fgr(rs);
-                    self.ctx.set_fpr(rd, fgs as i64 as f64);
-                }
-                _ => {
-                    error!(self.logger, "unimplemented COP1 L: func={:x?}", func);
-                    return t.break_here("unimplemented COP1 L opcode");
-                }
-            },
+            0x14 | 0x15 => self.cvt_from_fixed(cpu, fmt, func, rs, rd), // CVT.[SD].[WL]
 
             _ => {
                 error!(self.logger, "unimplemented COP1 fmt: fmt={:x?}", fmt);
@@ -647,3 +966,130 @@ impl RegisterView for Fpu {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::Discard;
+
+    const FMT_S: u32 = 0x10;
+    const FMT_D: u32 = 0x11;
+    const ADD: u32 = 0x00;
+    const MUL: u32 = 0x02;
+
+    // Value of fd before the opcode is executed, to check that trapping
+    // opcodes leave it unmodified.
+    const FD_UNCHANGED: u64 = 0xDEAD_BEEF;
+
+    struct TestFpu {
+        fpu: Fpu,
+        cpu: CpuContext,
+    }
+
+    impl TestFpu {
+        fn new(name: &'static str) -> TestFpu {
+            let logger = slog::Logger::root(Discard, o!());
+            let mut cpu = CpuContext::default();
+            cpu.fpu64 = true;
+            TestFpu {
+                fpu: Fpu::new(name, logger),
+                cpu,
+            }
+        }
+
+        // Execute "func.fmt f4,f0,f2" with the specified FCSR and operands.
+        // Returns the final value of f4 and FCSR, and whether a Floating
+        // Point exception was raised.
+        fn fop(&mut self, fmt: u32, func: u32, fcsr: u64, fs: u64, ft: u64) -> (u64, u64, bool) {
+            self.fpu.ctx.fcsr = fcsr;
+            self.fpu.ctx.regs[0] = fs;
+            self.fpu.ctx.regs[2] = ft;
+            self.fpu.ctx.regs[4] = FD_UNCHANGED;
+            let opcode = 0x4600_0000 | fmt << 21 | 2 << 16 | 4 << 6 | func; // fs = f0
+            self.fpu.op(&mut self.cpu, opcode, &Tracer::null()).unwrap();
+            let trap = match self.cpu.cop_exception.take() {
+                Some(Exception::FloatingPoint) => true,
+                Some(_) => panic!("unexpected exception"),
+                None => false,
+            };
+            (self.fpu.ctx.regs[4], self.fpu.ctx.fcsr, trap)
+        }
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let mut t = TestFpu::new("fpu-rounding");
+
+        // 1 + 0.75 ulp, and its negation. Inexact is signaled in Cause
+        // (bit 12) and Flags (bit 2).
+        for &(rm, exp_pos, exp_neg) in &[
+            (0, 0x3F80_0001, 0xBF80_0001), // RN
+            (1, 0x3F80_0000, 0xBF80_0000), // RZ
+            (2, 0x3F80_0001, 0xBF80_0000), // RP
+            (3, 0x3F80_0000, 0xBF80_0001), // RM
+        ] {
+            let res = t.fop(FMT_S, ADD, rm, 0x3F80_0000, 0x33C0_0000);
+            assert_eq!(res, (exp_pos, rm | 0x1004, false), "rm={}", rm);
+            let res = t.fop(FMT_S, ADD, rm, 0xBF80_0000, 0xB3C0_0000);
+            assert_eq!(res, (exp_neg, rm | 0x1004, false), "rm={}", rm);
+        }
+
+        // Same in double precision
+        let (one, frac) = (0x3FF0_0000_0000_0000, 0x3CA8_0000_0000_0000);
+        for &(rm, exp) in &[
+            (0, 0x3FF0_0000_0000_0001),
+            (1, 0x3FF0_0000_0000_0000),
+            (2, 0x3FF0_0000_0000_0001),
+            (3, 0x3FF0_0000_0000_0000),
+        ] {
+            let res = t.fop(FMT_D, ADD, rm, one, frac);
+            assert_eq!(res, (exp, rm | 0x1004, false), "rm={}", rm);
+        }
+    }
+
+    #[test]
+    fn exception_flags() {
+        let mut t = TestFpu::new("fpu-flags");
+
+        // Flags accumulate across opcodes, while Cause is cleared by each
+        // computational opcode.
+        let (_, fcsr, _) = t.fop(FMT_S, ADD, 0, 0x3F80_0000, 0x33C0_0000);
+        let res = t.fop(FMT_S, ADD, fcsr, 0x3F80_0000, 0x3F80_0000);
+        assert_eq!(res, (0x4000_0000, 0x0000_0004, false));
+
+        // Overflow: max * 2 rounds to infinity (RN) or to max (RZ).
+        let (max, two) = (0x7F7F_FFFF, 0x4000_0000);
+        let res = t.fop(FMT_S, MUL, 0, max, two);
+        assert_eq!(res, (0x7F80_0000, 0x0001_4050, false));
+        let res = t.fop(FMT_S, MUL, 1, max, two);
+        assert_eq!(res, (0x7F7F_FFFF, 0x0001_4051, false));
+
+        // Underflow: with FS set, a denormalized result is flushed to zero.
+        let (min, half) = (0x0080_0000, 0x3F00_0000);
+        let res = t.fop(FMT_S, MUL, FCSR_FS, min, half);
+        assert_eq!(res, (0, FCSR_FS | 0x0000_300C, false));
+
+        // Without FS, denormalized results are not supported by the
+        // hardware: Unimplemented Operation is always raised.
+        let res = t.fop(FMT_S, MUL, 0, min, half);
+        assert_eq!(res, (FD_UNCHANGED, 0x0002_0000, true));
+
+        // An enabled exception traps, without updating fd or Flags.
+        let res = t.fop(FMT_S, MUL, 0x0000_0200, max, two);
+        assert_eq!(res, (FD_UNCHANGED, 0x0001_4200, true));
+    }
+
+    #[test]
+    fn denormal_input() {
+        let mut t = TestFpu::new("fpu-denormal");
+
+        // Denormalized operands raise Unimplemented Operation, which cannot
+        // be masked, even with FS set.
+        for &fcsr in &[0, FCSR_FS] {
+            let res = t.fop(FMT_S, ADD, fcsr, 0x0000_0001, 0x3F80_0000);
+            assert_eq!(res, (FD_UNCHANGED, fcsr | 0x0002_0000, true));
+            let res = t.fop(FMT_D, MUL, fcsr, 0x3FF0_0000_0000_0000, 1);
+            assert_eq!(res, (FD_UNCHANGED, fcsr | 0x0002_0000, true));
+        }
+    }
+}
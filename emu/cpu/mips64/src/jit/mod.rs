@@ -0,0 +1,614 @@
+//! Dynamic recompiler for x86-64 hosts.
+//!
+//! Blocks are delimited exactly like in the cached interpreter (see
+//! Cpu::build_block), and translated into host code. Simple integer opcodes
+//! are compiled natively; everything else (loads/stores, branches, COP0/COP2
+//! and all other coprocessor opcodes) is executed by calling back into the
+//! interpreter handler of the opcode, so that the final CpuContext is
+//! identical to the one produced by the interpreter.
+mod x64;
+
+use self::x64::{Alu, Cond, Emitter, Label, Reg, Shift};
+use super::block::{Block, BlockCache};
+use super::cpu::OpFn;
+use super::{Arch, Config, Cpu, CpuContext};
+
+use emu::bus::be::MemIoR;
+use emu::bus::WriteWatch;
+use emu::dbg::{Result, Tracer};
+use emu::int::Numerics;
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::rc::Rc;
+
+/// Size of the buffer holding the generated code. When full, all the
+/// compiled blocks are discarded and compilation starts over.
+const CODE_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+// Signature of a compiled block: it receives the CpuContext and the JitEnv.
+type BlockFn = extern "sysv64" fn(*mut CpuContext, *mut u8);
+
+/// A block of code translated into host code. The pre-decoded block is kept
+/// to execute the opcodes that are not compiled natively.
+pub(crate) struct JitBlock<C: Config> {
+    block: Block<OpFn<C>>,
+    code: BlockFn,
+}
+
+/// Executable memory area for the generated code. The area is never writable
+/// and executable at the same time: pages are made writable only while new
+/// code is being copied into them.
+struct CodeBuffer {
+    ptr: *mut u8,
+    used: usize,
+}
+
+impl CodeBuffer {
+    fn new() -> CodeBuffer {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                CODE_BUFFER_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANON,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            panic!("cannot allocate JIT code buffer");
+        }
+        CodeBuffer {
+            ptr: ptr as *mut u8,
+            used: 0,
+        }
+    }
+
+    // Copy the code into the buffer, returning its address, or None if the
+    // buffer is full.
+    fn alloc(&mut self, code: &[u8]) -> Option<*const u8> {
+        if self.used + code.len() > CODE_BUFFER_SIZE {
+            return None;
+        }
+        unsafe {
+            let dst = self.ptr.add(self.used);
+            let pages = self.pages(self.used, code.len());
+            self.protect(pages, libc::PROT_READ | libc::PROT_WRITE);
+            ptr::copy_nonoverlapping(code.as_ptr(), dst, code.len());
+            self.protect(pages, libc::PROT_READ | libc::PROT_EXEC);
+            self.used = (self.used + code.len() + 15) & !15;
+            Some(dst)
+        }
+    }
+
+    // Page-aligned range (offset, length) covering len bytes at offset.
+    fn pages(&self, offset: usize, len: usize) -> (usize, usize) {
+        let page_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+            n if n > 0 => n as usize,
+            _ => 4096,
+        };
+        let start = offset & !(page_size - 1);
+        let end = (offset + len + page_size - 1) & !(page_size - 1);
+        (start, end.min(CODE_BUFFER_SIZE) - start)
+    }
+
+    fn protect(&self, (offset, len): (usize, usize), prot: libc::c_int) {
+        let ret = unsafe { libc::mprotect(self.ptr.add(offset) as *mut libc::c_void, len, prot) };
+        if ret != 0 {
+            panic!("cannot change protection of JIT code buffer");
+        }
+    }
+
+    fn reset(&mut self) {
+        self.used = 0;
+    }
+}
+
+impl Drop for CodeBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, CODE_BUFFER_SIZE);
+        }
+    }
+}
+
+/// Offsets of the CpuContext fields accessed by the generated code.
+struct Offsets {
+    regs: i32,
+    hi: i32,
+    lo: i32,
+    pc: i32,
+    next_pc: i32,
+    clock: i32,
+    tight_exit: i32,
+    delay_slot: i32,
+    exc_pc: i32,
+    exc_delay_slot: i32,
+}
+
+impl Offsets {
+    fn new() -> Offsets {
+        let ctx = CpuContext::default();
+        let base = &ctx as *const CpuContext as usize;
+        let off = |field: *const u8| (field as usize - base) as i32;
+        Offsets {
+            regs: off(&ctx.regs as *const _ as *const u8),
+            hi: off(&ctx.hi as *const _ as *const u8),
+            lo: off(&ctx.lo as *const _ as *const u8),
+            pc: off(&ctx.pc as *const _ as *const u8),
+            next_pc: off(&ctx.next_pc as *const _ as *const u8),
+            clock: off(&ctx.clock as *const _ as *const u8),
+            tight_exit: off(&ctx.tight_exit as *const _ as *const u8),
+            delay_slot: off(&ctx.delay_slot as *const _ as *const u8),
+            exc_pc: off(&ctx.exc_pc as *const _ as *const u8),
+            exc_delay_slot: off(&ctx.exc_delay_slot as *const _ as *const u8),
+        }
+    }
+
+    fn reg(&self, r: u32) -> i32 {
+        self.regs + r as i32 * 8
+    }
+}
+
+/// State shared between run_block and the generated code. `until` must be
+/// the first field, as it is accessed directly by the generated code.
+#[repr(C)]
+struct JitEnv<C: Config> {
+    until: i64,
+    cpu: *mut Cpu<C>,
+    ctx: *mut CpuContext,
+    ops: *const (OpFn<C>, u32),
+    tracer: *const Tracer<'static>,
+    result: Result<()>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+// Called by the generated code to run an opcode through the interpreter.
+// Returns 1 if the block must be exited.
+extern "sysv64" fn fallback<C: Config>(env: *mut JitEnv<C>, idx: usize) -> u32 {
+    let env = unsafe { &mut *env };
+    let (f, opcode) = unsafe { *env.ops.add(idx) };
+    let (cpu, ctx, t) = unsafe { (&mut *env.cpu, &mut *env.ctx, &*env.tracer) };
+
+    // Panics cannot unwind through the generated code: catch them here and
+    // resume unwinding after the block exits.
+    match panic::catch_unwind(AssertUnwindSafe(|| cpu.exec(ctx, opcode, f, t))) {
+        Ok(Ok(())) => (ctx.clock >= env.until || ctx.tight_exit) as u32,
+        Ok(Err(evt)) => {
+            env.result = Err(evt);
+            1
+        }
+        Err(p) => {
+            env.panic = Some(p);
+            1
+        }
+    }
+}
+
+pub(crate) struct Jit<C: Config> {
+    blocks: BlockCache<JitBlock<C>>,
+    code: CodeBuffer,
+    offs: Offsets,
+}
+
+impl<C: Config> Jit<C> {
+    pub fn new() -> Jit<C> {
+        Jit {
+            blocks: BlockCache::new(),
+            code: CodeBuffer::new(),
+            offs: Offsets::new(),
+        }
+    }
+
+    /// Run the block starting at the specified physical address. Execution
+    /// stops at the end of the block, or earlier when the interpreter loop
+    /// would exit its tight loop (exceptions, taken branch-likely, etc.) or
+    /// the clock reaches `until`.
+    pub fn run_block(
+        &mut self,
+        cpu: &mut Cpu<C>,
+        ctx: &mut CpuContext,
+        paddr: u32,
+        mem: &MemIoR<u32>,
+        until: i64,
+        t: &Tracer,
+    ) -> Result<()> {
+        let block = self.get_block(paddr, mem, &cpu.code_watch);
+        let mut env = JitEnv {
+            until,
+            cpu,
+            ctx,
+            ops: block.block.ops.as_ptr(),
+            tracer: t as *const Tracer as *const Tracer<'static>,
+            result: Ok(()),
+            panic: None,
+        };
+        (block.code)(env.ctx, &mut env as *mut JitEnv<C> as *mut u8);
+        if let Some(p) = env.panic {
+            panic::resume_unwind(p);
+        }
+        env.result
+    }
+
+    /// Drop the compiled blocks within the specified page, because the code
+    /// has changed.
+    pub fn invalidate_page(&mut self, page: u32) {
+        self.blocks.invalidate_page(page);
+    }
+
+    // Get the compiled block starting at the specified physical address,
+    // compiling it if it is not cached (or if it was invalidated).
+    fn get_block(&mut self, paddr: u32, mem: &MemIoR<u32>, watch: &WriteWatch) -> Rc<JitBlock<C>> {
+        if let Some(block) = self.blocks.get(paddr) {
+            return block;
+        }
+
+        let iter = mem
+            .iter()
+            .unwrap_or_else(|| panic!("jumped to non-linear memory: {}", paddr.hex()));
+        let block = Cpu::<C>::build_block(paddr, iter);
+        let code = self.compile(&block);
+        let ptr = match self.code.alloc(&code) {
+            Some(ptr) => ptr,
+            None => {
+                // Code buffer is full: flush everything and start over.
+                self.blocks.clear();
+                self.code.reset();
+                self.code.alloc(&code).unwrap()
+            }
+        };
+        let code = unsafe { std::mem::transmute::<*const u8, BlockFn>(ptr) };
+        self.blocks.insert(paddr, JitBlock { block, code }, watch)
+    }
+
+    fn compile(&self, block: &Block<OpFn<C>>) -> Vec<u8> {
+        use self::Reg::*;
+        let o = &self.offs;
+        let mut e = Emitter::new();
+        let mut exit = Label::new();
+
+        // Prologue: RBX holds the CpuContext, RBP the JitEnv. The extra push
+        // keeps the stack aligned to 16 bytes for the fallback calls.
+        e.push(RBX);
+        e.push(RBP);
+        e.push(RAX);
+        e.mov(RBX, RDI);
+        e.mov(RBP, RSI);
+
+        for (idx, &(_, opcode)) in block.ops.iter().enumerate() {
+            // Same bookkeeping of the interpreter tight loop.
+            e.load8(RAX, RBX, o.delay_slot);
+            e.store8(RBX, o.tight_exit, RAX);
+            e.store8(RBX, o.exc_delay_slot, RAX);
+            e.store8_imm(RBX, o.delay_slot, 0);
+            e.load(true, RCX, RBX, o.pc);
+            e.store(true, RBX, o.exc_pc, RCX);
+            e.load(true, RCX, RBX, o.next_pc);
+            e.store(true, RBX, o.pc, RCX);
+            e.alu_imm(true, Alu::Add, RCX, 4);
+            e.store(true, RBX, o.next_pc, RCX);
+
+            if self.compile_op(&mut e, opcode) {
+                e.add_mem_imm(RBX, o.clock, 1);
+                e.cmp8_imm(RBX, o.tight_exit, 0);
+                e.jcc(Cond::NE, &mut exit);
+                e.load(true, RAX, RBX, o.clock);
+                e.alu_mem(true, Alu::Cmp, RAX, RBP, 0); // JitEnv::until
+                e.jcc(Cond::GE, &mut exit);
+            } else {
+                e.mov(RDI, RBP);
+                e.mov_imm64(RSI, idx as u64);
+                e.mov_imm64(RAX, fallback::<C> as usize as u64);
+                e.call(RAX);
+                e.test(RAX);
+                e.jcc(Cond::NE, &mut exit);
+            }
+        }
+
+        e.bind(exit);
+        e.pop(RCX);
+        e.pop(RBP);
+        e.pop(RBX);
+        e.ret();
+        e.buf
+    }
+
+    // Emit native code for the opcode, if supported. Returns false if the
+    // opcode must go through the interpreter instead. The semantic must
+    // match exactly the one of the interpreter (see Cpu::decode_op).
+    fn compile_op(&self, e: &mut Emitter, opcode: u32) -> bool {
+        use self::Reg::*;
+        let o = &self.offs;
+        let h = |s| C::Arch::has_op(s);
+        let rs = (opcode >> 21) & 0x1F;
+        let rt = (opcode >> 16) & 0x1F;
+        let rd = (opcode >> 11) & 0x1F;
+        let sa = ((opcode >> 6) & 0x1F) as u8;
+        let imm = opcode & 0xFFFF;
+        let sximm = imm as i16 as i32;
+
+        // 32-bit op on RAX, result sign-extended into the destination
+        let sx32 = |e: &mut Emitter, dst: u32| {
+            e.movsxd(RAX, RAX);
+            e.store(true, RBX, o.reg(dst), RAX);
+        };
+        let shift32 = |e: &mut Emitter, op: Shift| {
+            e.load(false, RAX, RBX, o.reg(rt));
+            e.shift_imm(false, op, RAX, sa);
+            sx32(e, rd);
+        };
+        let shift32v = |e: &mut Emitter, op: Shift| {
+            e.load(false, RCX, RBX, o.reg(rs));
+            e.load(false, RAX, RBX, o.reg(rt));
+            e.shift_cl(false, op, RAX);
+            sx32(e, rd);
+        };
+        let shift64 = |e: &mut Emitter, op: Shift, sa: u8| {
+            e.load(true, RAX, RBX, o.reg(rt));
+            e.shift_imm(true, op, RAX, sa);
+            e.store(true, RBX, o.reg(rd), RAX);
+        };
+        let shift64v = |e: &mut Emitter, op: Shift| {
+            e.load(false, RCX, RBX, o.reg(rs));
+            e.load(true, RAX, RBX, o.reg(rt));
+            e.shift_cl(true, op, RAX);
+            e.store(true, RBX, o.reg(rd), RAX);
+        };
+        let alu32 = |e: &mut Emitter, op: Alu| {
+            e.load(false, RAX, RBX, o.reg(rs));
+            e.alu_mem(false, op, RAX, RBX, o.reg(rt));
+            sx32(e, rd);
+        };
+        let alu64 = |e: &mut Emitter, op: Alu, not: bool| {
+            e.load(true, RAX, RBX, o.reg(rs));
+            e.alu_mem(true, op, RAX, RBX, o.reg(rt));
+            if not {
+                e.not(true, RAX);
+            }
+            e.store(true, RBX, o.reg(rd), RAX);
+        };
+        let slt = |e: &mut Emitter, cond: Cond| {
+            e.load(false, RAX, RBX, o.reg(rs));
+            e.alu_mem(false, Alu::Cmp, RAX, RBX, o.reg(rt));
+            e.setcc(cond, RAX);
+            e.store(true, RBX, o.reg(rd), RAX);
+        };
+        let slti = |e: &mut Emitter, cond: Cond| {
+            e.load(false, RAX, RBX, o.reg(rs));
+            e.alu_imm(false, Alu::Cmp, RAX, sximm);
+            e.setcc(cond, RAX);
+            e.store(true, RBX, o.reg(rt), RAX);
+        };
+        let alui64 = |e: &mut Emitter, op: Alu, imm: i32| {
+            e.load(true, RAX, RBX, o.reg(rs));
+            e.alu_imm(true, op, RAX, imm);
+            e.store(true, RBX, o.reg(rt), RAX);
+        };
+        let mov = |e: &mut Emitter, dst: i32, src: i32| {
+            e.load(true, RAX, RBX, src);
+            e.store(true, RBX, dst, RAX);
+        };
+
+        match opcode >> 26 {
+            // SPECIAL
+            0x00 => match opcode & 0x3F {
+                0x00 if h("sll") => shift32(e, Shift::Shl),
+                0x02 if h("srl") => shift32(e, Shift::Shr),
+                0x03 if h("sra") => shift32(e, Shift::Sar),
+                0x04 if h("sllv") => shift32v(e, Shift::Shl),
+                0x06 if h("srll") => shift32v(e, Shift::Shr),
+                0x07 if h("srav") => shift32v(e, Shift::Sar),
+                0x10 if h("mfhi") => mov(e, o.reg(rd), o.hi),
+                0x11 if h("mthi") => mov(e, o.hi, o.reg(rs)),
+                0x12 if h("mflo") => mov(e, o.reg(rd), o.lo),
+                0x13 if h("mtlo") => mov(e, o.lo, o.reg(rs)),
+                0x14 if h("dsllv") => shift64v(e, Shift::Shl),
+                0x16 if h("dsrlv") => shift64v(e, Shift::Shr),
+                0x17 if h("dsrav") => shift64v(e, Shift::Sar),
+                0x21 if h("addu") => alu32(e, Alu::Add),
+                0x23 if h("subu") => alu32(e, Alu::Sub),
+                0x24 if h("and") => alu64(e, Alu::And, false),
+                0x25 if h("or") => alu64(e, Alu::Or, false),
+                0x26 if h("xor") => alu64(e, Alu::Xor, false),
+                0x27 if h("nor") => alu64(e, Alu::Or, true),
+                0x2A if h("slt") => slt(e, Cond::L),
+                0x2B if h("sltu") => slt(e, Cond::B),
+                0x2D if h("daddu") => alu64(e, Alu::Add, false),
+                0x2F if h("dsubu") => alu64(e, Alu::Sub, false),
+                0x38 if h("dsll") => shift64(e, Shift::Shl, sa),
+                0x3A if h("dsrl") => shift64(e, Shift::Shr, sa),
+                0x3B if h("dsra") => shift64(e, Shift::Sar, sa),
+                0x3C if h("dsll32") => shift64(e, Shift::Shl, sa + 32),
+                0x3E if h("dsrl32") => shift64(e, Shift::Shr, sa + 32),
+                0x3F if h("dsra32") => shift64(e, Shift::Sar, sa + 32),
+                _ => return false,
+            },
+            0x09 if h("addiu") => {
+                e.load(false, RAX, RBX, o.reg(rs));
+                e.alu_imm(false, Alu::Add, RAX, sximm);
+                sx32(e, rt);
+            }
+            0x0A if h("slti") => slti(e, Cond::L),
+            0x0B if h("sltiu") => slti(e, Cond::B),
+            0x0C if h("andi") => alui64(e, Alu::And, imm as i32),
+            0x0D if h("ori") => alui64(e, Alu::Or, imm as i32),
+            0x0E if h("xori") => alui64(e, Alu::Xor, imm as i32),
+            0x0F if h("lui") => {
+                e.mov_imm64(RAX, (sximm << 16).sx64());
+                e.store(true, RBX, o.reg(rt), RAX);
+            }
+            0x19 if h("daddiu") => alui64(e, Alu::Add, sximm),
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ArchIII, Config, CopNull, Cp0, Cpu, CpuContext, Fpu};
+    use byteorder::{BigEndian, ByteOrder};
+    use emu::bus::be::{Bus, Mem};
+    use emu::bus::{BusFill, MemFlags};
+    use emu::dbg::{Debugger, TraceEvent, Tracer};
+    use slog::Discard;
+
+    struct TestConfig;
+
+    impl Config for TestConfig {
+        type Arch = ArchIII;
+        type Cop0 = Cp0;
+        type Cop1 = Fpu;
+        type Cop2 = CopNull;
+        type Cop3 = CopNull;
+    }
+
+    fn special(rs: u32, rt: u32, rd: u32, sa: u32, func: u32) -> u32 {
+        (rs << 21) | (rt << 16) | (rd << 11) | (sa << 6) | func
+    }
+
+    fn itype(op: u32, rs: u32, rt: u32, imm: u32) -> u32 {
+        (op << 26) | (rs << 21) | (rt << 16) | (imm & 0xFFFF)
+    }
+
+    // Generate a test program: a mix of natively-compiled opcodes, with
+    // loads/stores, branches and COP0 accesses that go through the
+    // interpreter fallback.
+    fn program() -> Vec<u32> {
+        let mut seed = 0x1234_5678u32;
+        let mut rand = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            seed >> 8
+        };
+
+        let mut prog = Vec::new();
+        // Initialize all registers with some random values
+        for r in 1..32 {
+            prog.push(itype(0x0F, 0, r, rand())); // LUI
+            prog.push(itype(0x0D, r, r, rand())); // ORI
+            prog.push(special(0, r, r, 0, 0x3C)); // DSLL32
+            prog.push(itype(0x0D, r, r, rand())); // ORI
+        }
+        // r28 is the base for loads and stores
+        prog.push(itype(0x0F, 0, 28, 0xBFC0)); // LUI
+        prog.push(itype(0x0D, 28, 28, 0x8000)); // ORI
+
+        // Loop a few times around a random sequence
+        prog.push(itype(0x09, 0, 30, 20)); // ADDIU r30, r0, 20
+        let loop_start = prog.len();
+        const SPECIALS: [u32; 29] = [
+            0x00, 0x02, 0x03, 0x04, 0x06, 0x07, 0x10, 0x11, 0x12, 0x13, 0x14, 0x16, 0x17, 0x18,
+            0x19, 0x1C, 0x1D, 0x21, 0x23, 0x24, 0x25, 0x26, 0x27, 0x2A, 0x2B, 0x2D, 0x2F, 0x38,
+            0x3C,
+        ];
+        const ITYPES: [u32; 8] = [0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F, 0x19];
+        for i in 0..400 {
+            let (rs, rt, rd) = (rand() % 32, rand() % 32, 1 + rand() % 27);
+            let op = match rand() % 4 {
+                0 | 1 => special(rs, rt, rd, rand() % 32, SPECIALS[rand() as usize % 29]),
+                2 => itype(ITYPES[rand() as usize % 8], rs, rd, rand()),
+                _ => match i % 4 {
+                    0 => itype(0x3F, 28, rd, (rand() % 64) * 8),  // SD
+                    1 => itype(0x23, 28, rd, (rand() % 128) * 4), // LW
+                    2 => 0x4000_0000 | (rd << 16) | (9 << 11),    // MFC0 rd, Count
+                    _ => special(0, rd, 31, 0x1F, 0x3F),          // DSRA32
+                },
+            };
+            prog.push(op);
+            if i % 50 == 49 {
+                // Forward branch over a few instructions
+                prog.push(itype(0x05, rd, 0, 2)); // BNE rd, r0, +2
+                prog.push(itype(0x09, rd, rd, 1)); // ADDIU (delay slot)
+                prog.push(itype(0x0D, rd, rd, 0x55)); // ORI
+            }
+        }
+        let offset = (loop_start as i32 - prog.len() as i32 - 2) as u32;
+        prog.push(itype(0x09, 30, 30, 0xFFFF)); // ADDIU r30, r30, -1
+        prog.push(itype(0x05, 30, 0, offset)); // BNE r30, r0, loop
+        prog.push(0); // NOP
+
+        // Endless loop
+        prog.push(itype(0x04, 0, 0, 0xFFFF)); // BEQ r0, r0, -1
+        prog.push(0); // NOP
+        prog
+    }
+
+    fn make_cpu(name: &'static str, prog: &[u32], jit: bool) -> (Cpu<TestConfig>, Mem) {
+        let logger = slog::Logger::root(Discard, o!());
+        let mut bus = Bus::new(logger.new(o!()));
+        let mut mem = Mem::new(name, 0x10000, MemFlags::default(), None);
+        for (i, op) in prog.iter().enumerate() {
+            BigEndian::write_u32(&mut mem[0x1000 + i * 4..], *op);
+        }
+        bus.map_mem(0x1FC0_0000, 0x1FC0_FFFF, &mem, BusFill::None)
+            .unwrap();
+        let mut cpu = Cpu::new(
+            name,
+            logger.new(o!()),
+            bus,
+            (
+                Cp0::new(name, logger.new(o!())),
+                Fpu::new(name, logger.new(o!())),
+                CopNull {},
+                CopNull {},
+            ),
+        );
+        cpu.ctx_mut().set_pc(0xFFFF_FFFF_BFC0_1000);
+        cpu.enable_jit(jit);
+        (cpu, mem)
+    }
+
+    fn assert_ctx_eq(a: &CpuContext, b: &CpuContext) {
+        assert_eq!(a.regs, b.regs, "regs mismatch at pc {:x}", a.pc);
+        assert_eq!((a.hi, a.lo), (b.hi, b.lo), "hi/lo mismatch");
+        assert_eq!((a.pc, a.next_pc), (b.pc, b.next_pc), "pc mismatch");
+        assert_eq!(a.clock, b.clock, "clock mismatch at pc {:x}", a.pc);
+        assert_eq!(
+            (a.delay_slot, a.tight_exit, a.llbit),
+            (b.delay_slot, b.tight_exit, b.llbit),
+            "flags mismatch at pc {:x}",
+            a.pc
+        );
+        assert_eq!(a.exception_pc(), b.exception_pc(), "exception pc mismatch");
+    }
+
+    #[test]
+    fn lockstep() {
+        let prog = program();
+        let (mut interp, interp_mem) = make_cpu("lockstep-interp", &prog, false);
+        let (mut jit, jit_mem) = make_cpu("lockstep-jit", &prog, true);
+
+        // Run both CPUs for slices of varying length, and compare the
+        // contexts after each of them.
+        let t = Tracer::null();
+        let mut until = interp.ctx().clock;
+        for i in 0..2000 {
+            until += 1 + (i * 7) % 23;
+            interp.run(until, &t).unwrap();
+            jit.run(until, &t).unwrap();
+            assert_ctx_eq(interp.ctx(), jit.ctx());
+        }
+        assert_eq!(&interp_mem[..], &jit_mem[..], "memory mismatch");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let prog = program();
+        let (mut jit, _mem) = make_cpu("breakpoints-jit", &prog, true);
+
+        // While a debugger is attached, the JIT must step aside and let
+        // the interpreter stop exactly on the breakpoint.
+        let mut dbg = Debugger::new(&vec!["breakpoints-jit".to_owned()]);
+        dbg.add_breakpoint("breakpoints-jit", 0x1FC0_1020, "test");
+        let t = dbg.new_tracer();
+        match jit.run(1_000_000, &t) {
+            Err(evt) => match *evt {
+                TraceEvent::Breakpoint(_, _, pc) => assert_eq!(pc, 0x1FC0_1020),
+                _ => panic!("unexpected trace event"),
+            },
+            Ok(()) => panic!("breakpoint not hit"),
+        }
+    }
+}
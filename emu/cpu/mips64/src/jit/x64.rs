@@ -0,0 +1,260 @@
+//! Minimal x86-64 assembler, covering just the instructions emitted by the
+//! recompiler. All memory operands are in the form [base + disp32].
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum Reg {
+    RAX = 0,
+    RCX = 1,
+    RBX = 3,
+    RSP = 4,
+    RBP = 5,
+    RSI = 6,
+    RDI = 7,
+}
+
+/// Two-operands ALU opcodes, in the "op r/m, reg" form. The same value,
+/// shifted right by 3, is the ModRM extension of the "op r/m, imm32" form.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum Alu {
+    Add = 0x01,
+    Or = 0x09,
+    And = 0x21,
+    Sub = 0x29,
+    Xor = 0x31,
+    Cmp = 0x39,
+}
+
+/// Shift operations (ModRM extension of the C1/D3 opcodes).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum Shift {
+    Shl = 4,
+    Shr = 5,
+    Sar = 7,
+}
+
+/// Condition codes for Jcc/SETcc.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum Cond {
+    B = 0x2,  // unsigned <
+    NE = 0x5, // !=
+    L = 0xC,  // signed <
+    GE = 0xD, // signed >=
+}
+
+/// A forward jump whose target has not been emitted yet.
+pub(crate) struct Label(Vec<usize>);
+
+impl Label {
+    pub fn new() -> Label {
+        Label(Vec::new())
+    }
+}
+
+pub(crate) struct Emitter {
+    pub buf: Vec<u8>,
+}
+
+impl Emitter {
+    pub fn new() -> Emitter {
+        Emitter {
+            buf: Vec::with_capacity(1024),
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        self.buf.push(b);
+    }
+
+    fn dword(&mut self, d: u32) {
+        self.buf.write_u32::<LittleEndian>(d).unwrap();
+    }
+
+    fn rex(&mut self, w: bool) {
+        if w {
+            self.byte(0x48);
+        }
+    }
+
+    // Emit a ModRM for a [base + disp32] operand. RSP cannot be used as
+    // base, as it would require a SIB byte.
+    fn mem(&mut self, reg: u8, base: Reg, disp: i32) {
+        debug_assert!(base != Reg::RSP);
+        self.byte(0x80 | ((reg & 7) << 3) | base as u8);
+        self.dword(disp as u32);
+    }
+
+    // Emit a ModRM for a register-direct operand.
+    fn direct(&mut self, reg: u8, rm: Reg) {
+        self.byte(0xC0 | ((reg & 7) << 3) | rm as u8);
+    }
+
+    /// mov reg, [base+disp] (64-bit if w, else 32-bit)
+    pub fn load(&mut self, w: bool, dst: Reg, base: Reg, disp: i32) {
+        self.rex(w);
+        self.byte(0x8B);
+        self.mem(dst as u8, base, disp);
+    }
+
+    /// mov [base+disp], reg (64-bit if w, else 32-bit)
+    pub fn store(&mut self, w: bool, base: Reg, disp: i32, src: Reg) {
+        self.rex(w);
+        self.byte(0x89);
+        self.mem(src as u8, base, disp);
+    }
+
+    /// mov reg8, byte [base+disp] (only AL..BL)
+    pub fn load8(&mut self, dst: Reg, base: Reg, disp: i32) {
+        debug_assert!((dst as u8) < 4);
+        self.byte(0x8A);
+        self.mem(dst as u8, base, disp);
+    }
+
+    /// mov byte [base+disp], reg8 (only AL..BL)
+    pub fn store8(&mut self, base: Reg, disp: i32, src: Reg) {
+        debug_assert!((src as u8) < 4);
+        self.byte(0x88);
+        self.mem(src as u8, base, disp);
+    }
+
+    /// mov byte [base+disp], imm8
+    pub fn store8_imm(&mut self, base: Reg, disp: i32, imm: u8) {
+        self.byte(0xC6);
+        self.mem(0, base, disp);
+        self.byte(imm);
+    }
+
+    /// cmp byte [base+disp], imm8
+    pub fn cmp8_imm(&mut self, base: Reg, disp: i32, imm: u8) {
+        self.byte(0x80);
+        self.mem(7, base, disp);
+        self.byte(imm);
+    }
+
+    /// add qword [base+disp], imm32
+    pub fn add_mem_imm(&mut self, base: Reg, disp: i32, imm: i32) {
+        self.rex(true);
+        self.byte(0x81);
+        self.mem(0, base, disp);
+        self.dword(imm as u32);
+    }
+
+    /// op dst, src
+    pub fn alu(&mut self, w: bool, op: Alu, dst: Reg, src: Reg) {
+        self.rex(w);
+        self.byte(op as u8);
+        self.direct(src as u8, dst);
+    }
+
+    /// op dst, [base+disp]
+    pub fn alu_mem(&mut self, w: bool, op: Alu, dst: Reg, base: Reg, disp: i32) {
+        self.rex(w);
+        self.byte(op as u8 + 2);
+        self.mem(dst as u8, base, disp);
+    }
+
+    /// op dst, imm32 (sign-extended to 64-bit if w)
+    pub fn alu_imm(&mut self, w: bool, op: Alu, dst: Reg, imm: i32) {
+        self.rex(w);
+        self.byte(0x81);
+        self.direct(op as u8 >> 3, dst);
+        self.dword(imm as u32);
+    }
+
+    /// not reg
+    pub fn not(&mut self, w: bool, reg: Reg) {
+        self.rex(w);
+        self.byte(0xF7);
+        self.direct(2, reg);
+    }
+
+    /// shl/shr/sar reg, imm8
+    pub fn shift_imm(&mut self, w: bool, op: Shift, reg: Reg, imm: u8) {
+        self.rex(w);
+        self.byte(0xC1);
+        self.direct(op as u8, reg);
+        self.byte(imm);
+    }
+
+    /// shl/shr/sar reg, cl
+    pub fn shift_cl(&mut self, w: bool, op: Shift, reg: Reg) {
+        self.rex(w);
+        self.byte(0xD3);
+        self.direct(op as u8, reg);
+    }
+
+    /// movsxd dst, src32
+    pub fn movsxd(&mut self, dst: Reg, src: Reg) {
+        self.rex(true);
+        self.byte(0x63);
+        self.direct(dst as u8, src);
+    }
+
+    /// setcc reg8 + movzx reg32, reg8 (only AL..BL)
+    pub fn setcc(&mut self, cond: Cond, reg: Reg) {
+        debug_assert!((reg as u8) < 4);
+        self.byte(0x0F);
+        self.byte(0x90 | cond as u8);
+        self.direct(0, reg);
+        self.byte(0x0F);
+        self.byte(0xB6);
+        self.direct(reg as u8, reg);
+    }
+
+    /// mov reg, imm64
+    pub fn mov_imm64(&mut self, reg: Reg, imm: u64) {
+        self.rex(true);
+        self.byte(0xB8 | reg as u8);
+        self.buf.write_u64::<LittleEndian>(imm).unwrap();
+    }
+
+    /// mov reg, src
+    pub fn mov(&mut self, dst: Reg, src: Reg) {
+        self.rex(true);
+        self.byte(0x89);
+        self.direct(src as u8, dst);
+    }
+
+    /// test reg32, reg32
+    pub fn test(&mut self, reg: Reg) {
+        self.byte(0x85);
+        self.direct(reg as u8, reg);
+    }
+
+    /// call reg
+    pub fn call(&mut self, reg: Reg) {
+        self.byte(0xFF);
+        self.direct(2, reg);
+    }
+
+    pub fn push(&mut self, reg: Reg) {
+        self.byte(0x50 | reg as u8);
+    }
+
+    pub fn pop(&mut self, reg: Reg) {
+        self.byte(0x58 | reg as u8);
+    }
+
+    pub fn ret(&mut self) {
+        self.byte(0xC3);
+    }
+
+    /// jcc label (rel32)
+    pub fn jcc(&mut self, cond: Cond, label: &mut Label) {
+        self.byte(0x0F);
+        self.byte(0x80 | cond as u8);
+        label.0.push(self.buf.len());
+        self.dword(0);
+    }
+
+    /// Bind the label to the current position, patching all the jumps
+    /// emitted so far.
+    pub fn bind(&mut self, label: Label) {
+        let pos = self.buf.len();
+        for fixup in label.0 {
+            let rel = (pos - (fixup + 4)) as u32;
+            LittleEndian::write_u32(&mut self.buf[fixup..fixup + 4], rel);
+        }
+    }
+}
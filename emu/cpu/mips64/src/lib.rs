@@ -11,17 +11,23 @@ extern crate num;
 extern crate slog;
 
 mod arch;
+mod block;
+mod cache;
 mod cp0;
 mod cpu;
 mod fpu;
+#[cfg(all(target_arch = "x86_64", unix))]
+mod jit;
 mod traits;
 
+pub mod asm;
 pub(crate) mod decode;
 pub(crate) mod mmu;
 
 pub use self::arch::{ArchI, ArchII, ArchIII};
-pub use self::cp0::Cp0;
-pub use self::cpu::{Cpu, CpuContext, Exception};
+pub use self::cache::{Cache, CacheConfig, CacheLine};
+pub use self::cp0::{Cp0, COP0_REG_NAMES};
+pub use self::cpu::{Cpu, CpuContext, Exception, MemAccess};
 pub use self::decode::REG_NAMES;
 pub use self::fpu::Fpu;
-pub use self::traits::{Arch, Config, Cop, Cop0, CopNull};
+pub use self::traits::{Arch, Config, Cop, Cop0, CopNull, NoTiming, Timing};
@@ -81,6 +81,24 @@ impl fmt::Debug for TlbEntry {
     }
 }
 
+/// Result of a TLB lookup
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TlbResult {
+    /// No entry matches the virtual address (TLB Refill exception)
+    Miss,
+    /// A matching entry was found, but the page is not valid (TLB Invalid exception)
+    Invalid,
+    /// A matching valid page was found.
+    Hit {
+        /// Translated physical address
+        paddr: u64,
+        /// Dirty flag of the page (if false, writes must trigger a TLB Modified exception)
+        dirty: bool,
+        /// Page is cacheable (cache algorithm is not "uncached")
+        cached: bool,
+    },
+}
+
 // Memory mapping unit of a MIPS processor
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
 pub struct Mmu([TlbEntry; 32]);
@@ -92,8 +110,9 @@ impl Mmu {
         let vpn2 = calc_vpn2(vaddr);
 
         for (i, entry) in self.0.iter().enumerate() {
+            let mask = !(entry.page_mask >> 13);
             let asid_match = entry.global || entry.asid == vasid;
-            let vpn_match = entry.vpn2 == vpn2 & !(entry.page_mask >> 13);
+            let vpn_match = entry.vpn2 & mask == vpn2 & mask;
 
             if asid_match && vpn_match {
                 return Some(i);
@@ -103,6 +122,34 @@ impl Mmu {
         None
     }
 
+    /// Translates a virtual address through the TLB, selecting the even or odd
+    /// page of the matching entry.
+    pub fn lookup(&self, vaddr: u64, vasid: u8) -> TlbResult {
+        let entry = match self.probe(vaddr, vasid) {
+            Some(idx) => &self.0[idx],
+            None => return TlbResult::Miss,
+        };
+
+        // Each entry maps a pair of consecutive pages; the first bit above
+        // the page offset selects between the even and the odd page.
+        let offset_mask = (entry.page_mask as u64 >> 1) | 0xFFF;
+        let lo = if vaddr & (offset_mask + 1) != 0 {
+            entry.lo1
+        } else {
+            entry.lo0
+        };
+        if !lo.get_bit(1) {
+            return TlbResult::Invalid;
+        }
+
+        let pfn = (lo << 6) & 0xF_FFFF_F000;
+        TlbResult::Hit {
+            paddr: (pfn & !offset_mask) | (vaddr & offset_mask),
+            dirty: lo.get_bit(2),
+            cached: lo.get_bits(3..6) != 2,
+        }
+    }
+
     /// Reads a specific TLB index.
     pub fn read(&self, index: usize) -> &TlbEntry {
         &self.0[index]
@@ -142,12 +189,12 @@ mod tests {
 
     // Available Page Masks
     const PAGE_MASK_4_KB: u32 = 0b0000_0000_0000_0000_0000_0000;
-    const PAGE_MASK_16_KB: u32 = 0b0000_0000_0011_0000_0000_0000;
-    const PAGE_MASK_64_KB: u32 = 0b0000_0000_1111_0000_0000_0000;
-    const PAGE_MASK_256_KB: u32 = 0b0000_0011_1111_0000_0000_0000;
-    const PAGE_MASK_1_MB: u32 = 0b0000_1111_1111_0000_0000_0000;
-    const PAGE_MASK_4_MB: u32 = 0b0011_1111_1111_0000_0000_0000;
-    const PAGE_MASK_16_MB: u32 = 0b1111_1111_1111_0000_0000_0000;
+    const PAGE_MASK_16_KB: u32 = 0b0_0000_0000_0110_0000_0000_0000;
+    const PAGE_MASK_64_KB: u32 = 0b0_0000_0001_1110_0000_0000_0000;
+    const PAGE_MASK_256_KB: u32 = 0b0_0000_0111_1110_0000_0000_0000;
+    const PAGE_MASK_1_MB: u32 = 0b0_0001_1111_1110_0000_0000_0000;
+    const PAGE_MASK_4_MB: u32 = 0b0_0111_1111_1110_0000_0000_0000;
+    const PAGE_MASK_16_MB: u32 = 0b1_1111_1111_1110_0000_0000_0000;
 
     #[test]
     fn test_mmu() {
@@ -215,6 +262,60 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mmu_lookup() {
+        let mut mmu = Mmu::default();
+
+        mmu.write(
+            3,
+            PAGE_MASK_4_KB,
+            0x0000_0000_4000_0000,
+            0b0000_0000_0000_0000_1000_0000_0000_0110, // dirty and valid
+            0b0000_0000_0000_0000_0100_0000_0000_0000, // not valid
+        );
+        mmu.write(
+            4,
+            PAGE_MASK_16_KB,
+            0x0000_0000_4001_0000,
+            0b0000_0000_0000_0000_1000_0000_0000_0011, // global and valid
+            0b0000_0000_0000_0000_0100_0000_0000_0111, // global, dirty and valid
+        );
+
+        // Even page of a 4 KB entry
+        assert_eq!(
+            mmu.lookup(0x0000_0000_4000_0123, 0),
+            TlbResult::Hit {
+                paddr: 0x0020_0123,
+                dirty: true,
+                cached: true,
+            }
+        );
+        // Odd page of a 4 KB entry is not valid
+        assert_eq!(mmu.lookup(0x0000_0000_4000_1123, 0), TlbResult::Invalid);
+        // No entry for this address
+        assert_eq!(mmu.lookup(0x0000_0000_4000_2000, 0), TlbResult::Miss);
+        // Wrong ASID
+        assert_eq!(mmu.lookup(0x0000_0000_4000_0123, 1), TlbResult::Miss);
+
+        // Even and odd pages of a 16 KB entry
+        assert_eq!(
+            mmu.lookup(0x0000_0000_4001_2345, 7),
+            TlbResult::Hit {
+                paddr: 0x0020_2345,
+                dirty: false,
+                cached: true,
+            }
+        );
+        assert_eq!(
+            mmu.lookup(0x0000_0000_4001_6345, 7),
+            TlbResult::Hit {
+                paddr: 0x0010_2345,
+                dirty: true,
+                cached: true,
+            }
+        );
+    }
+
     #[bench]
     fn bench_tlb_probe_match(b: &mut Bencher) {
         let mut mmu = Mmu::default();
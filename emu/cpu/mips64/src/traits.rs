@@ -1,4 +1,4 @@
-use super::{CpuContext, Exception};
+use super::{CpuContext, Exception, MemAccess};
 use emu::bus::be::Bus;
 use emu::dbg::{DebuggerRenderer, DecodedInsn, Result, Tracer};
 use emu::memint::MemInt;
@@ -26,6 +26,10 @@ pub trait Config {
     type Cop2: Cop;
     type Cop3: Cop;
 
+    /// Cycle timing model, used when timing is enabled on the CPU
+    /// (see Cpu::enable_timing). If not bound, use NoTiming.
+    type Timing: Timing = NoTiming;
+
     // Mask PC before fetching from the bus. This should be reimplemented
     // by architectures that do not have a full 64-bit bus to simplify
     // bus mapping.
@@ -39,8 +43,80 @@ pub trait Config {
     fn addr_mask<U: MemInt>(addr: u32) -> u32 {
         addr & 0x1FFF_FFFF & !(U::SIZE as u32 - 1)
     }
+
+    // Return true if the architecture allows memory accesses at addresses
+    // not aligned to the access size. If false, unaligned accesses trigger
+    // an address error exception.
+    fn unaligned_access() -> bool {
+        false
+    }
+
+    // Return true if signed arithmetic instructions (ADD, ADDI, SUB, etc.)
+    // raise an overflow exception. If false, they behave like their
+    // unsigned counterparts.
+    fn overflow_traps() -> bool {
+        true
+    }
+}
+
+/// Timing describes the cycle timing of a specific MIPS implementation.
+/// All functions return the number of cycles to add on top of the single
+/// cycle accounted for each executed instruction.
+pub trait Timing {
+    /// Private state of the timing model, kept by the CPU across
+    /// instructions (see issue).
+    type State: Default = ();
+
+    /// Latency of a multiplication, before the result is available in HI/LO.
+    /// Reading HI/LO earlier stalls the pipeline.
+    fn mul_latency(_dword: bool) -> i64 {
+        0
+    }
+
+    /// Latency of a division, before the result is available in HI/LO.
+    /// Reading HI/LO earlier stalls the pipeline.
+    fn div_latency(_dword: bool) -> i64 {
+        0
+    }
+
+    /// Stall when an instruction uses the register loaded by the
+    /// immediately preceding load.
+    fn load_use_stall() -> i64 {
+        0
+    }
+
+    /// Penalty when a branch-likely is not taken and its delay slot is
+    /// nullified.
+    fn branch_likely_penalty() -> i64 {
+        0
+    }
+
+    /// Latency of a COP1 computational instruction, identified by its
+    /// function and format fields.
+    fn fpu_latency(_func: u32, _fmt: u32) -> i64 {
+        0
+    }
+
+    /// Penalty of an uncached memory access at the specified physical address.
+    fn uncached_penalty(_paddr: u32, _acc: MemAccess) -> i64 {
+        0
+    }
+
+    /// Cycles taken to issue an instruction, for pipelines whose hazards
+    /// are not described by the functions above (eg: multiple issue, or
+    /// interlocks on coprocessor registers). `clock` is the cycle in which
+    /// the instruction would be issued without stalls. The result can be
+    /// negative, if the instruction is issued together with the previous one.
+    fn issue(_state: &mut Self::State, _opcode: u32, _clock: i64) -> i64 {
+        0
+    }
 }
 
+/// NoTiming is a timing model in which all instructions take one cycle.
+pub struct NoTiming;
+
+impl Timing for NoTiming {}
+
 /// Cop is a MIPS64 coprocessor that can be installed within the core.
 pub trait Cop {
     fn reg(&self, cpu: &CpuContext, idx: usize) -> u128;
@@ -51,35 +127,63 @@ pub trait Cop {
         DecodedInsn::new0("unkcop")
     }
 
-    fn lwc(&mut self, op: u32, ctx: &mut CpuContext, bus: &Bus, _t: &Tracer) -> Result<()> {
+    // Coprocessor load/store opcodes. The core computes the standard effective
+    // address (base + offset), translates it through COP0, and passes the
+    // resulting physical address as `paddr`. Coprocessors that have a custom
+    // addressing mode are free to ignore it.
+    fn lwc(
+        &mut self,
+        op: u32,
+        ctx: &mut CpuContext,
+        bus: &Bus,
+        paddr: u32,
+        _t: &Tracer,
+    ) -> Result<()> {
         let rt = ((op >> 16) & 0x1f) as usize;
-        let ea = ctx.regs[((op >> 21) & 0x1f) as usize] as u32 + (op & 0xffff) as i16 as i32 as u32;
-        let val = bus.read::<u32>(ea & 0x1FFF_FFFC) as u64;
+        let val = bus.read::<u32>(paddr) as u64;
         self.set_reg(ctx, rt, val as u128);
         Ok(())
     }
 
-    fn ldc(&mut self, op: u32, ctx: &mut CpuContext, bus: &Bus, _t: &Tracer) -> Result<()> {
+    fn ldc(
+        &mut self,
+        op: u32,
+        ctx: &mut CpuContext,
+        bus: &Bus,
+        paddr: u32,
+        _t: &Tracer,
+    ) -> Result<()> {
         let rt = ((op >> 16) & 0x1f) as usize;
-        let ea = ctx.regs[((op >> 21) & 0x1f) as usize] as u32 + (op & 0xffff) as i16 as i32 as u32;
-        let val = bus.read::<u64>(ea & 0x1FFF_FFFC) as u64;
+        let val = bus.read::<u64>(paddr) as u64;
         self.set_reg(ctx, rt, val as u128);
         Ok(())
     }
 
-    fn swc(&mut self, op: u32, ctx: &CpuContext, bus: &mut Bus, _t: &Tracer) -> Result<()> {
+    fn swc(
+        &mut self,
+        op: u32,
+        ctx: &CpuContext,
+        bus: &mut Bus,
+        paddr: u32,
+        _t: &Tracer,
+    ) -> Result<()> {
         let rt = ((op >> 16) & 0x1f) as usize;
-        let ea = ctx.regs[((op >> 21) & 0x1f) as usize] as u32 + (op & 0xffff) as i16 as i32 as u32;
         let val = self.reg(ctx, rt) as u32;
-        bus.write::<u32>(ea & 0x1FFF_FFFC, val);
+        bus.write::<u32>(paddr, val);
         Ok(())
     }
 
-    fn sdc(&mut self, op: u32, ctx: &CpuContext, bus: &mut Bus, _t: &Tracer) -> Result<()> {
+    fn sdc(
+        &mut self,
+        op: u32,
+        ctx: &CpuContext,
+        bus: &mut Bus,
+        paddr: u32,
+        _t: &Tracer,
+    ) -> Result<()> {
         let rt = ((op >> 16) & 0x1f) as usize;
-        let ea = ctx.regs[((op >> 21) & 0x1f) as usize] as u32 + (op & 0xffff) as i16 as i32 as u32;
         let val = self.reg(ctx, rt) as u64;
-        bus.write::<u64>(ea & 0x1FFF_FFFC, val);
+        bus.write::<u64>(paddr, val);
         Ok(())
     }
 
@@ -114,6 +218,45 @@ pub trait Cop0: Cop {
 
     /// Trigger the specified excepion.
     fn exception(&mut self, ctx: &mut CpuContext, exc: Exception);
+
+    /// Translate a virtual address into a physical address, for the specified
+    /// kind of memory access. If the translation fails, the exception that
+    /// must be raised is returned. This function must not have side effects,
+    /// as it is also used by debugging facilities.
+    /// The default implementation is an identity mapping, for cores that
+    /// have no MMU.
+    fn translate(
+        &self,
+        _ctx: &CpuContext,
+        vaddr: u64,
+        _acc: MemAccess,
+    ) -> std::result::Result<u32, Exception> {
+        Ok(vaddr as u32)
+    }
+
+    /// Check whether accesses to the specified virtual address go through
+    /// the caches. This is only used when the cache model is enabled.
+    /// The default implementation treats all memory as uncached.
+    fn is_cached(&self, _ctx: &CpuContext, _vaddr: u64) -> bool {
+        false
+    }
+
+    /// Check whether a memory access to the specified physical address must
+    /// raise a watch exception. The default implementation has no watchpoints.
+    fn watch(&self, _ctx: &CpuContext, _paddr: u32, _acc: MemAccess) -> bool {
+        false
+    }
+
+    /// Notify that a load-linked instruction (LL/LLD) was executed at the
+    /// specified physical address.
+    fn load_linked(&mut self, _ctx: &mut CpuContext, _paddr: u32) {}
+
+    /// Check whether the specified coprocessor (0-3) is currently usable.
+    /// If not, instructions accessing it raise a coprocessor unusable exception.
+    /// The default implementation always allows access.
+    fn cop_usable(&self, _ctx: &CpuContext, _cop: u32) -> bool {
+        true
+    }
 }
 
 pub struct CopNull {}
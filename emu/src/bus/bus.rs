@@ -2,6 +2,7 @@ use super::device::Device;
 use super::mem::Mem;
 use super::radix::RadixTree;
 use super::regs::Reg;
+use super::watch::WriteWatch;
 use crate::memint::{AccessSize, ByteOrderCombiner, MemInt};
 use crate::state::ArrayField;
 
@@ -262,6 +263,9 @@ pub struct Bus<Order: ByteOrderCombiner> {
     logger: slog::Logger,
     mems: Vec<MemoryDesc>, // List of mapped memory areas (for debugging)
 
+    // Write watches: (begin, end, base, watch). See watch_writes().
+    watches: Vec<(u32, u32, u32, Rc<WriteWatch>)>,
+
     phantom: PhantomData<Order>,
 }
 
@@ -291,6 +295,7 @@ where
             unmap_w: unmapped_area_w(),
             logger: logger,
             mems: Vec::new(),
+            watches: Vec::new(),
             phantom: PhantomData,
         })
     }
@@ -303,6 +308,38 @@ where
     pub fn write<U: MemInt + 'a>(&mut self, addr: u32, val: U) {
         self.internal_fetch_write::<U>(addr, true)
             .write::<Order, U>(addr, val);
+        if !self.watches.is_empty() {
+            self.written(addr, U::SIZE as u32);
+        }
+    }
+
+    /// Notify the specified WriteWatch of all writes to the range [begin, end]
+    /// of this bus. Addresses are rebased, so that `begin` is reported to the
+    /// watch as `base`. Attaching the same watch twice to the same range has
+    /// no effect.
+    pub fn watch_writes(&mut self, begin: u32, end: u32, base: u32, watch: &Rc<WriteWatch>) {
+        if self
+            .watches
+            .iter()
+            .any(|w| w.0 == begin && w.1 == end && Rc::ptr_eq(&w.3, watch))
+        {
+            return;
+        }
+        self.watches.push((begin, end, base, watch.clone()));
+    }
+
+    /// Report a write of `len` bytes at the specified address to the attached
+    /// write watches. This is done automatically by `write()`, but it must be
+    /// called explicitly after writing memory through the slice returned by
+    /// `fetch_write()`.
+    pub fn written(&self, addr: u32, len: u32) {
+        let last = addr.saturating_add(len.max(1) - 1);
+        for (begin, end, base, watch) in self.watches.iter() {
+            let (first, last) = (addr.max(*begin), last.min(*end));
+            if first <= last {
+                watch.written(first - begin + base, last - first + 1);
+            }
+        }
     }
 
     #[inline(never)]
@@ -826,4 +863,34 @@ mod tests {
         assert_eq!(bus.read::<u8>(0xFF000006), 0x11);
         assert_eq!(bus.read::<u8>(0xFF000007), 0x22);
     }
+
+    #[test]
+    fn write_watch() {
+        use crate::bus::WriteWatch;
+        let ram1 = Mem::new("mem", 0x4000, MemFlags::default(), None);
+        let mut bus = Bus::<BigEndian>::new(logger());
+        assert_eq!(
+            bus.map_mem(0x0400_0000, 0x0400_3FFF, &ram1, BusFill::None)
+                .is_ok(),
+            true
+        );
+
+        // The watch sees the memory at its own addresses (0x1000-0x2FFF)
+        let watch = WriteWatch::new();
+        bus.watch_writes(0x0400_1000, 0x0400_2FFF, 0x1000, &watch);
+        watch.watch(0x1000);
+        watch.watch(0x2000);
+
+        bus.write::<u32>(0x0400_0000, 0xaabbccdd);
+        bus.write::<u32>(0x0400_3000, 0xaabbccdd);
+        assert_eq!(watch.take_dirty(), vec![]);
+        bus.write::<u64>(0x0400_2008, 0xaabbccdd11223344);
+        assert_eq!(watch.take_dirty(), vec![0x2]);
+
+        // Writes through fetch_write() must be reported explicitly
+        let mut hwio = bus.fetch_write::<u8>(0x0400_0800);
+        hwio.mem().unwrap()[0..0x1000].copy_from_slice(&[0; 0x1000]);
+        bus.written(0x0400_0800, 0x1000);
+        assert_eq!(watch.take_dirty(), vec![0x1]);
+    }
 }
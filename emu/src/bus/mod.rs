@@ -5,11 +5,13 @@ mod device;
 mod mem;
 mod radix;
 mod regs;
+mod watch;
 
 pub use self::bus::{Bus, BusFill, MemIoR, MemIoRIterator, MemIoW};
 pub use self::device::{CurrentDeviceMap, Device, DeviceMap};
 pub use self::mem::{Mem, MemFlags};
 pub use self::regs::{Reg, RegDeref, RegFlags, RegRef};
+pub use self::watch::{WriteWatch, WATCH_PAGE_SHIFT};
 
 pub mod le {
     use super::byteorder::LittleEndian;
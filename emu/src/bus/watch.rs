@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+/// Size (log2) of the pages tracked by a WriteWatch.
+pub const WATCH_PAGE_SHIFT: u32 = 12;
+
+const NUM_PAGES: usize = 1 << (32 - WATCH_PAGE_SHIFT);
+
+/// WriteWatch records writes to a set of memory pages. It is used to discard
+/// data derived from the contents of memory (like pre-decoded code) when the
+/// memory is modified, either by a CPU store or by a DMA transfer.
+///
+/// Pages are watched explicitly by the owner of the derived data. When a
+/// watched page is written, it is marked as dirty and stops being watched,
+/// so that further writes to it are cheap. The owner collects the dirty
+/// pages with [`take_dirty()`](struct.WriteWatch.html#method.take_dirty).
+///
+/// A WriteWatch is attached to a bus with
+/// [`Bus::watch_writes()`](struct.Bus.html#method.watch_writes); the same
+/// WriteWatch can be attached to several buses, so that all the paths to a
+/// memory are covered.
+pub struct WriteWatch {
+    watched: RefCell<Vec<u64>>, // Bitmap of watched pages
+    dirty: RefCell<Vec<u32>>,   // Watched pages that were written
+}
+
+impl WriteWatch {
+    pub fn new() -> Rc<WriteWatch> {
+        Rc::new(WriteWatch {
+            watched: RefCell::new(vec![0; NUM_PAGES / 64]),
+            dirty: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Start watching the page containing the specified address.
+    pub fn watch(&self, addr: u32) {
+        let page = (addr >> WATCH_PAGE_SHIFT) as usize;
+        self.watched.borrow_mut()[page / 64] |= 1 << (page % 64);
+    }
+
+    /// Stop watching all pages, and forget about dirty pages.
+    pub fn clear(&self) {
+        for w in self.watched.borrow_mut().iter_mut() {
+            *w = 0;
+        }
+        self.dirty.borrow_mut().clear();
+    }
+
+    /// Record a write of `len` bytes at the specified address. Normally,
+    /// this is called by the bus.
+    pub fn written(&self, addr: u32, len: u32) {
+        if len == 0 {
+            return;
+        }
+        let first = (addr >> WATCH_PAGE_SHIFT) as usize;
+        let last = ((addr as u64 + len as u64 - 1).min(0xFFFF_FFFF) >> WATCH_PAGE_SHIFT) as usize;
+        let mut watched = self.watched.borrow_mut();
+        for page in first..=last {
+            let bit = 1u64 << (page % 64);
+            if watched[page / 64] & bit != 0 {
+                watched[page / 64] &= !bit;
+                self.dirty.borrow_mut().push(page as u32);
+            }
+        }
+    }
+
+    /// Returns the watched pages that were written since the last call
+    /// (as page numbers, that is addresses shifted by WATCH_PAGE_SHIFT).
+    /// These pages are not watched anymore.
+    pub fn take_dirty(&self) -> Vec<u32> {
+        let mut dirty = self.dirty.borrow_mut();
+        if dirty.is_empty() {
+            return Vec::new();
+        }
+        mem::replace(&mut *dirty, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirty_pages() {
+        let w = WriteWatch::new();
+        w.watch(0x1000);
+        w.watch(0x8000_3FFC);
+
+        w.written(0x2000, 4);
+        assert_eq!(w.take_dirty(), vec![]);
+
+        // A write crossing a page boundary dirties both pages
+        w.written(0x0FFE, 4);
+        assert_eq!(w.take_dirty(), vec![0x1]);
+
+        // The page is not watched anymore
+        w.written(0x1000, 4);
+        assert_eq!(w.take_dirty(), vec![]);
+
+        w.written(0x8000_3000, 0x1000);
+        w.written(0xFFFF_FFFC, 0x100);
+        assert_eq!(w.take_dirty(), vec![0x8_0003]);
+
+        w.watch(0x1000);
+        w.clear();
+        w.written(0x1000, 4);
+        assert_eq!(w.take_dirty(), vec![]);
+    }
+}
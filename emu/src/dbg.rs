@@ -35,6 +35,8 @@ mod logview;
 pub use self::logview::*;
 mod memoryview;
 pub use self::memoryview::*;
+mod symbols;
+pub use self::symbols::*;
 
 pub trait DebuggerModel {
     /// Return a vector of the name of all CPUS.
@@ -72,6 +74,12 @@ pub trait DebuggerModel {
     fn reset(&mut self, hard: bool);
 
     fn render_debug<'a, 'ui>(&mut self, dr: &DebuggerRenderer<'a, 'ui>);
+
+    /// Return the symbol table for the specified CPU, if any. Symbols are
+    /// used to display and resolve addresses by name.
+    fn symbols(&self, _cpu_name: &str) -> Option<SymbolTable> {
+        None
+    }
 }
 
 pub struct DebuggerUI {
@@ -110,11 +118,19 @@ impl DebuggerUI {
         for idx in 0..uictx.cpus.len() {
             let name = &uictx.cpus[idx];
             uictx.disasm.insert(name.clone(), UiCtxDisasm::default());
+            if let Some(syms) = producer.symbols(name) {
+                uictx.symbols.insert(name.clone(), Rc::new(syms));
+            }
         }
 
         // Initial event
         uictx.event = Some((box TraceEvent::Paused(), Instant::now()));
 
+        let mut debugger = Debugger::new(&uictx.cpus);
+        for (name, syms) in uictx.symbols.iter() {
+            debugger.set_symbols(name, syms.clone());
+        }
+
         let mut dbg = Self {
             imgui: Rc::new(RefCell::new(imgui)),
             imgui_sdl2,
@@ -123,7 +139,7 @@ impl DebuggerUI {
             logpool,
             tex_screen: Texture::new(),
             screen_size: (320, 240),
-            dbg: Debugger::new(&uictx.cpus),
+            dbg: debugger,
             uictx: RefCell::new(uictx),
             paused: true,
             last_render: Instant::now(),
@@ -213,6 +229,13 @@ impl DebuggerUI {
                         self.dbg.disable_breakpoint_oneshot();
                         return false;
                     }
+                    TraceEvent::StepOut(cpu_name, pc) => {
+                        // The subroutine executed its return instruction: keep
+                        // running through the delay slot (if any), and stop
+                        // at the return address.
+                        self.dbg.set_breakpoint_oneshot(&cpu_name, Some(pc));
+                        return false;
+                    }
                     TraceEvent::GenericBreak(msg) => {
                         self.paused = true;
                         self.dbg.disable_breakpoint_oneshot();
@@ -274,6 +297,11 @@ impl DebuggerUI {
                 self.dbg.set_breakpoint_oneshot(&cpu_name, Some(pc));
                 self.paused = false;
             }
+            Some(UiCommand::StepOut(ref cpu_name, sp)) => {
+                let cpu_name = cpu_name.clone();
+                self.dbg.set_step_out(&cpu_name, sp);
+                self.paused = false;
+            }
             Some(UiCommand::CpuStep(ref cpu_name)) => {
                 let _ = model.trace_step(&cpu_name, &Tracer::null());
                 self.paused = true;
@@ -368,8 +396,9 @@ impl DebuggerUI {
         let numframes = model.frames();
         let mut logviewcmd = None;
         let mut logpool = self.logpool.clone();
-        for mut ctxlog in self.uictx.get_mut().logviews.iter_mut() {
-            let cmd = render_logview(ui, &mut ctxlog, &mut logpool, numframes);
+        let uictx = self.uictx.get_mut();
+        for mut ctxlog in uictx.logviews.iter_mut() {
+            let cmd = render_logview(ui, &mut ctxlog, &mut logpool, &uictx.symbols, numframes);
             logviewcmd = logviewcmd.or(cmd);
         }
         self.uictx.get_mut().logviews.retain(|view| view.opened);
@@ -442,6 +471,9 @@ impl DebuggerUI {
     ) -> std::result::Result<(), Box<dyn std::error::Error + 'static>> {
         self.dbg = serde_json::from_str(&fs::read_to_string(filename)?)?;
         self.dbg.after_deserialize();
+        for (name, syms) in self.uictx.get_mut().symbols.iter() {
+            self.dbg.set_symbols(name, syms.clone());
+        }
         Ok(())
     }
 
@@ -475,9 +507,10 @@ impl<'a, 'ui> DebuggerRenderer<'a, 'ui> {
     }
     pub fn render_memoryview<V: MemoryView>(&self, v: &mut V) {
         let mut ctx = self.ctx.borrow_mut();
+        let syms = ctx.symbols.get(v.name()).cloned();
         ctx.memviews
             .entry(v.name().to_string())
             .or_insert_with(|| MemWindow::default())
-            .render(self.ui, v);
+            .render(self.ui, v, syms.as_ref().map(|s| &**s));
     }
 }
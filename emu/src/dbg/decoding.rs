@@ -258,26 +258,27 @@ impl DecodedInsn {
     // Return a string representation of the insn, which represents
     // the disassembled instruction.
     pub fn disasm(&self) -> String {
+        self.disasm_with_labels(|_| None)
+    }
+
+    // Like disasm(), but branch targets are displayed through the labels
+    // function, when it returns a name for them.
+    pub fn disasm_with_labels<F: Fn(u64) -> Option<String>>(&self, labels: F) -> String {
         // Get all args which are not hidden
-        let args = self.args().filter(|o| !o.is_hidden()).collect::<Vec<_>>();
+        let args: Vec<String> = self
+            .args()
+            .filter(|o| !o.is_hidden())
+            .map(|o| match o {
+                Operand::Target(v) => labels(*v).unwrap_or_else(|| o.to_string()),
+                _ => o.to_string(),
+            })
+            .collect();
 
         if let Some(ref f) = self.fmt {
-            let args1: Vec<String> = args.iter().map(|o| o.to_string()).collect();
-            let argss: Vec<&str> = args1.iter().map(|o| &**o).collect();
+            let argss: Vec<&str> = args.iter().map(|o| &**o).collect();
             self.op.to_string() + "\t" + &Template::new(f).render_positional(&argss)
         } else {
-            // Standard formatting with commas. Use compile-time formatting.
-            match args.len() {
-                4 => format!(
-                    "{}\t{},{},{},{}",
-                    self.op, args[0], args[1], args[2], args[3]
-                ),
-                3 => format!("{}\t{},{},{}", self.op, args[0], args[1], args[2]),
-                2 => format!("{}\t{},{}", self.op, args[0], args[1]),
-                1 => format!("{}\t{}", self.op, args[0]),
-                0 => format!("{}\t", self.op),
-                _ => unreachable!(),
-            }
+            self.op.to_string() + "\t" + &args.join(",")
         }
     }
 }
@@ -4,7 +4,7 @@ use sdl2::keyboard::Scancode;
 
 use super::decoding::DecodedInsn;
 use super::uisupport::*;
-use super::{RegHighlight, TraceEvent, UiCommand, UiCtx};
+use super::{resolve_addr, RegHighlight, TraceEvent, UiCommand, UiCtx, UiCtxDisasm};
 
 use std::time::Instant;
 
@@ -28,6 +28,25 @@ pub trait DisasmView {
     /// with 0x3FFF.
     fn pc_mask(&self, pc: u64) -> u64;
 
+    /// If the specified instruction (at the specified PC) is a subroutine call,
+    /// return the PC at which execution continues after the subroutine returns,
+    /// taking delay slots into account. This is used to implement "step over".
+    fn call_return_pc(&self, _pc: u64, _insn: &DecodedInsn) -> Option<u64> {
+        None
+    }
+
+    /// Return true if the specified instruction is a return from subroutine.
+    fn is_return(&self, _insn: &DecodedInsn) -> bool {
+        false
+    }
+
+    /// Return the current stack pointer, if known. This is used while stepping
+    /// over or out of a subroutine, to skip returns from deeper (recursive)
+    /// invocations. The stack is assumed to grow downward.
+    fn stack_pointer(&self) -> Option<u64> {
+        None
+    }
+
     /// Disassemble a single instruction at the specified program counter;
     /// Returns the bytes composing the instruction and the string representation.
     fn disasm_block<Func: FnMut(u64, &[u8], &DecodedInsn)>(&self, pc_range: (u64, u64), f: Func);
@@ -48,6 +67,33 @@ fn color(r: usize, g: usize, b: usize) -> [f32; 4] {
     [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]
 }
 
+// Run up to the specified return address. The current stack pointer is
+// recorded, so that the step completes only when the return address is
+// reached at the same or a shallower stack depth.
+fn step_return_command<DV: DisasmView>(
+    v: &DV,
+    dctx: &mut UiCtxDisasm,
+    cpu_name: &str,
+    ret: u64,
+) -> UiCommand {
+    dctx.step_return = Some((ret, v.stack_pointer()));
+    UiCommand::BreakpointOneShot(cpu_name.to_owned(), ret)
+}
+
+// Step over the instruction at the current PC: if it is a subroutine call,
+// run until the subroutine returns, otherwise just step into it.
+fn step_over_command<DV: DisasmView>(v: &DV, dctx: &mut UiCtxDisasm, cpu_name: &str) -> UiCommand {
+    let cur_pc = v.pc();
+    let mut ret = None;
+    v.disasm_block((cur_pc, cur_pc + 4), |pc, _, insn| {
+        ret = v.call_return_pc(pc, insn);
+    });
+    match ret {
+        Some(ret) => step_return_command(v, dctx, cpu_name, ret),
+        None => UiCommand::CpuStep(cpu_name.to_owned()),
+    }
+}
+
 pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
     ui: &'a Ui<'ui>,
     ctx: &mut UiCtx,
@@ -56,6 +102,7 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
     let cpu_name = v.name().to_owned();
     let cur_pc = v.pc();
     let mut set_command: Option<UiCommand> = None;
+    let syms = ctx.symbols.get(&cpu_name).cloned();
     let dctx = ctx.disasm.get_mut(&cpu_name).unwrap();
 
     // If we were asked to show a certain PC, then also get focus
@@ -79,6 +126,7 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
                 }
 
                 dctx.cursor_pc = None;
+                dctx.step_return = None;
 
                 // Start blinking effect
                 dctx.blink_pc = Some((bp_pc, Instant::now()));
@@ -96,6 +144,7 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
                 }
 
                 dctx.cursor_pc = None;
+                dctx.step_return = None;
 
                 // Start blinking effect
                 dctx.blink_pc = Some((cur_pc, Instant::now()));
@@ -111,11 +160,26 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
 
                 dctx.blink_pc = None;
                 dctx.cursor_pc = None;
+
+                // While stepping over a subroutine, the return address can be
+                // reached first by a deeper (recursive) invocation: in that
+                // case, keep running.
+                if let Some((ret, sp)) = dctx.step_return.take() {
+                    let deeper = match (v.stack_pointer(), sp) {
+                        (Some(cur_sp), Some(sp)) => cur_sp < sp,
+                        _ => false,
+                    };
+                    if bp_pc == ret && deeper {
+                        dctx.step_return = Some((ret, sp));
+                        set_command = Some(UiCommand::BreakpointOneShot(cpu_name.clone(), ret));
+                    }
+                }
             }
             TraceEvent::Stepped() | TraceEvent::Paused() | TraceEvent::GenericBreak(_) => {
                 dctx.force_pc = Some(cur_pc);
                 dctx.blink_pc = None;
                 dctx.cursor_pc = None;
+                dctx.step_return = None;
             }
             _ => {}
         },
@@ -132,16 +196,16 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
             // Goto popup
             // *******************************************
             ui.popup(im_str!("###goto"), || {
-                let mut s = ImString::new("00000000");
-                ui.text(im_str!("Insert PC:"));
+                let mut s = ImString::with_capacity(64);
+                s.push_str("00000000");
+                ui.text(im_str!("Insert PC or symbol:"));
                 if ui
                     .input_text(im_str!("###goto#input"), &mut s)
-                    .chars_hexadecimal(true)
                     .enter_returns_true(true)
                     .auto_select_all(true)
                     .build()
                 {
-                    dctx.force_pc = u64::from_str_radix(s.as_ref(), 16).ok();
+                    dctx.force_pc = resolve_addr(syms.as_ref().map(|s| &**s), s.to_str());
                     ui.close_current_popup();
                 }
             });
@@ -185,10 +249,23 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
                 set_command = Some(UiCommand::CpuStep(cpu_name.clone()));
             }
             ui.same_line(0.0);
+            if ui.small_button(im_str!("Over"))
+                || (has_focus && ui.is_key_pressed(Scancode::O as _))
+            {
+                set_command = Some(step_over_command(&*v, dctx, &cpu_name));
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Out")) || (has_focus && ui.is_key_pressed(Scancode::U as _))
+            {
+                dctx.step_return = None;
+                set_command = Some(UiCommand::StepOut(cpu_name.clone(), v.stack_pointer()));
+            }
+            ui.same_line(0.0);
             if ui.small_button(im_str!("Here"))
                 || (has_focus && ui.is_key_pressed(Scancode::Return as _))
             {
                 if let Some(cpc) = dctx.cursor_pc {
+                    dctx.step_return = None;
                     set_command = Some(UiCommand::BreakpointOneShot(cpu_name.clone(), cpc));
                 }
             }
@@ -239,6 +316,7 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
                     }
 
                     // Display the non-clipped part of the listbox
+                    let mut line_popup = false;
                     let blink_pc = dctx.blink_pc;
                     let cursor_pc = dctx.cursor_pc;
                     ImGuiListClipper::new(num_lines as usize).build(|start, end| {
@@ -307,7 +385,12 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
                                     }
                                 }
 
-                                let dis = insn.disasm();
+                                let dis = match syms {
+                                    Some(ref syms) => {
+                                        insn.disasm_with_labels(|tgt| syms.describe(tgt))
+                                    }
+                                    None => insn.disasm(),
+                                };
                                 let fields: Vec<&str> = dis.splitn(2, "\t").collect();
 
                                 let gr = ui.begin_group();
@@ -327,6 +410,23 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
                                 ui.same_line(230.0);
                                 ui.text_colored(color(230, 219, 116), im_str!("{:80}", fields[1]));
 
+                                // Symbol (brighter at the start of a function)
+                                if let Some((name, off)) = syms.as_ref().and_then(|s| s.lookup(pc))
+                                {
+                                    ui.same_line(400.0);
+                                    if off == 0 {
+                                        ui.text_colored(
+                                            color(102, 217, 239),
+                                            im_str!("<{}>", name),
+                                        );
+                                    } else {
+                                        ui.text_colored(
+                                            color(102, 99, 83),
+                                            im_str!("<{}+0x{:x}>", name, off),
+                                        );
+                                    }
+                                }
+
                                 gr.end(&ui);
                                 if ui.is_item_hovered()
                                     && ui.is_window_focused()
@@ -334,9 +434,27 @@ pub(crate) fn render_disasmview<'a, 'ui, DV: DisasmView>(
                                 {
                                     dctx.cursor_pc = Some(pc);
                                 }
+                                if ui.is_item_hovered() && ui.is_mouse_clicked(MouseButton::Right) {
+                                    dctx.cursor_pc = Some(pc);
+                                    line_popup = true;
+                                }
                             },
                         );
-                    })
+                    });
+
+                    // Context menu of a disasm line
+                    if line_popup {
+                        ui.open_popup(im_str!("###line_popup"));
+                    }
+                    ui.popup(im_str!("###line_popup"), || {
+                        if let Some(cpc) = dctx.cursor_pc {
+                            if MenuItem::new(&im_str!("Run to {:08x}", cpc)).build(ui) {
+                                dctx.step_return = None;
+                                set_command =
+                                    Some(UiCommand::BreakpointOneShot(cpu_name.clone(), cpc));
+                            }
+                        }
+                    });
                 })
         });
 
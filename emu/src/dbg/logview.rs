@@ -1,6 +1,6 @@
 use super::uisupport::{is_shortcut_pressed, ImGuiListClipper};
-use super::{LogViewCommand, UiCtxLog};
-use crate::log::{LogPool, LogPoolPtr, LogView};
+use super::{LogViewCommand, SymbolTable, UiCtxLog};
+use crate::log::{LogLine, LogPool, LogPoolPtr, LogView};
 use sdl2::keyboard::Scancode;
 
 use imgui::*;
@@ -8,6 +8,8 @@ use slog::LOG_LEVEL_SHORT_NAMES;
 use textwrap;
 use tinyfiledialogs::save_file_dialog_with_filter;
 
+use std::collections::HashMap;
+use std::rc::Rc;
 use std::time::Instant;
 
 const LOG_LEVEL_COLOR: [[f32; 4]; 7] = [
@@ -314,10 +316,24 @@ fn render_save<'a, 'ui>(ui: &'a Ui<'ui>, view: &mut LogView, pool: &LogPool) {
     }
 }
 
+// Format the location at which a log line was generated, adding the
+// symbol of the PC if it is known.
+fn describe_location(line: &LogLine, symbols: &HashMap<String, Rc<SymbolTable>>) -> String {
+    let loc = line.location.clone().unwrap_or_default();
+    match line
+        .location()
+        .and_then(|(cpu, pc)| symbols.get(&cpu)?.describe(pc))
+    {
+        Some(sym) => format!("{} <{}>", loc, sym),
+        None => loc,
+    }
+}
+
 pub(crate) fn render_logview<'a, 'ui>(
     ui: &'a Ui<'ui>,
     ctx: &mut UiCtxLog,
     pool: &mut LogPoolPtr,
+    symbols: &HashMap<String, Rc<SymbolTable>>,
     num_frames: i64,
 ) -> Option<LogViewCommand> {
     let mut opened = ctx.opened;
@@ -510,7 +526,7 @@ pub(crate) fn render_logview<'a, 'ui>(
                                     if !ctx.following && ui.is_item_hovered() {
                                         ui.tooltip_text(im_str!(
                                             "Generated at: {}",
-                                            v.location.as_ref().unwrap()
+                                            describe_location(v, symbols)
                                         ));
                                     }
                                 } else {
@@ -579,7 +595,7 @@ pub(crate) fn render_logview<'a, 'ui>(
                             if let Some(loc) = ctx.selected.location() {
                                 if MenuItem::new(&im_str!(
                                     "Go to {}..",
-                                    ctx.selected.location.as_ref().unwrap()
+                                    describe_location(&ctx.selected, symbols)
                                 ))
                                 .build(ui)
                                 {
@@ -1,4 +1,5 @@
 use super::uisupport::ImGuiListClipper;
+use super::{resolve_addr, SymbolTable};
 use crate::bus;
 use crate::memint::ByteOrderCombiner;
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
@@ -170,7 +171,12 @@ impl MemWindow {
         .build();
     }
 
-    pub(crate) fn render(&mut self, ui: &Ui, memview: &mut dyn MemoryView) {
+    pub(crate) fn render(
+        &mut self,
+        ui: &Ui,
+        memview: &mut dyn MemoryView,
+        syms: Option<&SymbolTable>,
+    ) {
         let banks = memview.banks();
         let bank = &banks[self.curr_bank];
         let s = self.calc_sizes(ui, bank.end);
@@ -200,16 +206,16 @@ impl MemWindow {
                     ui.open_popup(im_str!("##goto"));
                 }
                 ui.popup(im_str!("##goto"), || {
-                    let mut s = ImString::new("00000000");
-                    ui.text(im_str!("Address:"));
+                    let mut s = ImString::with_capacity(64);
+                    s.push_str("00000000");
+                    ui.text(im_str!("Address or symbol:"));
                     if ui
                         .input_text(im_str!("##input"), &mut s)
-                        .chars_hexadecimal(true)
                         .enter_returns_true(true)
                         .auto_select_all(true)
                         .build()
                     {
-                        self.force_addr = u64::from_str_radix(s.as_ref(), 16).ok();
+                        self.force_addr = resolve_addr(syms, s.to_str());
                         self.edit_addr = None;
                         self.inspect_addr = None;
                         self.highlight_addr = None;
@@ -222,7 +228,7 @@ impl MemWindow {
                 ui.separator();
 
                 // Footer
-                self.render_footer(ui, memview, &s);
+                self.render_footer(ui, memview, syms, &s);
 
                 self.curr_bank = curr_bank;
             });
@@ -471,7 +477,13 @@ impl MemWindow {
             });
     }
 
-    fn render_footer(&mut self, ui: &Ui, memview: &mut dyn MemoryView, s: &Sizes) {
+    fn render_footer(
+        &mut self,
+        ui: &Ui,
+        memview: &mut dyn MemoryView,
+        syms: Option<&SymbolTable>,
+        s: &Sizes,
+    ) {
         let isizes: [&ImStr; 8] = [
             im_str!("Uint8"),
             im_str!("Uint16"),
@@ -488,7 +500,15 @@ impl MemWindow {
 
         ui.align_text_to_frame_padding();
         if let Some(addr) = self.inspect_addr {
-            ui.text(im_str!("Inspect {:01$X} as:", addr, s.addr_digits_count));
+            match syms.and_then(|syms| syms.describe(addr)) {
+                Some(sym) => ui.text(im_str!(
+                    "Inspect {:02$X} <{}> as:",
+                    addr,
+                    sym,
+                    s.addr_digits_count
+                )),
+                None => ui.text(im_str!("Inspect {:01$X} as:", addr, s.addr_digits_count)),
+            }
         } else {
             ui.text(im_str!("Inspect as:"));
         }
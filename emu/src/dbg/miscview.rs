@@ -31,6 +31,14 @@ pub(crate) fn render_help(ui: &Ui<'_>) -> ImString {
         ui.same_line(90.0);
         ui.text("Step into");
 
+        ui.bullet_text(im_str!("O"));
+        ui.same_line(90.0);
+        ui.text("Step over");
+
+        ui.bullet_text(im_str!("U"));
+        ui.same_line(90.0);
+        ui.text("Step out");
+
         ui.bullet_text(im_str!("UP/DOWN"));
         ui.same_line(90.0);
         ui.text("Move selection");
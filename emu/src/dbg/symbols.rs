@@ -0,0 +1,381 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A table of symbols (function and variable names) of the code being
+/// debugged, used to display addresses as `name+offset` and to let the
+/// user refer to addresses by name.
+///
+/// Symbols can be loaded from an ELF file, a GNU ld linker map, or the
+/// output of `nm`.
+#[derive(Clone)]
+pub struct SymbolTable {
+    addr_mask: fn(u64) -> u64,
+    by_addr: BTreeMap<u64, (String, Option<u64>)>,
+    by_name: HashMap<String, u64>,
+}
+
+impl SymbolTable {
+    /// Create an empty symbol table. `addr_mask` is applied to all addresses,
+    /// both when adding symbols and when looking them up, so that symbols
+    /// match the addresses used by the debugger (usually, this is the same
+    /// function as [`DisasmView::pc_mask`](trait.DisasmView.html)).
+    pub fn new(addr_mask: fn(u64) -> u64) -> SymbolTable {
+        SymbolTable {
+            addr_mask,
+            by_addr: BTreeMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    /// Load a symbol table from the specified file. The file format is
+    /// autodetected.
+    pub fn load(path: &Path, addr_mask: fn(u64) -> u64) -> io::Result<SymbolTable> {
+        SymbolTable::from_bytes(&fs::read(path)?, addr_mask)
+    }
+
+    /// Parse a symbol table from the contents of a file (either an ELF
+    /// binary, or a text file).
+    pub fn from_bytes(data: &[u8], addr_mask: fn(u64) -> u64) -> io::Result<SymbolTable> {
+        let mut syms = SymbolTable::new(addr_mask);
+        if data.starts_with(b"\x7fELF") {
+            syms.parse_elf(data)?;
+        } else {
+            let text = std::str::from_utf8(data)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid symbol file"))?;
+            syms.parse_text(text);
+        }
+        Ok(syms)
+    }
+
+    /// Add a symbol. If size is specified, addresses past the end of the
+    /// symbol will not be resolved to it.
+    pub fn insert(&mut self, addr: u64, name: &str, size: Option<u64>) {
+        let addr = (self.addr_mask)(addr);
+        self.by_addr
+            .entry(addr)
+            .or_insert_with(|| (name.to_owned(), size));
+        self.by_name.insert(name.to_owned(), addr);
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    /// Iterate over all symbols, sorted by address.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &str)> {
+        self.by_addr
+            .iter()
+            .map(|(addr, (name, _))| (*addr, name.as_str()))
+    }
+
+    /// Return the address of the symbol with the specified name.
+    pub fn addr(&self, name: &str) -> Option<u64> {
+        self.by_name.get(name).cloned()
+    }
+
+    /// Find the symbol containing the specified address. Returns the
+    /// name of the symbol and the offset of the address within it.
+    pub fn lookup(&self, addr: u64) -> Option<(&str, u64)> {
+        let addr = (self.addr_mask)(addr);
+        let (start, (name, size)) = self.by_addr.range(..=addr).next_back()?;
+        let off = addr - start;
+        match size {
+            Some(size) if off >= *size => None,
+            _ => Some((name, off)),
+        }
+    }
+
+    /// Describe the specified address symbolically, as `name` or
+    /// `name+0xOFF`.
+    pub fn describe(&self, addr: u64) -> Option<String> {
+        self.lookup(addr).map(|(name, off)| {
+            if off == 0 {
+                name.to_owned()
+            } else {
+                format!("{}+0x{:x}", name, off)
+            }
+        })
+    }
+
+    /// Resolve an address typed by the user: either a symbol name, optionally
+    /// followed by an offset (`name+0x10`), or a hexadecimal address.
+    pub fn resolve(&self, s: &str) -> Option<u64> {
+        let s = s.trim();
+        let mut parts = s.splitn(2, '+');
+        let name = parts.next().unwrap().trim();
+        let off = match parts.next() {
+            Some(off) => parse_hex(off.trim())?,
+            None => 0,
+        };
+        match self.addr(name) {
+            Some(addr) => Some(addr.wrapping_add(off)),
+            None if off == 0 => parse_hex(name),
+            None => None,
+        }
+    }
+
+    // Parse a text symbol file. Supported formats are `nm` output
+    // ("ADDR [SIZE] TYPE NAME") and GNU ld maps, in which symbols appear
+    // as "ADDR NAME" lines. All other lines are ignored.
+    fn parse_text(&mut self, text: &str) {
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let addr = match fields.first().and_then(|f| parse_hex(f)) {
+                Some(addr) => addr,
+                None => continue,
+            };
+            let (name, size, typ) = match fields.len() {
+                2 => (fields[1], None, "T"),
+                3 if fields[1].len() == 1 => (fields[2], None, fields[1]),
+                4 if fields[2].len() == 1 => (fields[3], parse_hex(fields[1]), fields[2]),
+                _ => continue,
+            };
+            // Skip undefined, absolute and debugging symbols.
+            if "UwvaAN".contains(typ) || !is_symbol_name(name) {
+                continue;
+            }
+            self.insert(addr, name, size);
+        }
+    }
+
+    fn parse_elf(&mut self, data: &[u8]) -> io::Result<()> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let is64 = match data.get(4) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(invalid("invalid ELF class")),
+        };
+        let be = match data.get(5) {
+            Some(1) => false,
+            Some(2) => true,
+            _ => return Err(invalid("invalid ELF endianness")),
+        };
+        let read = |off: u64, size: usize| -> io::Result<u64> {
+            let buf = data
+                .get(off as usize..off as usize + size)
+                .ok_or_else(|| invalid("truncated ELF file"))?;
+            Ok(if be {
+                BigEndian::read_uint(buf, size)
+            } else {
+                LittleEndian::read_uint(buf, size)
+            })
+        };
+
+        // Section header table: offset, entry size, number of entries.
+        let (shoff, shentsize, shnum) = if is64 {
+            (read(0x28, 8)?, read(0x3A, 2)?, read(0x3C, 2)?)
+        } else {
+            (read(0x20, 4)?, read(0x2E, 2)?, read(0x30, 2)?)
+        };
+
+        // Read a section header: (type, offset, size, link).
+        let section = |idx: u64| -> io::Result<(u64, u64, u64, u64)> {
+            let sh = shoff + idx * shentsize;
+            if is64 {
+                Ok((
+                    read(sh + 4, 4)?,
+                    read(sh + 24, 8)?,
+                    read(sh + 32, 8)?,
+                    read(sh + 40, 4)?,
+                ))
+            } else {
+                Ok((
+                    read(sh + 4, 4)?,
+                    read(sh + 16, 4)?,
+                    read(sh + 20, 4)?,
+                    read(sh + 24, 4)?,
+                ))
+            }
+        };
+
+        for idx in 0..shnum {
+            // SHT_SYMTAB or SHT_DYNSYM
+            let (typ, offset, size, link) = section(idx)?;
+            if typ != 2 && typ != 11 {
+                continue;
+            }
+            let (_, stroff, strsize, _) = section(link)?;
+            let strtab = data
+                .get(stroff as usize..(stroff + strsize) as usize)
+                .ok_or_else(|| invalid("truncated ELF file"))?;
+
+            let entsize = if is64 { 24 } else { 16 };
+            for n in 0..size / entsize {
+                let sym = offset + n * entsize;
+                let (name, value, size, info, shndx) = if is64 {
+                    (
+                        read(sym, 4)?,
+                        read(sym + 8, 8)?,
+                        read(sym + 16, 8)?,
+                        read(sym + 4, 1)?,
+                        read(sym + 6, 2)?,
+                    )
+                } else {
+                    (
+                        read(sym, 4)?,
+                        read(sym + 4, 4)?,
+                        read(sym + 8, 4)?,
+                        read(sym + 12, 1)?,
+                        read(sym + 14, 2)?,
+                    )
+                };
+
+                // Only keep defined symbols (not SHN_UNDEF or SHN_ABS) of
+                // type NOTYPE, OBJECT or FUNC.
+                if info & 0xF > 2 || shndx == 0 || shndx == 0xFFF1 {
+                    continue;
+                }
+                let name = match strtab.get(name as usize..) {
+                    Some(s) => &s[..s.iter().position(|c| *c == 0).unwrap_or(s.len())],
+                    None => continue,
+                };
+                let name = String::from_utf8_lossy(name);
+                if !is_symbol_name(&name) {
+                    continue;
+                }
+                let size = if size != 0 { Some(size) } else { None };
+                self.insert(value, &name, size);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resolve an address typed by the user, using the symbol table if
+/// available, or parsing it as hexadecimal otherwise.
+pub(crate) fn resolve_addr(syms: Option<&SymbolTable>, s: &str) -> Option<u64> {
+    match syms {
+        Some(syms) => syms.resolve(s),
+        None => parse_hex(s.trim()),
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    let s = if s.starts_with("0x") { &s[2..] } else { s };
+    u64::from_str_radix(s, 16).ok()
+}
+
+// Check whether a name looks like a symbol worth displaying: this skips
+// empty names, file names, and compiler-generated local labels.
+fn is_symbol_name(name: &str) -> bool {
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.')
+        && !name.ends_with(".o")
+        && !name.ends_with(".c")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kseg(addr: u64) -> u64 {
+        addr & 0x1FFF_FFFF
+    }
+
+    #[test]
+    fn test_nm() {
+        let syms = SymbolTable::from_bytes(
+            b"80000400 T main\n\
+              80000480 00000010 t helper\n\
+              80001000 B buffer\n\
+              \x20        U printf\n\
+              00000010 a FOO\n",
+            kseg,
+        )
+        .unwrap();
+        assert_eq!(syms.len(), 3);
+        assert_eq!(syms.addr("main"), Some(0x400));
+        assert_eq!(syms.describe(0x8000_0400), Some("main".into()));
+        assert_eq!(syms.describe(0xA000_0410), Some("main+0x10".into()));
+        assert_eq!(syms.describe(0x8000_048C), Some("helper+0xc".into()));
+        assert_eq!(syms.describe(0x8000_0490), None); // past end of helper
+        assert_eq!(syms.describe(0x8000_0200), None);
+        assert_eq!(syms.resolve("main+0x8"), Some(0x408));
+        assert_eq!(syms.resolve("80000400"), Some(0x8000_0400));
+        assert_eq!(syms.resolve("printf"), None);
+    }
+
+    #[test]
+    fn test_map() {
+        let syms = SymbolTable::from_bytes(
+            b" .text          0x0000000080000400     0x1234 build/main.o\n\
+              \x20               0x0000000080000400                main\n\
+              \x20               0x0000000080000520                do_stuff\n\
+              \x20               0x0000000080001634                __bss_start = .\n",
+            kseg,
+        )
+        .unwrap();
+        assert_eq!(syms.len(), 2);
+        assert_eq!(syms.describe(0x8000_0530), Some("do_stuff+0x10".into()));
+    }
+
+    #[test]
+    fn test_elf() {
+        // Build a minimal big-endian ELF32 file with a symbol table.
+        let strtab = b"\0main\0data\0";
+        let mut elf = vec![0u8; 0x34];
+        elf[..6].copy_from_slice(b"\x7fELF\x01\x02");
+
+        let sym = |name: u32, value: u32, size: u32, info: u8, shndx: u16| {
+            let mut buf = [0u8; 16];
+            BigEndian::write_u32(&mut buf[0..], name);
+            BigEndian::write_u32(&mut buf[4..], value);
+            BigEndian::write_u32(&mut buf[8..], size);
+            buf[12] = info;
+            BigEndian::write_u16(&mut buf[14..], shndx);
+            buf
+        };
+        let symtab_off = elf.len();
+        for s in [
+            sym(0, 0, 0, 0, 0),                 // null symbol
+            sym(1, 0x8000_0400, 0x20, 0x12, 1), // main (global func)
+            sym(6, 0x8000_2000, 0x4, 0x11, 2),  // data (global object)
+            sym(6, 0x8000_3000, 0x0, 0x03, 1),  // section symbol
+        ]
+        .iter()
+        {
+            elf.extend_from_slice(s);
+        }
+        let strtab_off = elf.len();
+        elf.extend_from_slice(strtab);
+
+        let section = |typ: u32, off: usize, size: usize, link: u32| {
+            let mut buf = [0u8; 40];
+            BigEndian::write_u32(&mut buf[4..], typ);
+            BigEndian::write_u32(&mut buf[16..], off as u32);
+            BigEndian::write_u32(&mut buf[20..], size as u32);
+            BigEndian::write_u32(&mut buf[24..], link);
+            buf
+        };
+        let shoff = elf.len();
+        let sections = [
+            section(0, 0, 0, 0),
+            section(2, symtab_off, 4 * 16, 2),
+            section(3, strtab_off, strtab.len(), 0),
+        ];
+        for s in sections.iter() {
+            elf.extend_from_slice(s);
+        }
+        BigEndian::write_u32(&mut elf[0x20..], shoff as u32);
+        BigEndian::write_u16(&mut elf[0x2E..], 40);
+        BigEndian::write_u16(&mut elf[0x30..], sections.len() as u16);
+
+        let syms = SymbolTable::from_bytes(&elf, kseg).unwrap();
+        assert_eq!(syms.len(), 2);
+        assert_eq!(syms.describe(0x8000_041C), Some("main+0x1c".into()));
+        assert_eq!(syms.describe(0x8000_0420), None);
+        assert_eq!(syms.addr("data"), Some(0x2000));
+    }
+}
@@ -1,5 +1,5 @@
 use super::uisupport::imgui_input_hex;
-use super::UiCtx;
+use super::{resolve_addr, SymbolTable, UiCtx};
 use array_macro::array;
 use bitflags::bitflags;
 use imgui::*;
@@ -10,6 +10,7 @@ use crate::memint::{AccessSize, MemInt};
 use std::cell::Cell;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::time::Instant;
 
 #[derive(Debug, Clone)]
@@ -19,6 +20,7 @@ pub enum TraceEvent {
     Stepped(), // A CPU just stepped
     Breakpoint(String, usize, u64), // A breakpoint was hit (cpu_idx, bp_idx, pc)
     BreakpointOneShot(String, u64), // A one-shot breakpoint was hit (cpu_idx, pc)
+    StepOut(String, u64), // The subroutine being stepped out of is returning (cpu_idx, return pc)
     WatchpointWrite(String, usize), // A watchpoint was hit during a write (cpu_idx, wp_idx)
     WatchpointRead(String, usize), // A watchpoint was hit during a read (cpu_idx, wp_idx)
     GenericBreak(String), // Another kind of condition was hit, and we want to stop the tracing.
@@ -49,6 +51,7 @@ impl TraceGuard {
 pub struct Tracer<'a> {
     dbg: Option<&'a Debugger>,
     trace_guards: [TraceGuard; 256],
+    step_out: bool,
 }
 
 impl Tracer<'_> {
@@ -58,9 +61,18 @@ impl Tracer<'_> {
         Tracer {
             dbg: None,
             trace_guards: array![TraceGuard::empty(); 256],
+            step_out: false,
         }
     }
 
+    /// Returns true if the tracer is connected to a debugger, and thus the
+    /// emulator must call trace_insn() after each instruction (as well as
+    /// the other tracing functions).
+    #[inline(always)]
+    pub fn is_active(&self) -> bool {
+        self.dbg.is_some()
+    }
+
     #[inline(always)]
     pub fn break_here(&self, msg: &str) -> Result<()> {
         if self.dbg.is_none() {
@@ -94,6 +106,25 @@ impl Tracer<'_> {
         }
     }
 
+    /// Notify the tracer that a subroutine call was just executed.
+    #[inline(always)]
+    pub fn trace_call(&self, cpu_name: &str) -> Result<()> {
+        if !self.step_out {
+            return Ok(());
+        }
+        self.dbg.unwrap().trace_call(cpu_name)
+    }
+
+    /// Notify the tracer that a return from subroutine was just executed,
+    /// with the address it is returning to and the current stack pointer.
+    #[inline(always)]
+    pub fn trace_return(&self, cpu_name: &str, pc: u64, sp: u64) -> Result<()> {
+        if !self.step_out {
+            return Ok(());
+        }
+        self.dbg.unwrap().trace_return(cpu_name, pc, sp)
+    }
+
     #[inline(always)]
     pub fn trace_mem_write(
         &self,
@@ -253,6 +284,9 @@ struct DbgCpu {
     #[serde(skip)]
     bp_oneshot: Option<u64>, // Special one-shot breakpoint
 
+    #[serde(skip)]
+    step_out: Cell<Option<(u64, usize)>>, // Step out in progress (stack pointer, call depth)
+
     #[serde(skip)]
     bp_fastmap: IntHashMap<u64, usize>,
 
@@ -265,6 +299,7 @@ impl DbgCpu {
         self.update_bp_fastmap();
         self.update_wp_fastmap();
         self.bp_oneshot = None;
+        self.step_out.set(None);
     }
 
     fn add_breakpoint(&mut self, pc: u64, description: &str) {
@@ -324,6 +359,8 @@ pub struct Debugger {
     cpus: HashMap<String, DbgCpu>,
     #[serde(skip)]
     next_poll: Cell<Option<Instant>>,
+    #[serde(skip)]
+    symbols: HashMap<String, Rc<SymbolTable>>,
 }
 
 impl Debugger {
@@ -336,6 +373,7 @@ impl Debugger {
         Self {
             cpus: cpumap,
             next_poll: Cell::new(None),
+            symbols: HashMap::new(),
         }
     }
 
@@ -352,12 +390,26 @@ impl Debugger {
             .set_breakpoint_oneshot(pc);
     }
 
+    /// Disable the one-shot breakpoint, and abort any step out in progress.
     pub fn disable_breakpoint_oneshot(&mut self) {
         for (_, cpu) in &mut self.cpus {
             cpu.set_breakpoint_oneshot(None);
+            cpu.step_out.set(None);
         }
     }
 
+    /// Start stepping out of the current subroutine, whose stack pointer is
+    /// the one specified. The tracer will generate a StepOut event as soon as
+    /// the subroutine executes its return instruction; returns of nested
+    /// subroutines, or executed at a deeper stack pointer (recursive
+    /// invocations), are skipped. If the stack pointer is not known, only
+    /// the call depth is tracked.
+    pub fn set_step_out(&mut self, cpu_name: &str, sp: Option<u64>) {
+        self.cpus[cpu_name]
+            .step_out
+            .set(Some((sp.unwrap_or(0), 0)));
+    }
+
     pub fn set_poll_event(&mut self, when: Instant) {
         self.next_poll.set(Some(when));
     }
@@ -368,6 +420,29 @@ impl Debugger {
             .unwrap()
             .add_breakpoint(pc, description);
     }
+
+    /// Add a breakpoint at the address of the specified symbol (optionally
+    /// followed by an offset, eg: "main+0x10"), or at an hexadecimal address.
+    /// Returns false if the symbol cannot be resolved.
+    pub fn add_breakpoint_by_name(&mut self, cpu_name: &str, name: &str, description: &str) -> bool {
+        match resolve_addr(self.symbols(cpu_name).map(|s| &**s), name) {
+            Some(pc) => {
+                self.add_breakpoint(cpu_name, pc, description);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Set the symbol table for the specified CPU.
+    pub fn set_symbols(&mut self, cpu_name: &str, syms: Rc<SymbolTable>) {
+        self.symbols.insert(cpu_name.to_owned(), syms);
+    }
+
+    /// Return the symbol table for the specified CPU, if any.
+    pub fn symbols(&self, cpu_name: &str) -> Option<&Rc<SymbolTable>> {
+        self.symbols.get(cpu_name)
+    }
 }
 
 impl Debugger {
@@ -390,6 +465,30 @@ impl Debugger {
         Tracer {
             dbg: Some(&self),
             trace_guards: trace_guards,
+            step_out: self.cpus.values().any(|cpu| cpu.step_out.get().is_some()),
+        }
+    }
+
+    fn trace_call(&self, cpu_name: &str) -> Result<()> {
+        let cpu = &self.cpus[cpu_name];
+        if let Some((sp, depth)) = cpu.step_out.get() {
+            cpu.step_out.set(Some((sp, depth + 1)));
+        }
+        Ok(())
+    }
+
+    fn trace_return(&self, cpu_name: &str, pc: u64, cur_sp: u64) -> Result<()> {
+        let cpu = &self.cpus[cpu_name];
+        match cpu.step_out.get() {
+            Some((sp, 0)) if cur_sp >= sp => {
+                cpu.step_out.set(None);
+                Err(box TraceEvent::StepOut(cpu_name.to_owned(), pc))
+            }
+            Some((sp, depth)) if depth > 0 => {
+                cpu.step_out.set(Some((sp, depth - 1)));
+                Ok(())
+            }
+            _ => Ok(()),
         }
     }
 
@@ -458,12 +557,15 @@ impl Debugger {
 
 impl Debugger {
     fn render_breakpoints(&mut self, ui: &Ui<'_>, ctx: &mut UiCtx, cpu_name: &str) {
+        let syms = self.symbols.get(cpu_name).cloned();
         let cpu = self.cpus.get_mut(cpu_name).unwrap();
 
         ui.popup(im_str!("##bp#new"), || {
             ui.text(im_str!("PC:"));
             ui.same_line(60.0);
-            imgui_input_hex(ui, im_str!("###bp#new_pc"), &mut ctx.new_bp_pc, false);
+            ui.input_text(im_str!("###bp#new_pc"), &mut ctx.new_bp_loc)
+                .auto_select_all(true)
+                .build();
 
             ui.text(im_str!("Desc:"));
             ui.same_line(60.0);
@@ -472,13 +574,20 @@ impl Debugger {
                 .build();
 
             if ui.button(im_str!("Add"), [40.0, 20.0]) {
-                let desc = ctx.new_bp_desc.to_str().to_owned();
-                cpu.add_breakpoint(ctx.new_bp_pc, &desc);
+                let loc = ctx.new_bp_loc.to_str().to_owned();
+                match resolve_addr(syms.as_ref().map(|s| &**s), &loc) {
+                    Some(pc) => {
+                        let desc = ctx.new_bp_desc.to_str().to_owned();
+                        cpu.add_breakpoint(pc, &desc);
+                    }
+                    None => ctx.add_flash_msg(&format!("Unknown symbol: {}", loc)),
+                }
                 ui.close_current_popup();
             }
         });
         if ui.small_button(im_str!("New BP")) {
-            ctx.new_bp_pc = 0;
+            ctx.new_bp_loc = ImString::with_capacity(64);
+            ctx.new_bp_loc.push_str("0");
             ctx.new_bp_desc = ImString::new("New breakpoint");
             ui.open_popup(im_str!("##bp#new"));
         }
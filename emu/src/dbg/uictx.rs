@@ -1,14 +1,16 @@
-use super::{MemWindow, TraceEvent};
+use super::{MemWindow, SymbolTable, TraceEvent};
 use crate::log::{LogLine, LogView};
 use imgui::ImString;
 
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::time::Instant;
 
 // UiCommand is an action triggered by the GUI that is executed
 // by the main debugger loop (cannot be done while drawing the window)
 pub(crate) enum UiCommand {
     BreakpointOneShot(String, u64), // Run with a temporary breakpoint set
+    StepOut(String, Option<u64>),   // Run until the current subroutine returns (stack pointer)
     CpuStep(String),                // Step a single opcode for the specified CPU
     Pause(bool),                    // Set global pause status
 }
@@ -31,6 +33,10 @@ pub(crate) struct UiCtxDisasm {
     pub cursor_pc: Option<u64>,
     // If set, the disasmview will automatically scroll to display this PC
     pub force_pc: Option<u64>,
+    // If set, a "step over" is in progress: we are running up to this return
+    // address, and stop there only once the stack pointer is back at (or
+    // above) the recorded value.
+    pub step_return: Option<(u64, Option<u64>)>,
     // Map of registers that must be highlighted (because are involved in cur_pc's opcode).
     pub regs_highlight: HashMap<&'static str, RegHighlight>,
 }
@@ -99,6 +105,9 @@ pub(crate) struct UiCtx {
     // Memory views
     pub memviews: HashMap<String, MemWindow>,
 
+    // Symbol tables (per CPU), if available
+    pub symbols: HashMap<String, Rc<SymbolTable>>,
+
     // Flash messages (auto-hide after 2s)
     pub flash_msg: Option<(String, Instant)>,
 
@@ -106,7 +115,7 @@ pub(crate) struct UiCtx {
     pub error_msg: Option<String>,
 
     // Popup "New breakpoint": local state
-    pub new_bp_pc: u64,
+    pub new_bp_loc: ImString,
     pub new_bp_desc: ImString,
 
     // Popup "New watchpoint": local state
@@ -173,6 +173,14 @@ static mut STATE: Option<RefCell<State>> = None;
 // incremented.
 static STATE_ID: AtomicU32 = AtomicU32::new(0);
 
+/// Return an identifier that changes every time the current
+/// [`State`](struct.State.html) is replaced (eg: when a savestate is loaded).
+/// Caches of data derived from the state can compare it with the identifier
+/// seen when they were filled, to know when they must be discarded.
+pub fn current_state_id() -> u32 {
+    STATE_ID.load(Ordering::Relaxed)
+}
+
 /// Return a mutable reference to the current [`State`](struct.State.html) (for
 /// the current thread).
 ///
@@ -0,0 +1,118 @@
+//! Standalone disassembler, to dump R4300 or RSP code to text without
+//! running the emulator (eg: boot code, RDRAM dumps or RSP microcode).
+use super::errors::*;
+use super::r4300::R4300;
+use super::sp::RSPCPU;
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::dbg::{DecodedInsn, Operand, SymbolTable};
+use mips64;
+use slog;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The instruction set of the code being disassembled.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Isa {
+    R4300,
+    Rsp,
+}
+
+impl FromStr for Isa {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "r4300" | "cpu" => Ok(Isa::R4300),
+            "rsp" => Ok(Isa::Rsp),
+            _ => Err(format!("invalid ISA: {} (expected: r4300, rsp)", s)),
+        }
+    }
+}
+
+impl Isa {
+    // Compute the address of a branch target, as decoded, within the
+    // memory region starting at base. The RSP PC is 12 bits, so jumps
+    // always land within IMEM.
+    fn target(self, base: u32, tgt: u64) -> u32 {
+        match self {
+            Isa::R4300 => tgt as u32,
+            Isa::Rsp => (base & !0xFFF) | (tgt as u32 & 0xFFF),
+        }
+    }
+}
+
+/// Load a symbol table from an ELF file, a linker map, or the output of
+/// `nm` (see [`SymbolTable`](../../emu/dbg/struct.SymbolTable.html)).
+pub fn load_symbols(path: &Path) -> Result<HashMap<u32, String>> {
+    let syms = SymbolTable::load(path, |addr| addr)
+        .chain_err(|| format!("cannot load symbols: {}", path.display()))?;
+    Ok(syms
+        .iter()
+        .map(|(addr, name)| (addr as u32, name.to_owned()))
+        .collect())
+}
+
+/// Disassemble the specified code, loaded at address base, writing an
+/// objdump-like listing: address, raw opcode and disassembly. Branch
+/// targets within the code are labelled, using the provided symbols
+/// when available.
+pub fn disasm<W: Write>(
+    w: &mut W,
+    isa: Isa,
+    code: &[u8],
+    base: u32,
+    symbols: &HashMap<u32, String>,
+) -> Result<()> {
+    let logger = slog::Logger::root(slog::Discard, o!());
+    match isa {
+        Isa::R4300 => disasm_cpu(w, &**R4300::new(logger), isa, code, base, symbols),
+        Isa::Rsp => disasm_cpu(w, &**RSPCPU::new(logger)?, isa, code, base, symbols),
+    }
+}
+
+fn disasm_cpu<C: mips64::Config, W: Write>(
+    w: &mut W,
+    cpu: &mips64::Cpu<C>,
+    isa: Isa,
+    code: &[u8],
+    base: u32,
+    symbols: &HashMap<u32, String>,
+) -> Result<()> {
+    let end = base.wrapping_add(code.len() as u32);
+    let insns: Vec<(u32, u32, DecodedInsn)> = code
+        .chunks_exact(4)
+        .enumerate()
+        .map(|(idx, buf)| {
+            let pc = base.wrapping_add(idx as u32 * 4);
+            let opcode = BigEndian::read_u32(buf);
+            (pc, opcode, cpu.disasm_opcode(opcode, pc as u64))
+        })
+        .collect();
+
+    // Add a label for all branch targets within the code that do not
+    // have a symbol.
+    let mut labels = symbols.clone();
+    for (_, _, insn) in insns.iter() {
+        for arg in insn.args() {
+            if let Operand::Target(tgt) = arg {
+                let tgt = isa.target(base, *tgt);
+                if tgt >= base && tgt < end && !labels.contains_key(&tgt) {
+                    labels.insert(tgt, format!("L{:08x}", tgt));
+                }
+            }
+        }
+    }
+
+    for (pc, opcode, insn) in insns.iter() {
+        if let Some(name) = labels.get(pc) {
+            writeln!(w, "\n{}:", name)?;
+        }
+        let dis = insn.disasm_with_labels(|tgt| labels.get(&isa.target(base, tgt)).cloned());
+        writeln!(w, "{:08x}:  {:08x}  {}", pc, opcode, dis.trim_end())?;
+    }
+    Ok(())
+}
@@ -112,6 +112,16 @@ impl Dp {
         }
     }
 
+    // Process a command generated by HLE of a graphics task, which bypasses
+    // the command buffer.
+    pub(crate) fn hle_cmd(&mut self, cmd: u64) {
+        self.gfx.op(cmd);
+        if (cmd >> 56) & 0x3F == 0x29 {
+            // Sync Full
+            Mi::get_mut().set_irq_line(IrqMask::DP, true);
+        }
+    }
+
     fn check_start(&mut self) {
         let mut status = self.cmd_status_ref();
         if !status.contains(StatusFlags::END_VALID) {
@@ -28,9 +28,11 @@ pub mod errors {
 }
 
 mod rdp;
+mod simd;
 
 pub mod ai;
 pub mod cartridge;
+pub mod disasm;
 pub mod dp;
 pub mod mi;
 pub mod pi;
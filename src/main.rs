@@ -4,11 +4,15 @@ extern crate error_chain;
 use emu::dbg;
 use emu::hw;
 use emu::log;
+use r64emu::cartridge::romswap;
+use r64emu::disasm::{self, Isa};
 use r64emu::errors::*;
 use r64emu::N64;
 
-use std::path::Path;
-
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -18,6 +22,34 @@ struct Cli {
     #[structopt(short = "d", long = "debugger")]
     debugger: bool,
 
+    /// Enable the CPU cycle timing model (slower, but more accurate)
+    #[structopt(short = "t", long = "timing")]
+    timing: bool,
+
+    /// Emulate the CPU instruction and data caches
+    #[structopt(long = "caches")]
+    caches: bool,
+
+    /// Enable the RSP cycle timing model (dual issue and pipeline stalls)
+    #[structopt(long = "rsp-timing")]
+    rsp_timing: bool,
+
+    /// Use the cached interpreter (pre-decoded instruction blocks)
+    #[structopt(short = "c", long = "cached")]
+    cached: bool,
+
+    /// Use the dynamic recompiler (x86-64 only)
+    #[structopt(short = "j", long = "jit")]
+    jit: bool,
+
+    /// Process RSP audio tasks natively, for known microcodes (faster)
+    #[structopt(long = "hle-audio")]
+    hle_audio: bool,
+
+    /// Process RSP graphics tasks natively, for F3D/F3DEX/F3DEX2 (faster)
+    #[structopt(long = "hle-gfx")]
+    hle_gfx: bool,
+
     /// Path to the BIOS file
     #[structopt(
         short = "b",
@@ -25,24 +57,124 @@ struct Cli {
         parse(from_os_str),
         default_value = "bios/pifdata.bin"
     )]
-    bios: std::path::PathBuf,
+    bios: PathBuf,
 
     /// Path to the ROM file
     #[structopt(parse(from_os_str))]
-    rom: std::path::PathBuf,
+    rom: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Disassemble R4300 or RSP code (ROM, RDRAM dump or RSP microcode)
+    #[structopt(name = "disasm")]
+    Disasm(DisasmArgs),
+}
+
+#[derive(StructOpt)]
+struct DisasmArgs {
+    /// Instruction set of the code (r4300 or rsp)
+    #[structopt(short = "i", long = "isa", default_value = "r4300")]
+    isa: Isa,
+
+    /// The file is a ROM image (convert from byteswapped format, if needed)
+    #[structopt(long = "rom")]
+    rom: bool,
+
+    /// Offset of the code within the file
+    #[structopt(
+        short = "o",
+        long = "offset",
+        default_value = "0",
+        parse(try_from_str = "parse_num")
+    )]
+    offset: u32,
+
+    /// Number of bytes to disassemble [default: up to the end of file]
+    #[structopt(short = "n", long = "size", parse(try_from_str = "parse_num"))]
+    size: Option<u32>,
+
+    /// Address the code is loaded at [default: same as offset]
+    #[structopt(short = "a", long = "base", parse(try_from_str = "parse_num"))]
+    base: Option<u32>,
+
+    /// Symbol file (ELF, linker map, or nm output)
+    #[structopt(short = "s", long = "symbols", parse(from_os_str))]
+    symbols: Option<PathBuf>,
+
+    /// Path to the file to disassemble
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+}
+
+fn parse_num(s: &str) -> std::result::Result<u32, std::num::ParseIntError> {
+    if s.starts_with("0x") {
+        u32::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse()
+    }
 }
 
 quick_main!(run);
 
-fn create_n64(romfn: &Path, biosfn: &Path, logger: slog::Logger) -> Result<N64> {
-    let mut n64 = N64::new(logger, romfn, biosfn).unwrap();
+fn create_n64(args: &Cli, logger: slog::Logger) -> Result<N64> {
+    let rom = args.rom.as_ref().unwrap();
+    let mut n64 = N64::new(logger, rom, &args.bios).unwrap();
     n64.setup_cic(true)?;
+    n64.enable_cpu_caches(args.caches);
+    n64.enable_cpu_timing(args.timing);
+    n64.enable_rsp_timing(args.rsp_timing);
+    n64.enable_block_cache(args.cached);
+    n64.enable_jit(args.jit);
+    n64.enable_hle_audio(args.hle_audio);
+    n64.enable_hle_gfx(args.hle_gfx);
     Ok(n64)
 }
 
+fn run_disasm(args: &DisasmArgs) -> Result<()> {
+    let mut data = fs::read(&args.file)?;
+    if args.rom {
+        data = romswap(data);
+    }
+
+    let start = args.offset as usize;
+    let end = match args.size {
+        Some(size) => start + size as usize,
+        None => data.len(),
+    };
+    if start > end || end > data.len() {
+        bail!("region out of file bounds");
+    }
+
+    let symbols = match args.symbols {
+        Some(ref path) => disasm::load_symbols(path)?,
+        None => HashMap::new(),
+    };
+
+    let stdout = io::stdout();
+    disasm::disasm(
+        &mut stdout.lock(),
+        args.isa,
+        &data[start..end],
+        args.base.unwrap_or(args.offset),
+        &symbols,
+    )
+}
+
 fn run() -> Result<()> {
     let args = Cli::from_args();
 
+    if let Some(Command::Disasm(ref dargs)) = args.cmd {
+        return run_disasm(dargs);
+    }
+    let rom = match args.rom {
+        Some(ref rom) => rom.clone(),
+        None => bail!("no ROM file specified"),
+    };
+
     let mut out = hw::Output::new(
         hw::VideoConfig {
             window_title: "R64EMU - Nintendo 64 Emulator".into(),
@@ -59,14 +191,14 @@ fn run() -> Result<()> {
 
     if args.debugger {
         let (logger, logpool) = log::new_pool_logger();
-        let mut n64 = create_n64(&args.rom, &args.bios, logger).unwrap();
-        let mut dbgconfig = args.rom.clone();
+        let mut n64 = create_n64(&args, logger).unwrap();
+        let mut dbgconfig = rom;
         dbgconfig.set_extension("dbg");
         out.run_and_debug(&mut n64, &dbgconfig, logpool);
     } else {
         out.run_threaded(move || {
             let logger = log::new_console_logger();
-            let n64 = create_n64(&args.rom, &args.bios, logger).unwrap();
+            let n64 = create_n64(&args, logger).unwrap();
             Ok(Box::new(n64))
         });
     }
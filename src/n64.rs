@@ -21,7 +21,7 @@ use super::errors::*;
 use super::mi::Mi;
 use super::mips64;
 use super::pi::Pi;
-use super::r4300::R4300;
+use super::r4300::{R4300Config, R4300};
 use super::ri::Ri;
 use super::si::Si;
 use super::sp::{Sp, RSPCPU};
@@ -35,6 +35,7 @@ pub struct N64 {
     logger: slog::Logger,
     sync: Box<sync::Sync<SyncEmu>>,
     initial_state: State,
+    symbols: Option<dbg::SymbolTable>,
 }
 
 // N64 timings
@@ -45,7 +46,7 @@ const X1: i64 = 14_705_000;
 const X2: i64 = 14_318_000;
 
 const RDRAM_CLOCK: i64 = X1 * 17;
-const MAIN_CLOCK: i64 = RDRAM_CLOCK / 4;
+pub(crate) const MAIN_CLOCK: i64 = RDRAM_CLOCK / 4;
 const _PIF_CLOCK: i64 = MAIN_CLOCK / 4;
 const _CARTRIDGE_CLOCK: i64 = _PIF_CLOCK / 8; // 1.953 MHZ
 pub(crate) const VCLK: i64 = X2 * 17 / 5; // 48.6812 MHZ
@@ -66,9 +67,10 @@ impl sync::SyncEmu for SyncEmu {
         match idx {
             0 => Some((R4300::get_mut().deref_mut(), MAIN_CLOCK + MAIN_CLOCK / 2)), // FIXME: uses DIVMOD),
             1 => Some((RSPCPU::get_mut().deref_mut(), MAIN_CLOCK)),
-            2 => Some((Dp::get_mut(), MAIN_CLOCK)),
-            3 => Some((Ai::get_mut(), VCLK)),
-            4 => Some((Pi::get_mut(), MAIN_CLOCK)),
+            2 => Some((Sp::get_mut(), MAIN_CLOCK)),
+            3 => Some((Dp::get_mut(), MAIN_CLOCK)),
+            4 => Some((Ai::get_mut(), VCLK)),
+            5 => Some((Pi::get_mut(), MAIN_CLOCK)),
             _ => None,
         }
     }
@@ -143,13 +145,76 @@ impl N64 {
         R4300::get_mut().map_bus()?;
         RSPCPU::get_mut().map_bus()?;
 
+        let symbols = load_rom_symbols(&logger, romfn);
+
         return Ok(N64 {
             logger,
             sync,
             initial_state: CurrentState().clone(),
+            symbols,
         });
     }
 
+    // Enable or disable the cycle timing model of the main CPU.
+    pub fn enable_cpu_timing(&mut self, enable: bool) {
+        R4300::get_mut().enable_timing(enable);
+    }
+
+    // Enable the instruction and data cache model of the main CPU. This
+    // allocates new state fields, so it must be called right after creation,
+    // and the initial state (restored on hard reset) is captured again.
+    pub fn enable_cpu_caches(&mut self, enable: bool) {
+        if enable {
+            R4300::get_mut().enable_caches(
+                mips64::CacheConfig::R4300_ICACHE,
+                mips64::CacheConfig::R4300_DCACHE,
+            );
+            self.initial_state = CurrentState().clone();
+        }
+    }
+
+    // Enable or disable the cycle timing model of the RSP.
+    pub fn enable_rsp_timing(&mut self, enable: bool) {
+        RSPCPU::get_mut().enable_timing(enable);
+    }
+
+    // Enable or disable the cached interpreter on both CPUs.
+    pub fn enable_block_cache(&mut self, enable: bool) {
+        R4300::get_mut().enable_block_cache(enable);
+        RSPCPU::get_mut().enable_block_cache(enable);
+        if enable {
+            Self::watch_rsp_imem();
+        }
+    }
+
+    // Enable or disable the dynamic recompiler on both CPUs.
+    pub fn enable_jit(&mut self, enable: bool) {
+        R4300::get_mut().enable_jit(enable);
+        RSPCPU::get_mut().enable_jit(enable);
+        if enable {
+            Self::watch_rsp_imem();
+        }
+    }
+
+    // IMEM is written through the main bus (by the CPU, or by SP DMA), so
+    // report those writes to the RSP, that must discard its stale blocks.
+    fn watch_rsp_imem() {
+        let watch = RSPCPU::get().code_watch().clone();
+        R4300::get_mut()
+            .bus
+            .watch_writes(0x0400_1000, 0x0400_1FFF, 0x1000, &watch);
+    }
+
+    // Enable or disable HLE of RSP audio tasks (for known microcodes).
+    pub fn enable_hle_audio(&mut self, enable: bool) {
+        Sp::get_mut().enable_hle_audio(enable);
+    }
+
+    // Enable or disable HLE of RSP graphics tasks (for known microcodes).
+    pub fn enable_hle_gfx(&mut self, enable: bool) {
+        Sp::get_mut().enable_hle_gfx(enable);
+    }
+
     // Setup the CIC (copy protection) emulation.
     pub fn setup_cic(&mut self, hard_reset: bool) -> Result<()> {
         // The 32-bit word at offset 0x24 in PIF RAM (bus addr: 0x1FC0_07E4)
@@ -192,6 +257,28 @@ impl N64 {
     }
 }
 
+// Load the symbols of the main CPU code, if a symbol file is found next
+// to the ROM (eg: "rom.elf", "rom.map" or "rom.sym" for "rom.z64").
+fn load_rom_symbols(logger: &slog::Logger, romfn: &Path) -> Option<dbg::SymbolTable> {
+    for ext in &["elf", "map", "sym"] {
+        let path = romfn.with_extension(ext);
+        if !path.exists() {
+            continue;
+        }
+        let pc_mask = |pc: u64| <R4300Config as mips64::Config>::pc_mask(pc as u32) as u64;
+        match dbg::SymbolTable::load(&path, pc_mask) {
+            Ok(syms) => {
+                info!(logger, "loaded symbols"; "file" => path.display().to_string(), "count" => syms.len());
+                return Some(syms);
+            }
+            Err(err) => {
+                warn!(logger, "cannot load symbols"; "file" => path.display().to_string(), "err" => err.to_string());
+            }
+        }
+    }
+    None
+}
+
 impl hw::OutputProducer for N64 {
     type AudioSampleFormat = S16_STEREO;
 
@@ -269,6 +356,13 @@ impl DebuggerModel for N64 {
         vec![MAINCPU_NAME.into(), RSPCPU_NAME.into()]
     }
 
+    fn symbols(&self, cpu_name: &str) -> Option<dbg::SymbolTable> {
+        match cpu_name {
+            MAINCPU_NAME => self.symbols.clone(),
+            _ => None,
+        }
+    }
+
     fn cycles(&self) -> i64 {
         self.sync.cycles()
     }
@@ -284,10 +378,12 @@ impl DebuggerModel for N64 {
             self.setup_cic(true).unwrap();
             self.sync.reset();
         } else {
-            // Soft reset: just trigger a reset on CPUs and hope for the best
-            R4300::get_mut().reset();
+            // Soft reset: go through the same sequence as the reset button.
+            // The CPU only gets the pre-NMI interrupt now, and is reset by
+            // the NMI about 0.5s (emulated) later (see Pi::press_reset).
+            // The RSP is reset right away.
             RSPCPU::get_mut().reset();
-            self.setup_cic(false).unwrap();
+            Pi::get_mut().press_reset();
         }
     }
 }
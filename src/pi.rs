@@ -1,6 +1,6 @@
 use super::mi::{IrqMask, Mi};
 use super::r4300::R4300;
-use super::n64::JOY_NAMES;
+use super::n64::{JOY_NAMES, MAIN_CLOCK};
 use super::si::Si;
 use crate::errors::*;
 use bitfield::Bit;
@@ -12,12 +12,17 @@ use emu::int::Numerics;
 use emu::state::Field;
 use emu::sync;
 use emu_derive::DeviceBE;
+use mips64::Cop0;
 use std::fs::File;
 use std::io::Read;
 use std::ops::Range;
 use std::path::Path;
 use std::result;
 
+/// Delay (in main clock cycles) between the pre-NMI interrupt and the NMI,
+/// when the reset button is pressed (about 0.5s).
+pub const NMI_DELAY: i64 = MAIN_CLOCK / 2;
+
 #[derive(DeviceBE)]
 pub struct Pi {
     #[mem(bank = 1, offset = 0x0, vsize = 0x7C0)]
@@ -82,6 +87,8 @@ pub struct Pi {
 
     logger: slog::Logger,
     cycles: Field<i64>,
+    reset_pressed: Field<bool>,
+    nmi_cycles: Field<Option<i64>>,
     pub(crate) input: InputManager,
 }
 
@@ -95,6 +102,8 @@ impl Pi {
             rom: Mem::from_buffer("pif_rom", contents, MemFlags::READACCESS),
             ram: Mem::default(),
             cycles: Field::new("Pi::cycles", 0),
+            reset_pressed: Field::new("Pi::reset_pressed", false),
+            nmi_cycles: Field::new("Pi::nmi_cycles", None),
             input: input,
             dma_ram_addr: Reg32::default(),
             dma_rom_addr: Reg32::default(),
@@ -163,6 +172,31 @@ impl Pi {
         unimplemented!();
     }
 
+    /// Start the reset sequence, as if the reset button was pressed. PIF
+    /// raises the pre-NMI interrupt (IP4) on the CPU, to give the OS a
+    /// chance to prepare for the reset, and then triggers the NMI.
+    pub fn press_reset(&mut self) {
+        if self.nmi_cycles.is_some() {
+            return; // reset already in progress
+        }
+        info!(self.logger, "reset button pressed, raising pre-NMI");
+        R4300::get_mut().cop0.set_hwint_line(2, true);
+        *self.nmi_cycles = Some(*self.cycles + NMI_DELAY);
+    }
+
+    fn nmi(&mut self) {
+        info!(self.logger, "raising NMI");
+        *self.nmi_cycles = None;
+
+        // Set osResetType=NMI in the CIC word (see N64::setup_cic), so that
+        // IPL3 knows that this is not a cold boot.
+        self.ram[0x25] |= 0x02;
+
+        let cpu = R4300::get_mut();
+        cpu.cop0.set_hwint_line(2, false);
+        cpu.nmi();
+    }
+
     pub fn begin_frame(&mut self) {
         self.input.begin_frame();
     }
@@ -263,6 +297,24 @@ impl sync::Subsystem for Pi {
         // we can when we are called.
         *self.cycles = target_cycles;
 
+        // Reset button (console/reset): start the reset sequence on press,
+        // and raise the NMI once the delay is expired.
+        let pressed = self
+            .input
+            .device("console")
+            .and_then(|dev| dev.input("reset"))
+            .and_then(|input| input.digital())
+            .unwrap_or(false);
+        if pressed && !*self.reset_pressed {
+            self.press_reset();
+        }
+        *self.reset_pressed = pressed;
+        if let Some(nmi_cycles) = *self.nmi_cycles {
+            if target_cycles >= nmi_cycles {
+                self.nmi();
+            }
+        }
+
         let status = self.ram[0x3F];
         if status & 0x20 != 0 {
             info!(self.logger, "unlock boot");
@@ -22,6 +22,67 @@ impl mips64::Config for R4300Config {
     type Cop1 = mips64::Fpu;
     type Cop2 = mips64::CopNull;
     type Cop3 = mips64::CopNull;
+    type Timing = R4300Timing;
+}
+
+/// Approximate cycle timings of the VR4300, based on the user manual.
+/// Uncached access penalties are rough averages, as the real cost depends
+/// on the state of the target bus.
+pub struct R4300Timing;
+
+impl mips64::Timing for R4300Timing {
+    fn mul_latency(dword: bool) -> i64 {
+        if dword {
+            7
+        } else {
+            4
+        }
+    }
+
+    fn div_latency(dword: bool) -> i64 {
+        if dword {
+            68
+        } else {
+            36
+        }
+    }
+
+    fn load_use_stall() -> i64 {
+        1
+    }
+
+    fn branch_likely_penalty() -> i64 {
+        1
+    }
+
+    fn fpu_latency(func: u32, fmt: u32) -> i64 {
+        let dbl = fmt == 0x11;
+        match (func, dbl) {
+            (0x00, _) | (0x01, _) => 2,          // ADD, SUB
+            (0x02, false) => 4,                  // MUL.S
+            (0x02, true) => 7,                   // MUL.D
+            (0x03, false) | (0x04, false) => 28, // DIV.S, SQRT.S
+            (0x03, true) | (0x04, true) => 57,   // DIV.D, SQRT.D
+            (0x08..=0x0F, _) => 4,               // ROUND, TRUNC, CEIL, FLOOR
+            (0x20..=0x25, _) => 4,               // CVT
+            _ => 0,                              // ABS, MOV, NEG, C.cond
+        }
+    }
+
+    fn uncached_penalty(paddr: u32, acc: mips64::MemAccess) -> i64 {
+        let write = acc == mips64::MemAccess::Write;
+        match (paddr, write) {
+            // RDRAM
+            (0x0000_0000..=0x03EF_FFFF, false) => 30,
+            (0x0000_0000..=0x03EF_FFFF, true) => 10,
+            // PI: cartridge domains and PIF
+            (0x0500_0000..=0x1FCF_FFFF, false) => 100,
+            (0x0500_0000..=0x1FCF_FFFF, true) => 40,
+            // Other hardware registers
+            (_, false) => 20,
+            (_, true) => 10,
+        }
+    }
 }
 
 #[derive(DeviceBE)]
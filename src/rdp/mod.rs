@@ -3,7 +3,6 @@ extern crate emu;
 use byteorder::{ByteOrder, LittleEndian};
 use emu::gfx::{Color, ColorConverter, ColorFormat, Rgba8888};
 use packed_simd::*;
-use std::arch::x86_64::*;
 
 type MultiColor = u16x8;
 
@@ -29,17 +28,14 @@ impl MColor for MultiColor {
     }
 
     fn get_color<CF: ColorFormat>(&self, idx: usize) -> Color<CF> {
-        // Rust does not expose a _mm_pack* functions through the uAAxBB SIMD
-        // structs, so there is no way to convert from u16x8 to u8x16 without
-        // using scalar code. The following code is able to keep it fully
-        // vectorized, and generate a final "MOVD XMM" instruction to
-        // extract the required color index.
-        let c = unsafe {
-            let c = __m128i::from_bits(*self);
-            let c = _mm_packus_epi16(c, _mm_setzero_si128());
-            u8x16::from_bits(c)
-        };
-        let mut cbuf: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        // Saturate each component to 0..255 (treating it as signed, like
+        // PACKUSWB does) and narrow it to 8 bits. This is portable, and still
+        // generates vector instructions on hosts that have them.
+        let c = i16x8::from_bits(*self)
+            .max(i16x8::splat(0))
+            .min(i16x8::splat(0xFF));
+        let c = u8x8::from_cast(c);
+        let mut cbuf = [0u8; 8];
         c.write_to_slice_unaligned(&mut cbuf);
         match idx {
             0 => Color::<Rgba8888>::from_bits(LittleEndian::read_u32(&cbuf[0..4])).cconv(),
@@ -56,8 +56,11 @@ pub struct Rdp {
 
     pipeline: PixelPipeline,
 
-    cmdbuf: [u64; 16],
+    cmdbuf: [u64; 22],
     cmdlen: usize,
+
+    // Triangles are not rasterized yet: warn only about the first one.
+    tri_warned: bool,
 }
 
 impl Rdp {
@@ -74,8 +77,9 @@ impl Rdp {
             fill_color: 0,
             cycle_mode: CycleMode::One,
             pipeline: PixelPipeline::new(),
-            cmdbuf: [0u64; 16],
+            cmdbuf: [0u64; 22],
             cmdlen: 0,
+            tri_warned: false,
         }
     }
 
@@ -104,6 +108,23 @@ impl Rdp {
 
         let op = self.cmdbuf[0].get_bits(56..62);
         match op {
+            0x08..=0x0F => {
+                // Triangle: edge coefficients, followed by the optional shade,
+                // texture and z-buffer coefficients.
+                let len = 4
+                    + op.get_bit(2) as usize * 8
+                    + op.get_bit(1) as usize * 8
+                    + op.get_bit(0) as usize * 2;
+                if self.cmdlen != len {
+                    return;
+                }
+                if !self.tri_warned {
+                    warn!(self.logger, "DP: triangles not implemented"; "cmd" => op.hex());
+                    self.tri_warned = true;
+                }
+                debug!(self.logger, "DP: triangle"; "cmd" => op.hex());
+                self.cmdlen = 0;
+            }
             0x2D => {
                 // Set Scissor
                 self.clip = Rect::from_bits(
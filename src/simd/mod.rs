@@ -0,0 +1,190 @@
+//! 128-bit integer SIMD operations, as used by the RSP vector unit.
+//!
+//! The vector unit is written against the SSE intrinsics. On x86-64 hosts
+//! they are re-exported from `std::arch`; on all other hosts (or when the
+//! `scalar-simd` feature is enabled), a scalar implementation with the same
+//! names and semantics is used instead.
+//!
+//! Functions that use the intrinsics must request the SSE target features
+//! only on x86-64 (`cfg_attr(target_arch = "x86_64", target_feature(...))`).
+
+#[cfg_attr(
+    all(target_arch = "x86_64", not(feature = "scalar-simd")),
+    allow(dead_code)
+)]
+mod scalar;
+
+#[cfg(all(target_arch = "x86_64", not(feature = "scalar-simd")))]
+pub(crate) use std::arch::x86_64::{
+    __m128i, _mm_add_epi16, _mm_add_epi32, _mm_adds_epi16, _mm_adds_epu16, _mm_and_si128,
+    _mm_andnot_si128, _mm_blendv_epi8, _mm_cmpeq_epi16, _mm_cmpeq_epi32, _mm_cmpgt_epi16,
+    _mm_cmpgt_epi32, _mm_loadu_si128, _mm_max_epi16, _mm_min_epi16, _mm_mulhi_epi16,
+    _mm_mullo_epi16, _mm_or_si128, _mm_packs_epi32, _mm_packus_epi32, _mm_set1_epi16,
+    _mm_set1_epi32, _mm_setzero_si128, _mm_shufflehi_epi16, _mm_shufflelo_epi16, _mm_sign_epi16,
+    _mm_slli_epi32, _mm_srai_epi16, _mm_srai_epi32, _mm_srli_epi32, _mm_store_si128, _mm_sub_epi16,
+    _mm_sub_epi32, _mm_subs_epi16, _mm_subs_epu16, _mm_unpackhi_epi16, _mm_unpacklo_epi16,
+    _mm_xor_si128,
+};
+
+#[cfg(not(all(target_arch = "x86_64", not(feature = "scalar-simd"))))]
+pub(crate) use self::scalar::*;
+
+// Differential tests: the scalar implementation must match SSE bit by bit.
+#[cfg(all(test, target_arch = "x86_64"))]
+mod test {
+    use super::scalar;
+    use std::arch::x86_64 as sse;
+
+    const ITERATIONS: usize = 2000;
+
+    // Lane values that are most likely to expose saturation, sign and
+    // carry bugs.
+    const EDGES: [u16; 8] = [
+        0x0000, 0x0001, 0x00FF, 0x0100, 0x7FFF, 0x8000, 0x8001, 0xFFFF,
+    ];
+
+    struct Rng(u64);
+
+    impl Rng {
+        // xorshift64*
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        fn vector(&mut self) -> scalar::__m128i {
+            let mut v = scalar::__m128i([0; 16]);
+            for lane in v.0.chunks_mut(2) {
+                let r = self.next();
+                let val = if r & 3 == 0 {
+                    EDGES[(r >> 2) as usize & 7]
+                } else {
+                    (r >> 16) as u16
+                };
+                lane[0] = val as u8;
+                lane[1] = (val >> 8) as u8;
+            }
+            v
+        }
+    }
+
+    fn to_sse(v: scalar::__m128i) -> sse::__m128i {
+        unsafe { sse::_mm_loadu_si128(v.0.as_ptr() as *const _) }
+    }
+
+    fn from_sse(v: sse::__m128i) -> scalar::__m128i {
+        let mut r = scalar::__m128i([0; 16]);
+        unsafe { sse::_mm_storeu_si128(r.0.as_mut_ptr() as *mut _, v) };
+        r
+    }
+
+    macro_rules! check {
+        ($rng:expr, $name:ident($($arg:ident),*) $(, $imm:expr)*) => {
+            for _ in 0..ITERATIONS {
+                $(let $arg = $rng.vector();)*
+                let expected = from_sse(unsafe { sse::$name($(to_sse($arg)),* $(, $imm)*) });
+                let got = unsafe { scalar::$name($($arg),* $(, $imm)*) };
+                assert_eq!(got, expected, "{}{:?}", stringify!($name), ($($arg,)*));
+            }
+        };
+    }
+
+    #[test]
+    fn bitwise() {
+        let mut rng = Rng(0x1234_5678_9ABC_DEF0);
+        check!(rng, _mm_and_si128(a, b));
+        check!(rng, _mm_andnot_si128(a, b));
+        check!(rng, _mm_or_si128(a, b));
+        check!(rng, _mm_xor_si128(a, b));
+        check!(rng, _mm_blendv_epi8(a, b, m));
+    }
+
+    #[test]
+    fn arith() {
+        let mut rng = Rng(0x0F1E_2D3C_4B5A_6978);
+        check!(rng, _mm_add_epi16(a, b));
+        check!(rng, _mm_sub_epi16(a, b));
+        check!(rng, _mm_add_epi32(a, b));
+        check!(rng, _mm_sub_epi32(a, b));
+        check!(rng, _mm_adds_epi16(a, b));
+        check!(rng, _mm_subs_epi16(a, b));
+        check!(rng, _mm_adds_epu16(a, b));
+        check!(rng, _mm_subs_epu16(a, b));
+        check!(rng, _mm_mullo_epi16(a, b));
+        check!(rng, _mm_mulhi_epi16(a, b));
+        check!(rng, _mm_min_epi16(a, b));
+        check!(rng, _mm_max_epi16(a, b));
+        check!(rng, _mm_sign_epi16(a, b));
+    }
+
+    #[test]
+    fn compare() {
+        let mut rng = Rng(0xDEAD_BEEF_CAFE_BABE);
+        check!(rng, _mm_cmpeq_epi16(a, b));
+        check!(rng, _mm_cmpgt_epi16(a, b));
+        check!(rng, _mm_cmpeq_epi32(a, b));
+        check!(rng, _mm_cmpgt_epi32(a, b));
+    }
+
+    #[test]
+    fn shift() {
+        let mut rng = Rng(0x0123_4567_89AB_CDEF);
+        check!(rng, _mm_srai_epi16(a), 0);
+        check!(rng, _mm_srai_epi16(a), 1);
+        check!(rng, _mm_srai_epi16(a), 15);
+        check!(rng, _mm_srai_epi16(a), 16);
+        check!(rng, _mm_srai_epi32(a), 16);
+        check!(rng, _mm_srai_epi32(a), 31);
+        check!(rng, _mm_srai_epi32(a), 40);
+        check!(rng, _mm_srli_epi32(a), 1);
+        check!(rng, _mm_srli_epi32(a), 16);
+        check!(rng, _mm_srli_epi32(a), 32);
+        check!(rng, _mm_slli_epi32(a), 1);
+        check!(rng, _mm_slli_epi32(a), 16);
+        check!(rng, _mm_slli_epi32(a), 32);
+    }
+
+    #[test]
+    fn shuffle() {
+        let mut rng = Rng(0x5555_AAAA_3333_CCCC);
+        check!(rng, _mm_shufflelo_epi16(a), 0b11_11_01_01);
+        check!(rng, _mm_shufflelo_epi16(a), 0b00_01_10_11);
+        check!(rng, _mm_shufflehi_epi16(a), 0b10_10_00_00);
+        check!(rng, _mm_shufflehi_epi16(a), 0b00_01_10_11);
+        check!(rng, _mm_unpacklo_epi16(a, b));
+        check!(rng, _mm_unpackhi_epi16(a, b));
+    }
+
+    #[test]
+    fn pack() {
+        let mut rng = Rng(0x7777_1111_9999_3333);
+        check!(rng, _mm_packs_epi32(a, b));
+        check!(rng, _mm_packus_epi32(a, b));
+    }
+
+    #[test]
+    fn set() {
+        let mut rng = Rng(0x2468_ACE0_1357_9BDF);
+        for _ in 0..ITERATIONS {
+            let v = rng.next();
+            unsafe {
+                assert_eq!(
+                    scalar::_mm_set1_epi16(v as i16),
+                    from_sse(sse::_mm_set1_epi16(v as i16))
+                );
+                assert_eq!(
+                    scalar::_mm_set1_epi32(v as i32),
+                    from_sse(sse::_mm_set1_epi32(v as i32))
+                );
+            }
+        }
+        unsafe {
+            assert_eq!(
+                scalar::_mm_setzero_si128(),
+                from_sse(sse::_mm_setzero_si128())
+            );
+        }
+    }
+}
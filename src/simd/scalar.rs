@@ -0,0 +1,249 @@
+// Scalar implementation of the SSE intrinsics used by the emulator.
+//
+// Each function has the same name, signature and semantics of its x86-64
+// counterpart, so that code written against the intrinsics can be compiled
+// on any host. This is also used as a reference implementation, to verify
+// the SSE code paths.
+#![allow(non_camel_case_types)]
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::ptr;
+
+/// A 128-bit integer vector. Like a SSE register, lanes are stored in
+/// little-endian order, so that loads/stores from memory have the same
+/// layout on every host.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C, align(16))]
+pub struct __m128i(pub(crate) [u8; 16]);
+
+impl __m128i {
+    fn from_i16(v: [i16; 8]) -> __m128i {
+        let mut r = __m128i([0; 16]);
+        LittleEndian::write_i16_into(&v, &mut r.0);
+        r
+    }
+    fn from_i32(v: [i32; 4]) -> __m128i {
+        let mut r = __m128i([0; 16]);
+        LittleEndian::write_i32_into(&v, &mut r.0);
+        r
+    }
+    fn i16(self) -> [i16; 8] {
+        let mut v = [0i16; 8];
+        LittleEndian::read_i16_into(&self.0, &mut v);
+        v
+    }
+    fn i32(self) -> [i32; 4] {
+        let mut v = [0i32; 4];
+        LittleEndian::read_i32_into(&self.0, &mut v);
+        v
+    }
+}
+
+fn map8(a: __m128i, b: __m128i, f: impl Fn(u8, u8) -> u8) -> __m128i {
+    let mut r = __m128i([0; 16]);
+    for i in 0..16 {
+        r.0[i] = f(a.0[i], b.0[i]);
+    }
+    r
+}
+
+fn map16(a: __m128i, b: __m128i, f: impl Fn(i16, i16) -> i16) -> __m128i {
+    let (a, b) = (a.i16(), b.i16());
+    let mut r = [0i16; 8];
+    for i in 0..8 {
+        r[i] = f(a[i], b[i]);
+    }
+    __m128i::from_i16(r)
+}
+
+fn map32(a: __m128i, b: __m128i, f: impl Fn(i32, i32) -> i32) -> __m128i {
+    let (a, b) = (a.i32(), b.i32());
+    let mut r = [0i32; 4];
+    for i in 0..4 {
+        r[i] = f(a[i], b[i]);
+    }
+    __m128i::from_i32(r)
+}
+
+fn mask<T: From<i8>>(cond: bool) -> T {
+    T::from(-(cond as i8))
+}
+
+fn clamp(v: i32, min: i32, max: i32) -> i32 {
+    v.max(min).min(max)
+}
+
+// Loads and stores
+
+pub unsafe fn _mm_loadu_si128(mem_addr: *const __m128i) -> __m128i {
+    ptr::read_unaligned(mem_addr)
+}
+pub unsafe fn _mm_store_si128(mem_addr: *mut __m128i, a: __m128i) {
+    ptr::write(mem_addr, a)
+}
+
+pub unsafe fn _mm_setzero_si128() -> __m128i {
+    __m128i([0; 16])
+}
+pub unsafe fn _mm_set1_epi16(a: i16) -> __m128i {
+    __m128i::from_i16([a; 8])
+}
+pub unsafe fn _mm_set1_epi32(a: i32) -> __m128i {
+    __m128i::from_i32([a; 4])
+}
+
+// Bitwise operations
+
+pub unsafe fn _mm_and_si128(a: __m128i, b: __m128i) -> __m128i {
+    map8(a, b, |a, b| a & b)
+}
+pub unsafe fn _mm_andnot_si128(a: __m128i, b: __m128i) -> __m128i {
+    map8(a, b, |a, b| !a & b)
+}
+pub unsafe fn _mm_or_si128(a: __m128i, b: __m128i) -> __m128i {
+    map8(a, b, |a, b| a | b)
+}
+pub unsafe fn _mm_xor_si128(a: __m128i, b: __m128i) -> __m128i {
+    map8(a, b, |a, b| a ^ b)
+}
+pub unsafe fn _mm_blendv_epi8(a: __m128i, b: __m128i, mask: __m128i) -> __m128i {
+    let mut r = a;
+    for i in 0..16 {
+        if mask.0[i] & 0x80 != 0 {
+            r.0[i] = b.0[i];
+        }
+    }
+    r
+}
+
+// Arithmetic
+
+pub unsafe fn _mm_add_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, i16::wrapping_add)
+}
+pub unsafe fn _mm_sub_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, i16::wrapping_sub)
+}
+pub unsafe fn _mm_add_epi32(a: __m128i, b: __m128i) -> __m128i {
+    map32(a, b, i32::wrapping_add)
+}
+pub unsafe fn _mm_sub_epi32(a: __m128i, b: __m128i) -> __m128i {
+    map32(a, b, i32::wrapping_sub)
+}
+pub unsafe fn _mm_adds_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, i16::saturating_add)
+}
+pub unsafe fn _mm_subs_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, i16::saturating_sub)
+}
+pub unsafe fn _mm_adds_epu16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, |a, b| (a as u16).saturating_add(b as u16) as i16)
+}
+pub unsafe fn _mm_subs_epu16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, |a, b| (a as u16).saturating_sub(b as u16) as i16)
+}
+pub unsafe fn _mm_mullo_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, i16::wrapping_mul)
+}
+pub unsafe fn _mm_mulhi_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, |a, b| ((a as i32 * b as i32) >> 16) as i16)
+}
+pub unsafe fn _mm_min_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, |a, b| a.min(b))
+}
+pub unsafe fn _mm_max_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, |a, b| a.max(b))
+}
+pub unsafe fn _mm_sign_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, |a, b| {
+        if b < 0 {
+            a.wrapping_neg()
+        } else if b == 0 {
+            0
+        } else {
+            a
+        }
+    })
+}
+
+// Comparisons
+
+pub unsafe fn _mm_cmpeq_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, |a, b| mask(a == b))
+}
+pub unsafe fn _mm_cmpgt_epi16(a: __m128i, b: __m128i) -> __m128i {
+    map16(a, b, |a, b| mask(a > b))
+}
+pub unsafe fn _mm_cmpeq_epi32(a: __m128i, b: __m128i) -> __m128i {
+    map32(a, b, |a, b| mask(a == b))
+}
+pub unsafe fn _mm_cmpgt_epi32(a: __m128i, b: __m128i) -> __m128i {
+    map32(a, b, |a, b| mask(a > b))
+}
+
+// Shifts (counts out of range fill with zeros, or with the sign bit)
+
+pub unsafe fn _mm_srai_epi16(a: __m128i, imm8: i32) -> __m128i {
+    let n = (imm8 as u32 & 0xFF).min(15);
+    map16(a, a, |a, _| a >> n)
+}
+pub unsafe fn _mm_srai_epi32(a: __m128i, imm8: i32) -> __m128i {
+    let n = (imm8 as u32 & 0xFF).min(31);
+    map32(a, a, |a, _| a >> n)
+}
+pub unsafe fn _mm_srli_epi32(a: __m128i, imm8: i32) -> __m128i {
+    let n = imm8 as u32 & 0xFF;
+    map32(a, a, |a, _| (a as u32).checked_shr(n).unwrap_or(0) as i32)
+}
+pub unsafe fn _mm_slli_epi32(a: __m128i, imm8: i32) -> __m128i {
+    let n = imm8 as u32 & 0xFF;
+    map32(a, a, |a, _| (a as u32).checked_shl(n).unwrap_or(0) as i32)
+}
+
+// Shuffles
+
+pub unsafe fn _mm_shufflelo_epi16(a: __m128i, imm8: i32) -> __m128i {
+    let v = a.i16();
+    let mut r = v;
+    for i in 0..4 {
+        r[i] = v[(imm8 as usize >> (i * 2)) & 3];
+    }
+    __m128i::from_i16(r)
+}
+pub unsafe fn _mm_shufflehi_epi16(a: __m128i, imm8: i32) -> __m128i {
+    let v = a.i16();
+    let mut r = v;
+    for i in 0..4 {
+        r[4 + i] = v[4 + ((imm8 as usize >> (i * 2)) & 3)];
+    }
+    __m128i::from_i16(r)
+}
+pub unsafe fn _mm_unpacklo_epi16(a: __m128i, b: __m128i) -> __m128i {
+    let (a, b) = (a.i16(), b.i16());
+    __m128i::from_i16([a[0], b[0], a[1], b[1], a[2], b[2], a[3], b[3]])
+}
+pub unsafe fn _mm_unpackhi_epi16(a: __m128i, b: __m128i) -> __m128i {
+    let (a, b) = (a.i16(), b.i16());
+    __m128i::from_i16([a[4], b[4], a[5], b[5], a[6], b[6], a[7], b[7]])
+}
+
+// Packing with saturation
+
+pub unsafe fn _mm_packs_epi32(a: __m128i, b: __m128i) -> __m128i {
+    let (a, b) = (a.i32(), b.i32());
+    let mut r = [0i16; 8];
+    for i in 0..4 {
+        r[i] = clamp(a[i], -0x8000, 0x7FFF) as i16;
+        r[i + 4] = clamp(b[i], -0x8000, 0x7FFF) as i16;
+    }
+    __m128i::from_i16(r)
+}
+pub unsafe fn _mm_packus_epi32(a: __m128i, b: __m128i) -> __m128i {
+    let (a, b) = (a.i32(), b.i32());
+    let mut r = [0i16; 8];
+    for i in 0..4 {
+        r[i] = clamp(a[i], 0, 0xFFFF) as u16 as i16;
+        r[i + 4] = clamp(b[i], 0, 0xFFFF) as u16 as i16;
+    }
+    __m128i::from_i16(r)
+}
@@ -1,7 +1,7 @@
-use std::arch::x86_64::*;
+use crate::simd::*;
 
 #[inline]
-#[target_feature(enable = "sse4.1")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
 pub(crate) unsafe fn acc_add(
     acc1_lo: __m128i,
     acc1_md: __m128i,
@@ -43,7 +43,7 @@ pub(crate) unsafe fn acc_add(
 }
 
 #[inline]
-#[target_feature(enable = "sse4.1")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
 pub(crate) unsafe fn acc_clamp_signed(acc_md: __m128i, acc_hi: __m128i) -> __m128i {
     _mm_packs_epi32(
         _mm_unpacklo_epi16(acc_md, acc_hi),
@@ -52,7 +52,7 @@ pub(crate) unsafe fn acc_clamp_signed(acc_md: __m128i, acc_hi: __m128i) -> __m12
 }
 
 #[inline]
-#[target_feature(enable = "sse4.1")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
 pub(crate) unsafe fn acc_clamp_unsigned3(
     mut x: __m128i,
     acc_md: __m128i,
@@ -77,7 +77,7 @@ pub(crate) unsafe fn acc_clamp_unsigned3(
 }
 
 #[inline]
-#[target_feature(enable = "sse4.1")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
 pub(crate) unsafe fn acc_clamp_unsigned2(mut x: __m128i, acc_hi: __m128i) -> __m128i {
     // Same as acc_clamp_unsigned2, but with X==ACCUM_MD.
     // This allows us to skip a few operations.
@@ -124,7 +124,11 @@ impl mips64::Cop0 for SpCop0 {
                     None => {}
                 }
             }
-            _ => unimplemented!(),
+            // RSP has no exception handling: other exceptions (eg: reserved
+            // instructions) are simply ignored.
+            _ => {
+                warn!(self._logger, "unhandled RSP exception"; "exc" => ?exc);
+            }
         }
     }
 }
@@ -7,6 +7,7 @@ use super::vmul;
 use super::vrcp;
 
 use crate::errors::*;
+use crate::simd::*;
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use emu::bus::be::{Bus, Device};
 use emu::dbg;
@@ -16,7 +17,6 @@ use emu::state::Field;
 use mips64::{Cop, CpuContext};
 use serde_derive::{Deserialize, Serialize};
 use slog;
-use std::arch::x86_64::*;
 
 // Vector registers as array of u8.
 // Kept as little endian so that it's easier to directly load into SSE registers
@@ -259,6 +259,24 @@ impl<'a> Vectorop<'a> {
     }
 }
 
+macro_rules! op_vrnd {
+    ($op:expr, $name:ident) => {{
+        // The lowest bit of the VS register index selects whether VT
+        // is shifted into the middle part of the accumulator.
+        let (res, acc_lo, acc_md, acc_hi) = vmul::$name(
+            $op.vte(),
+            $op.rs() & 1 != 0,
+            $op.accum(0),
+            $op.accum(1),
+            $op.accum(2),
+        );
+        $op.setvd(res);
+        $op.setaccum(0, acc_lo);
+        $op.setaccum(1, acc_md);
+        $op.setaccum(2, acc_hi);
+    }};
+}
+
 macro_rules! op_vmul {
     ($op:expr, $name:ident) => {{
         let (res, acc_lo, acc_md, acc_hi) = vmul::$name(
@@ -276,7 +294,7 @@ macro_rules! op_vmul {
 }
 
 impl SpCop2 {
-    #[target_feature(enable = "sse2")]
+    #[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
     unsafe fn uop(&mut self, cpu: &mut CpuContext, op: u32, t: &dbg::Tracer) -> dbg::Result<()> {
         let mut op = Vectorop {
             op,
@@ -291,12 +309,16 @@ impl SpCop2 {
             match op.func() {
                 0x00 => op_vmul!(op, vmulf), // VMULF
                 0x01 => op_vmul!(op, vmulu), // VMULU
+                0x02 => op_vrnd!(op, vrndp), // VRNDP
+                0x03 => op_vmul!(op, vmulq), // VMULQ
                 0x04 => op_vmul!(op, vmudl), // VMUDL
                 0x05 => op_vmul!(op, vmudm), // VMUDM
                 0x06 => op_vmul!(op, vmudn), // VMUDN
                 0x07 => op_vmul!(op, vmudh), // VMUDH
                 0x08 => op_vmul!(op, vmacf), // VMACF
                 0x09 => op_vmul!(op, vmacu), // VMACU
+                0x0A => op_vrnd!(op, vrndn), // VRNDN
+                0x0B => op_vmul!(op, vmacq), // VMACQ
                 0x0C => op_vmul!(op, vmadl), // VMADL
                 0x0D => op_vmul!(op, vmadm), // VMADM
                 0x0E => op_vmul!(op, vmadn), // VMADN
@@ -347,7 +369,16 @@ impl SpCop2 {
                     let vt = op.vte();
                     let res = _mm_sign_epi16(vt, vs);
                     op.setaccum(0, res);
-                    op.setvd(res);
+
+                    // Negating -0x8000 wraps around in the accumulator, but
+                    // is saturated to 0x7FFF in VD.
+                    #[allow(overflowing_literals)]
+                    let min = _mm_set1_epi16(0x8000);
+                    let sign = _mm_srai_epi16(vs, 15);
+                    op.setvd(_mm_add_epi16(
+                        res,
+                        _mm_and_si128(_mm_cmpeq_epi16(res, min), sign),
+                    ));
                 }
                 0x14 => {
                     // VADDC
@@ -378,16 +409,11 @@ impl SpCop2 {
                     ));
                     op.setne(_mm_xor_si128(_mm_cmpeq_epi16(vs, vt), vones));
                 }
-                0x17 => {
-                    // VSUBB -- undocumented?
-                    let vs = op.vs();
-                    let vt = op.vte();
-                    let res = _mm_add_epi16(vs, vt);
-                    op.setvd(vzero);
-                    op.setaccum(0, res);
-                }
-                0x19 => {
-                    // VSUCB -- undocumented?
+                0x12 | 0x16..=0x1C | 0x1E..=0x1F | 0x2E..=0x2F | 0x38..=0x3E => {
+                    // VSUT, VADDB, VSUBB, VACCB, VSUCB, VSAD, VSAC, VSUM
+                    // and the unnamed opcodes. These are not implemented
+                    // in the silicon: the adder is still run (and ACCUM_LO
+                    // updated), but VD is cleared.
                     let vs = op.vs();
                     let vt = op.vte();
                     let res = _mm_add_epi16(vs, vt);
@@ -398,16 +424,13 @@ impl SpCop2 {
                     // VSAR
                     let e = op.e();
                     match e {
-                        0..=2 => {
-                            op.setvd(vzero);
-                        }
                         8..=10 => {
                             // NOTE: VSAR is not able to write the accumulator,
                             // contrary to what documentation says.
                             let sar = op.accum(2 - (e - 8));
                             op.setvd(sar);
                         }
-                        _ => unimplemented!(),
+                        _ => op.setvd(vzero),
                     }
                 }
                 0x20 => {
@@ -449,8 +472,7 @@ impl SpCop2 {
                         _mm_or_si128(_mm_cmpgt_epi16(vt, vs), _mm_cmpgt_epi16(vs, vt)),
                         _mm_and_si128(op.ne(), _mm_cmpeq_epi16(vs, vt)),
                     );
-                    let res =
-                        _mm_or_si128(_mm_and_si128(vcc, op.vs()), _mm_andnot_si128(vcc, op.vt()));
+                    let res = _mm_or_si128(_mm_and_si128(vcc, vs), _mm_andnot_si128(vcc, vt));
 
                     op.setvccnormal(vcc);
                     op.setvccclip(vzero);
@@ -571,8 +593,8 @@ impl SpCop2 {
                     // VRCP
                     let x = op.vt_lane(op.e() & 7);
                     let res = vrcp::vrcp(x.sx32());
-                    op.setvd_lane(op.rs() & 7, res as u16);
                     op.setaccum(0, op.vt());
+                    op.setvd_lane(op.rs() & 7, res as u16);
                     op.ctx.div_out = res;
                 }
                 0x31 => {
@@ -582,16 +604,16 @@ impl SpCop2 {
                         Some(div_in) => vrcp::vrcp((x as u32) | div_in),
                         None => vrcp::vrcp(x.sx32()),
                     };
-                    op.setvd_lane(op.rs() & 7, res as u16);
                     op.setaccum(0, op.vt());
+                    op.setvd_lane(op.rs() & 7, res as u16);
                     op.ctx.div_out = res;
                     op.ctx.div_in = None;
                 }
                 0x32 => {
                     // VRCPH
                     let x = op.vt_lane(op.e() & 7);
-                    op.setvd_lane(op.rs() & 7, (op.ctx.div_out >> 16) as u16);
                     op.setaccum(0, op.vt());
+                    op.setvd_lane(op.rs() & 7, (op.ctx.div_out >> 16) as u16);
                     op.ctx.div_in = Some((x as u32) << 16);
                 }
                 0x33 => {
@@ -605,16 +627,15 @@ impl SpCop2 {
                     };
 
                     let res = op.vt_lane(se);
+                    op.setaccum(0, op.vte());
                     op.setvd_lane(op.rs() & 7, res);
-                    // FIXME: update ACCUM with VMOV?
-                    op.setaccum(0, op.vt());
                 }
                 0x34 => {
                     // VRSQ
                     let x = op.vt_lane(op.e() & 7);
                     let res = vrcp::vrsq(x.sx32());
-                    op.setvd_lane(op.rs() & 7, res as u16);
                     op.setaccum(0, op.vt());
+                    op.setvd_lane(op.rs() & 7, res as u16);
                     op.ctx.div_out = res;
                 }
                 0x35 => {
@@ -624,22 +645,22 @@ impl SpCop2 {
                         Some(div_in) => vrcp::vrsq((x as u32) | div_in),
                         None => vrcp::vrsq(x.sx32()),
                     };
-                    op.setvd_lane(op.rs() & 7, res as u16);
                     op.setaccum(0, op.vt());
+                    op.setvd_lane(op.rs() & 7, res as u16);
                     op.ctx.div_out = res;
                     op.ctx.div_in = None;
                 }
                 0x36 => {
                     // VRSQH
                     let x = op.vt_lane(op.e() & 7);
-                    op.setvd_lane(op.rs() & 7, (op.ctx.div_out >> 16) as u16);
                     op.setaccum(0, op.vt());
+                    op.setvd_lane(op.rs() & 7, (op.ctx.div_out >> 16) as u16);
                     op.ctx.div_in = Some((x as u32) << 16);
                 }
                 0x37 => {} // VNOP
-                0x3f => {} // VNULL
+                0x3F => {} // VNULL
 
-                _ => panic!("unimplemented COP2 VU opcode={}", op.func().hex()),
+                _ => unreachable!(),
             }
         } else {
             match op.e() {
@@ -651,12 +672,12 @@ impl SpCop2 {
                     val |= op.vs_byte((e + 1) & 15) as u16;
                     cpu.regs[op.rt()] = val.sx64();
                 }
-                0x2 => match op.rs() {
-                    // CFC2
+                0x2 => match op.rs() & 3 {
+                    // CFC2. Only the lower 2 bits of the register index are
+                    // decoded, and index 3 is a mirror of VCE.
                     0 => cpu.regs[op.rt()] = op.ctx.vco().sx64(),
                     1 => cpu.regs[op.rt()] = op.ctx.vcc().sx64(),
-                    2 => cpu.regs[op.rt()] = op.ctx.vce() as u64,
-                    _ => panic!("unimplement COP2 CFC2 reg:{}", op.rs()),
+                    _ => cpu.regs[op.rt()] = op.ctx.vce() as u64,
                 },
                 0x4 => {
                     // MTC2
@@ -666,12 +687,11 @@ impl SpCop2 {
                         op.setvs_byte(e + 1, cpu.regs[op.rt()] as u8);
                     }
                 }
-                0x6 => match op.rs() {
-                    // CTC2
+                0x6 => match op.rs() & 3 {
+                    // CTC2 (see CFC2 for register decoding)
                     0 => op.ctx.set_vco(cpu.regs[op.rt()] as u16),
                     1 => op.ctx.set_vcc(cpu.regs[op.rt()] as u16),
-                    2 => op.ctx.set_vce(cpu.regs[op.rt()] as u8),
-                    _ => panic!("unimplement COP2 CTC2 reg:{}", op.rd()),
+                    _ => op.ctx.set_vce(cpu.regs[op.rt()] as u8),
                 },
                 _ => {
                     error!(
@@ -716,16 +736,28 @@ fn write_partial_right<B: ByteOrder>(dst: &mut [u8], src: u128, skip_bits: usize
     B::write_u128(dst, d);
 }
 
+// DMEM accesses wrap around at the end of the 4K. To keep the implementation
+// of the opcodes linear, DMEM is allocated with 16 bytes of excess memory:
+// before an access that crosses the end, the beginning of DMEM is mirrored
+// there; after a store, the excess memory is copied back to the beginning.
+fn dmem_wrap_begin(dmem: &mut [u8], ea: usize, size: usize) {
+    if ea + size > 0x1000 {
+        let (mem, excess) = dmem.split_at_mut(0x1000);
+        excess[..16].copy_from_slice(&mem[..16]);
+    }
+}
+
+fn dmem_wrap_end(dmem: &mut [u8], ea: usize, size: usize) {
+    if ea + size > 0x1000 {
+        let (mem, excess) = dmem.split_at_mut(0x1000);
+        mem[..16].copy_from_slice(&excess[..16]);
+    }
+}
+
 // Plain "load vector subword from memory"
 fn lxv<T: MemInt>(reg: &mut VectorReg, element: usize, dmem: &mut [u8], base: u32, offset: u32) {
     let ea = ((base + (offset << T::SIZE_LOG)) & 0xFFF) as usize;
-    if ea + T::SIZE > 0x1000 {
-        for i in 0..16 {
-            // Mirror the beginning of DMEM after the end (using excess memory that
-            // was allocated for this scope).
-            dmem[0x1000 + i] = dmem[i];
-        }
-    }
+    dmem_wrap_begin(dmem, ea, T::SIZE);
     let mem64: u64 = T::endian_read_from::<BigEndian>(&dmem[ea..ea + T::SIZE]).into();
     let mut mem: u128 = mem64.into();
     mem <<= 128 - T::SIZE * 8;
@@ -741,7 +773,9 @@ fn sxv<T: MemInt>(dmem: &mut [u8], base: u32, offset: u32, reg: &VectorReg, elem
     reg = reg.rotate_left(element as u32 * 8);
     reg >>= 128 - T::SIZE * 8;
 
+    dmem_wrap_begin(dmem, ea, T::SIZE);
     T::endian_write_to::<BigEndian>(&mut dmem[ea..ea + T::SIZE], T::truncate_from(reg as u64));
+    dmem_wrap_end(dmem, ea, T::SIZE);
 }
 
 impl Cop for SpCop2 {
@@ -777,7 +811,8 @@ impl Cop for SpCop2 {
         op: u32,
         ctx: &mut CpuContext,
         _bus: &Bus,
-        t: &dbg::Tracer,
+        _paddr: u32,
+        _t: &dbg::Tracer,
     ) -> dbg::Result<()> {
         let sp = Sp::get_mut();
         let mut dmem = &mut sp.dmem;
@@ -794,9 +829,11 @@ impl Cop for SpCop2 {
                 let qw_start = ea & !0xF;
                 let ea_idx = ea & 0xF;
 
+                // Only the bytes up to the end of the quadword are loaded.
                 let mut mem = BigEndian::read_u128(&dmem[qw_start..qw_start + 0x10]);
                 mem <<= ea_idx * 8;
-                write_partial_right::<LittleEndian>(&mut vt.0, mem, element as usize * 8, 128);
+                let nbits = (16 - ea_idx) * 8;
+                write_partial_right::<LittleEndian>(&mut vt.0, mem, element as usize * 8, nbits);
             }
             0x05 => {
                 // LRV
@@ -904,7 +941,21 @@ impl Cop for SpCop2 {
                     vtoff &= 7;
                 }
             }
-            _ => return t.panic(&format!("unimplemented VU load opcode={}", op.hex())),
+            0x0A => {
+                // LWV
+                // Bytes are read every 4 bytes in memory (wrapping around DMEM),
+                // into VT[16-element..15] and then VT[0..element-1] (wrapping around
+                // the vector). With element=0, nothing is loaded.
+                let mut ea = ((base + (offset << 4)) & 0xFFF) as usize;
+                for idx in (16 - element as usize)..(16 + element as usize) {
+                    vt.setbyte(idx & 0xF, dmem[ea]);
+                    ea = (ea + 4) & 0xFFF;
+                }
+            }
+            _ => {
+                // Reserved opcodes are ignored by the RSP.
+                warn!(self.logger, "reserved VU load opcode ignored"; "op" => op.hex());
+            }
         }
         Ok(())
     }
@@ -913,7 +964,8 @@ impl Cop for SpCop2 {
         op: u32,
         ctx: &CpuContext,
         _bus: &mut Bus,
-        t: &dbg::Tracer,
+        _paddr: u32,
+        _t: &dbg::Tracer,
     ) -> dbg::Result<()> {
         let sp = Sp::get_mut();
         let mut dmem = &mut sp.dmem;
@@ -952,20 +1004,18 @@ impl Cop for SpCop2 {
                 // SPV
                 let ea = ((base + (offset << 3)) & 0xFFF) as usize;
 
-                let memptr = &mut dmem[ea..ea + 0x10];
                 for e in 0 as usize..8 as usize {
                     let eidx = (e + element as usize) & 0xF;
-                    memptr[e] = ((vt.lane(eidx & 0x7) << (eidx >> 3)) >> 8) as u8;
+                    dmem[(ea + e) & 0xFFF] = ((vt.lane(eidx & 0x7) << (eidx >> 3)) >> 8) as u8;
                 }
             }
             0x07 => {
                 // SUV
                 let ea = ((base + (offset << 3)) & 0xFFF) as usize;
 
-                let memptr = &mut dmem[ea..ea + 0x10];
                 for e in 0 as usize..8 as usize {
                     let eidx = (e + element as usize) & 0xF;
-                    memptr[e] = ((vt.lane(eidx & 0x7) >> (eidx >> 3)) >> 7) as u8;
+                    dmem[(ea + e) & 0xFFF] = ((vt.lane(eidx & 0x7) >> (eidx >> 3)) >> 7) as u8;
                 }
             }
             0x08 => {
@@ -974,12 +1024,11 @@ impl Cop for SpCop2 {
                 let qw_start = ea as usize & !0x7;
                 let ea_idx = ea & 0x7;
 
-                let memptr = &mut dmem[qw_start..qw_start + 0x10];
                 for e in 0 as usize..8 as usize {
                     let eidx = (e * 2 + element as usize) & 0xF;
                     let midx = (e * 2 + ea_idx) & 0xF;
                     let v = ((vt.byte(eidx) as u16) << 8) | vt.byte((eidx + 1) & 0xF) as u16;
-                    memptr[midx] = (v >> 7) as u8;
+                    dmem[(qw_start + midx) & 0xFFF] = (v >> 7) as u8;
                 }
             }
             0x09 => {
@@ -1009,7 +1058,6 @@ impl Cop for SpCop2 {
                 let qw_start = ea as usize & !0x7;
                 let ea_idx = ea & 0x7;
 
-                let memptr = &mut dmem[qw_start..qw_start + 0x10];
                 for e in 0 as usize..4 as usize {
                     let eidx = LANES[element as usize][e];
                     let v = if eidx < 0 {
@@ -1018,7 +1066,7 @@ impl Cop for SpCop2 {
                         vt.lane(eidx as usize) as u16
                     };
                     let midx = (e * 4 + ea_idx) & 0xF;
-                    memptr[midx] = (v >> 7) as u8;
+                    dmem[(qw_start + midx) & 0xFFF] = (v >> 7) as u8;
                 }
             }
             0x0A => {
@@ -1029,7 +1077,9 @@ impl Cop for SpCop2 {
                 let mut reg = vt.u128();
                 reg = reg.rotate_right((ea & 7) * 8);
                 reg = reg.rotate_left(element * 8);
+                dmem_wrap_begin(&mut dmem, qw_start, 0x10);
                 BigEndian::write_u128(&mut dmem[qw_start..qw_start + 0x10], reg);
+                dmem_wrap_end(&mut dmem, qw_start, 0x10);
             }
             0x0B => {
                 // STV
@@ -1049,30 +1099,40 @@ impl Cop for SpCop2 {
                 }
 
                 mem = mem.rotate_right((ea & 7) * 8);
+                dmem_wrap_begin(&mut dmem, qw_start, 0x10);
                 BigEndian::write_u128(&mut dmem[qw_start..qw_start + 0x10], mem);
+                dmem_wrap_end(&mut dmem, qw_start, 0x10);
+            }
+            _ => {
+                // Reserved opcodes are ignored by the RSP.
+                warn!(self.logger, "reserved VU store opcode ignored"; "op" => op.hex());
             }
-            _ => return t.panic(&format!("unimplemented VU store opcode={}", op.hex())),
         }
         Ok(())
     }
 
+    // LDC2/SDC2 are not decoded by the RSP, so they are just ignored.
     fn ldc(
         &mut self,
-        _op: u32,
+        op: u32,
         _ctx: &mut CpuContext,
         _bus: &Bus,
+        _paddr: u32,
         _t: &dbg::Tracer,
     ) -> dbg::Result<()> {
-        unimplemented!()
+        warn!(self.logger, "LDC2 opcode ignored"; "op" => op.hex());
+        Ok(())
     }
     fn sdc(
         &mut self,
-        _op: u32,
+        op: u32,
         _ctx: &CpuContext,
         _bus: &mut Bus,
+        _paddr: u32,
         _t: &dbg::Tracer,
     ) -> dbg::Result<()> {
-        unimplemented!()
+        warn!(self.logger, "SDC2 opcode ignored"; "op" => op.hex());
+        Ok(())
     }
     fn decode(&self, opcode: u32, pc: u64) -> dbg::DecodedInsn {
         decode(opcode, pc)
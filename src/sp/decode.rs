@@ -76,28 +76,46 @@ pub(crate) fn decode(opcode: u32, _pc: u64) -> DecodedInsn {
                 match func {
                     0x00 => vmulinsn_new("vmulf"),
                     0x01 => vmulinsn_new("vmulu"),
+                    0x02 => vmulinsn_new("vrndp"),
+                    0x03 => vmulinsn_new("vmulq"),
                     0x04 => vmulinsn_new("vmudl"),
                     0x05 => vmulinsn_new("vmudm"),
                     0x06 => vmulinsn_new("vmudn"),
                     0x07 => vmulinsn_new("vmudh"),
                     0x08 => vmulinsn_new("vmacf"),
                     0x09 => vmulinsn_new("vmacu"),
+                    0x0A => vmulinsn_new("vrndn"),
+                    0x0B => vmulinsn_new("vmacq"),
                     0x0C => vmulinsn_new("vmadl"),
                     0x0D => vmulinsn_new("vmadm"),
                     0x0E => vmulinsn_new("vmadn"),
                     0x0F => vmulinsn_new("vmadh"),
                     0x10 => vreg3insn_new("vadd"),
                     0x11 => vreg3insn_new("vsub"),
+                    0x12 => vreg3insn_new("vsut"),
                     0x13 => vreg3insn_new("vabs"),
                     0x14 => vreg3insn_new("vaddc"),
                     0x15 => vreg3insn_new("vsubc"),
+                    0x16 => vreg3insn_new("vaddb"),
                     0x17 => vreg3insn_new("vsubb"),
+                    0x18 => vreg3insn_new("vaccb"),
                     0x19 => vreg3insn_new("vsucb"),
+                    0x1A => vreg3insn_new("vsad"),
+                    0x1B => vreg3insn_new("vsac"),
+                    0x1C => vreg3insn_new("vsum"),
                     0x1D => match e {
-                        8 => DecodedInsn::new2("vsar", OReg(vrd), IReg(ACC_NAMES[0])),
-                        9 => DecodedInsn::new2("vsar", OReg(vrd), IReg(ACC_NAMES[1])),
-                        10 => DecodedInsn::new2("vsar", OReg(vrd), IReg(ACC_NAMES[2])),
-                        _ => DecodedInsn::new2("vsar?", OReg(vrd), Imm8(e)),
+                        8..=10 => DecodedInsn::new5(
+                            "vsar",
+                            OReg(vrd),
+                            IReg(vrs),
+                            IReg(vrt),
+                            Imm8(e),
+                            HidIReg(ACC_NAMES[10 - e as usize]),
+                        )
+                        .with_fmt(VREG3_FMT),
+                        // Other elements just clear VD.
+                        _ => DecodedInsn::new4("vsar", OReg(vrd), IReg(vrs), IReg(vrt), Imm8(e))
+                            .with_fmt(VREG3_FMT),
                     },
                     0x20 => vreg3insn_new("vlt"),
                     0x21 => vreg3insn_new("veq"),
@@ -125,29 +143,37 @@ pub(crate) fn decode(opcode: u32, _pc: u64) -> DecodedInsn {
                         Imm8(e),
                     )
                     .with_fmt(VMOV_FMT),
-                    0x34 => vreg2insn_new("vsqr"),
-                    0x35 => vreg2insn_new("vsqrl"),
-                    0x36 => vreg2insn_new("vsqrh"),
-                    0x37 => vreg2insn_new("vnop"),
-                    _ => DecodedInsn::new1("cop2", Imm32(func)),
+                    0x34 => vreg2insn_new("vrsq"),
+                    0x35 => vreg2insn_new("vrsql"),
+                    0x36 => vreg2insn_new("vrsqh"),
+                    0x37 => DecodedInsn::new0("vnop"),
+                    0x3F => DecodedInsn::new0("vnull"),
+                    // Unnamed opcodes: they behave like VSUT & co. (VD is
+                    // cleared, ACCUM_LO is written).
+                    _ => DecodedInsn::new5(
+                        "cop2",
+                        Imm32(func),
+                        OReg(vrd),
+                        IReg(vrs),
+                        IReg(vrt),
+                        HidOReg(ACC_NAMES[0]),
+                    ),
                 }
             } else {
                 match e {
                     0x0 => DecodedInsn::new3("mfc2", IReg(grt), OReg(vrs), Imm8(rdx as u8 >> 1))
                         .with_fmt(VREG2_FMT),
-                    0x2 => match rsx {
+                    0x2 => match rsx & 3 {
                         0 => DecodedInsn::new2("cfc2", OReg(grt), IReg("vco")),
                         1 => DecodedInsn::new2("cfc2", OReg(grt), IReg("vcc")),
-                        2 => DecodedInsn::new2("cfc2", OReg(grt), IReg("vce")),
-                        _ => DecodedInsn::new2("cfc2?", OReg(grt), Imm8(rsx as u8)),
+                        _ => DecodedInsn::new2("cfc2", OReg(grt), IReg("vce")),
                     },
                     0x4 => DecodedInsn::new3("mtc2", IReg(grt), OReg(vrs), Imm8(rdx as u8 >> 1))
                         .with_fmt(VREG2_FMT),
-                    0x6 => match rsx {
+                    0x6 => match rsx & 3 {
                         0 => DecodedInsn::new2("ctc2", OReg(grt), IReg("vco")),
                         1 => DecodedInsn::new2("ctc2", OReg(grt), IReg("vcc")),
-                        2 => DecodedInsn::new2("ctc2", OReg(grt), IReg("vce")),
-                        _ => DecodedInsn::new2("ctc2?", OReg(grt), Imm8(rsx as u8)),
+                        _ => DecodedInsn::new2("ctc2", OReg(grt), IReg("vce")),
                     },
                     _ => DecodedInsn::new1("cop2su?", Imm8(e)),
                 }
@@ -175,6 +201,7 @@ pub(crate) fn decode(opcode: u32, _pc: u64) -> DecodedInsn {
                 0x07 => vloadinsn_new("luv", off * 8),
                 0x08 => vloadinsn_new("lhv", off * 16),
                 0x09 => vloadinsn_new("lfv", off * 16),
+                0x0A => vloadinsn_new("lwv", off * 16),
                 0x0B => vloadinsn_new("ltv", off * 16),
                 _ => DecodedInsn::new1("lwc2", Imm32(oploadstore)),
             }
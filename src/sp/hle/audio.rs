@@ -0,0 +1,636 @@
+// HLE of the libultra audio microcode (aspMain, "ABI1").
+//
+// The CPU-side synthesizer builds a list of 64-bit commands (the "alist"),
+// that the microcode executes to decode, resample, mix and interleave
+// sample data in DMEM, moving it to/from RDRAM. Here we execute the same
+// commands natively, reproducing the fixed-point arithmetic of the
+// microcode.
+use super::{OsTask, Rdram};
+use byteorder::{BigEndian, ByteOrder};
+use emu::int::Numerics;
+use slog;
+
+// Buffer addresses in ABI1 commands are relative to this DMEM address.
+const DMEM_BASE: u16 = 0x5C0;
+
+const N_SEGMENTS: usize = 16;
+
+// Command flags
+const A_INIT: u8 = 0x01;
+const A_LOOP: u8 = 0x02;
+const A_LEFT: u8 = 0x02;
+const A_VOL: u8 = 0x04;
+const A_AUX: u8 = 0x08;
+
+// Words of the microcode data segment that identify ABI1 (offset, value).
+const ABI1_SIGNATURE: [(u32, u32); 3] = [
+    (0x00, 0x0000_0001),
+    (0x28, 0x1E24_138C),
+    (0x30, 0xF000_0F00),
+];
+
+// The resample filter (64 phases of 4 taps each) is part of the microcode
+// data segment: we locate it through its first phase.
+const RESAMPLE_LUT_PHASE0: [u16; 4] = [0x0C39, 0x66AD, 0x0D46, 0xFFDF];
+const RESAMPLE_LUT_SIZE: usize = 64 * 4;
+
+// An audio microcode that we can emulate.
+pub(super) struct AudioUcode {
+    resample_lut: Vec<i16>,
+}
+
+// Identify the microcode through the data segment pointed by the OSTask.
+pub(super) fn detect(task: &OsTask, rdram: &Rdram) -> Option<AudioUcode> {
+    if !ABI1_SIGNATURE
+        .iter()
+        .all(|&(off, val)| rdram.read_u32(task.ucode_data + off) == val)
+    {
+        return None;
+    }
+
+    let data = rdram.slice(task.ucode_data, task.ucode_data_size as usize);
+    let lut_bytes = RESAMPLE_LUT_SIZE * 2;
+    if data.len() < lut_bytes {
+        return None;
+    }
+    (0..=data.len() - lut_bytes)
+        .step_by(2)
+        .find(|&off| {
+            RESAMPLE_LUT_PHASE0
+                .iter()
+                .enumerate()
+                .all(|(i, &v)| BigEndian::read_u16(&data[off + i * 2..]) == v)
+        })
+        .map(|off| {
+            let mut resample_lut = vec![0i16; RESAMPLE_LUT_SIZE];
+            BigEndian::read_i16_into(&data[off..off + lut_bytes], &mut resample_lut);
+            AudioUcode { resample_lut }
+        })
+}
+
+// Execute the alist of the task.
+pub(super) fn process(
+    ucode: &AudioUcode,
+    task: &OsTask,
+    dmem: &mut [u8],
+    rdram: &mut Rdram,
+    logger: &slog::Logger,
+) {
+    let mut abi = Abi1::new(ucode, dmem, rdram);
+    let mut cmd = [0u32; 2];
+    for i in 0..task.data_size / 8 {
+        let ptr = task.data_ptr + i * 8;
+        cmd[0] = abi.rdram.read_u32(ptr);
+        cmd[1] = abi.rdram.read_u32(ptr + 4);
+        if !abi.command(cmd[0], cmd[1]) {
+            warn!(logger, "unknown audio command"; "w1" => cmd[0].hex(), "w2" => cmd[1].hex());
+        }
+    }
+}
+
+fn clamp_s16(v: i32) -> i16 {
+    v.max(-0x8000).min(0x7FFF) as i16
+}
+
+fn align(v: u16, n: u16) -> u16 {
+    v.wrapping_add(n - 1) & !(n - 1)
+}
+
+// Fractional multiply, with rounding (like VMULF).
+fn vmulf(x: i16, y: i16) -> i16 {
+    ((x as i32 * y as i32 + 0x4000) >> 15) as i16
+}
+
+// A volume ramp, in 16.16 fixed point.
+struct Ramp {
+    value: i32,
+    target: i32,
+    step: i32,
+}
+
+impl Ramp {
+    fn advance(&mut self) -> i16 {
+        self.value = self.value.wrapping_add(self.step);
+        let reached = if self.step <= 0 {
+            self.value <= self.target
+        } else {
+            self.value >= self.target
+        };
+        if reached {
+            self.value = self.target;
+            self.step = 0;
+        }
+        (self.value >> 16) as i16
+    }
+}
+
+struct Abi1<'a, 'r> {
+    resample_lut: &'a [i16],
+    dmem: &'a mut [u8],
+    rdram: &'a mut Rdram<'r>,
+
+    segments: [u32; N_SEGMENTS],
+    dmemi: u16,
+    dmemo: u16,
+    count: u16,
+    dry_right: u16,
+    wet_left: u16,
+    wet_right: u16,
+    vol: [i16; 2],
+    target: [i16; 2],
+    rate: [i32; 2],
+    dry: i16,
+    wet: i16,
+    loop_addr: u32,
+    table: [i16; 16 * 16], // ADPCM codebook
+}
+
+impl<'a, 'r> Abi1<'a, 'r> {
+    fn new(ucode: &'a AudioUcode, dmem: &'a mut [u8], rdram: &'a mut Rdram<'r>) -> Self {
+        Abi1 {
+            resample_lut: &ucode.resample_lut,
+            dmem,
+            rdram,
+            segments: [0; N_SEGMENTS],
+            dmemi: 0,
+            dmemo: 0,
+            count: 0,
+            dry_right: 0,
+            wet_left: 0,
+            wet_right: 0,
+            vol: [0; 2],
+            target: [0; 2],
+            rate: [0; 2],
+            dry: 0,
+            wet: 0,
+            loop_addr: 0,
+            table: [0; 16 * 16],
+        }
+    }
+
+    fn u8(&self, addr: u16) -> u8 {
+        self.dmem[addr as usize & 0xFFF]
+    }
+    fn s16(&self, addr: u16) -> i16 {
+        BigEndian::read_i16(&self.dmem[addr as usize & 0xFFE..])
+    }
+    fn set_s16(&mut self, addr: u16, val: i16) {
+        BigEndian::write_i16(&mut self.dmem[addr as usize & 0xFFE..], val);
+    }
+
+    // Access to samples by index (rather than by address).
+    fn sample(&self, pos: u16) -> i16 {
+        self.s16(pos << 1)
+    }
+    fn set_sample(&mut self, pos: u16, val: i16) {
+        self.set_s16(pos << 1, val)
+    }
+
+    fn address(&self, so: u32) -> u32 {
+        let seg = ((so >> 24) & 0x3F) as usize;
+        let offset = so & 0xFF_FFFF;
+        match self.segments.get(seg) {
+            Some(base) => base.wrapping_add(offset),
+            None => offset,
+        }
+    }
+
+    fn command(&mut self, w1: u32, w2: u32) -> bool {
+        match (w1 >> 24) & 0x7F {
+            0x00 => {} // SPNOOP
+            0x01 => self.adpcm(w1, w2),
+            0x02 => self.clearbuff(w1, w2),
+            0x03 => self.envmixer(w1, w2),
+            0x04 => self.loadbuff(w2),
+            0x05 => self.resample(w1, w2),
+            0x06 => self.savebuff(w2),
+            0x07 => {
+                // SEGMENT
+                let seg = ((w2 >> 24) & 0x3F) as usize;
+                if seg < N_SEGMENTS {
+                    self.segments[seg] = w2 & 0xFF_FFFF;
+                }
+            }
+            0x08 => self.setbuff(w1, w2),
+            0x09 => self.setvol(w1, w2),
+            0x0A => self.dmemmove(w1, w2),
+            0x0B => {
+                // LOADADPCM
+                let count = align(w1 as u16, 8) as usize / 2;
+                let addr = self.address(w2);
+                for i in 0..count.min(self.table.len()) {
+                    self.table[i] = self.rdram.read_u16(addr + i as u32 * 2) as i16;
+                }
+            }
+            0x0C => self.mixer(w1, w2),
+            0x0D => self.interleave(w2),
+            0x0E => self.polef(w1, w2),
+            0x0F => self.loop_addr = self.address(w2), // SETLOOP
+            _ => return false,
+        }
+        true
+    }
+
+    fn setbuff(&mut self, w1: u32, w2: u32) {
+        let flags = (w1 >> 16) as u8;
+        if flags & A_AUX != 0 {
+            self.dry_right = (w1 as u16).wrapping_add(DMEM_BASE);
+            self.wet_left = ((w2 >> 16) as u16).wrapping_add(DMEM_BASE);
+            self.wet_right = (w2 as u16).wrapping_add(DMEM_BASE);
+        } else {
+            self.dmemi = (w1 as u16).wrapping_add(DMEM_BASE);
+            self.dmemo = ((w2 >> 16) as u16).wrapping_add(DMEM_BASE);
+            self.count = w2 as u16;
+        }
+    }
+
+    fn setvol(&mut self, w1: u32, w2: u32) {
+        let flags = (w1 >> 16) as u8;
+        match (flags & A_VOL != 0, flags & A_LEFT != 0) {
+            (true, true) => {
+                self.vol[0] = w1 as i16;
+                self.dry = (w2 >> 16) as i16;
+                self.wet = w2 as i16;
+            }
+            (true, false) => self.vol[1] = w1 as i16,
+            (false, true) => {
+                self.target[0] = w1 as i16;
+                self.rate[0] = w2 as i32;
+            }
+            (false, false) => {
+                self.target[1] = w1 as i16;
+                self.rate[1] = w2 as i32;
+            }
+        }
+    }
+
+    fn clearbuff(&mut self, w1: u32, w2: u32) {
+        let dmem = (w1 as u16).wrapping_add(DMEM_BASE);
+        let count = align(w2 as u16 & 0xFFF, 16);
+        for i in 0..count {
+            self.dmem[dmem.wrapping_add(i) as usize & 0xFFF] = 0;
+        }
+    }
+
+    fn dmemmove(&mut self, w1: u32, w2: u32) {
+        let dmemi = (w1 as u16).wrapping_add(DMEM_BASE);
+        let dmemo = ((w2 >> 16) as u16).wrapping_add(DMEM_BASE);
+        let count = align(w2 as u16, 16);
+        for i in 0..count {
+            let b = self.u8(dmemi.wrapping_add(i));
+            self.dmem[dmemo.wrapping_add(i) as usize & 0xFFF] = b;
+        }
+    }
+
+    // LOADBUFF/SAVEBUFF go through DMA, so they follow its alignment rules.
+    fn loadbuff(&mut self, w2: u32) {
+        if self.count == 0 {
+            return;
+        }
+        let addr = self.address(w2) & !7;
+        let dmem = (self.dmemi & !3) as usize;
+        let count = align(self.count, 8) as usize;
+        let src = self.rdram.slice(addr, count);
+        for (i, b) in src.iter().enumerate() {
+            self.dmem[(dmem + i) & 0xFFF] = *b;
+        }
+    }
+
+    fn savebuff(&mut self, w2: u32) {
+        if self.count == 0 {
+            return;
+        }
+        let addr = self.address(w2) & !7;
+        let dmem = (self.dmemo & !3) as usize;
+        let count = align(self.count, 8) as usize;
+        let dst = self.rdram.slice_mut(addr, count);
+        for (i, b) in dst.iter_mut().enumerate() {
+            *b = self.dmem[(dmem + i) & 0xFFF];
+        }
+    }
+
+    fn mixer(&mut self, w1: u32, w2: u32) {
+        if self.count == 0 {
+            return;
+        }
+        let gain = w1 as i16;
+        let dmemi = ((w2 >> 16) as u16).wrapping_add(DMEM_BASE);
+        let dmemo = (w2 as u16).wrapping_add(DMEM_BASE);
+        let count = align(self.count, 32);
+        for i in (0..count).step_by(2) {
+            let src = self.s16(dmemi.wrapping_add(i));
+            let dst = self.s16(dmemo.wrapping_add(i));
+            let v = clamp_s16(dst as i32 + vmulf(src, gain) as i32);
+            self.set_s16(dmemo.wrapping_add(i), v);
+        }
+    }
+
+    fn interleave(&mut self, w2: u32) {
+        if self.count == 0 {
+            return;
+        }
+        let left = ((w2 >> 16) as u16).wrapping_add(DMEM_BASE);
+        let right = (w2 as u16).wrapping_add(DMEM_BASE);
+        let count = align(self.count, 16);
+
+        // Output can overlap the inputs, so read them first.
+        let samples = (0..count / 2)
+            .map(|i| {
+                (
+                    self.s16(left.wrapping_add(i * 2)),
+                    self.s16(right.wrapping_add(i * 2)),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut dmemo = self.dmemo;
+        for (l, r) in samples {
+            self.set_s16(dmemo, l);
+            self.set_s16(dmemo.wrapping_add(2), r);
+            dmemo = dmemo.wrapping_add(4);
+        }
+    }
+
+    fn adpcm(&mut self, w1: u32, w2: u32) {
+        let flags = (w1 >> 16) as u8;
+        let last_addr = self.address(w2);
+        let mut dmemi = self.dmemi;
+        let mut dmemo = self.dmemo;
+        let mut count = align(self.count, 32);
+
+        // The output starts with the last frame decoded by the previous
+        // command, which the resampler uses as history.
+        let mut last = [0i16; 16];
+        if flags & A_INIT == 0 {
+            let addr = if flags & A_LOOP != 0 {
+                self.loop_addr
+            } else {
+                last_addr
+            };
+            for (i, s) in last.iter_mut().enumerate() {
+                *s = self.rdram.read_u16(addr + i as u32 * 2) as i16;
+            }
+        }
+        for &s in last.iter() {
+            self.set_s16(dmemo, s);
+            dmemo = dmemo.wrapping_add(2);
+        }
+
+        while count != 0 {
+            // Each frame is 9 bytes: a header with scale and predictor
+            // index, and 16 4-bit residuals.
+            let code = self.u8(dmemi);
+            dmemi = dmemi.wrapping_add(1);
+            let scale = code >> 4;
+            let rshift = if scale < 12 { 12 - scale } else { 0 };
+            let book = ((code & 0xF) as usize) << 4;
+
+            let mut frame = [0i16; 16];
+            for i in 0..8 {
+                let b = self.u8(dmemi);
+                dmemi = dmemi.wrapping_add(1);
+                frame[i * 2] = ((((b & 0xF0) as u16) << 8) as i16) >> rshift;
+                frame[i * 2 + 1] = ((((b & 0x0F) as u16) << 12) as i16) >> rshift;
+            }
+
+            let cb = &self.table[book..book + 16];
+            let (l1, l2) = (last[14], last[15]);
+            adpcm_residuals(&mut last[0..8], &frame[0..8], cb, l1, l2);
+            let (l1, l2) = (last[6], last[7]);
+            adpcm_residuals(&mut last[8..16], &frame[8..16], cb, l1, l2);
+
+            for &s in last.iter() {
+                self.set_s16(dmemo, s);
+                dmemo = dmemo.wrapping_add(2);
+            }
+            count -= 32;
+        }
+
+        for (i, &s) in last.iter().enumerate() {
+            self.rdram.write_u16(last_addr + i as u32 * 2, s as u16);
+        }
+    }
+
+    fn resample(&mut self, w1: u32, w2: u32) {
+        let flags = (w1 >> 16) as u8;
+        let pitch = (w1 & 0xFFFF) << 1; // 16.16 fixed point
+        let addr = self.address(w2);
+        let mut ipos = (self.dmemi >> 1).wrapping_sub(4);
+        let mut opos = self.dmemo >> 1;
+        let count = align(self.count, 16) >> 1;
+
+        // The 4 samples before the input buffer are the history of the
+        // filter, saved (with the pitch accumulator) at the end of the
+        // previous command.
+        let mut accu: u32;
+        if flags & A_INIT != 0 {
+            for k in 0..4 {
+                self.set_sample(ipos.wrapping_add(k), 0);
+            }
+            accu = 0;
+        } else {
+            for k in 0..4 {
+                let s = self.rdram.read_u16(addr + k as u32 * 2) as i16;
+                self.set_sample(ipos.wrapping_add(k), s);
+            }
+            accu = self.rdram.read_u16(addr + 8) as u32;
+        }
+
+        for _ in 0..count {
+            let lut = ((accu & 0xFC00) >> 8) as usize;
+            let mut v: i64 = 0;
+            for k in 0..4 {
+                v += self.sample(ipos.wrapping_add(k as u16)) as i64
+                    * self.resample_lut[lut + k] as i64;
+            }
+            self.set_sample(opos, (v >> 15).max(-0x8000).min(0x7FFF) as i16);
+            opos = opos.wrapping_add(1);
+
+            accu += pitch;
+            ipos = ipos.wrapping_add((accu >> 16) as u16);
+            accu &= 0xFFFF;
+        }
+
+        for k in 0..4 {
+            let s = self.sample(ipos.wrapping_add(k));
+            self.rdram.write_u16(addr + k as u32 * 2, s as u16);
+        }
+        self.rdram.write_u16(addr + 8, accu as u16);
+    }
+
+    fn envmixer(&mut self, w1: u32, w2: u32) {
+        let flags = (w1 >> 16) as u8;
+        let addr = self.address(w2);
+        let aux = flags & A_AUX != 0;
+
+        // The state of the ramps is saved in RDRAM between commands; the
+        // layout is private to the microcode.
+        let mut dry = self.dry;
+        let mut wet = self.wet;
+        let mut ramps: [Ramp; 2];
+        let mut exp_seq: [i32; 2];
+        let exp_rates: [i32; 2];
+        if flags & A_INIT != 0 {
+            ramps = [
+                Ramp {
+                    value: (self.vol[0] as i32) << 16,
+                    target: (self.target[0] as i32) << 16,
+                    step: 0,
+                },
+                Ramp {
+                    value: (self.vol[1] as i32) << 16,
+                    target: (self.target[1] as i32) << 16,
+                    step: 0,
+                },
+            ];
+            exp_rates = self.rate;
+            exp_seq = [
+                (self.vol[0] as i32).wrapping_mul(self.rate[0]),
+                (self.vol[1] as i32).wrapping_mul(self.rate[1]),
+            ];
+        } else {
+            let save = self.rdram.slice(addr, 40);
+            let r32 = |off: usize| BigEndian::read_i32(&save[off..]);
+            wet = BigEndian::read_i16(&save[0..]);
+            dry = BigEndian::read_i16(&save[4..]);
+            ramps = [
+                Ramp {
+                    value: r32(32),
+                    target: r32(8),
+                    step: 0,
+                },
+                Ramp {
+                    value: r32(36),
+                    target: r32(12),
+                    step: 0,
+                },
+            ];
+            exp_rates = [r32(16), r32(20)];
+            exp_seq = [r32(24), r32(28)];
+        }
+
+        // A ramp is running iff its value has not reached the target yet.
+        for r in ramps.iter_mut() {
+            r.step = r.target.wrapping_sub(r.value);
+        }
+
+        let dmemi = self.dmemi;
+        let outs = [self.dmemo, self.dry_right, self.wet_left, self.wet_right];
+        let nout = if aux { 4 } else { 2 };
+        let mut ptr: u16 = 0;
+        for _ in (0..self.count).step_by(16) {
+            for i in 0..2 {
+                if ramps[i].step != 0 {
+                    exp_seq[i] = ((exp_seq[i] as i64 * exp_rates[i] as i64) >> 16) as i32;
+                    ramps[i].step = exp_seq[i].wrapping_sub(ramps[i].value) >> 3;
+                }
+            }
+
+            for _ in 0..8 {
+                let lvol = ramps[0].advance() as i32;
+                let rvol = ramps[1].advance() as i32;
+                let gains = [
+                    clamp_s16((lvol * dry as i32 + 0x4000) >> 15),
+                    clamp_s16((rvol * dry as i32 + 0x4000) >> 15),
+                    clamp_s16((lvol * wet as i32 + 0x4000) >> 15),
+                    clamp_s16((rvol * wet as i32 + 0x4000) >> 15),
+                ];
+
+                let src = self.s16(dmemi.wrapping_add(ptr * 2));
+                for k in 0..nout {
+                    let addr = outs[k].wrapping_add(ptr * 2);
+                    let v = self.s16(addr) as i32 + ((src as i32 * gains[k] as i32) >> 15);
+                    self.set_s16(addr, clamp_s16(v));
+                }
+                ptr += 1;
+            }
+        }
+
+        let save = self.rdram.slice_mut(addr, 80);
+        BigEndian::write_i16(&mut save[0..], wet);
+        BigEndian::write_i16(&mut save[4..], dry);
+        BigEndian::write_i32(&mut save[8..], ramps[0].target);
+        BigEndian::write_i32(&mut save[12..], ramps[1].target);
+        BigEndian::write_i32(&mut save[16..], exp_rates[0]);
+        BigEndian::write_i32(&mut save[20..], exp_rates[1]);
+        BigEndian::write_i32(&mut save[24..], exp_seq[0]);
+        BigEndian::write_i32(&mut save[28..], exp_seq[1]);
+        BigEndian::write_i32(&mut save[32..], ramps[0].value);
+        BigEndian::write_i32(&mut save[36..], ramps[1].value);
+    }
+
+    fn polef(&mut self, w1: u32, w2: u32) {
+        if self.count == 0 {
+            return;
+        }
+        let flags = (w1 >> 16) as u8;
+        let gain = w1 as i16 as i32;
+        let addr = self.address(w2);
+        let mut dmemi = self.dmemi;
+        let mut dmemo = self.dmemo;
+        let mut count = align(self.count, 16);
+
+        let (mut l1, mut l2) = if flags & A_INIT != 0 {
+            (0, 0)
+        } else {
+            (
+                self.rdram.read_u16(addr + 4) as i16,
+                self.rdram.read_u16(addr + 6) as i16,
+            )
+        };
+
+        // The second half of the coefficients is scaled by the gain (and
+        // stays scaled for the following commands).
+        let mut h1 = [0i16; 8];
+        let mut h2_before = [0i16; 8];
+        let mut h2 = [0i16; 8];
+        for i in 0..8 {
+            h1[i] = self.table[i];
+            h2_before[i] = self.table[8 + i];
+            h2[i] = ((h2_before[i] as i32 * gain) >> 14) as i16;
+            self.table[8 + i] = h2[i];
+        }
+
+        let mut last = [0i16; 8];
+        while count != 0 {
+            let mut frame = [0i16; 8];
+            for f in frame.iter_mut() {
+                *f = self.s16(dmemi);
+                dmemi = dmemi.wrapping_add(2);
+            }
+            for i in 0..8 {
+                let mut accu = (frame[i] as i32).wrapping_mul(gain);
+                accu = accu
+                    .wrapping_add(h1[i] as i32 * l1 as i32)
+                    .wrapping_add(h2_before[i] as i32 * l2 as i32);
+                for j in 0..i {
+                    accu = accu.wrapping_add(h2[j] as i32 * frame[i - 1 - j] as i32);
+                }
+                last[i] = clamp_s16(accu >> 14);
+                self.set_s16(dmemo.wrapping_add(i as u16 * 2), last[i]);
+            }
+            l1 = last[6];
+            l2 = last[7];
+            dmemo = dmemo.wrapping_add(16);
+            count -= 16;
+        }
+
+        for i in 0..4 {
+            self.rdram
+                .write_u16(addr + i as u32 * 2, last[4 + i] as u16);
+        }
+    }
+}
+
+// Decode 8 samples of an ADPCM frame, using the two previous samples and
+// the predictor entry of the codebook.
+fn adpcm_residuals(dst: &mut [i16], src: &[i16], cb: &[i16], l1: i16, l2: i16) {
+    let (book1, book2) = cb.split_at(8);
+    for i in 0..8 {
+        let mut accu = (src[i] as i32) << 11;
+        accu += book1[i] as i32 * l1 as i32 + book2[i] as i32 * l2 as i32;
+        for j in 0..i {
+            accu += book2[j] as i32 * src[i - 1 - j] as i32;
+        }
+        dst[i] = clamp_s16(accu >> 11);
+    }
+}
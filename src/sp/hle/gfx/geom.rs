@@ -0,0 +1,298 @@
+// Geometry pipeline of the Fast3D microcodes: matrices, vertex transform and
+// lighting, and clipping of triangles in homogeneous coordinates.
+//
+// The microcodes use fixed point math; we work in floating point, and only
+// convert back to fixed point when generating RDP commands.
+use byteorder::{BigEndian, ByteOrder};
+
+// A 4x4 matrix. Like in the GBI, vectors are rows, so transforming a vertex
+// is v * M, and a * b is "a, then b".
+pub(super) type Matrix = [[f32; 4]; 4];
+
+pub(super) const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+pub(super) fn mtx_mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0f32; 4]; 4];
+    for i in 0..4 {
+        for j in 0..4 {
+            m[i][j] = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+// Decode a matrix in the GBI format (Mtx): 16 s16 integer parts, followed
+// by 16 u16 fractional parts.
+pub(super) fn mtx_from_fixed(raw: &[u8]) -> Matrix {
+    let mut m = [[0.0f32; 4]; 4];
+    for i in 0..16 {
+        let int = BigEndian::read_i16(&raw[i * 2..]) as i32;
+        let frac = BigEndian::read_u16(&raw[0x20 + i * 2..]) as i32;
+        m[i / 4][i % 4] = ((int << 16) | frac) as f32 / 65536.0;
+    }
+    m
+}
+
+// Modify the integer or fractional part of two consecutive elements of a
+// matrix, like a word write into a Mtx structure (offset in bytes).
+pub(super) fn mtx_poke(m: &mut Matrix, offset: usize, val: u32) {
+    // The RSP accesses DMEM as aligned words.
+    let offset = offset & 0x3C;
+    let idx = (offset & 0x1F) / 2;
+    for (k, half) in [(val >> 16) as u16, val as u16].iter().enumerate() {
+        let e = &mut m[(idx + k) / 4][(idx + k) % 4];
+        let fixed = (*e * 65536.0) as i32;
+        let fixed = if offset < 0x20 {
+            ((*half as i16 as i32) << 16) | (fixed & 0xFFFF)
+        } else {
+            (fixed & !0xFFFF) | *half as i32
+        };
+        *e = fixed as f32 / 65536.0;
+    }
+}
+
+pub(super) fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if len == 0.0 {
+        return v;
+    }
+    [v[0] / len, v[1] / len, v[2] / len]
+}
+
+pub(super) fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+// Transform a direction with the rotational part of a matrix.
+pub(super) fn transform_dir(v: [f32; 3], m: &Matrix) -> [f32; 3] {
+    let mut r = [0.0f32; 3];
+    for j in 0..3 {
+        r[j] = v[0] * m[0][j] + v[1] * m[1][j] + v[2] * m[2][j];
+    }
+    r
+}
+
+pub(super) fn transform_point(v: [f32; 3], m: &Matrix) -> [f32; 4] {
+    let mut r = [0.0f32; 4];
+    for j in 0..4 {
+        r[j] = v[0] * m[0][j] + v[1] * m[1][j] + v[2] * m[2][j] + m[3][j];
+    }
+    r
+}
+
+#[derive(Copy, Clone, Default, Debug)]
+pub(super) struct Light {
+    pub color: [f32; 3],
+    pub dir: [f32; 3], // normalized, pointing towards the light
+}
+
+impl Light {
+    // Decode a light in the GBI format (Light_t).
+    pub(super) fn from_raw(raw: &[u8]) -> Light {
+        Light {
+            color: [raw[0] as f32, raw[1] as f32, raw[2] as f32],
+            dir: normalize([
+                raw[8] as i8 as f32,
+                raw[9] as i8 as f32,
+                raw[10] as i8 as f32,
+            ]),
+        }
+    }
+}
+
+// Outcodes, relative to the clip box (scaled by the clip ratio) and to
+// the near/far planes.
+pub(super) const CLIP_NEGX: u8 = 1 << 0;
+pub(super) const CLIP_POSX: u8 = 1 << 1;
+pub(super) const CLIP_NEGY: u8 = 1 << 2;
+pub(super) const CLIP_POSY: u8 = 1 << 3;
+pub(super) const CLIP_NEAR: u8 = 1 << 4;
+pub(super) const CLIP_FAR: u8 = 1 << 5;
+
+// A vertex in the vertex buffer, after transformation.
+#[derive(Copy, Clone, Default, Debug)]
+pub(super) struct Vertex {
+    pub pos: [f32; 4],   // clip space
+    pub color: [f32; 4], // RGBA, 0..255
+    pub tex: [f32; 2],   // S/T, in s10.5 units
+}
+
+impl Vertex {
+    pub(super) fn clip_codes(&self, ratio: f32) -> u8 {
+        let [x, y, z, w] = self.pos;
+        let mut codes = 0;
+        if x < -w * ratio {
+            codes |= CLIP_NEGX;
+        }
+        if x > w * ratio {
+            codes |= CLIP_POSX;
+        }
+        if y < -w * ratio {
+            codes |= CLIP_NEGY;
+        }
+        if y > w * ratio {
+            codes |= CLIP_POSY;
+        }
+        if z < -w {
+            codes |= CLIP_NEAR;
+        }
+        if z > w {
+            codes |= CLIP_FAR;
+        }
+        codes
+    }
+
+    fn lerp(&self, other: &Vertex, t: f32) -> Vertex {
+        let mut v = Vertex::default();
+        for i in 0..4 {
+            v.pos[i] = self.pos[i] + (other.pos[i] - self.pos[i]) * t;
+            v.color[i] = self.color[i] + (other.color[i] - self.color[i]) * t;
+        }
+        for i in 0..2 {
+            v.tex[i] = self.tex[i] + (other.tex[i] - self.tex[i]) * t;
+        }
+        v
+    }
+}
+
+// Clip a triangle against the near plane and the guard band, returning the
+// resulting convex polygon (possibly empty).
+pub(super) fn clip_triangle(tri: [Vertex; 3], ratio: f32) -> Vec<Vertex> {
+    // Signed distance of a vertex from each clipping plane (inside if >= 0).
+    let planes: [&dyn Fn(&Vertex) -> f32; 5] = [
+        &|v| v.pos[2] + v.pos[3],
+        &|v| v.pos[0] + v.pos[3] * ratio,
+        &|v| v.pos[3] * ratio - v.pos[0],
+        &|v| v.pos[1] + v.pos[3] * ratio,
+        &|v| v.pos[3] * ratio - v.pos[1],
+    ];
+
+    let mut poly = tri.to_vec();
+    for plane in planes.iter() {
+        if poly.iter().all(|v| plane(v) >= 0.0) {
+            continue;
+        }
+        let mut out = Vec::with_capacity(poly.len() + 1);
+        for i in 0..poly.len() {
+            let (a, b) = (&poly[i], &poly[(i + 1) % poly.len()]);
+            let (da, db) = (plane(a), plane(b));
+            if da >= 0.0 {
+                out.push(*a);
+            }
+            if (da >= 0.0) != (db >= 0.0) {
+                out.push(a.lerp(b, da / (da - db)));
+            }
+        }
+        poly = out;
+        if poly.len() < 3 {
+            return Vec::new();
+        }
+    }
+    poly
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vtx(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex {
+            pos: [x, y, z, 1.0],
+            ..Vertex::default()
+        }
+    }
+
+    fn assert_poly(poly: &[Vertex], exp: &[[f32; 4]]) {
+        assert_eq!(poly.len(), exp.len(), "wrong number of vertices");
+        for (v, e) in poly.iter().zip(exp.iter()) {
+            for i in 0..4 {
+                assert!((v.pos[i] - e[i]).abs() < 1e-5, "{:?} != {:?}", v.pos, e);
+            }
+        }
+    }
+
+    #[test]
+    fn clip_inside() {
+        let tri = [vtx(0.0, 0.0, 0.0), vtx(0.5, 0.0, 0.0), vtx(0.0, 0.5, 0.0)];
+        assert_poly(
+            &clip_triangle(tri, 1.0),
+            &[
+                [0.0, 0.0, 0.0, 1.0],
+                [0.5, 0.0, 0.0, 1.0],
+                [0.0, 0.5, 0.0, 1.0],
+            ],
+        );
+    }
+
+    #[test]
+    fn clip_near() {
+        // One vertex inside: the result is a smaller triangle, with the new
+        // vertices on the near plane (z = -w).
+        let tri = [vtx(0.0, 0.0, 0.0), vtx(0.6, 0.0, -4.0), vtx(0.0, 0.6, -4.0)];
+        assert_poly(
+            &clip_triangle(tri, 1.0),
+            &[
+                [0.0, 0.0, 0.0, 1.0],
+                [0.15, 0.0, -1.0, 1.0],
+                [0.0, 0.15, -1.0, 1.0],
+            ],
+        );
+
+        // Two vertices inside: the result is a quad.
+        let tri = [vtx(0.0, 0.0, -4.0), vtx(0.6, 0.0, 0.0), vtx(0.0, 0.6, 0.0)];
+        assert_poly(
+            &clip_triangle(tri, 1.0),
+            &[
+                [0.45, 0.0, -1.0, 1.0],
+                [0.6, 0.0, 0.0, 1.0],
+                [0.0, 0.6, 0.0, 1.0],
+                [0.0, 0.45, -1.0, 1.0],
+            ],
+        );
+
+        // All vertices outside
+        let tri = [
+            vtx(0.0, 0.0, -2.0),
+            vtx(0.6, 0.0, -2.0),
+            vtx(0.0, 0.6, -2.0),
+        ];
+        assert_eq!(clip_triangle(tri, 1.0).len(), 0);
+    }
+
+    #[test]
+    fn clip_guard_band() {
+        // The guard band is the clip box scaled by the clip ratio: a vertex
+        // at x=3w is outside with ratio 2, and inside with ratio 4.
+        let tri = [vtx(0.0, 0.0, 0.0), vtx(3.0, 0.0, 0.0), vtx(0.0, 1.0, 0.0)];
+        assert_poly(
+            &clip_triangle(tri, 2.0),
+            &[
+                [0.0, 0.0, 0.0, 1.0],
+                [2.0, 0.0, 0.0, 1.0],
+                [2.0, 1.0 / 3.0, 0.0, 1.0],
+                [0.0, 1.0, 0.0, 1.0],
+            ],
+        );
+        assert_eq!(clip_triangle(tri, 4.0).len(), 3);
+    }
+
+    #[test]
+    fn mtx_poke_offsets() {
+        // Integer parts of elements [3][2] and [3][3], then their fractions
+        let mut m = [[0.0f32; 4]; 4];
+        mtx_poke(&mut m, 0x1C, 0x0001_FFFF);
+        mtx_poke(&mut m, 0x3C, 0x8000_4000);
+        assert_eq!(m[3], [0.0, 0.0, 1.5, -0.75]);
+
+        // Unaligned offsets modify the word that contains them
+        let mut u = [[0.0f32; 4]; 4];
+        mtx_poke(&mut u, 0x1E, 0x0001_FFFF);
+        mtx_poke(&mut u, 0x3F, 0x8000_4000);
+        assert_eq!(u, m);
+    }
+}
@@ -0,0 +1,1135 @@
+// HLE of the Fast3D family of graphics microcodes (F3D, F3DEX, F3DEX2).
+//
+// The display list is walked directly in RDRAM, and the geometry commands
+// (vertices, matrices, lighting, clipping) are processed natively, producing
+// RDP commands that are fed directly to the RDP, bypassing the command
+// FIFO/buffer in RDRAM. The three microcodes share the same design, but
+// differ in the encoding of most commands.
+mod geom;
+mod tri;
+
+use self::geom::*;
+use self::tri::{triangle, ScreenVertex, TriMode};
+use super::{OsTask, Rdram};
+use byteorder::{BigEndian, ByteOrder};
+use emu::int::Numerics;
+use slog;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) enum GfxUcode {
+    F3d,
+    F3dex,
+    F3dex2,
+}
+
+impl GfxUcode {
+    fn num_vertices(self) -> usize {
+        match self {
+            GfxUcode::F3d => 16,
+            GfxUcode::F3dex | GfxUcode::F3dex2 => 32,
+        }
+    }
+
+    fn dl_stack_size(self) -> usize {
+        match self {
+            GfxUcode::F3d | GfxUcode::F3dex => 10,
+            GfxUcode::F3dex2 => 18,
+        }
+    }
+
+    fn mtx_stack_size(self) -> usize {
+        match self {
+            GfxUcode::F3d | GfxUcode::F3dex => 10,
+            GfxUcode::F3dex2 => 32,
+        }
+    }
+}
+
+// Identify the microcode through the version string in its data segment,
+// like "RSP SW Version: 2.0D, 04-01-96" (F3D) or
+// "RSP Gfx ucode F3DEX       fifo 2.05  Yoshitake Kimura Nintendo." (the
+// major version tells F3DEX and F3DEX2 apart).
+pub(super) fn detect(task: &OsTask, rdram: &Rdram) -> Option<GfxUcode> {
+    let data = rdram.slice(task.ucode_data, task.ucode_data_size as usize);
+    let find = |needle: &[u8]| {
+        data.windows(needle.len())
+            .position(|w| w == needle)
+            .map(|pos| &data[pos + needle.len()..])
+    };
+
+    if let Some(s) = find(b"RSP Gfx ucode ") {
+        let version = s.iter().find(|c| c.is_ascii_digit()).cloned();
+        return if s.starts_with(b"F3DZEX") {
+            Some(GfxUcode::F3dex2)
+        } else if s.starts_with(b"F3D") {
+            match version {
+                Some(b'1') => Some(GfxUcode::F3dex),
+                Some(b'2') => Some(GfxUcode::F3dex2),
+                _ => None,
+            }
+        } else {
+            None
+        };
+    }
+    if find(b"RSP SW Version: 2.0").is_some() {
+        return Some(GfxUcode::F3d);
+    }
+    None
+}
+
+// Geometry mode flags, with their F3D/F3DEX encoding. F3DEX2 moves some of
+// them around, see Gfx::geometry_mode().
+const G_ZBUFFER: u32 = 0x0000_0001;
+const G_SHADE: u32 = 0x0000_0004;
+const G_SHADING_SMOOTH: u32 = 0x0000_0200;
+const G_CULL_FRONT: u32 = 0x0000_1000;
+const G_CULL_BACK: u32 = 0x0000_2000;
+const G_FOG: u32 = 0x0001_0000;
+const G_LIGHTING: u32 = 0x0002_0000;
+const G_TEXTURE_GEN: u32 = 0x0004_0000;
+
+// Bit of othermode (high word) that enables perspective correction.
+const G_MDSFT_TEXTPERSP: u32 = 19;
+
+// Parameters of G_MTX, with their F3D encoding.
+const G_MTX_PROJECTION: u32 = 0x01;
+const G_MTX_LOAD: u32 = 0x02;
+const G_MTX_PUSH: u32 = 0x04;
+
+// Indices for G_MOVEWORD.
+const G_MW_MATRIX: u32 = 0x00;
+const G_MW_NUMLIGHT: u32 = 0x02;
+const G_MW_CLIP: u32 = 0x04;
+const G_MW_SEGMENT: u32 = 0x06;
+const G_MW_FOG: u32 = 0x08;
+const G_MW_LIGHTCOL: u32 = 0x0A;
+const G_MW_PERSPNORM: u32 = 0x0E;
+
+// Offsets for G_MODIFYVTX.
+const G_MWO_POINT_RGBA: u32 = 0x10;
+const G_MWO_POINT_ST: u32 = 0x14;
+
+// Protection against runaway display lists.
+const MAX_COMMANDS: usize = 1 << 20;
+
+// Normalized commands; the ucode-specific encodings are decoded into these.
+enum Cmd {
+    Nop,
+    Mtx {
+        params: u32,
+        addr: u32,
+    },
+    PopMtx {
+        num: usize,
+    },
+    Vtx {
+        v0: usize,
+        n: usize,
+        addr: u32,
+    },
+    Tri(usize, usize, usize),
+    Tri2([usize; 3], [usize; 3]),
+    CullDl {
+        v0: usize,
+        vn: usize,
+    },
+    BranchZ {
+        v: usize,
+        z: u32,
+    },
+    Dl {
+        push: bool,
+        addr: u32,
+    },
+    EndDl,
+    MoveWord {
+        index: u32,
+        offset: u32,
+        val: u32,
+    },
+    MoveMem {
+        index: u32,
+        offset: usize,
+        len: usize,
+        addr: u32,
+    },
+    ModifyVtx {
+        v: usize,
+        offset: u32,
+        val: u32,
+    },
+    Texture {
+        w0: u32,
+        w1: u32,
+        on: bool,
+    },
+    GeometryMode {
+        clear: u32,
+        set: u32,
+    },
+    OtherMode {
+        hi: bool,
+        shift: u32,
+        len: u32,
+        val: u32,
+    },
+    RdpHalf(usize, u32),
+    Rdp,
+    Unsupported(&'static str),
+    Unknown,
+}
+
+fn decode(ucode: GfxUcode, w0: u32, w1: u32) -> Cmd {
+    let op = w0 >> 24;
+    let b = |w: u32, shift: u32| ((w >> shift) & 0xFF) as usize;
+
+    // RDP commands are shared by all microcodes
+    if op >= 0xE4 && !(ucode == GfxUcode::F3dex2 && op == 0xF1) {
+        return Cmd::Rdp;
+    }
+
+    match ucode {
+        GfxUcode::F3d | GfxUcode::F3dex => {
+            // Vertex indices are multiplied by the size of a vertex in DMEM
+            // (F3D), or by 2 (F3DEX).
+            let (vdiv, cdiv) = if ucode == GfxUcode::F3d {
+                (10, 40)
+            } else {
+                (2, 2)
+            };
+            match op {
+                0x00 => Cmd::Nop,
+                0x01 => Cmd::Mtx {
+                    params: (w0 >> 16) & 0xFF,
+                    addr: w1,
+                },
+                0x03 => Cmd::MoveMem {
+                    index: (w0 >> 16) & 0xFF,
+                    offset: 0,
+                    len: (w0 & 0xFFFF) as usize,
+                    addr: w1,
+                },
+                0x04 if ucode == GfxUcode::F3d => Cmd::Vtx {
+                    v0: ((w0 >> 16) & 0xF) as usize,
+                    n: ((w0 >> 20) & 0xF) as usize + 1,
+                    addr: w1,
+                },
+                0x04 => Cmd::Vtx {
+                    v0: b(w0, 16) / 2,
+                    n: ((w0 >> 10) & 0x3F) as usize,
+                    addr: w1,
+                },
+                0x06 => Cmd::Dl {
+                    push: (w0 >> 16) & 0xFF == 0,
+                    addr: w1,
+                },
+                0xAF if ucode == GfxUcode::F3dex => Cmd::Unsupported("G_LOAD_UCODE"),
+                0xB0 if ucode == GfxUcode::F3dex => Cmd::BranchZ {
+                    v: (w0 & 0xFFF) as usize / 2,
+                    z: w1,
+                },
+                0xB1 if ucode == GfxUcode::F3dex => Cmd::Tri2(
+                    [b(w0, 16) / 2, b(w0, 8) / 2, b(w0, 0) / 2],
+                    [b(w1, 16) / 2, b(w1, 8) / 2, b(w1, 0) / 2],
+                ),
+                0xB2 if ucode == GfxUcode::F3dex => Cmd::ModifyVtx {
+                    v: (w0 & 0xFFFF) as usize / 2,
+                    offset: (w0 >> 16) & 0xFF,
+                    val: w1,
+                },
+                0xB2 => Cmd::RdpHalf(1, w1), // G_RDPHALF_CONT
+                0xB3 => Cmd::RdpHalf(1, w1),
+                0xB4 => Cmd::RdpHalf(0, w1),
+                // G_LINE3D, reused by F3DEX for quadrangles
+                0xB5 if ucode == GfxUcode::F3dex => Cmd::Tri2(
+                    [b(w1, 24) / 2, b(w1, 16) / 2, b(w1, 8) / 2],
+                    [b(w1, 24) / 2, b(w1, 8) / 2, b(w1, 0) / 2],
+                ),
+                0xB5 => Cmd::Unsupported("G_LINE3D"),
+                0xB6 => Cmd::GeometryMode { clear: w1, set: 0 },
+                0xB7 => Cmd::GeometryMode { clear: 0, set: w1 },
+                0xB8 => Cmd::EndDl,
+                0xB9 | 0xBA => Cmd::OtherMode {
+                    hi: op == 0xBA,
+                    shift: (w0 >> 8) & 0xFF,
+                    len: w0 & 0xFF,
+                    val: w1,
+                },
+                0xBB => Cmd::Texture {
+                    w0,
+                    w1,
+                    on: w0 & 0xFF != 0,
+                },
+                0xBC => Cmd::MoveWord {
+                    index: w0 & 0xFF,
+                    offset: (w0 >> 8) & 0xFFFF,
+                    val: w1,
+                },
+                0xBD => Cmd::PopMtx { num: 1 },
+                0xBE => Cmd::CullDl {
+                    v0: (w0 & 0xFFFF) as usize / cdiv,
+                    vn: (w1 & 0xFFFF) as usize / cdiv,
+                },
+                0xBF => Cmd::Tri(b(w1, 16) / vdiv, b(w1, 8) / vdiv, b(w1, 0) / vdiv),
+                0xC0 => Cmd::Nop,
+                _ => Cmd::Unknown,
+            }
+        }
+
+        GfxUcode::F3dex2 => match op {
+            0x00 | 0xC0 => Cmd::Nop,
+            0x01 => {
+                let n = ((w0 >> 12) & 0xFF) as usize;
+                Cmd::Vtx {
+                    v0: (((w0 >> 1) & 0x7F) as usize).wrapping_sub(n),
+                    n,
+                    addr: w1,
+                }
+            }
+            0x02 => Cmd::ModifyVtx {
+                v: (w0 & 0xFFFF) as usize / 2,
+                offset: (w0 >> 16) & 0xFF,
+                val: w1,
+            },
+            0x03 => Cmd::CullDl {
+                v0: (w0 & 0xFFFF) as usize / 2,
+                vn: (w1 & 0xFFFF) as usize / 2,
+            },
+            0x04 => Cmd::BranchZ {
+                v: (w0 & 0xFFF) as usize / 2,
+                z: w1,
+            },
+            0x05 => Cmd::Tri(b(w0, 16) / 2, b(w0, 8) / 2, b(w0, 0) / 2),
+            0x06 | 0x07 => Cmd::Tri2(
+                [b(w0, 16) / 2, b(w0, 8) / 2, b(w0, 0) / 2],
+                [b(w1, 16) / 2, b(w1, 8) / 2, b(w1, 0) / 2],
+            ),
+            0xD7 => Cmd::Texture {
+                w0,
+                w1,
+                on: (w0 >> 1) & 0x7F != 0,
+            },
+            0xD8 => Cmd::PopMtx {
+                num: (w1 / 64) as usize,
+            },
+            0xD9 => Cmd::GeometryMode {
+                clear: !w0 & 0xFF_FFFF,
+                set: w1,
+            },
+            0xDA => {
+                // Convert parameters to the F3D encoding (PUSH is inverted).
+                let p = (w0 & 0xFF) ^ 0x01;
+                Cmd::Mtx {
+                    params: (p & 0x01) << 2 | (p & 0x02) | (p & 0x04) >> 2,
+                    addr: w1,
+                }
+            }
+            0xDB => Cmd::MoveWord {
+                index: (w0 >> 16) & 0xFF,
+                offset: w0 & 0xFFFF,
+                val: w1,
+            },
+            0xDC => Cmd::MoveMem {
+                index: w0 & 0xFF,
+                offset: ((w0 >> 8) & 0xFF) as usize * 8,
+                len: (((w0 >> 19) & 0x1F) as usize + 1) * 8,
+                addr: w1,
+            },
+            0xDD => Cmd::Unsupported("G_LOAD_UCODE"),
+            0xDE => Cmd::Dl {
+                push: (w0 >> 16) & 0xFF == 0,
+                addr: w1,
+            },
+            0xDF => Cmd::EndDl,
+            0xE1 => Cmd::RdpHalf(0, w1),
+            0xF1 => Cmd::RdpHalf(1, w1),
+            0xE2 | 0xE3 => {
+                let len = (w0 & 0xFF) + 1;
+                Cmd::OtherMode {
+                    hi: op == 0xE3,
+                    shift: 32u32.wrapping_sub((w0 >> 8) & 0xFF).wrapping_sub(len),
+                    len,
+                    val: w1,
+                }
+            }
+            0xD6 => Cmd::Unsupported("G_DMA_IO"),
+            0xD3..=0xD5 => Cmd::Nop, // G_SPECIAL_*
+            _ => Cmd::Unknown,
+        },
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Viewport {
+    scale: [f32; 3],
+    trans: [f32; 3],
+}
+
+struct Gfx<'a, 'r> {
+    ucode: GfxUcode,
+    rdram: &'a mut Rdram<'r>,
+    emit: &'a mut dyn FnMut(u64),
+    logger: &'a slog::Logger,
+
+    segments: [u32; 16],
+    dl_stack: Vec<u32>,
+    pc: u32,
+    rdp_half: [u32; 2],
+
+    modelview: Vec<Matrix>, // stack: the last one is the current matrix
+    projection: Matrix,
+    mvp: Matrix,
+    force_mtx: [u8; 64],
+
+    vertices: Vec<Vertex>,
+    viewport: Viewport,
+    lights: [Light; 8],
+    lookat: [[f32; 3]; 2],
+    num_lights: usize,
+    geometry_mode: u32,
+    othermode: [u32; 2], // high, low
+    tex_scale: [f32; 2],
+    tex_w0: u32,
+    tex_on: bool,
+    fog: [f32; 2], // multiplier, offset
+    clip_ratio: f32,
+}
+
+// Process the display list of the task, feeding the RDP commands to emit.
+pub(super) fn process(
+    ucode: GfxUcode,
+    task: &OsTask,
+    rdram: &mut Rdram,
+    emit: &mut dyn FnMut(u64),
+    logger: &slog::Logger,
+) {
+    let mut gfx = Gfx {
+        ucode,
+        rdram,
+        emit,
+        logger,
+        segments: [0; 16],
+        dl_stack: Vec::new(),
+        pc: task.data_ptr,
+        rdp_half: [0; 2],
+        modelview: vec![IDENTITY],
+        projection: IDENTITY,
+        mvp: IDENTITY,
+        force_mtx: [0; 64],
+        vertices: vec![Vertex::default(); ucode.num_vertices()],
+        viewport: Viewport {
+            scale: [640.0, 480.0, 511.0],
+            trans: [640.0, 480.0, 511.0],
+        },
+        lights: [Light::default(); 8],
+        lookat: [[127.0, 0.0, 0.0], [0.0, 127.0, 0.0]],
+        num_lights: 1,
+        geometry_mode: 0,
+        othermode: [0, 0],
+        tex_scale: [0.0, 0.0],
+        tex_w0: 0,
+        tex_on: false,
+        fog: [0.0, 0.0],
+        clip_ratio: 2.0,
+    };
+    gfx.run();
+}
+
+impl<'a, 'r> Gfx<'a, 'r> {
+    fn address(&self, so: u32) -> u32 {
+        self.segments[((so >> 24) & 0xF) as usize] + (so & 0xFF_FFFF)
+    }
+
+    // Geometry mode, normalized to the F3D encoding.
+    fn geometry_mode(&self) -> u32 {
+        let gm = self.geometry_mode;
+        if self.ucode != GfxUcode::F3dex2 {
+            return gm;
+        }
+        let mut norm = gm & !(0x0000_0200 | 0x0000_0400 | 0x0020_0000);
+        if gm & 0x0000_0200 != 0 {
+            norm |= G_CULL_FRONT;
+        }
+        if gm & 0x0000_0400 != 0 {
+            norm |= G_CULL_BACK;
+        }
+        if gm & 0x0020_0000 != 0 {
+            norm |= G_SHADING_SMOOTH;
+        }
+        norm
+    }
+
+    fn run(&mut self) {
+        for _ in 0..MAX_COMMANDS {
+            let w0 = self.rdram.read_u32(self.pc);
+            let w1 = self.rdram.read_u32(self.pc + 4);
+            self.pc += 8;
+            if !self.command(w0, w1) {
+                return;
+            }
+        }
+        error!(self.logger, "display list too long, aborting"; "pc" => self.pc.hex());
+    }
+
+    // Execute a command. Returns false when the display list is over.
+    fn command(&mut self, w0: u32, w1: u32) -> bool {
+        match decode(self.ucode, w0, w1) {
+            Cmd::Nop => {}
+            Cmd::Mtx { params, addr } => self.load_matrix(params, addr),
+            Cmd::PopMtx { num } => {
+                for _ in 0..num {
+                    if self.modelview.len() > 1 {
+                        self.modelview.pop();
+                    }
+                }
+                self.update_mvp();
+            }
+            Cmd::Vtx { v0, n, addr } => self.load_vertices(v0, n, addr),
+            Cmd::Tri(v0, v1, v2) => self.draw_triangle(v0, v1, v2),
+            Cmd::Tri2(t0, t1) => {
+                self.draw_triangle(t0[0], t0[1], t0[2]);
+                self.draw_triangle(t1[0], t1[1], t1[2]);
+            }
+            Cmd::CullDl { v0, vn } => {
+                // Skip the rest of the display list if all the vertices are
+                // outside the screen, on the same side.
+                let codes = self
+                    .vertices
+                    .get(v0..=vn)
+                    .unwrap_or(&[])
+                    .iter()
+                    .fold(0xFF, |acc, v| acc & v.clip_codes(1.0));
+                if codes & (CLIP_NEGX | CLIP_POSX | CLIP_NEGY | CLIP_POSY) != 0 {
+                    return self.end_dl();
+                }
+            }
+            Cmd::BranchZ { v, z } => {
+                if let Some(vtx) = self.vertices.get(v) {
+                    let depth = self.project(vtx, 1.0).z;
+                    if depth as u32 <= z {
+                        self.pc = self.address(self.rdp_half[0]);
+                    }
+                }
+            }
+            Cmd::Dl { push, addr } => {
+                if push {
+                    if self.dl_stack.len() >= self.ucode.dl_stack_size() {
+                        error!(self.logger, "display list stack overflow"; "pc" => self.pc.hex());
+                        return true;
+                    }
+                    self.dl_stack.push(self.pc);
+                }
+                self.pc = self.address(addr);
+            }
+            Cmd::EndDl => return self.end_dl(),
+            Cmd::MoveWord { index, offset, val } => self.move_word(index, offset, val),
+            Cmd::MoveMem {
+                index,
+                offset,
+                len,
+                addr,
+            } => self.move_mem(index, offset, len, addr),
+            Cmd::ModifyVtx { v, offset, val } => {
+                if let Some(vtx) = self.vertices.get_mut(v) {
+                    match offset {
+                        G_MWO_POINT_RGBA => {
+                            for i in 0..4 {
+                                vtx.color[i] = ((val >> (24 - i * 8)) & 0xFF) as f32;
+                            }
+                        }
+                        G_MWO_POINT_ST => {
+                            vtx.tex = [(val >> 16) as i16 as f32, val as i16 as f32];
+                        }
+                        _ => {
+                            warn!(self.logger, "unsupported G_MODIFYVTX"; "offset" => offset.hex());
+                        }
+                    }
+                }
+            }
+            Cmd::Texture { w0, w1, on } => {
+                self.tex_w0 = w0;
+                self.tex_on = on;
+                self.tex_scale = [(w1 >> 16) as f32 / 65536.0, (w1 & 0xFFFF) as f32 / 65536.0];
+            }
+            Cmd::GeometryMode { clear, set } => {
+                self.geometry_mode = (self.geometry_mode & !clear) | set;
+            }
+            Cmd::OtherMode {
+                hi,
+                shift,
+                len,
+                val,
+            } => {
+                // Malformed commands can describe fields that do not fit
+                // within the word: only the bits within it are modified.
+                let field = 1u64.checked_shl(len).map_or(!0, |m| m - 1);
+                let mask = field.checked_shl(shift).unwrap_or(0) as u32;
+                let om = &mut self.othermode[if hi { 0 } else { 1 }];
+                *om = (*om & !mask) | (val & mask);
+                self.emit_othermode();
+            }
+            Cmd::RdpHalf(idx, val) => self.rdp_half[idx] = val,
+            Cmd::Rdp => self.rdp_command(w0, w1),
+            Cmd::Unsupported(name) => {
+                error!(self.logger, "unsupported gfx command"; "cmd" => name, "w0" => w0.hex(), "w1" => w1.hex());
+                if name == "G_LOAD_UCODE" {
+                    return false;
+                }
+            }
+            Cmd::Unknown => {
+                warn!(self.logger, "unknown gfx command"; "w0" => w0.hex(), "w1" => w1.hex());
+            }
+        }
+        true
+    }
+
+    fn end_dl(&mut self) -> bool {
+        match self.dl_stack.pop() {
+            Some(pc) => {
+                self.pc = pc;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn emit_othermode(&mut self) {
+        let [hi, lo] = self.othermode;
+        (self.emit)(0x2F << 56 | ((hi & 0xFF_FFFF) as u64) << 32 | lo as u64);
+    }
+
+    // RDP commands embedded in the display list.
+    fn rdp_command(&mut self, w0: u32, w1: u32) {
+        match w0 >> 24 {
+            0xE4 | 0xE5 => {
+                // Texture rectangle: the texture coordinates and slopes are
+                // in the two following commands (whatever their opcode).
+                let half1 = self.rdram.read_u32(self.pc + 4);
+                let half2 = self.rdram.read_u32(self.pc + 12);
+                self.pc += 16;
+                (self.emit)((w0 as u64) << 32 | w1 as u64);
+                (self.emit)((half1 as u64) << 32 | half2 as u64);
+            }
+            0xEF => {
+                // Set Other Modes: keep track of it, for G_SETOTHERMODE_*.
+                self.othermode = [w0 & 0xFF_FFFF, w1];
+                self.emit_othermode();
+            }
+            0xFD | 0xFE | 0xFF => {
+                // Set Texture/Depth/Color Image: translate the segmented address
+                let addr = self.address(w1);
+                (self.emit)((w0 as u64) << 32 | addr as u64);
+            }
+            _ => (self.emit)((w0 as u64) << 32 | w1 as u64),
+        }
+    }
+
+    fn load_matrix(&mut self, params: u32, addr: u32) {
+        let addr = self.address(addr);
+        let m = mtx_from_fixed(self.rdram.slice(addr, 64));
+        if params & G_MTX_PROJECTION != 0 {
+            self.projection = if params & G_MTX_LOAD != 0 {
+                m
+            } else {
+                mtx_mul(&m, &self.projection)
+            };
+        } else {
+            let cur = *self.modelview.last().unwrap();
+            let m = if params & G_MTX_LOAD != 0 {
+                m
+            } else {
+                mtx_mul(&m, &cur)
+            };
+            if params & G_MTX_PUSH != 0 {
+                if self.modelview.len() < self.ucode.mtx_stack_size() {
+                    self.modelview.push(m);
+                } else {
+                    error!(self.logger, "matrix stack overflow"; "pc" => self.pc.hex());
+                    *self.modelview.last_mut().unwrap() = m;
+                }
+            } else {
+                *self.modelview.last_mut().unwrap() = m;
+            }
+        }
+        self.update_mvp();
+    }
+
+    fn update_mvp(&mut self) {
+        self.mvp = mtx_mul(self.modelview.last().unwrap(), &self.projection);
+    }
+
+    fn move_word(&mut self, index: u32, offset: u32, val: u32) {
+        match index {
+            G_MW_MATRIX => mtx_poke(&mut self.mvp, offset as usize, val),
+            G_MW_NUMLIGHT => {
+                self.num_lights = match self.ucode {
+                    GfxUcode::F3dex2 => val as usize / 24,
+                    _ => ((val.wrapping_sub(0x8000_0000) >> 5) as usize).saturating_sub(1),
+                }
+                .min(7);
+            }
+            G_MW_CLIP => {
+                if offset == 0x04 {
+                    self.clip_ratio = (val & 0xFFFF).max(1) as f32;
+                }
+            }
+            G_MW_SEGMENT => {
+                self.segments[((offset >> 2) & 0xF) as usize] = val & 0xFF_FFFF;
+            }
+            G_MW_FOG => {
+                self.fog = [(val >> 16) as i16 as f32, val as i16 as f32];
+            }
+            G_MW_LIGHTCOL => {
+                let (light, field) = match self.ucode {
+                    GfxUcode::F3dex2 => (offset / 24, offset % 24),
+                    _ => (offset / 32, offset % 32),
+                };
+                if field == 0 && (light as usize) < self.lights.len() {
+                    self.lights[light as usize].color = [
+                        (val >> 24) as f32,
+                        ((val >> 16) & 0xFF) as f32,
+                        ((val >> 8) & 0xFF) as f32,
+                    ];
+                }
+            }
+            G_MW_PERSPNORM => {}
+            _ => {
+                warn!(self.logger, "unsupported G_MOVEWORD"; "index" => index.hex(), "val" => val.hex());
+            }
+        }
+    }
+
+    fn move_mem(&mut self, index: u32, offset: usize, len: usize, addr: u32) {
+        let addr = self.address(addr);
+        let data = self.rdram.slice(addr, len).to_vec();
+        if data.len() < len || len < 16 {
+            return;
+        }
+
+        // Convert to a common (light index) or special (viewport, lookat,
+        // matrix) destination.
+        enum Dest {
+            Viewport,
+            LookAt(usize),
+            Light(usize),
+            Matrix(usize),
+            Unknown,
+        }
+        let dest = match self.ucode {
+            GfxUcode::F3d | GfxUcode::F3dex => match index {
+                0x80 => Dest::Viewport,
+                0x82 => Dest::LookAt(1),
+                0x84 => Dest::LookAt(0),
+                0x86..=0x94 => Dest::Light((index as usize - 0x86) / 2),
+                0x9E..=0xA4 => Dest::Matrix((index as usize - 0x9E) / 2 * 16),
+                _ => Dest::Unknown,
+            },
+            GfxUcode::F3dex2 => match index {
+                8 => Dest::Viewport,
+                10 => match offset / 24 {
+                    0 => Dest::LookAt(0),
+                    1 => Dest::LookAt(1),
+                    n => Dest::Light(n - 2),
+                },
+                14 => Dest::Matrix(0),
+                _ => Dest::Unknown,
+            },
+        };
+
+        match dest {
+            Dest::Viewport => {
+                let v = |i: usize| BigEndian::read_i16(&data[i * 2..]) as f32;
+                self.viewport = Viewport {
+                    scale: [v(0), v(1), v(2)],
+                    trans: [v(4), v(5), v(6)],
+                };
+            }
+            Dest::LookAt(i) => self.lookat[i] = Light::from_raw(&data).dir,
+            Dest::Light(i) if i < self.lights.len() => self.lights[i] = Light::from_raw(&data),
+            Dest::Matrix(off) => {
+                let n = data.len().min(64 - off);
+                self.force_mtx[off..off + n].copy_from_slice(&data[..n]);
+                self.mvp = mtx_from_fixed(&self.force_mtx);
+            }
+            _ => {
+                warn!(self.logger, "unsupported G_MOVEMEM"; "index" => index.hex(), "offset" => offset);
+            }
+        }
+    }
+
+    fn load_vertices(&mut self, v0: usize, n: usize, addr: u32) {
+        if v0 + n > self.vertices.len() {
+            error!(self.logger, "vertex buffer overflow"; "v0" => v0, "n" => n);
+            return;
+        }
+        let addr = self.address(addr);
+        let raw = self.rdram.slice(addr, n * 16).to_vec();
+        let gm = self.geometry_mode();
+        let modelview = *self.modelview.last().unwrap();
+
+        for (i, r) in raw.chunks_exact(16).enumerate() {
+            let h = |off: usize| BigEndian::read_i16(&r[off..]) as f32;
+            let mut v = Vertex::default();
+            v.pos = transform_point([h(0), h(2), h(4)], &self.mvp);
+            v.color = [r[12] as f32, r[13] as f32, r[14] as f32, r[15] as f32];
+            v.tex = [h(8) * self.tex_scale[0], h(10) * self.tex_scale[1]];
+
+            if gm & G_LIGHTING != 0 {
+                // The color is replaced by the normal.
+                let n = normalize(transform_dir(
+                    [r[12] as i8 as f32, r[13] as i8 as f32, r[14] as i8 as f32],
+                    &modelview,
+                ));
+                let ambient = &self.lights[self.num_lights];
+                let mut color = ambient.color;
+                for l in &self.lights[..self.num_lights] {
+                    let intensity = dot(n, l.dir).max(0.0);
+                    for c in 0..3 {
+                        color[c] += l.color[c] * intensity;
+                    }
+                }
+                for c in 0..3 {
+                    v.color[c] = color[c].min(255.0);
+                }
+
+                if gm & G_TEXTURE_GEN != 0 {
+                    // Spherical mapping: the scale is the texture size << 6.
+                    for i in 0..2 {
+                        let d = dot(n, normalize(self.lookat[i]));
+                        v.tex[i] = (d * 0.5 + 0.5) * self.tex_scale[i] * 65536.0 / 2.0;
+                    }
+                }
+            }
+
+            if gm & G_FOG != 0 && v.pos[3] != 0.0 {
+                let fog = v.pos[2] / v.pos[3] * self.fog[0] + self.fog[1];
+                v.color[3] = fog.max(0.0).min(255.0);
+            }
+
+            self.vertices[v0 + i] = v;
+        }
+    }
+
+    fn project(&self, v: &Vertex, wmax: f32) -> ScreenVertex {
+        let invw = 1.0 / v.pos[3];
+        let vp = &self.viewport;
+        ScreenVertex {
+            x: (v.pos[0] * invw * vp.scale[0] + vp.trans[0]) / 4.0,
+            y: (-v.pos[1] * invw * vp.scale[1] + vp.trans[1]) / 4.0,
+            z: ((v.pos[2] * invw * vp.scale[2] + vp.trans[2]) * 32.0)
+                .max(0.0)
+                .min(32767.0),
+            w: invw / wmax,
+            color: v.color,
+            tex: v.tex,
+        }
+    }
+
+    fn draw_triangle(&mut self, v0: usize, v1: usize, v2: usize) {
+        let mut tri = match (
+            self.vertices.get(v0),
+            self.vertices.get(v1),
+            self.vertices.get(v2),
+        ) {
+            (Some(a), Some(b), Some(c)) => [*a, *b, *c],
+            _ => {
+                error!(self.logger, "invalid vertex index"; "v0" => v0, "v1" => v1, "v2" => v2);
+                return;
+            }
+        };
+
+        // Trivial rejection: all vertices outside of the same plane.
+        let codes = tri
+            .iter()
+            .fold(0xFF, |acc, v| acc & v.clip_codes(self.clip_ratio));
+        if codes != 0 {
+            return;
+        }
+
+        let gm = self.geometry_mode();
+        if gm & G_SHADING_SMOOTH == 0 {
+            let color = tri[0].color;
+            for v in tri.iter_mut() {
+                v.color = color;
+            }
+        }
+
+        let poly = clip_triangle(tri, self.clip_ratio);
+        if poly.is_empty() {
+            return;
+        }
+        let wmax = poly
+            .iter()
+            .map(|v| 1.0 / v.pos[3])
+            .fold(0.0f32, |a, b| a.max(b));
+        let screen = poly
+            .iter()
+            .map(|v| self.project(v, wmax))
+            .collect::<Vec<_>>();
+
+        // Culling: front faces are counter-clockwise on screen (Y up), so
+        // they have negative area with Y going down.
+        let area: f32 = (0..screen.len())
+            .map(|i| {
+                let (a, b) = (&screen[i], &screen[(i + 1) % screen.len()]);
+                a.x * b.y - b.x * a.y
+            })
+            .sum();
+        if (area > 0.0 && gm & G_CULL_BACK != 0) || (area < 0.0 && gm & G_CULL_FRONT != 0) {
+            return;
+        }
+
+        let mode = TriMode {
+            shade: gm & G_SHADE != 0,
+            texture: self.tex_on,
+            zbuffer: gm & G_ZBUFFER != 0,
+            persp: self.othermode[0] & (1 << G_MDSFT_TEXTPERSP) != 0,
+            tile: (self.tex_w0 >> 8) & 7,
+            level: (self.tex_w0 >> 11) & 7,
+        };
+        for i in 1..screen.len() - 1 {
+            for cmd in triangle([screen[0], screen[i], screen[i + 1]], &mode) {
+                (self.emit)(cmd);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::Discard;
+
+    // Layout of the test RDRAM
+    const DLIST: u32 = 0x1000;
+    const SUBDLIST: u32 = 0x1800;
+    const MTX: u32 = 0x2000; // scale 1/64, X += 0.25
+    const MTX_NOTRANS: u32 = 0x2040; // scale 1/64
+    const MTX_FAR: u32 = 0x2080; // scale 1/64, X += 1.5
+    const MTX_TRANS: u32 = 0x20C0; // X += 64
+    const LIGHTS: u32 = 0x2100;
+    const VERTICES: u32 = 0x2200;
+
+    // Encode a matrix in the GBI format: a scale, followed by a translation
+    // along X.
+    fn mtx(scale: f32, tx: f32) -> Vec<u32> {
+        let mut m = IDENTITY;
+        for i in 0..3 {
+            m[i][i] = scale;
+        }
+        m[3][0] = tx;
+        let fixed = m
+            .iter()
+            .flat_map(|row| row.iter())
+            .map(|e| (e * 65536.0) as i32 as u32)
+            .collect::<Vec<_>>();
+        let mut words = Vec::new();
+        for pair in fixed.chunks(2) {
+            words.push((pair[0] & 0xFFFF_0000) | pair[1] >> 16);
+        }
+        for pair in fixed.chunks(2) {
+            words.push(pair[0] << 16 | (pair[1] & 0xFFFF));
+        }
+        words
+    }
+
+    // Run a F3DEX2 display list (and the optional sub display list), and
+    // return the RDP commands that were generated.
+    fn run(dlist: &[[u32; 2]], subdlist: &[[u32; 2]]) -> Vec<u64> {
+        let mut ram = vec![0u8; 0x4000];
+        let mut write = |addr: u32, words: &[u32]| {
+            let addr = addr as usize;
+            BigEndian::write_u32_into(words, &mut ram[addr..addr + words.len() * 4]);
+        };
+        write(DLIST, &dlist.concat());
+        write(SUBDLIST, &subdlist.concat());
+        write(MTX, &mtx(1.0 / 64.0, 0.25));
+        write(MTX_NOTRANS, &mtx(1.0 / 64.0, 0.0));
+        write(MTX_FAR, &mtx(1.0 / 64.0, 1.5));
+        write(MTX_TRANS, &mtx(1.0, 64.0));
+
+        // Directional light (color 200,100,0; towards +Z), and ambient
+        // light (20,20,20).
+        #[rustfmt::skip]
+        write(LIGHTS, &[
+            0xC864_0000, 0xC864_0000, 0x0000_7F00, 0x0000_0000,
+            0x1414_1400, 0x1414_1400, 0x0000_0000, 0x0000_0000,
+        ]);
+
+        // Three vertices, with normals instead of colors: A and C face the
+        // light, B is orthogonal to it.
+        #[rustfmt::skip]
+        write(VERTICES, &[
+            0x0000_0000, 0x0000_0000, 0x0000_0000, 0x0000_7FFF, // A (0,0,0)
+            0x0020_0000, 0x0020_0000, 0x0000_0000, 0x7F00_00FF, // B (32,0,32)
+            0x0000_0020, 0xFFE0_0000, 0x0000_0000, 0x0000_7FFF, // C (0,32,-32)
+        ]);
+
+        let mut rdram = Rdram { mem: &mut ram };
+        let task = OsTask {
+            data_ptr: DLIST,
+            ..OsTask::default()
+        };
+        let logger = slog::Logger::root(Discard, o!());
+        let mut cmds = Vec::new();
+        process(
+            GfxUcode::F3dex2,
+            &task,
+            &mut rdram,
+            &mut |cmd: u64| cmds.push(cmd),
+            &logger,
+        );
+        cmds
+    }
+
+    const SETUP: [[u32; 2]; 4] = [
+        [0xDC08_060A, LIGHTS],      // G_MOVEMEM: light 0
+        [0xDC08_090A, LIGHTS + 16], // G_MOVEMEM: light 1 (ambient)
+        [0xDB02_0000, 24],          // G_MOVEWORD: 1 light
+        [0xD9FF_FFFF, 0x0022_0405], // G_GEOMETRYMODE: zbuffer, shade, cull back, lighting, smooth
+    ];
+    const LOAD_MTX: [u32; 2] = [0xDA38_0003, MTX]; // G_MTX: load modelview
+    const DRAW: [[u32; 2]; 3] = [
+        [0x0100_3006, VERTICES],    // G_VTX: 3 vertices at 0
+        [0x0500_0204, 0x0000_0000], // G_TRI1: A B C
+        [0xDF00_0000, 0x0000_0000], // G_ENDDL
+    ];
+
+    // The lit triangle drawn by SETUP + LOAD_MTX + DRAW.
+    //
+    // The modelview scales by 1/64 and translates X by 0.25; with the default
+    // viewport, the screen coordinates are A=(200,120), B=(280,120) and
+    // C=(200,60), and Z goes from 8176 (C) to 24528 (B). A and C are lit by
+    // the directional light (20+200, 20+100, 20+0), B only by the ambient one.
+    const LIT_TRIANGLE: [u64; 14] = [
+        0x0D00_01E0_01E0_00F0, // Shade+Z triangle, major edge on the right, YL=120 YM=120 YH=60
+        0x00C8_0000_0000_0000, // XL=200, DxLDy=0
+        0x00C8_0000_0001_5555, // XH=200, DxHDy=4/3
+        0x00C8_0000_0000_0000, // XM=200, DxMDy=0
+        0x00DC_0078_0014_00FF, // R=220 G=120 B=20 A=255 (integer)
+        0xFFFD_FFFE_0000_0000, // DrDx=-2.5 DgDx=-1.25 (integer)
+        0x0000_0000_0000_0000, // R G B A (fraction)
+        0x8000_C000_0000_0000, // DrDx=-2.5 DgDx=-1.25 (fraction)
+        0xFFFC_FFFE_0000_0000, // DrDe=-10/3 DgDe=-5/3 (integer)
+        0x0000_0000_0000_0000, // DrDy DgDy DbDy DaDy = 0 (integer)
+        0xAAAB_5555_0000_0000, // DrDe=-10/3 DgDe=-5/3 (fraction)
+        0x0000_0000_0000_0000, // DrDy DgDy DbDy DaDy = 0 (fraction)
+        0x1FF0_0000_0066_3333, // Z=8176, DzDx=102.2
+        0x0110_8888_0088_4444, // DzDe=272.53, DzDy=136.27
+    ];
+
+    fn dl(parts: &[&[[u32; 2]]]) -> Vec<[u32; 2]> {
+        parts.concat()
+    }
+
+    #[test]
+    fn lit_triangle() {
+        let cmds = run(&dl(&[&SETUP, &[LOAD_MTX], &DRAW]), &[]);
+        assert_eq!(cmds, LIT_TRIANGLE.to_vec());
+
+        // Same triangle with the opposite winding: it is a back face.
+        let cull = [
+            [0x0100_3006, VERTICES],
+            [0x0502_0004, 0x0000_0000], // G_TRI1: B A C
+            [0xDF00_0000, 0x0000_0000],
+        ];
+        assert_eq!(run(&dl(&[&SETUP, &[LOAD_MTX], &cull]), &[]), vec![]);
+    }
+
+    #[test]
+    fn matrix_stack() {
+        // Push a translation on top of the modelview, then pop it: the
+        // triangle is drawn with the original matrix.
+        let push = [0xDA38_0000, MTX_TRANS]; // G_MTX: push, multiply modelview
+        let pop = [0xD838_0002, 64]; // G_POPMTX: 1 matrix
+        let cmds = run(&dl(&[&SETUP, &[LOAD_MTX, push, pop], &DRAW]), &[]);
+        assert_eq!(cmds, LIT_TRIANGLE.to_vec());
+
+        // Without the pop, the triangle moves right by 64 units (1.0 after
+        // the scale), that is 160 pixels.
+        let cmds = run(&dl(&[&SETUP, &[LOAD_MTX, push], &DRAW]), &[]);
+        assert_eq!(cmds[1], 0x0168_0000_0000_0000); // XL=360
+    }
+
+    #[test]
+    fn matrix_poke() {
+        // Poke the fractional part of the X translation into the combined
+        // matrix; unaligned offsets modify the containing word.
+        let load = [0xDA38_0003, MTX_NOTRANS];
+        for &offset in &[0x38, 0x3A] {
+            let poke = [0xDB00_0000 | offset, 0x4000_0000]; // G_MOVEWORD: G_MW_MATRIX
+            let cmds = run(&dl(&[&SETUP, &[load, poke], &DRAW]), &[]);
+            assert_eq!(cmds, LIT_TRIANGLE.to_vec());
+        }
+    }
+
+    #[test]
+    fn cull_dl() {
+        let cull = [0x0300_0000, 0x0000_0004]; // G_CULLDL: vertices 0..2
+        let draw = [
+            [0x0100_3006, VERTICES],
+            cull,
+            [0x0500_0204, 0x0000_0000],
+            [0xDF00_0000, 0x0000_0000],
+        ];
+        assert_eq!(
+            run(&dl(&[&SETUP, &[LOAD_MTX], &draw]), &[]),
+            LIT_TRIANGLE.to_vec()
+        );
+
+        // Moved right by 1.5, all the vertices are outside of the screen
+        // (but B only is outside of the guard band, so without G_CULLDL the
+        // triangle would be clipped and drawn).
+        let far = [0xDA38_0003, MTX_FAR];
+        assert_eq!(run(&dl(&[&SETUP, &[far], &draw]), &[]), vec![]);
+        let nocull = [draw[0], draw[2], draw[3]];
+        assert!(!run(&dl(&[&SETUP, &[far], &nocull]), &[]).is_empty());
+    }
+
+    #[test]
+    fn branch_z() {
+        // Branch to the sub display list drawing the triangle if the depth
+        // of vertex A (16352) is less or equal than the specified one.
+        for &(z, drawn) in &[(16352, true), (16351, false)] {
+            let cmds = run(
+                &dl(&[
+                    &SETUP,
+                    &[LOAD_MTX],
+                    &[
+                        [0x0100_3006, VERTICES],    // G_VTX
+                        [0xE100_0000, SUBDLIST],    // G_RDPHALF_1: branch address
+                        [0x0400_0000, z],           // G_BRANCH_Z: vertex 0
+                        [0xDF00_0000, 0x0000_0000], // G_ENDDL
+                    ],
+                ]),
+                &[[0x0500_0204, 0x0000_0000], [0xDF00_0000, 0x0000_0000]],
+            );
+            assert_eq!(cmds.len(), if drawn { 14 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn othermode_out_of_range() {
+        // Fields that do not fit in the word are ignored (or truncated)
+        // rather than overflowing.
+        let cmds = run(
+            &[
+                [0xE300_0A01, 0x0030_0000], // G_SETOTHERMODE_H: cycle type = fill
+                [0xE300_00FF, 0xFFFF_FFFF], // len=256
+                [0xE300_2800, 0xFFFF_FFFF], // field past the end of the word
+                [0xE200_1F00, 0xFFFF_FFFF], // G_SETOTHERMODE_L: bit 0
+                [0xDF00_0000, 0x0000_0000],
+            ],
+            &[],
+        );
+        assert_eq!(
+            cmds,
+            vec![
+                0x2F30_0000_0000_0000,
+                0x2F30_0000_0000_0000,
+                0x2F30_0000_0000_0000,
+                0x2F30_0000_0000_0001,
+            ]
+        );
+    }
+}
@@ -0,0 +1,261 @@
+// Generation of RDP triangle commands (edge walker setup, and shade,
+// texture and depth coefficients) from screen-space vertices.
+
+// A vertex after projection to screen space.
+#[derive(Copy, Clone, Default, Debug)]
+pub(super) struct ScreenVertex {
+    pub x: f32, // pixels
+    pub y: f32, // pixels
+    pub z: f32, // depth, 0..0x7FFF
+    pub w: f32, // normalized 1/w, 0..1
+    pub color: [f32; 4],
+    pub tex: [f32; 2],
+}
+
+// Which coefficients are emitted, and with which tile.
+#[derive(Copy, Clone, Default, Debug)]
+pub(super) struct TriMode {
+    pub shade: bool,
+    pub texture: bool,
+    pub zbuffer: bool,
+    pub persp: bool,
+    pub tile: u32,
+    pub level: u32,
+}
+
+// Convert to s15.16, saturating.
+fn fx(v: f32) -> u32 {
+    let v = (v * 65536.0).round();
+    v.max(-2147483648.0).min(2147483647.0) as i32 as u32
+}
+
+// Pack the integer (or fractional) halves of four s15.16 values.
+fn pack(v: [u32; 4], frac: bool) -> u64 {
+    let half = |x: u32| (if frac { x & 0xFFFF } else { x >> 16 }) as u64;
+    (half(v[0]) << 48) | (half(v[1]) << 32) | (half(v[2]) << 16) | half(v[3])
+}
+
+// Generate the command for a triangle (in any winding order). Returns an
+// empty vector if the triangle has no area.
+pub(super) fn triangle(verts: [ScreenVertex; 3], mode: &TriMode) -> Vec<u64> {
+    // Sort by Y (snapped to the 1/4 pixel precision of the RDP).
+    let mut v = verts;
+    for vtx in v.iter_mut() {
+        vtx.y = (vtx.y * 4.0).round() / 4.0;
+    }
+    v.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap_or(::std::cmp::Ordering::Equal));
+    let (v1, v2, v3) = (&v[0], &v[1], &v[2]);
+
+    let area = (v2.x - v1.x) * (v3.y - v1.y) - (v3.x - v1.x) * (v2.y - v1.y);
+    if v3.y == v1.y || area == 0.0 {
+        return Vec::new();
+    }
+
+    // Edges: H is the major edge (v1->v3), M goes from v1 to v2 and L from
+    // v2 to v3. H and M start from the scanline containing v1, while L starts
+    // exactly at v2.
+    let slope = |a: &ScreenVertex, b: &ScreenVertex| {
+        if b.y != a.y {
+            (b.x - a.x) / (b.y - a.y)
+        } else {
+            0.0
+        }
+    };
+    let dxhdy = slope(v1, v3);
+    let dxmdy = slope(v1, v2);
+    let dxldy = slope(v2, v3);
+    let ytop = v1.y.floor();
+    let xh = v1.x + dxhdy * (ytop - v1.y);
+    let xm = v1.x + dxmdy * (ytop - v1.y);
+    let xl = v2.x;
+
+    // The major edge is on the left if v2 is on its right.
+    let lft = area > 0.0;
+
+    let op = 0x08 | (mode.shade as u64) << 2 | (mode.texture as u64) << 1 | mode.zbuffer as u64;
+    let y = |y: f32| ((y * 4.0) as i32 as u64) & 0x3FFF;
+    let mut cmd = vec![
+        op << 56
+            | (lft as u64) << 55
+            | (mode.level as u64 & 7) << 51
+            | (mode.tile as u64 & 7) << 48
+            | y(v3.y) << 32
+            | y(v2.y) << 16
+            | y(v1.y),
+        (fx(xl) as u64) << 32 | fx(dxldy) as u64,
+        (fx(xh) as u64) << 32 | fx(dxhdy) as u64,
+        (fx(xm) as u64) << 32 | fx(dxmdy) as u64,
+    ];
+
+    // Each attribute is described by its value at the start of the major
+    // edge, and its derivatives along X, along the major edge and along Y.
+    let gradients = |a1: f32, a2: f32, a3: f32| {
+        let dadx = ((a2 - a1) * (v3.y - v1.y) - (a3 - a1) * (v2.y - v1.y)) / area;
+        let dady = ((a3 - a1) * (v2.x - v1.x) - (a2 - a1) * (v3.x - v1.x)) / area;
+        let dade = dady + dadx * dxhdy;
+        (fx(a1 + dade * (ytop - v1.y)), fx(dadx), fx(dade), fx(dady))
+    };
+    let coeffs = |attrs: [[f32; 3]; 4]| {
+        let mut a = [[0u32; 4]; 4];
+        for (i, at) in attrs.iter().enumerate() {
+            let (v, dx, de, dy) = gradients(at[0], at[1], at[2]);
+            a[0][i] = v;
+            a[1][i] = dx;
+            a[2][i] = de;
+            a[3][i] = dy;
+        }
+        vec![
+            pack(a[0], false),
+            pack(a[1], false),
+            pack(a[0], true),
+            pack(a[1], true),
+            pack(a[2], false),
+            pack(a[3], false),
+            pack(a[2], true),
+            pack(a[3], true),
+        ]
+    };
+
+    if mode.shade {
+        let c = |i: usize| [v1.color[i], v2.color[i], v3.color[i]];
+        cmd.extend(coeffs([c(0), c(1), c(2), c(3)]));
+    }
+    if mode.texture {
+        // With perspective correction, the RDP interpolates S/W, T/W and
+        // 1/W; W is normalized so that its maximum is 1.0 (in s0.15).
+        let (w1, w2, w3) = if mode.persp {
+            (v1.w, v2.w, v3.w)
+        } else {
+            (1.0, 1.0, 1.0)
+        };
+        cmd.extend(coeffs([
+            [v1.tex[0] * w1, v2.tex[0] * w2, v3.tex[0] * w3],
+            [v1.tex[1] * w1, v2.tex[1] * w2, v3.tex[1] * w3],
+            [w1 * 32767.0, w2 * 32767.0, w3 * 32767.0],
+            [0.0, 0.0, 0.0],
+        ]));
+    }
+    if mode.zbuffer {
+        let (z, dzdx, dzde, dzdy) = gradients(v1.z, v2.z, v3.z);
+        cmd.push((z as u64) << 32 | dzdx as u64);
+        cmd.push((dzde as u64) << 32 | dzdy as u64);
+    }
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vtx(x: f32, y: f32, z: f32) -> ScreenVertex {
+        ScreenVertex {
+            x,
+            y,
+            z,
+            w: 1.0,
+            color: [255.0, 128.0, 0.0, 255.0],
+            tex: [0.0, 0.0],
+        }
+    }
+
+    #[test]
+    fn edges() {
+        // Major edge on the left (x=10), middle vertex on the right. The
+        // vertex order does not matter.
+        let mode = TriMode::default();
+        let exp = vec![
+            0x0880_0078_0050_0028, // Fill triangle, lft, YL=30 YM=20 YH=10
+            0x001E_0000_FFFE_0000, // XL=30, DxLDy=-2
+            0x000A_0000_0000_0000, // XH=10, DxHDy=0
+            0x000A_0000_0002_0000, // XM=10, DxMDy=2
+        ];
+        let (a, b, c) = (
+            vtx(10.0, 10.0, 0.0),
+            vtx(30.0, 20.0, 0.0),
+            vtx(10.0, 30.0, 0.0),
+        );
+        assert_eq!(triangle([a, b, c], &mode), exp);
+        assert_eq!(triangle([c, a, b], &mode), exp);
+        assert_eq!(triangle([b, c, a], &mode), exp);
+
+        // Mirrored: major edge on the right
+        let (a, b, c) = (
+            vtx(30.0, 10.0, 0.0),
+            vtx(10.0, 20.0, 0.0),
+            vtx(30.0, 30.0, 0.0),
+        );
+        assert_eq!(
+            triangle([a, b, c], &mode),
+            vec![
+                0x0800_0078_0050_0028,
+                0x000A_0000_0002_0000,
+                0x001E_0000_0000_0000,
+                0x001E_0000_FFFE_0000,
+            ]
+        );
+
+        // Degenerate triangles produce no command
+        let (a, b, c) = (
+            vtx(10.0, 10.0, 0.0),
+            vtx(20.0, 20.0, 0.0),
+            vtx(30.0, 30.0, 0.0),
+        );
+        assert_eq!(triangle([a, b, c], &mode), vec![]);
+    }
+
+    #[test]
+    fn subpixel_top() {
+        // H and M start from the scanline containing the top vertex, so
+        // their X is moved back along the edge by half a line.
+        let mode = TriMode::default();
+        let (a, b, c) = (
+            vtx(10.0, 10.5, 0.0),
+            vtx(30.0, 20.5, 0.0),
+            vtx(10.0, 30.5, 0.0),
+        );
+        assert_eq!(
+            triangle([a, b, c], &mode),
+            vec![
+                0x0880_007A_0052_002A, // YL=30.5 YM=20.5 YH=10.5
+                0x001E_0000_FFFE_0000, // XL=30, DxLDy=-2
+                0x000A_0000_0000_0000, // XH=10, DxHDy=0
+                0x0009_0000_0002_0000, // XM=9, DxMDy=2
+            ]
+        );
+    }
+
+    #[test]
+    fn shade_zbuffer() {
+        // Flat color, and Z growing by 7.5 per pixel along X and by 5 per
+        // line along Y.
+        let mode = TriMode {
+            shade: true,
+            zbuffer: true,
+            ..TriMode::default()
+        };
+        let (a, b, c) = (
+            vtx(10.0, 10.0, 0.0),
+            vtx(30.0, 20.0, 200.0),
+            vtx(10.0, 30.0, 100.0),
+        );
+        assert_eq!(
+            triangle([a, b, c], &mode),
+            vec![
+                0x0D80_0078_0050_0028, // Shade+Z triangle
+                0x001E_0000_FFFE_0000,
+                0x000A_0000_0000_0000,
+                0x000A_0000_0002_0000,
+                0x00FF_0080_0000_00FF, // R G B A (integer)
+                0x0000_0000_0000_0000, // DrDx DgDx DbDx DaDx (integer)
+                0x0000_0000_0000_0000, // R G B A (fraction)
+                0x0000_0000_0000_0000, // DrDx DgDx DbDx DaDx (fraction)
+                0x0000_0000_0000_0000, // DrDe DgDe DbDe DaDe (integer)
+                0x0000_0000_0000_0000, // DrDy DgDy DbDy DaDy (integer)
+                0x0000_0000_0000_0000, // DrDe DgDe DbDe DaDe (fraction)
+                0x0000_0000_0000_0000, // DrDy DgDy DbDy DaDy (fraction)
+                0x0000_0000_0007_8000, // Z=0, DzDx=7.5
+                0x0005_0000_0005_0000, // DzDe=5, DzDy=5
+            ]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+// High-level emulation (HLE) of RSP tasks.
+//
+// When the CPU starts an RSP task, the OSTask structure describing it is
+// found at the end of DMEM. If the task is handled by a microcode that we
+// know about (and HLE is enabled for that kind of task), the task is
+// processed natively instead of running the microcode on RSPCPU.
+mod audio;
+mod gfx;
+
+use super::super::dp::Dp;
+use super::super::r4300::R4300;
+use byteorder::{BigEndian, ByteOrder};
+use emu::int::Numerics;
+use slog;
+
+// Position of the OSTask structure in DMEM (see libultra's sptask.h).
+const TASK_DMEM_ADDR: usize = 0xFC0;
+
+const TASK_TYPE_GFX: u32 = 1;
+const TASK_TYPE_AUDIO: u32 = 2;
+
+// The OSTask structure that the CPU writes into DMEM before starting the RSP.
+// Not all fields are used by the HLE implementations.
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Default)]
+pub(crate) struct OsTask {
+    pub typ: u32,
+    pub flags: u32,
+    pub ucode_boot: u32,
+    pub ucode_boot_size: u32,
+    pub ucode: u32,
+    pub ucode_size: u32,
+    pub ucode_data: u32,
+    pub ucode_data_size: u32,
+    pub dram_stack: u32,
+    pub dram_stack_size: u32,
+    pub output_buff: u32,
+    pub output_buff_size: u32,
+    pub data_ptr: u32,
+    pub data_size: u32,
+    pub yield_data_ptr: u32,
+    pub yield_data_size: u32,
+}
+
+impl OsTask {
+    pub(crate) fn from_dmem(dmem: &[u8]) -> OsTask {
+        let mut w = [0u32; 16];
+        BigEndian::read_u32_into(&dmem[TASK_DMEM_ADDR..TASK_DMEM_ADDR + 0x40], &mut w);
+        OsTask {
+            typ: w[0],
+            flags: w[1],
+            ucode_boot: w[2],
+            ucode_boot_size: w[3],
+            ucode: w[4],
+            ucode_size: w[5],
+            ucode_data: w[6],
+            ucode_data_size: w[7],
+            dram_stack: w[8],
+            dram_stack_size: w[9],
+            output_buff: w[10],
+            output_buff_size: w[11],
+            data_ptr: w[12],
+            data_size: w[13],
+            yield_data_ptr: w[14],
+            yield_data_size: w[15],
+        }
+    }
+}
+
+// RDRAM as accessed by HLE tasks. Microcodes only access RDRAM through DMA,
+// so addresses are physical, and wrap around the installed memory.
+pub(crate) struct Rdram<'a> {
+    mem: &'a mut [u8],
+}
+
+impl<'a> Rdram<'a> {
+    pub(crate) fn new() -> Option<Rdram<'a>> {
+        let mut hwio = R4300::get_mut().bus.fetch_write::<u8>(0);
+        hwio.mem().map(|mem| Rdram { mem })
+    }
+
+    fn offset(&self, addr: u32) -> usize {
+        (addr & 0xFF_FFFF) as usize % self.mem.len()
+    }
+
+    pub(crate) fn slice(&self, addr: u32, len: usize) -> &[u8] {
+        let off = self.offset(addr);
+        let end = (off + len).min(self.mem.len());
+        &self.mem[off..end]
+    }
+
+    pub(crate) fn slice_mut(&mut self, addr: u32, len: usize) -> &mut [u8] {
+        let off = self.offset(addr);
+        let end = (off + len).min(self.mem.len());
+        R4300::get().bus.written(off as u32, (end - off) as u32);
+        &mut self.mem[off..end]
+    }
+
+    pub(crate) fn read_u32(&self, addr: u32) -> u32 {
+        BigEndian::read_u32(&self.mem[self.offset(addr & !3)..])
+    }
+
+    pub(crate) fn read_u16(&self, addr: u32) -> u16 {
+        BigEndian::read_u16(&self.mem[self.offset(addr & !1)..])
+    }
+
+    pub(crate) fn write_u16(&mut self, addr: u32, val: u16) {
+        let off = self.offset(addr & !1);
+        BigEndian::write_u16(&mut self.mem[off..], val);
+        R4300::get().bus.written(off as u32, 2);
+    }
+}
+
+pub(crate) struct Hle {
+    audio: bool,
+    gfx: bool,
+    logger: slog::Logger,
+}
+
+impl Hle {
+    pub(crate) fn new(logger: slog::Logger) -> Hle {
+        Hle {
+            audio: false,
+            gfx: false,
+            logger,
+        }
+    }
+
+    pub(crate) fn enable_audio(&mut self, enable: bool) {
+        self.audio = enable;
+    }
+
+    pub(crate) fn enable_gfx(&mut self, enable: bool) {
+        self.gfx = enable;
+    }
+
+    // Try to process the task described in DMEM. Returns true if the task
+    // was processed (and thus the RSP must not be started), or false if it
+    // must be run on RSPCPU.
+    pub(crate) fn run_task(&mut self, dmem: &mut [u8]) -> bool {
+        let task = OsTask::from_dmem(dmem);
+        match task.typ {
+            TASK_TYPE_AUDIO if self.audio => {
+                let mut rdram = match Rdram::new() {
+                    Some(rdram) => rdram,
+                    None => return false,
+                };
+                match audio::detect(&task, &rdram) {
+                    Some(ucode) => {
+                        info!(self.logger, "HLE audio task";
+                            "ucode" => task.ucode.hex(),
+                            "alist" => task.data_ptr.hex(),
+                            "size" => task.data_size);
+                        audio::process(&ucode, &task, dmem, &mut rdram, &self.logger);
+                        true
+                    }
+                    None => {
+                        warn!(self.logger, "unknown audio microcode, running LLE";
+                            "ucode" => task.ucode.hex(),
+                            "ucode_data" => task.ucode_data.hex());
+                        false
+                    }
+                }
+            }
+            TASK_TYPE_GFX if self.gfx => {
+                let mut rdram = match Rdram::new() {
+                    Some(rdram) => rdram,
+                    None => return false,
+                };
+                match gfx::detect(&task, &rdram) {
+                    Some(ucode) => {
+                        info!(self.logger, "HLE gfx task";
+                            "ucode" => ?ucode,
+                            "dlist" => task.data_ptr.hex());
+                        let mut emit = |cmd| Dp::get_mut().hle_cmd(cmd);
+                        gfx::process(ucode, &task, &mut rdram, &mut emit, &self.logger);
+                        true
+                    }
+                    None => {
+                        warn!(self.logger, "unknown gfx microcode, running LLE";
+                            "ucode" => task.ucode.hex(),
+                            "ucode_data" => task.ucode_data.hex());
+                        false
+                    }
+                }
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,6 +1,7 @@
 mod sp;
 pub use self::sp::*;
 mod decode;
+mod hle;
 
 /// NOTE: please do not add tests here. To test ops, add them at the integration level
 /// (tests/spvector.rs) so that they can more easily cover all the different implementations
@@ -8,6 +9,8 @@ mod decode;
 mod accumulator;
 mod cop0;
 mod cop2;
+pub use self::cop2::SpCop2;
+mod timing;
 mod vclip;
 mod vmul;
 mod vrcp;
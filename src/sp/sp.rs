@@ -2,13 +2,20 @@ use super::super::mi::{IrqMask, Mi};
 use super::super::r4300::R4300;
 use super::cop0::SpCop0;
 use super::cop2::SpCop2;
+use super::hle::Hle;
+use super::timing::RspTiming;
 use crate::errors::*;
 use emu::bus::be::{Bus, Device, Mem, Reg32};
+use emu::dbg;
 use emu::int::Numerics;
 use emu::memint::MemInt;
+use emu::state::{ArrayField, Field};
+use emu::sync;
 use mips64;
+use serde_derive::{Deserialize, Serialize};
 
 use slog;
+use std::cmp;
 use std::ops::{Deref, DerefMut};
 
 bitflags! {
@@ -31,6 +38,40 @@ bitflags! {
     }
 }
 
+// Approximate cost of a DMA transfer between RDRAM and DMEM/IMEM, in RCP
+// cycles. Each row (see the length register) is a separate RDRAM transaction
+// that pays a fixed latency, and then moves data at the sustained RDRAM
+// bandwidth.
+const DMA_ROW_LATENCY: i64 = 8;
+const DMA_BYTES_PER_CYCLE: i64 = 4;
+
+// A DMA transfer, as programmed through the DMA registers.
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize)]
+struct DmaXfer {
+    rsp_addr: u32,   // DMEM/IMEM address (bit 12 selects IMEM)
+    rdram_addr: u32, // RDRAM address
+    len: u32,        // Value written to the length register
+    write: bool,     // True for RSP -> RDRAM, false for RDRAM -> RSP
+    full: bool,      // True if this slot holds a transfer
+}
+
+impl DmaXfer {
+    // Decode the length register into (width, count, skip). All DMA transfers
+    // are at least 8 bytes, and the RSP basically ignores the last 3 bits.
+    fn geometry(&self) -> (usize, usize, usize) {
+        let val = self.len | 0x7;
+        let width = (val & 0xFFF) as usize + 1;
+        let count = ((val >> 12) & 0xFF) as usize + 1;
+        let skip = ((val >> 20) & 0xFFF) as usize;
+        (width, count, skip)
+    }
+
+    fn cycles(&self) -> i64 {
+        let (width, count, _) = self.geometry();
+        count as i64 * (DMA_ROW_LATENCY + width as i64 / DMA_BYTES_PER_CYCLE)
+    }
+}
+
 pub struct RSPCPUConfig;
 pub struct ArchRSP;
 
@@ -44,6 +85,7 @@ impl mips64::Arch for ArchRSP {
             "lwl" | "lwr" | "swl" | "swr" => false,
             "mult" | "multu" | "div" | "divu" => false,
             "mfhi" | "mflo" | "mthi" | "mhlo" => false,
+            "syscall" => false,
             _ => true,
         }
     }
@@ -55,6 +97,7 @@ impl mips64::Config for RSPCPUConfig {
     type Cop1 = mips64::CopNull;
     type Cop2 = SpCop2;
     type Cop3 = mips64::CopNull;
+    type Timing = RspTiming; // Dual issue and pipeline stalls (opt-in)
     fn pc_mask(pc: u32) -> u32 {
         (pc & 0xFFF) | 0x1000
     }
@@ -63,6 +106,13 @@ impl mips64::Config for RSPCPUConfig {
         // do not mask lower bits here
         addr & 0xFFF
     }
+    fn unaligned_access() -> bool {
+        true
+    }
+    fn overflow_traps() -> bool {
+        // RSP has no exceptions: ADD/SUB simply wrap like ADDU/SUBU
+        false
+    }
 }
 
 #[derive(DeviceBE)]
@@ -71,7 +121,7 @@ pub struct RSPCPU {
 }
 
 impl RSPCPU {
-    fn new(logger: slog::Logger) -> Result<Box<Self>> {
+    pub(crate) fn new(logger: slog::Logger) -> Result<Box<Self>> {
         Ok(Box::new(RSPCPU {
             cpu: mips64::Cpu::new(
                 "RSP",
@@ -139,7 +189,7 @@ pub struct Sp {
     #[reg(bank = 1, offset = 0x0C, wcb)]
     reg_dma_wr_len: Reg32,
 
-    #[reg(bank = 1, offset = 0x10, init = 0x1, wcb)]
+    #[reg(bank = 1, offset = 0x10, init = 0x1, wcb, rcb)]
     reg_status: Reg32,
 
     #[reg(bank = 1, offset = 0x14, readonly, rcb)]
@@ -151,6 +201,13 @@ pub struct Sp {
     #[reg(bank = 1, offset = 0x1C, init = 0x0, rwmask = 0x1, rcb)]
     reg_semaphore: Reg32,
 
+    // DMA queue: slot 0 is the transfer in progress, slot 1 is the pending
+    // one (if any).
+    dma_fifo: ArrayField<DmaXfer>,
+    dma_end: Field<i64>, // cycle at which the transfer in progress completes
+    cycles: Field<i64>,
+
+    hle: Hle,
     logger: slog::Logger,
 }
 
@@ -172,9 +229,25 @@ impl Sp {
             reg_rsp_pc: Reg32::default(),
             reg_dma_full: Reg32::default(),
             reg_semaphore: Reg32::default(),
+            dma_fifo: ArrayField::new("Sp::dma_fifo", DmaXfer::default(), 2),
+            dma_end: Field::new("Sp::dma_end", 0),
+            cycles: Field::new("Sp::cycles", 0),
+            hle: Hle::new(logger.new(o!())),
         }))
     }
 
+    // Process audio tasks with HLE instead of running the microcode (when
+    // the microcode is recognized).
+    pub fn enable_hle_audio(&mut self, enable: bool) {
+        self.hle.enable_audio(enable);
+    }
+
+    // Process graphics tasks with HLE instead of running the microcode (when
+    // the microcode is recognized).
+    pub fn enable_hle_gfx(&mut self, enable: bool) {
+        self.hle.enable_gfx(enable);
+    }
+
     pub(crate) fn get_status(&self) -> StatusFlags {
         StatusFlags::from_bits(self.reg_status.get()).unwrap()
     }
@@ -297,6 +370,17 @@ impl Sp {
                 // Restore execution. RESET is *NOT* performed:
                 // execution continues from the point where it was halted
                 // before (verified on real hardware).
+                if self.hle.run_task(&mut self.dmem) {
+                    // The task was completed natively: behave as if the
+                    // microcode ran to its final break.
+                    let mut status = status;
+                    status.insert(StatusFlags::HALT | StatusFlags::BROKE | StatusFlags::SIG2);
+                    self.reg_status.set(status.bits());
+                    if status.contains(StatusFlags::INTBREAK) {
+                        Mi::get_mut().set_irq_line(IrqMask::SP, true);
+                    }
+                    return None;
+                }
                 info!(self.logger, "RSP started");
                 return Some(false);
             }
@@ -304,10 +388,16 @@ impl Sp {
         None
     }
 
-    fn cb_read_reg_dma_full(&self, _old: u32) -> u32 {
+    fn cb_read_reg_status(&mut self, _old: u32) -> u32 {
+        self.dma_update(self.dma_now());
+        self.reg_status.get()
+    }
+    fn cb_read_reg_dma_full(&mut self, _old: u32) -> u32 {
+        self.dma_update(self.dma_now());
         self.get_status().contains(StatusFlags::DMAFULL) as u32
     }
-    fn cb_read_reg_dma_busy(&self, _old: u32) -> u32 {
+    fn cb_read_reg_dma_busy(&mut self, _old: u32) -> u32 {
+        self.dma_update(self.dma_now());
         self.get_status().contains(StatusFlags::DMABUSY) as u32
     }
 
@@ -319,83 +409,140 @@ impl Sp {
         old
     }
 
-    fn dma_xfer(
-        &self,
-        mut src: u32,
-        mut dst: u32,
-        width: usize,
-        count: usize,
-        skip_src: usize,
-        skip_dst: usize,
-    ) {
-        let bus = &mut R4300::get_mut().bus;
-        for _ in 0..count {
-            let src_hwio = bus.fetch_read::<u8>(src);
-            let mut dst_hwio = bus.fetch_write::<u8>(dst);
-            if !src_hwio.is_mem() {
-                error!(self.logger, "DMA src address not in linear memory!");
-                break;
-            }
-            if !dst_hwio.is_mem() {
-                error!(self.logger, "DMA dst address not in linear memory!");
-                break;
-            }
-            let src_mem = src_hwio.mem().unwrap();
-            let dst_mem = dst_hwio.mem().unwrap();
-            dst_mem[0..width].copy_from_slice(&src_mem[0..width]);
-
-            src += (width + skip_src) as u32;
-            dst += (width + skip_dst) as u32;
-        }
+    // Current time as seen by the DMA engine. DMA registers are mostly
+    // accessed by the RSP itself (eg: polling DMA_BUSY), so use its clock
+    // when it is ahead of the last synchronization point.
+    fn dma_now(&self) -> i64 {
+        cmp::max(*self.cycles, RSPCPU::get().ctx().clock)
     }
 
-    fn cb_write_reg_dma_rd_len(&self, _old: u32, val: u32) {
-        // All DMA transfers are at least 8 bytes, and the RSP basically
-        // ignores the last 3 bits.
-        let val = val | 0x7;
-        let width = (val & 0xFFF) as usize + 1;
-        let count = ((val >> 12) & 0xFF) as usize + 1;
-        let skip = ((val >> 20) & 0xFFF) as usize;
+    fn update_dma_status(&mut self) {
+        let mut status = self.get_status();
+        status.set(StatusFlags::DMABUSY, self.dma_fifo[0].full);
+        status.set(StatusFlags::DMAFULL, self.dma_fifo[1].full);
+        self.reg_status.set(status.bits());
+    }
 
-        // Addresses are treated as 64-bit aligned.
-        let src = self.reg_dma_rdram_addr.get() & !0x7;
-        let dst = self.reg_dma_rsp_addr.get() & !0x7;
+    // Queue a new DMA transfer, using the current values of the address
+    // registers. Writes to the length registers while the queue is full
+    // are ignored.
+    fn dma_enqueue(&mut self, len: u32, write: bool) {
+        let now = self.dma_now();
+        self.dma_update(now);
+        if self.dma_fifo[1].full {
+            warn!(self.logger, "DMA queue full, transfer ignored");
+            return;
+        }
 
-        info!(self.logger, "DMA xfer: RDRAM -> RSP"; o!(
-            "rdram" => src.hex(),
-            "rsp" =>  dst.hex(),
+        let xfer = DmaXfer {
+            rsp_addr: self.reg_dma_rsp_addr.get(),
+            rdram_addr: self.reg_dma_rdram_addr.get(),
+            len,
+            write,
+            full: true,
+        };
+        let (width, count, skip) = xfer.geometry();
+        info!(self.logger, "DMA xfer queued"; o!(
+            "dir" => if write { "RSP -> RDRAM" } else { "RDRAM -> RSP" },
+            "rsp" => xfer.rsp_addr.hex(),
+            "rdram" => xfer.rdram_addr.hex(),
             "width" => width,
             "count" => count,
             "skip" => skip,
         ));
 
-        self.dma_xfer(src, dst + 0x0400_0000, width, count, skip, 0);
+        if !self.dma_fifo[0].full {
+            self.dma_fifo[0] = xfer;
+            *self.dma_end = now + xfer.cycles();
+        } else {
+            self.dma_fifo[1] = xfer;
+        }
+        self.update_dma_status();
     }
 
-    fn cb_write_reg_dma_wr_len(&self, _old: u32, val: u32) {
-        // All DMA transfers are at least 8 bytes, and the RSP basically
-        // ignores the last 3 bits.
-        let val = val | 0x7;
-        let width = (val & 0xFFF) as usize + 1;
-        let count = ((val >> 12) & 0xFF) as usize + 1;
-        let skip = ((val >> 20) & 0xFFF) as usize;
+    // Complete all the DMA transfers that are finished at the specified
+    // cycle. The pending transfer starts as soon as the previous one is done.
+    fn dma_update(&mut self, now: i64) {
+        while self.dma_fifo[0].full && *self.dma_end <= now {
+            let xfer = self.dma_fifo[0];
+            self.dma_exec(&xfer);
+
+            self.dma_fifo[0] = self.dma_fifo[1];
+            self.dma_fifo[1].full = false;
+            if self.dma_fifo[0].full {
+                *self.dma_end += self.dma_fifo[0].cycles();
+            }
+        }
+        self.update_dma_status();
+    }
 
-        info!(self.logger, "DMA xfer: RSP -> RDRAM"; o!(
-            "rsp" =>  self.reg_dma_rsp_addr.get().hex(),
-            "rdram" => self.reg_dma_rdram_addr.get().hex(),
-            "width" => width,
-            "count" => count,
-            "skip" => skip,
+    fn dma_exec(&mut self, xfer: &DmaXfer) {
+        let (width, count, skip) = xfer.geometry();
+
+        // Addresses are treated as 64-bit aligned.
+        let mut rsp_addr = xfer.rsp_addr & !0x7;
+        let mut rdram_addr = xfer.rdram_addr & !0x7;
+        for _ in 0..count {
+            // DMEM/IMEM addresses wrap around within the 4K bank.
+            let mut done = 0;
+            while done < width {
+                let off = (rsp_addr & 0xFFF) as usize;
+                let n = cmp::min(width - done, 0x1000 - off);
+                if xfer.write {
+                    self.dma_copy(rsp_addr + 0x0400_0000, rdram_addr, n);
+                } else {
+                    self.dma_copy(rdram_addr, rsp_addr + 0x0400_0000, n);
+                }
+                rsp_addr = (rsp_addr & 0x1000) | ((rsp_addr + n as u32) & 0xFFF);
+                rdram_addr += n as u32;
+                done += n;
+            }
+            rdram_addr += skip as u32;
+        }
+
+        // At the end of the transfer, the address registers point after the
+        // last transferred byte, and the length registers read back with
+        // both the length and the count exhausted.
+        self.reg_dma_rsp_addr.set(rsp_addr);
+        self.reg_dma_rdram_addr.set(rdram_addr & 0xFF_FFF8);
+        let len = (xfer.len & 0xFFF0_0000) | 0xFF8;
+        self.reg_dma_rd_len.set(len);
+        self.reg_dma_wr_len.set(len);
+    }
+
+    fn dma_copy(&self, src: u32, dst: u32, len: usize) {
+        let bus = &mut R4300::get_mut().bus;
+        {
+            let src_hwio = bus.fetch_read::<u8>(src);
+            let mut dst_hwio = bus.fetch_write::<u8>(dst);
+            match (src_hwio.mem(), dst_hwio.mem()) {
+                (Some(src_mem), Some(dst_mem)) if src_mem.len() >= len && dst_mem.len() >= len => {
+                    dst_mem[0..len].copy_from_slice(&src_mem[0..len]);
+                    bus.written(dst, len as u32);
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        // Non-linear memory (eg: registers): go through the bus one word
+        // at a time.
+        warn!(self.logger, "DMA through non-linear memory"; o!(
+            "src" => src.hex(),
+            "dst" => dst.hex(),
         ));
+        for off in (0..len as u32).step_by(4) {
+            let val = bus.read::<u32>(src + off);
+            bus.write::<u32>(dst + off, val);
+        }
+    }
+
+    fn cb_write_reg_dma_rd_len(&mut self, _old: u32, val: u32) {
+        self.dma_enqueue(val, false);
+    }
 
-        self.dma_xfer(
-            self.reg_dma_rsp_addr.get() + 0x0400_0000,
-            self.reg_dma_rdram_addr.get(),
-            width,
-            count,
-            0,
-            skip,
-        );
+    fn cb_write_reg_dma_wr_len(&mut self, _old: u32, val: u32) {
+        self.dma_enqueue(val, true);
     }
 
     fn cb_write_reg_rsp_pc(&self, _old: u32, val: u32) {
@@ -407,3 +554,25 @@ impl Sp {
         RSPCPU::get().ctx().get_pc() as u32 & 0xFFF
     }
 }
+
+impl sync::Subsystem for Sp {
+    fn name(&self) -> &str {
+        "RSP-DMA"
+    }
+
+    fn run(&mut self, target_cycles: i64, _tracer: &dbg::Tracer) -> dbg::Result<()> {
+        *self.cycles = target_cycles;
+        self.dma_update(target_cycles);
+        Ok(())
+    }
+
+    fn step(&mut self, _tracer: &dbg::Tracer) -> dbg::Result<()> {
+        panic!("Sp::step() should never be called");
+    }
+    fn cycles(&self) -> i64 {
+        *self.cycles
+    }
+    fn pc(&self) -> Option<u64> {
+        None // No program counter
+    }
+}
@@ -0,0 +1,235 @@
+// Cycle timing model of the RSP pipeline.
+//
+// The RSP has a scalar unit (SU) and a vector unit (VU), and can issue one
+// instruction to each of them in the same cycle. Vector loads/stores and
+// moves to/from COP2 are executed by the SU; only COP2 computational
+// instructions are executed by the VU.
+//
+// Timings are approximate: each register has a cycle from which it can be
+// read without stalling, and an instruction is issued as soon as all its
+// operands are ready.
+//
+// The latencies below were not measured on hardware by this project: they
+// follow the pipeline structure described in the public RSP documentation
+// (n64brew wiki, "Reality Signal Processor/CPU Pipeline"), and the rules of
+// thumb used when scheduling microcode by hand. The tests in
+// tests/rsp_timing_test.rs check the behaviour of the model, not hardware
+// cycle counts. See also the "Pipeline timing" section in doc/rsp.md.
+use mips64;
+
+// Cycles after issue before a value loaded from DMEM (or moved between
+// the SU and the VU) can be used. The SU is a 5-stage pipeline (IF RD EX DF
+// WB) with bypass from DF, so a load-use pair stalls for one cycle, as on
+// the R4300.
+const LOAD_LATENCY: i64 = 2;
+
+// Cycles after issue before the result of a VU op can be read, either by
+// the SU (vector stores, MFC2) or by another VU op. The VU writes VPRs at
+// the end of its own pipeline, with no bypass, so an op that depends on
+// the previous VU op stalls for three cycles.
+const VU_LATENCY: i64 = 4;
+
+// Cycles after issue before the divide unit accepts another DIV-family op
+// (VRCP, VRSQ and their variants): the DIV unit is not pipelined, so
+// back-to-back DIV-family ops stall for one cycle.
+const DIV_LATENCY: i64 = 2;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Unit {
+    Scalar,
+    Vector,
+}
+
+// Registers read and written by an instruction, as far as the timing
+// model is concerned.
+#[derive(Default)]
+struct Operands {
+    gpr_src: [usize; 2], // 0 if unused
+    vpr_src: [Option<usize>; 2],
+    gpr_dst: usize, // 0 if unused
+    gpr_load: bool, // true if gpr_dst is loaded from DMEM or COP2
+    vpr_dst: Option<usize>,
+    flags_src: bool,
+    flags_dst: bool,
+}
+
+#[derive(Default)]
+pub struct RspPipeline {
+    // Last issued instruction: unit, cycle, and whether it was issued
+    // together with the previous one, or by the divide unit.
+    last_unit: Option<Unit>,
+    last_clock: i64,
+    last_paired: bool,
+    last_div: bool,
+
+    // Cycle from which each register can be read without stalling. Flags
+    // (VCO/VCC/VCE) are seen at different times by each unit.
+    gpr: [i64; 32],
+    vpr: [i64; 32],
+    flags_su: i64,
+    flags_vu: i64,
+
+    // Cycle from which the divide unit is free
+    div: i64,
+}
+
+fn decode(op: u32) -> (Unit, bool, Operands) {
+    let rs = ((op >> 21) & 0x1F) as usize;
+    let rt = ((op >> 16) & 0x1F) as usize;
+    let rd = ((op >> 11) & 0x1F) as usize;
+    let mut ops = Operands::default();
+
+    if op >> 26 == 0x12 && op & (1 << 25) != 0 {
+        // VU computational: VT (rt) is read by all ops, VS (rd) by all ops
+        // except the single-lane ones (DIV family, VMOV and VNOP).
+        let func = op & 0x3F;
+        let vd = ((op >> 6) & 0x1F) as usize;
+        let div = match func {
+            0x30..=0x32 | 0x34..=0x36 => true,
+            _ => false,
+        };
+        ops.vpr_src[0] = Some(rt);
+        if func < 0x30 {
+            ops.vpr_src[1] = Some(rd);
+        }
+        ops.vpr_dst = Some(vd);
+        ops.flags_src = true;
+        ops.flags_dst = match func {
+            0x10 | 0x11 | 0x14 | 0x15 | 0x20..=0x27 => true,
+            _ => false,
+        };
+        return (Unit::Vector, div, ops);
+    }
+
+    match op >> 26 {
+        0x00 => {
+            ops.gpr_src = [rs, rt];
+            ops.gpr_dst = rd;
+        }
+        0x01 | 0x06 | 0x07 => ops.gpr_src[0] = rs,
+        0x03 => ops.gpr_dst = 31, // JAL
+        0x04 | 0x05 => ops.gpr_src = [rs, rt],
+        0x08..=0x0E => {
+            ops.gpr_src[0] = rs;
+            ops.gpr_dst = rt;
+        }
+        0x0F => ops.gpr_dst = rt,                  // LUI
+        0x10 if rs == 0x04 => ops.gpr_src[0] = rt, // MTC0
+        0x12 => match rs {
+            // MFC2
+            0x00 => {
+                ops.vpr_src[0] = Some(rd);
+                ops.gpr_dst = rt;
+                ops.gpr_load = true;
+            }
+            // CFC2
+            0x02 => {
+                ops.flags_src = true;
+                ops.gpr_dst = rt;
+                ops.gpr_load = true;
+            }
+            // MTC2
+            0x04 => {
+                ops.gpr_src[0] = rt;
+                ops.vpr_dst = Some(rd);
+            }
+            // CTC2
+            0x06 => {
+                ops.gpr_src[0] = rt;
+                ops.flags_dst = true;
+            }
+            _ => {}
+        },
+        // Loads
+        0x20..=0x25 => {
+            ops.gpr_src[0] = rs;
+            ops.gpr_dst = rt;
+            ops.gpr_load = true;
+        }
+        // Stores
+        0x28..=0x2B => ops.gpr_src = [rs, rt],
+        // LWC2 and SWC2. Transposed loads/stores (LTV/STV) access eight
+        // registers, but only the first one is accounted for.
+        0x32 => {
+            ops.gpr_src[0] = rs;
+            ops.vpr_dst = Some(rt);
+        }
+        0x3A => {
+            ops.gpr_src[0] = rs;
+            ops.vpr_src[0] = Some(rt);
+        }
+        _ => {}
+    }
+    (Unit::Scalar, false, ops)
+}
+
+/// Timing model of the RSP: SU/VU dual issue, and stalls on load-use,
+/// vector register and DIV-family hazards.
+pub struct RspTiming;
+
+impl mips64::Timing for RspTiming {
+    type State = RspPipeline;
+
+    fn issue(p: &mut RspPipeline, op: u32, clock: i64) -> i64 {
+        let (unit, div, ops) = decode(op);
+
+        // Find the first cycle in which all operands are available.
+        let mut ready = 0;
+        for &r in ops.gpr_src.iter().filter(|&&r| r != 0) {
+            ready = ready.max(p.gpr[r]);
+        }
+        for &r in ops.vpr_src.iter().flatten() {
+            ready = ready.max(p.vpr[r]);
+        }
+        if ops.flags_src {
+            ready = ready.max(match unit {
+                Unit::Scalar => p.flags_su,
+                Unit::Vector => p.flags_vu,
+            });
+        }
+        if div {
+            ready = ready.max(p.div);
+        }
+
+        // An instruction can be issued in the same cycle as the previous
+        // one if it goes to the other unit, and its operands are ready.
+        // DIV-family ops are never dual-issued.
+        let pair = p.last_unit.map_or(false, |u| u != unit)
+            && p.last_clock == clock - 1
+            && !p.last_paired
+            && !p.last_div
+            && !div
+            && ready <= clock - 1;
+        let at = if pair { clock - 1 } else { ready.max(clock) };
+
+        // Record when the results will be available. Flags written by a VU
+        // op are forwarded within the VU: the model assumes that all VU ops
+        // read them, while only a few actually do, so a VU latency on flags
+        // would stall most sequences of independent VU ops.
+        let latency = match unit {
+            Unit::Scalar => LOAD_LATENCY,
+            Unit::Vector => VU_LATENCY,
+        };
+        if ops.gpr_dst != 0 {
+            p.gpr[ops.gpr_dst] = if ops.gpr_load { at + LOAD_LATENCY } else { at };
+        }
+        if let Some(r) = ops.vpr_dst {
+            p.vpr[r] = at + latency;
+        }
+        if ops.flags_dst {
+            p.flags_su = at + latency;
+            if unit == Unit::Scalar {
+                p.flags_vu = at + LOAD_LATENCY;
+            }
+        }
+        if div {
+            p.div = at + DIV_LATENCY;
+        }
+
+        p.last_unit = Some(unit);
+        p.last_clock = at;
+        p.last_paired = pair;
+        p.last_div = div;
+        at - clock
+    }
+}
@@ -1,13 +1,13 @@
-use std::arch::x86_64::*;
+use crate::simd::*;
 
 #[inline]
-#[target_feature(enable = "sse2")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
 unsafe fn vselect(mask: __m128i, a: __m128i, b: __m128i) -> __m128i {
     _mm_or_si128(_mm_and_si128(mask, a), _mm_andnot_si128(mask, b))
 }
 
 #[inline] // FIXME: for some reason, Rust doesn't allow inline(always) here
-#[target_feature(enable = "sse2")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
 pub(crate) unsafe fn vch(
     vs: __m128i,
     vt: __m128i,
@@ -74,7 +74,7 @@ pub(crate) unsafe fn vch(
 }
 
 #[inline] // FIXME: for some reason, Rust doesn't allow inline(always) here
-#[target_feature(enable = "sse2")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
 pub(crate) unsafe fn vcr(
     vs: __m128i,
     vt: __m128i,
@@ -104,14 +104,12 @@ pub(crate) unsafe fn vcr(
     //
     // Optimize as:
     //   (VS & SIGN) + VT < 0
-    // (with saturation on last addition to avoid overflow)
-
-    // FIXME: missing test! MUST REMOVE ADDS(SIGN)
+    // (VS and VT have different signs when SIGN=-1, so it cannot overflow)
     let le = _mm_srai_epi16(_mm_add_epi16(_mm_and_si128(sign, vs), vt), 15);
 
     let res = vselect(
         sign,
-        vselect(le, _mm_mullo_epi16(vones, vt), vs),
+        vselect(le, _mm_xor_si128(vt, vones), vs),
         vselect(ge, vt, vs),
     );
 
@@ -119,7 +117,7 @@ pub(crate) unsafe fn vcr(
 }
 
 #[inline] // FIXME: for some reason, Rust doesn't allow inline(always) here
-#[target_feature(enable = "sse2")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
 pub(crate) unsafe fn vcl(
     vs: __m128i,
     vt: __m128i,
@@ -1,9 +1,9 @@
 use super::accumulator::{acc_add, acc_clamp_signed, acc_clamp_unsigned2, acc_clamp_unsigned3};
-use std::arch::x86_64::*;
+use crate::simd::*;
 
 // SSE 4.1 version
 #[inline]
-#[target_feature(enable = "sse2")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
 unsafe fn internal_vmulfu(
     vs: __m128i,
     vt: __m128i,
@@ -65,7 +65,7 @@ unsafe fn internal_vmulfu(
 
 // SSE 4.1 version
 #[inline] // FIXME: for some reason, Rust doesn't allow inline(always) here
-#[target_feature(enable = "sse2")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
 pub(crate) unsafe fn internal_vmudnm(
     vs: __m128i,
     vt: __m128i,
@@ -110,7 +110,7 @@ pub(crate) unsafe fn internal_vmudnm(
 
 // SSE 4.1 version
 #[inline] // FIXME: for some reason, Rust doesn't allow inline(always) here
-#[target_feature(enable = "sse2")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
 pub(crate) unsafe fn internal_vmudh(
     vs: __m128i,
     vt: __m128i,
@@ -137,7 +137,7 @@ pub(crate) unsafe fn internal_vmudh(
 
 // SSE 4.1 version
 #[inline] // FIXME: for some reason, Rust doesn't allow inline(always) here
-#[target_feature(enable = "sse2")]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
 pub(crate) unsafe fn internal_vmudl(
     vs: __m128i,
     vt: __m128i,
@@ -182,7 +182,7 @@ pub(crate) unsafe fn internal_vmudl(
 
 macro_rules! gen_mul_variant {
     ($name:ident, $base:ident, $target:expr, $($arg:expr),*) => {
-        #[target_feature(enable = $target)]
+        #[cfg_attr(target_arch = "x86_64", target_feature(enable = $target))]
         #[inline]
         pub unsafe fn $name(
             vs: __m128i,
@@ -211,3 +211,139 @@ gen_mul_variant!(vmulf, internal_vmulfu, "sse2", true, false);
 gen_mul_variant!(vmulu, internal_vmulfu, "sse2", false, false);
 gen_mul_variant!(vmacf, internal_vmulfu, "sse2", true, true);
 gen_mul_variant!(vmacu, internal_vmulfu, "sse2", false, true);
+
+// VMULQ: signed multiplication for MPEG dequantization. Negative products
+// are rounded towards zero (+31), and the result is truncated to a multiple
+// of 16.
+#[inline]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
+pub unsafe fn vmulq(
+    vs: __m128i,
+    vt: __m128i,
+    _old_acc_lo: __m128i,
+    _old_acc_md: __m128i,
+    _old_acc_hi: __m128i,
+) -> (__m128i, __m128i, __m128i, __m128i) {
+    let mlo = _mm_mullo_epi16(vs, vt);
+    let mhi = _mm_mulhi_epi16(vs, vt);
+    let mut acc1 = _mm_unpacklo_epi16(mlo, mhi);
+    let mut acc2 = _mm_unpackhi_epi16(mlo, mhi);
+
+    let round = _mm_set1_epi32(31);
+    acc1 = _mm_add_epi32(acc1, _mm_and_si128(_mm_srai_epi32(acc1, 31), round));
+    acc2 = _mm_add_epi32(acc2, _mm_and_si128(_mm_srai_epi32(acc2, 31), round));
+
+    internal_vmulq_result(acc1, acc2, _mm_setzero_si128())
+}
+
+// VMACQ: oddification of the accumulator (bits 47..16), used after VMULQ.
+// VS and VT are not used.
+#[inline]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
+pub unsafe fn vmacq(
+    _vs: __m128i,
+    _vt: __m128i,
+    old_acc_lo: __m128i,
+    old_acc_md: __m128i,
+    old_acc_hi: __m128i,
+) -> (__m128i, __m128i, __m128i, __m128i) {
+    let acc1 = internal_vmacq_adjust(_mm_unpacklo_epi16(old_acc_md, old_acc_hi));
+    let acc2 = internal_vmacq_adjust(_mm_unpackhi_epi16(old_acc_md, old_acc_hi));
+
+    internal_vmulq_result(acc1, acc2, old_acc_lo)
+}
+
+// Only values with bit 5 clear are adjusted: negative values are moved up
+// by 32, positive values (>=32) are moved down by 32.
+#[inline]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse2"))]
+unsafe fn internal_vmacq_adjust(acc: __m128i) -> __m128i {
+    let kzero = _mm_setzero_si128();
+    let bit5 = _mm_set1_epi32(32);
+    let clear = _mm_cmpeq_epi32(_mm_and_si128(acc, bit5), kzero);
+    let neg = _mm_and_si128(_mm_cmpgt_epi32(kzero, acc), bit5);
+    let pos = _mm_and_si128(_mm_cmpgt_epi32(acc, _mm_set1_epi32(31)), bit5);
+    _mm_add_epi32(acc, _mm_and_si128(clear, _mm_sub_epi32(neg, pos)))
+}
+
+// Common tail of VMULQ/VMACQ: split the 32-bit ACCUM(47..16) back into
+// MD/HI, and compute the result as Saturate(ACCUM(47..17)) & ~0xF.
+#[inline]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
+unsafe fn internal_vmulq_result(
+    acc1: __m128i,
+    acc2: __m128i,
+    acc_lo: __m128i,
+) -> (__m128i, __m128i, __m128i, __m128i) {
+    let klomask = _mm_set1_epi32(0xFFFF);
+    let acc_md = _mm_packus_epi32(_mm_and_si128(acc1, klomask), _mm_and_si128(acc2, klomask));
+    let acc_hi = _mm_packus_epi32(_mm_srli_epi32(acc1, 16), _mm_srli_epi32(acc2, 16));
+
+    #[allow(overflowing_literals)]
+    let res = _mm_and_si128(
+        _mm_packs_epi32(_mm_srai_epi32(acc1, 1), _mm_srai_epi32(acc2, 1)),
+        _mm_set1_epi16(0xFFF0),
+    );
+    (res, acc_lo, acc_md, acc_hi)
+}
+
+// VRNDP/VRNDN: add VT (optionally shifted by 16, if the lowest bit of the VS
+// register index is set) to the accumulator, only if the accumulator is
+// positive (VRNDP) or negative (VRNDN).
+#[inline]
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
+unsafe fn internal_vrnd(
+    vt: __m128i,
+    shift: bool,
+    old_acc_lo: __m128i,
+    old_acc_md: __m128i,
+    old_acc_hi: __m128i,
+    positive: bool,
+) -> (__m128i, __m128i, __m128i, __m128i) {
+    let sign = _mm_srai_epi16(vt, 15);
+    let (prod_lo, prod_md) = if shift {
+        (_mm_setzero_si128(), vt)
+    } else {
+        (vt, sign)
+    };
+
+    let (sum_lo, sum_md, sum_hi) =
+        acc_add(old_acc_lo, old_acc_md, old_acc_hi, prod_lo, prod_md, sign);
+
+    // Select the lanes that must be updated, depending on the sign of
+    // the accumulator.
+    let mut mask = _mm_srai_epi16(old_acc_hi, 15);
+    if positive {
+        mask = _mm_xor_si128(mask, _mm_cmpeq_epi16(mask, mask));
+    }
+    let acc_lo = _mm_blendv_epi8(old_acc_lo, sum_lo, mask);
+    let acc_md = _mm_blendv_epi8(old_acc_md, sum_md, mask);
+    let acc_hi = _mm_blendv_epi8(old_acc_hi, sum_hi, mask);
+
+    let res = acc_clamp_signed(acc_md, acc_hi);
+    (res, acc_lo, acc_md, acc_hi)
+}
+
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
+#[inline]
+pub unsafe fn vrndp(
+    vt: __m128i,
+    shift: bool,
+    aclo: __m128i,
+    acmd: __m128i,
+    achi: __m128i,
+) -> (__m128i, __m128i, __m128i, __m128i) {
+    internal_vrnd(vt, shift, aclo, acmd, achi, true)
+}
+
+#[cfg_attr(target_arch = "x86_64", target_feature(enable = "sse4.1"))]
+#[inline]
+pub unsafe fn vrndn(
+    vt: __m128i,
+    shift: bool,
+    aclo: __m128i,
+    acmd: __m128i,
+    achi: __m128i,
+) -> (__m128i, __m128i, __m128i, __m128i) {
+    internal_vrnd(vt, shift, aclo, acmd, achi, false)
+}
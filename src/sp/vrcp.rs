@@ -7,10 +7,19 @@ fn vrcp_vrsq(x: u32, rsq: bool) -> u32 {
         // special case (RCP/RSQ)
         return !x >> 1;
     }
-    let ax = if x >> 31 != 0 { !x + 1 } else { x };
+    // Inputs below -32768 (only reachable through VRCPL/VRSQL) are ones'
+    // complemented rather than negated.
+    let neg = x >> 31 != 0;
+    let ax = if (x as i32) < -32768 {
+        !x
+    } else if neg {
+        !x + 1
+    } else {
+        x
+    };
     let lshift = ax.leading_zeros() + 1;
     let mut rshift = 32 - lshift;
-    let mut nx = (ax << lshift) >> (32 - 10 + 1);
+    let mut nx = (((ax as u64) << lshift) as u32) >> (32 - 10 + 1);
     let rom = if !rsq {
         RCP_ROM[nx as usize]
     } else {
@@ -23,7 +32,7 @@ fn vrcp_vrsq(x: u32, rsq: bool) -> u32 {
         RSQ_ROM[(nx | ((lshift & 1) << 8)) as usize]
     };
     let r = ((0x10000 | (rom as u32)) << 14) >> rshift;
-    if ax != x {
+    if neg {
         !r
     } else {
         r
@@ -0,0 +1,52 @@
+// Setup shared by the integration tests. Each test binary uses only part of
+// it.
+#![allow(dead_code)]
+
+use emu::bus::be::{Device, Mem};
+use emu::bus::{BusFill, MemFlags};
+use r64emu::dp::Dp;
+use r64emu::mi::Mi;
+use r64emu::r4300::R4300;
+use r64emu::sp::{Sp, RSPCPU};
+use slog::Discard;
+use std::thread;
+
+// State fields and devices can be created only once per thread, so tests
+// that need several instances run each of them within its own thread.
+pub fn run_in_thread<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    thread::spawn(f).join().unwrap()
+}
+
+// Register SP and the devices it talks to, and map them on the R4300 bus
+// (just SP and MI registers). RSP uses its standard bus mapping.
+pub fn make_sp() {
+    let logger = slog::Logger::root(Discard, o!());
+    R4300::new(logger.new(o!())).register();
+    Mi::new(logger.new(o!())).register();
+    Dp::new(logger.new(o!())).register();
+    Sp::new(logger.new(o!())).unwrap().register();
+    {
+        let bus = &mut R4300::get_mut().bus;
+        bus.map_device(0x0400_0000, Sp::get(), 0).unwrap();
+        bus.map_device(0x0404_0000, Sp::get(), 1).unwrap();
+        bus.map_device(0x0408_0000, Sp::get(), 2).unwrap();
+        bus.map_device(0x0430_0000, Mi::get(), 0).unwrap();
+    }
+    RSPCPU::get_mut().map_bus().unwrap();
+}
+
+// Like make_sp, also mapping RDRAM of the specified size at address 0 of
+// the R4300 bus, for DMA and tasks.
+pub fn make_sp_with_rdram(size: usize) -> Mem {
+    make_sp();
+    let ram = Mem::new("rdram", size, MemFlags::default(), None);
+    R4300::get_mut()
+        .bus
+        .map_mem(0, size as u32 - 1, &ram, BusFill::None)
+        .unwrap();
+    ram
+}
@@ -0,0 +1,58 @@
+extern crate mips64;
+extern crate r64emu;
+
+use r64emu::disasm::{disasm, Isa};
+use std::collections::HashMap;
+
+fn disasm_src(isa: Isa, src: &str, base: u32, syms: &[(u32, &str)]) -> String {
+    let code = mips64::asm::assemble(src, base as u64).unwrap();
+    let syms: HashMap<u32, String> = syms.iter().map(|(a, n)| (*a, n.to_string())).collect();
+    let mut out = Vec::new();
+    disasm(&mut out, isa, &code, base, &syms).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn disasm_r4300() {
+    let src = "
+      Loop:
+        addiu t0,t0,-1
+        bnez t0,Loop
+        nop
+        jal $80001000
+        nop
+    ";
+    assert_eq!(
+        disasm_src(Isa::R4300, src, 0x8000_0400, &[(0x8000_1000, "func")]),
+        "
+L80000400:
+80000400:  2508ffff  addiu\tt0,t0,0xffff
+80000404:  1500fffe  bnez\tt0,L80000400
+80000408:  00000000  nop
+8000040c:  0c000400  jal\tfunc
+80000410:  00000000  nop
+"
+    );
+}
+
+#[test]
+fn disasm_rsp() {
+    // RSP jump targets are relative to IMEM, whatever the base address.
+    let src = "
+      Loop:
+        bnez t0,Loop
+        nop
+        j $1000
+        nop
+    ";
+    assert_eq!(
+        disasm_src(Isa::Rsp, src, 0x0400_1000, &[(0x0400_1000, "Start")]),
+        "
+Start:
+04001000:  1500ffff  bnez\tt0,Start
+04001004:  00000000  nop
+04001008:  08000400  j\tStart
+0400100c:  00000000  nop
+"
+    );
+}
@@ -1,6 +1,7 @@
 #[macro_use]
 extern crate serde_derive;
 extern crate byteorder;
+extern crate mips64;
 extern crate toml;
 
 use byteorder::{BigEndian, WriteBytesExt};
@@ -77,24 +78,14 @@ fn main() {
 
     // Generate RSP binary
     {
-        let prefix: String = r#"
-            arch n64.rsp
-            endian msb
-            base $0000
-            include "LIB/N64.INC"
-            include "LIB/N64_RSP.INC"
-        "#
-        .into();
-
-        fs::write("rsp.asm", prefix + &t.rsp_code).expect("cannot write RSP.ASM file");
-        let status = Command::new("bass")
-            .args(&["-o", "rsp.bin", "rsp.asm"])
-            .status()
-            .expect("failed to execute bass");
-        if !status.success() {
-            exit(1);
-        }
-        fs::remove_file("rsp.asm").unwrap();
+        let rspbin = match mips64::asm::assemble(&t.rsp_code, 0) {
+            Ok(bin) => bin,
+            Err(err) => {
+                println!("error assembling RSP code: {}", err);
+                exit(1);
+            }
+        };
+        fs::write("rsp.bin", rspbin).expect("cannot write rsp.bin file");
     }
 
     // Generate input vector
@@ -141,12 +132,8 @@ fn main() {
     }
 
     // Cleanup
-    fs::rename("rsp.bin", tomlname.with_extension("rsp")).unwrap();
+    fs::remove_file("rsp.bin").unwrap();
     fs::remove_file("input.bin").unwrap();
 
-    println!(
-        "Generated: {}, {}",
-        tomlname.with_extension("rsp").display(),
-        tomlname.with_extension("golden").display()
-    );
+    println!("Generated: {}", tomlname.with_extension("golden").display());
 }
@@ -0,0 +1,94 @@
+// Test of the reset button sequence: PIF raises the pre-NMI interrupt (IP4)
+// first, and then triggers the NMI after NMI_DELAY.
+#![feature(pin)]
+
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate mips64;
+extern crate r64emu;
+
+use emu::dbg::Tracer;
+use emu::input::InputManager;
+use emu::sync::Subsystem;
+use mips64::Cop;
+use r64emu::pi::{Pi, NMI_DELAY};
+use r64emu::r4300::R4300;
+use slog::Discard;
+use std::env;
+use std::fs;
+
+const PIF_CIC_WORD: u32 = 0x1FC0_07E4; // see N64::setup_cic
+const OS_RESET_TYPE: u32 = 0x0002_0000;
+
+const CAUSE_IP4: u64 = 1 << 12;
+const STATUS_ERL: u64 = 1 << 2;
+const STATUS_NMI: u64 = 1 << 19;
+const STATUS_SR: u64 = 1 << 20;
+const STATUS_BEV: u64 = 1 << 22;
+
+fn make_pi() {
+    let logger = slog::Logger::root(Discard, o!());
+    let pifrom = env::temp_dir().join("r64emu-pi-reset-test.bin");
+    fs::write(&pifrom, vec![0u8; 0x7C0]).unwrap();
+
+    R4300::new(logger.new(o!())).register();
+    Pi::new(logger.new(o!()), &pifrom, InputManager::new(vec![]))
+        .unwrap()
+        .register();
+    R4300::get_mut()
+        .bus
+        .map_device(0x1FC0_0000, Pi::get(), 1)
+        .unwrap();
+}
+
+fn cop0_reg(idx: usize) -> u64 {
+    let cpu = R4300::get();
+    cpu.cop0.reg(cpu.ctx(), idx) as u64
+}
+
+fn set_cop0_reg(idx: usize, val: u64) {
+    let cpu = R4300::get_mut();
+    let mut ctx = *cpu.ctx();
+    cpu.cop0.set_reg(&mut ctx, idx, val as u128);
+    *cpu.ctx_mut() = ctx;
+}
+
+fn run_pi(cycles: i64) {
+    Pi::get_mut().run(cycles, &Tracer::null()).unwrap();
+}
+
+#[test]
+fn reset_button() {
+    make_pi();
+
+    // Cold boot state, with the CPU running some code
+    R4300::get_mut().bus.write::<u32>(PIF_CIC_WORD, 0x0000_3F00);
+    R4300::get_mut().ctx_mut().set_pc(0xFFFF_FFFF_8000_1234);
+    set_cop0_reg(12, 0x3400_0000);
+
+    // Pre-NMI: only IP4 is raised
+    Pi::get_mut().press_reset();
+    run_pi(NMI_DELAY / 2);
+    Pi::get_mut().press_reset(); // ignored, reset already in progress
+    run_pi(NMI_DELAY - 1);
+    assert_eq!(cop0_reg(13) & CAUSE_IP4, CAUSE_IP4, "pre-NMI not raised");
+    assert_eq!(cop0_reg(12), 0x3400_0000);
+    assert_eq!(R4300::get().ctx().pc, 0xFFFF_FFFF_8000_1234);
+    assert_eq!(R4300::get().bus.read::<u32>(PIF_CIC_WORD), 0x0000_3F00);
+
+    // NMI: jump to the reset vector, and mark this as a NMI for IPL3
+    run_pi(NMI_DELAY);
+    assert_eq!(cop0_reg(13) & CAUSE_IP4, 0, "pre-NMI not cleared");
+    assert_eq!(R4300::get().ctx().pc, 0xFFFF_FFFF_BFC0_0000);
+    assert_eq!(cop0_reg(30), 0xFFFF_FFFF_8000_1234, "ErrorEPC");
+    assert_eq!(
+        cop0_reg(12),
+        0x3400_0000 | STATUS_ERL | STATUS_NMI | STATUS_SR | STATUS_BEV
+    );
+    assert_eq!(
+        R4300::get().bus.read::<u32>(PIF_CIC_WORD),
+        0x0000_3F00 | OS_RESET_TYPE
+    );
+}
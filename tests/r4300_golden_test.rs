@@ -0,0 +1,285 @@
+// Instruction-level test vectors for the R4300 core.
+//
+// Each TOML file in tests/r4300golden contains a list of tests; every test
+// runs a code snippet (either the suite-wide `code`, or its own `code`) on a
+// CPU with a clean state, after setting the registers and memory listed in
+// `input` / `input_mem`. When execution reaches the end of the snippet (or
+// an exception vector), the registers and memory listed in `output` /
+// `output_mem` are compared with the expected values.
+//
+// Registers are named as in the disassembler (GPRs, "hi", "lo"), plus
+// "f0".."f31" (raw FPU registers), "fcsr" and the COP0 register names.
+// Values are either TOML integers, or hex strings (for full 64-bit values).
+// Memory is addressed by physical address, as a list of 32-bit words.
+// Suites that set `caches = true` run with the R4300 cache model enabled.
+#[macro_use]
+extern crate slog;
+#[macro_use]
+extern crate serde_derive;
+
+extern crate byteorder;
+extern crate emu;
+extern crate mips64;
+extern crate r64emu;
+extern crate toml;
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::bus::be::{Bus, Mem};
+use emu::bus::{BusFill, MemFlags};
+use emu::dbg::Tracer;
+use emu::state::CurrentState;
+use mips64::{CacheConfig, Cop, CopNull, Cp0, Cpu, Fpu, COP0_REG_NAMES, REG_NAMES};
+use r64emu::r4300::R4300Config;
+use slog::Discard;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const RAM_SIZE: usize = 0x10_0000;
+const CODE_ADDR: u64 = 0xFFFF_FFFF_A000_1000; // KSEG1, physical address 0x1000
+const MAX_CYCLES: i64 = 100_000;
+
+// Status used unless a test overrides it: COP0 and COP1 usable, 64-bit FPU
+// registers (FR=1), exceptions through the normal (non-bootstrap) vectors.
+const DEFAULT_STATUS: u64 = 0x3400_0000;
+
+// Physical addresses of the exception vectors: execution stops there.
+const EXCEPTION_VECTORS: [u32; 8] = [
+    0x0000_0000,
+    0x0000_0080,
+    0x0000_0180,
+    0x0000_0200,
+    0x1FC0_0000,
+    0x1FC0_0200,
+    0x1FC0_0280,
+    0x1FC0_0380,
+];
+
+#[derive(Deserialize)]
+struct TestVector {
+    name: String,
+    code: Option<String>,
+    #[serde(default)]
+    input: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    output: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    input_mem: BTreeMap<String, Vec<u32>>,
+    #[serde(default)]
+    output_mem: BTreeMap<String, Vec<u32>>,
+}
+
+#[derive(Deserialize)]
+struct Testsuite {
+    code: Option<String>,
+    #[serde(default)]
+    caches: bool,
+    test: Vec<TestVector>,
+}
+
+#[derive(Copy, Clone)]
+enum Reg {
+    Gpr(usize),
+    Hi,
+    Lo,
+    Fpr(usize),
+    Fcsr,
+    Cop0(usize),
+}
+
+impl Reg {
+    fn parse(name: &str) -> Reg {
+        if let Some(idx) = REG_NAMES.iter().position(|r| *r == name) {
+            return match idx {
+                32 => Reg::Hi,
+                33 => Reg::Lo,
+                _ => Reg::Gpr(idx),
+            };
+        }
+        if name == "fcsr" {
+            return Reg::Fcsr;
+        }
+        if name.starts_with('f') {
+            match name[1..].parse::<usize>() {
+                Ok(idx) if idx < 32 => return Reg::Fpr(idx),
+                _ => {}
+            }
+        }
+        if let Some(idx) = COP0_REG_NAMES
+            .iter()
+            .position(|r| r.eq_ignore_ascii_case(name))
+        {
+            return Reg::Cop0(idx);
+        }
+        panic!("unknown register: {}", name);
+    }
+
+    fn read(self, cpu: &Cpu<R4300Config>) -> u64 {
+        let ctx = cpu.ctx();
+        match self {
+            Reg::Gpr(idx) => ctx.regs[idx],
+            Reg::Hi => ctx.hi,
+            Reg::Lo => ctx.lo,
+            Reg::Fpr(idx) => cpu.cop1.reg(ctx, idx) as u64,
+            Reg::Fcsr => cpu.cop1.fcsr(),
+            Reg::Cop0(idx) => cpu.cop0.reg(ctx, idx) as u64,
+        }
+    }
+
+    fn write(self, cpu: &mut Cpu<R4300Config>, val: u64) {
+        let mut ctx = *cpu.ctx();
+        match self {
+            Reg::Gpr(idx) => ctx.regs[idx] = val,
+            Reg::Hi => ctx.hi = val,
+            Reg::Lo => ctx.lo = val,
+            Reg::Fpr(idx) => cpu.cop1.set_reg(&mut ctx, idx, val as u128),
+            Reg::Fcsr => cpu.cop1.set_fcsr(val),
+            Reg::Cop0(idx) => cpu.cop0.set_reg(&mut ctx, idx, val as u128),
+        }
+        *cpu.ctx_mut() = ctx;
+    }
+}
+
+fn parse_hex(s: &str) -> u64 {
+    let s = s.trim_start_matches("0x").replace("_", "");
+    u64::from_str_radix(&s, 16).unwrap_or_else(|_| panic!("invalid hex value: {}", s))
+}
+
+fn parse_value(v: &toml::Value) -> u64 {
+    match v {
+        toml::Value::Integer(v) => *v as u64,
+        toml::Value::String(s) => parse_hex(s),
+        _ => panic!("unsupported value: {}", v),
+    }
+}
+
+fn make_cpu() -> (Cpu<R4300Config>, Mem) {
+    let logger = slog::Logger::root(Discard, o!());
+    let mut bus = Bus::new(logger.new(o!()));
+    let ram = Mem::new("r4300-golden-ram", RAM_SIZE, MemFlags::default(), None);
+    bus.map_mem(0, RAM_SIZE as u32 - 1, &ram, BusFill::None)
+        .unwrap();
+    let cpu = Cpu::new(
+        "R4300",
+        logger.new(o!()),
+        bus,
+        (
+            Cp0::new("R4300-COP0", logger.new(o!())),
+            Fpu::new("R4300-FPU", logger.new(o!())),
+            CopNull {},
+            CopNull {},
+        ),
+    );
+    (cpu, ram)
+}
+
+// Run until the end of the code, or until an exception is raised.
+fn run_code(cpu: &mut Cpu<R4300Config>, end: u64) {
+    let t = Tracer::null();
+    let start = cpu.ctx().clock;
+    loop {
+        let pc = cpu.ctx().pc;
+        if pc == end || EXCEPTION_VECTORS.contains(&(pc as u32 & 0x1FFF_FFFF)) {
+            break;
+        }
+        let clock = cpu.ctx().clock;
+        assert!(
+            clock - start < MAX_CYCLES,
+            "code did not terminate (pc: {:x})",
+            pc
+        );
+        cpu.run(clock + 1, &t).unwrap();
+    }
+}
+
+fn test_golden(testname: &str) {
+    let tomlsrc = fs::read_to_string(Path::new(testname)).expect("TOML file not found");
+    let suite: Testsuite = toml::from_str(&tomlsrc).unwrap();
+
+    let (mut cpu, mut ram) = make_cpu();
+    if suite.caches {
+        cpu.enable_caches(CacheConfig::R4300_ICACHE, CacheConfig::R4300_DCACHE);
+    }
+    let initial_state = CurrentState().clone();
+
+    for t in &suite.test {
+        println!("running test: {}", &t.name);
+        initial_state.clone().make_current();
+
+        // Load the code
+        let code = t
+            .code
+            .as_ref()
+            .or(suite.code.as_ref())
+            .expect("no code specified");
+        let bin = mips64::asm::assemble(code, CODE_ADDR).expect("cannot assemble code");
+        let code_paddr = CODE_ADDR as usize & 0x1FFF_FFFF;
+        ram[code_paddr..code_paddr + bin.len()].copy_from_slice(&bin);
+
+        // Setup initial state. Compare is moved away so that the timer
+        // interrupt does not show up in Cause.
+        Reg::parse("Compare").write(&mut cpu, 0xFFFF_FFFF);
+        Reg::parse("Status").write(&mut cpu, DEFAULT_STATUS);
+        println!("    inputs:");
+        for (name, val) in &t.input {
+            let val = parse_value(val);
+            println!("    {:>12}: {:016x}", name, val);
+            Reg::parse(name).write(&mut cpu, val);
+        }
+        for (addr, words) in &t.input_mem {
+            let addr = parse_hex(addr) as usize;
+            for (i, w) in words.iter().enumerate() {
+                BigEndian::write_u32(&mut ram[addr + i * 4..], *w);
+            }
+        }
+        cpu.ctx_mut().set_pc(CODE_ADDR);
+
+        run_code(&mut cpu, CODE_ADDR + bin.len() as u64);
+
+        // Compare the results
+        let mut ok = true;
+        println!("   outputs:");
+        for (name, exp) in &t.output {
+            let exp = parse_value(exp);
+            let val = Reg::parse(name).read(&cpu);
+            let mark = if val != exp { " <-- MISMATCH" } else { "" };
+            println!(
+                "    {:>12}: {:016x} (expected: {:016x}){}",
+                name, val, exp, mark
+            );
+            ok &= val == exp;
+        }
+        for (addr, words) in &t.output_mem {
+            let addr = parse_hex(addr) as usize;
+            for (i, exp) in words.iter().enumerate() {
+                let val = BigEndian::read_u32(&ram[addr + i * 4..]);
+                let mark = if val != *exp { " <-- MISMATCH" } else { "" };
+                println!(
+                    "    {:>12x}: {:08x} (expected: {:08x}){}",
+                    addr + i * 4,
+                    val,
+                    exp,
+                    mark
+                );
+                ok &= val == *exp;
+            }
+        }
+        assert!(ok, "output is different from expected result");
+    }
+}
+
+macro_rules! define_golden_test {
+    ($test:ident, $fn:expr) => {
+        #[test]
+        fn $test() {
+            test_golden(concat!("tests/r4300golden/", $fn));
+        }
+    };
+}
+
+define_golden_test!(golden_arith, "arith.toml");
+define_golden_test!(golden_shift, "shift.toml");
+define_golden_test!(golden_branch, "branch.toml");
+define_golden_test!(golden_loadstore, "loadstore.toml");
+define_golden_test!(golden_fpu, "fpu.toml");
+define_golden_test!(golden_cache, "cache.toml");
@@ -0,0 +1,214 @@
+// Tests for the R4300 cycle timing model: multiplier interlocks, load-use
+// stalls and penalties of uncached accesses.
+#![feature(pin)]
+
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate mips64;
+extern crate r64emu;
+
+use emu::bus::be::{Bus, Mem};
+use emu::bus::{BusFill, MemFlags};
+use emu::dbg::Tracer;
+use mips64::{Cop, CopNull, Cp0, Cpu, Fpu};
+use r64emu::r4300::R4300Config;
+use slog::Discard;
+
+mod common;
+
+const RAM_SIZE: usize = 0x1_0000;
+
+// Code runs from KSEG0 (cached, so that instruction fetches are free) unless
+// specified otherwise. Test data is at physical address 0x200, pointed to
+// by a0 (through KSEG0) and a1 (through KSEG1).
+const KSEG0_CODE: u64 = 0xFFFF_FFFF_8000_1000;
+const KSEG1_CODE: u64 = 0xFFFF_FFFF_A000_1000;
+const KSEG0_DATA: u64 = 0xFFFF_FFFF_8000_0200;
+const KSEG1_DATA: u64 = 0xFFFF_FFFF_A000_0200;
+
+// Run the specified code at the specified address, and return the number of
+// cycles it took.
+fn run_code(base: u64, code: &str, timing: bool) -> i64 {
+    let logger = slog::Logger::root(Discard, o!());
+    let mut bus = Bus::new(logger.new(o!()));
+    let mut ram = Mem::new("r4300-timing-ram", RAM_SIZE, MemFlags::default(), None);
+    bus.map_mem(0, RAM_SIZE as u32 - 1, &ram, BusFill::None)
+        .unwrap();
+    let mut cpu = Cpu::<R4300Config>::new(
+        "R4300",
+        logger.new(o!()),
+        bus,
+        (
+            Cp0::new("R4300-COP0", logger.new(o!())),
+            Fpu::new("R4300-FPU", logger.new(o!())),
+            CopNull {},
+            CopNull {},
+        ),
+    );
+
+    let bin = mips64::asm::assemble(code, base).expect("cannot assemble R4300 code");
+    ram[0x1000..0x1000 + bin.len()].copy_from_slice(&bin);
+
+    let mut ctx = *cpu.ctx();
+    cpu.cop0.set_reg(&mut ctx, 16, 0x7006_E463); // Config.K0 = 3 (cached)
+    ctx.regs[4] = KSEG0_DATA;
+    ctx.regs[5] = KSEG1_DATA;
+    ctx.set_pc(base);
+    *cpu.ctx_mut() = ctx;
+    cpu.enable_timing(timing);
+
+    let t = Tracer::null();
+    let end = base + bin.len() as u64;
+    let start = cpu.ctx().clock;
+    while cpu.ctx().pc != end {
+        let clock = cpu.ctx().clock;
+        cpu.run(clock + 1, &t).unwrap();
+    }
+    cpu.ctx().clock - start
+}
+
+fn cycles_at(base: u64, code: &str, timing: bool) -> i64 {
+    let code = code.to_owned();
+    common::run_in_thread(move || run_code(base, &code, timing))
+}
+
+fn cycles(code: &str, timing: bool) -> i64 {
+    cycles_at(KSEG0_CODE, code, timing)
+}
+
+#[test]
+fn r4300_timing_disabled() {
+    let code = "
+        mult t0,t1
+        mflo t2
+        lw t3,0(a1)
+        addu t4,t3,t3
+    ";
+    assert_eq!(cycles(code, false), 4);
+    assert_eq!(cycles_at(KSEG1_CODE, code, false), 4);
+}
+
+#[test]
+fn r4300_timing_mult_mflo() {
+    // MFLO waits until the multiplier is done (4 cycles for MULT).
+    let code = "
+        mult t0,t1
+        mflo t2
+    ";
+    assert_eq!(cycles(code, true), 5);
+
+    // Independent instructions hide part of the latency...
+    let code = "
+        mult t0,t1
+        nop
+        nop
+        mfhi t2
+    ";
+    assert_eq!(cycles(code, true), 5);
+
+    // ...or all of it.
+    let code = "
+        mult t0,t1
+        nop
+        nop
+        nop
+        nop
+        mflo t2
+    ";
+    assert_eq!(cycles(code, true), 6);
+
+    // 64-bit multiplication and division take longer.
+    let code = "
+        dmultu t0,t1
+        mflo t2
+    ";
+    assert_eq!(cycles(code, true), 8);
+    let code = "
+        li t1,3
+        div t0,t1
+        mflo t2
+    ";
+    assert_eq!(cycles(code, true), 2 + 37);
+}
+
+#[test]
+fn r4300_timing_load_use() {
+    // Using the result of a load in the next instruction costs one cycle,
+    // both as a source operand (rs) and as a stored value (rt).
+    let code = "
+        lw t0,0(a0)
+        addu t1,t0,t0
+    ";
+    assert_eq!(cycles(code, true), 3);
+    let code = "
+        lw t0,0(a0)
+        sw t0,4(a0)
+    ";
+    assert_eq!(cycles(code, true), 3);
+    let code = "
+        ld t0,0(a0)
+        daddiu t1,t0,1
+    ";
+    assert_eq!(cycles(code, true), 3);
+
+    // No stall if the loaded register is not used, or not used right away.
+    let code = "
+        lw t0,0(a0)
+        addu t1,t2,t2
+    ";
+    assert_eq!(cycles(code, true), 2);
+    let code = "
+        lw t0,0(a0)
+        nop
+        addu t1,t0,t0
+    ";
+    assert_eq!(cycles(code, true), 3);
+
+    // Nor if the next instruction only overwrites it (rt is the
+    // destination of I-type ALU opcodes and loads).
+    let code = "
+        lw t0,0(a0)
+        addiu t0,t1,1
+    ";
+    assert_eq!(cycles(code, true), 2);
+    let code = "
+        lw t0,0(a0)
+        lui t0,1
+    ";
+    assert_eq!(cycles(code, true), 2);
+    let code = "
+        lw t0,0(a0)
+        lw t0,4(a0)
+    ";
+    assert_eq!(cycles(code, true), 2);
+}
+
+#[test]
+fn r4300_timing_uncached_rdram() {
+    // Cached accesses (through KSEG0) are free...
+    let code = "
+        lw t0,0(a0)
+        sw t0,4(a0)
+        nop
+    ";
+    assert_eq!(cycles(code, true), 3);
+
+    // ...while uncached accesses to RDRAM (through KSEG1) stall the
+    // pipeline: 30 cycles for loads, 10 for stores.
+    let code = "
+        lw t0,0(a1)
+        sw t1,4(a1)
+        nop
+    ";
+    assert_eq!(cycles(code, true), 3 + 30 + 10);
+
+    // Uncached instruction fetches pay the load penalty for every
+    // instruction.
+    let code = "
+        nop
+        nop
+    ";
+    assert_eq!(cycles_at(KSEG1_CODE, code, true), 2 * 31);
+}
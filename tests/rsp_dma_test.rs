@@ -0,0 +1,143 @@
+// Tests for the RSP DMA engine: timing, two-entry queue, status bits and
+// DMEM wraparound.
+#![feature(pin)]
+
+#[macro_use]
+extern crate slog;
+
+extern crate byteorder;
+extern crate emu;
+extern crate r64emu;
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use emu::sync::Subsystem;
+use r64emu::r4300::R4300;
+use r64emu::sp::Sp;
+
+mod common;
+
+const RAM_SIZE: usize = 0x1_0000;
+
+const SP_MEM_ADDR: u32 = 0x0404_0000;
+const SP_DRAM_ADDR: u32 = 0x0404_0004;
+const SP_RD_LEN: u32 = 0x0404_0008;
+const SP_WR_LEN: u32 = 0x0404_000C;
+const SP_STATUS: u32 = 0x0404_0010;
+const SP_DMA_FULL: u32 = 0x0404_0014;
+const SP_DMA_BUSY: u32 = 0x0404_0018;
+
+const STATUS_DMABUSY: u32 = 1 << 2;
+const STATUS_DMAFULL: u32 = 1 << 3;
+
+fn read_reg(addr: u32) -> u32 {
+    R4300::get().bus.read::<u32>(addr)
+}
+
+fn write_reg(addr: u32, val: u32) {
+    R4300::get_mut().bus.write::<u32>(addr, val);
+}
+
+fn run_until(cycles: i64) {
+    Sp::get_mut().run(cycles, &Tracer::null()).unwrap();
+}
+
+fn fill(mem: &mut [u8], seed: u8) {
+    for (i, b) in mem.iter_mut().enumerate() {
+        *b = seed.wrapping_add(i as u8);
+    }
+}
+
+#[test]
+fn dma_read_timing() {
+    let mut ram = common::make_sp_with_rdram(RAM_SIZE);
+    fill(&mut ram[0x100..0x200], 0x40);
+
+    // 2 rows of 0x40 bytes, skipping 8 bytes in RDRAM after each row.
+    write_reg(SP_MEM_ADDR, 0x010);
+    write_reg(SP_DRAM_ADDR, 0x100);
+    write_reg(SP_RD_LEN, (8 << 20) | (1 << 12) | 0x3F);
+
+    assert_eq!(
+        read_reg(SP_STATUS) & (STATUS_DMABUSY | STATUS_DMAFULL),
+        STATUS_DMABUSY
+    );
+    assert_eq!(read_reg(SP_DMA_BUSY), 1);
+    assert_eq!(read_reg(SP_DMA_FULL), 0);
+    assert!(Sp::get().dmem[0x10..0x90].iter().all(|&b| b == 0));
+
+    // Each row costs 8 cycles of latency, plus 4 bytes per cycle.
+    run_until(47);
+    assert_eq!(read_reg(SP_DMA_BUSY), 1);
+    run_until(48);
+    assert_eq!(read_reg(SP_DMA_BUSY), 0);
+    assert_eq!(read_reg(SP_STATUS) & (STATUS_DMABUSY | STATUS_DMAFULL), 0);
+
+    assert_eq!(&Sp::get().dmem[0x10..0x50], &ram[0x100..0x140]);
+    assert_eq!(&Sp::get().dmem[0x50..0x90], &ram[0x148..0x188]);
+
+    // Registers point after the transferred data.
+    assert_eq!(read_reg(SP_MEM_ADDR), 0x090);
+    assert_eq!(read_reg(SP_DRAM_ADDR), 0x190);
+    assert_eq!(read_reg(SP_RD_LEN), (8 << 20) | 0xFF8);
+}
+
+#[test]
+fn dma_queue() {
+    let ram = common::make_sp_with_rdram(RAM_SIZE);
+    fill(&mut Sp::get_mut().dmem[0x000..0x100], 0x80);
+
+    write_reg(SP_MEM_ADDR, 0x000);
+    write_reg(SP_DRAM_ADDR, 0x1000);
+    write_reg(SP_WR_LEN, 0x7F);
+    write_reg(SP_MEM_ADDR, 0x080);
+    write_reg(SP_DRAM_ADDR, 0x2000);
+    write_reg(SP_WR_LEN, 0x7F);
+    assert_eq!(read_reg(SP_DMA_FULL), 1);
+    assert_eq!(
+        read_reg(SP_STATUS) & (STATUS_DMABUSY | STATUS_DMAFULL),
+        STATUS_DMABUSY | STATUS_DMAFULL
+    );
+
+    // The queue is full: this transfer is ignored.
+    write_reg(SP_MEM_ADDR, 0x000);
+    write_reg(SP_DRAM_ADDR, 0x3000);
+    write_reg(SP_WR_LEN, 0x7F);
+
+    // The first transfer completes after 40 cycles, and the second one
+    // starts right after it.
+    run_until(40);
+    assert_eq!(read_reg(SP_DMA_FULL), 0);
+    assert_eq!(read_reg(SP_DMA_BUSY), 1);
+    assert_eq!(&ram[0x1000..0x1080], &Sp::get().dmem[0x000..0x080]);
+    assert!(ram[0x2000..0x2080].iter().all(|&b| b == 0));
+
+    run_until(80);
+    assert_eq!(read_reg(SP_DMA_BUSY), 0);
+    assert_eq!(&ram[0x2000..0x2080], &Sp::get().dmem[0x080..0x100]);
+    assert!(ram[0x3000..0x3080].iter().all(|&b| b == 0));
+
+    // The queue is available again.
+    write_reg(SP_WR_LEN, 0x7);
+    assert_eq!(read_reg(SP_DMA_BUSY), 1);
+}
+
+#[test]
+fn dma_dmem_wraparound() {
+    let mut ram = common::make_sp_with_rdram(RAM_SIZE);
+    fill(&mut ram[0x400..0x420], 0x10);
+
+    // DMEM addresses wrap within the 4K bank, without spilling into IMEM.
+    write_reg(SP_MEM_ADDR, 0xFF0);
+    write_reg(SP_DRAM_ADDR, 0x400);
+    write_reg(SP_RD_LEN, 0x1F);
+    run_until(100);
+
+    let sp = Sp::get();
+    assert_eq!(&sp.dmem[0xFF0..0x1000], &ram[0x400..0x410]);
+    assert_eq!(&sp.dmem[0x000..0x010], &ram[0x410..0x420]);
+    assert!(sp.imem[0x000..0x010].iter().all(|&b| b == 0));
+    assert_eq!(read_reg(SP_MEM_ADDR), 0x010);
+    assert_eq!(BigEndian::read_u32(&sp.dmem[0xFF0..]), 0x1011_1213);
+}
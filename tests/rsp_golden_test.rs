@@ -7,44 +7,31 @@ extern crate serde_derive;
 
 extern crate byteorder;
 extern crate emu;
+extern crate mips64;
 extern crate r64emu;
 extern crate toml;
 
 use byteorder::{BigEndian, ByteOrder};
 use emu::bus::be::Device;
 use emu::dbg::Tracer;
-use r64emu::dp::Dp;
 use r64emu::r4300::R4300;
 use r64emu::sp::{Sp, RSPCPU};
-use slog::Discard;
 use std::borrow;
 use std::env;
 use std::fs;
 use std::iter::Iterator;
 use std::path::Path;
 
-fn make_sp() {
-    let logger = slog::Logger::root(Discard, o!());
-    R4300::new(logger.new(o!())).register();
-    Dp::new(logger.new(o!())).register();
-    Sp::new(logger.new(o!())).unwrap().register();
-
-    // Simplified bus mapping for R4300: just SP registers.
-    {
-        let bus = &mut R4300::get_mut().bus;
-        bus.map_device(0x0400_0000, Sp::get(), 0).unwrap();
-        bus.map_device(0x0404_0000, Sp::get(), 1).unwrap();
-        bus.map_device(0x0408_0000, Sp::get(), 2).unwrap();
-    }
-    // Standard bus mapping for RSP.
-    RSPCPU::get_mut().map_bus().unwrap();
-}
+mod common;
 
 #[allow(dead_code)]
 #[derive(Deserialize)]
 struct TestVector {
     name: String,
     input: Vec<u32>,
+    // Expected output, only for model suites (see test_model). Hardware
+    // suites read their expected output from the .golden file, in order.
+    output: Option<Vec<u32>>,
 }
 
 #[allow(dead_code)]
@@ -110,7 +97,7 @@ impl Testsuite {
     }
 }
 
-fn test_golden(testname: &str) {
+fn run_suite(testname: &str, model: bool) {
     let path = env::current_dir().unwrap();
     println!("The current directory is {}", path.display());
 
@@ -118,19 +105,34 @@ fn test_golden(testname: &str) {
     let tomlsrc = fs::read_to_string(tomlname).expect("TOML file not found");
     let test: Testsuite = toml::from_str(&tomlsrc).unwrap();
 
-    make_sp();
+    common::make_sp();
 
     {
         // Load RSP microcode into IMEM
         let spb = Sp::get_mut();
-        let rspbin = fs::read(tomlname.with_extension("rsp")).expect("rsp binary not found");
+        let rspbin = mips64::asm::assemble(&test.rsp_code, 0).expect("cannot assemble RSP code");
         spb.imem[..rspbin.len()].clone_from_slice(&rspbin);
     }
 
+    // Golden suites only hold results dumped from hardware, while model
+    // suites specify all their expected outputs: the two are never mixed.
+    for t in &test.test {
+        assert_eq!(
+            t.output.is_some(),
+            model,
+            "{}: unexpected (or missing) output field",
+            t.name
+        );
+    }
+
     // Open golden
     let goldenname = tomlname.with_extension("golden");
     let output_size = test.output_size();
-    let goldenbin = fs::read(goldenname).expect("golden file not found");
+    let goldenbin = if model {
+        Vec::new()
+    } else {
+        fs::read(goldenname).expect("golden file not found")
+    };
     let mut golden = goldenbin.chunks_exact(output_size);
 
     for t in &test.test {
@@ -149,7 +151,15 @@ fn test_golden(testname: &str) {
         }
 
         // Display expected results
-        let exp = golden.next().unwrap();
+        let exp = match t.output {
+            Some(ref output) => {
+                let mut exp = vec![0u8; output.len() * 4];
+                BigEndian::write_u32_into(output, &mut exp);
+                assert_eq!(exp.len(), output_size, "invalid output size");
+                exp
+            }
+            None => golden.next().unwrap().to_vec(),
+        };
         println!("  expected:");
         test.display_output(exp.chunks_exact(4).map(BigEndian::read_u32));
 
@@ -180,6 +190,22 @@ fn test_golden(testname: &str) {
     }
 }
 
+// Golden suites (tests/gengolden) check the emulator against results dumped
+// from hardware with gengolden.
+fn test_golden(testname: &str) {
+    run_suite(testname, false)
+}
+
+// Model suites (tests/rspmodel) cover behaviours for which no hardware dump
+// is available yet: their expected outputs are computed by hand from the
+// model described in doc/rsp.md, so they only catch regressions against
+// that model, not modelling errors. They use the same format as golden
+// suites: once dumped from hardware, a suite can be moved to gengolden
+// (dropping the `output` fields).
+fn test_model(testname: &str) {
+    run_suite(testname, true)
+}
+
 macro_rules! define_golden_test {
     ($test:ident, $fn:expr) => {
         #[test]
@@ -341,3 +367,24 @@ fn golden_vmadm() {
 
 define_golden_test!(golden_compelt, "compelt.toml");
 define_golden_test!(golden_memaccess, "memaccess.toml");
+
+macro_rules! define_model_test {
+    ($test:ident, $fn:expr) => {
+        #[test]
+        fn $test() {
+            test_model(concat!("tests/rspmodel/", $fn));
+        }
+    };
+}
+
+define_model_test!(model_vmulq, "vmulq.toml");
+define_model_test!(model_vrnd, "vrnd.toml");
+define_model_test!(model_vsar, "vsar.toml");
+define_model_test!(model_vmov, "vmov.toml");
+define_model_test!(model_vreserved, "vreserved.toml");
+define_model_test!(model_vmem_wrap, "vmem_wrap.toml");
+define_model_test!(model_vne_elem, "vne_elem.toml");
+define_model_test!(model_vabs, "vabs.toml");
+define_model_test!(model_vcr, "vcr.toml");
+define_model_test!(model_vdiv_alias, "vdiv_alias.toml");
+define_model_test!(model_vrcpl, "vrcpl.toml");
@@ -0,0 +1,492 @@
+// Tests for the HLE of RSP audio tasks. The synthetic tests run offline;
+// the comparison against LLE needs the aspMain microcode (text and data
+// segments, as found in ROMs), and is ignored by default.
+#![feature(pin)]
+
+#[macro_use]
+extern crate slog;
+
+extern crate byteorder;
+extern crate emu;
+extern crate r64emu;
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::bus::be::{Device, Mem};
+use emu::dbg::Tracer;
+use r64emu::r4300::R4300;
+use r64emu::sp::{Sp, RSPCPU};
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+static UCODE_PATH: &'static str = "roms/ucode";
+
+const RAM_SIZE: usize = 0x4_0000;
+
+const SP_PC: u32 = 0x0408_0000;
+const SP_STATUS: u32 = 0x0404_0010;
+
+const STATUS_HALT: u32 = 1 << 0;
+const STATUS_BROKE: u32 = 1 << 1;
+const STATUS_SIG2: u32 = 1 << 9;
+
+// Layout of the test RDRAM
+const UCODE_DATA: u32 = 0x1000;
+const ALIST: u32 = 0x1800;
+const INPUT: u32 = 0x2000;
+const STATE: u32 = 0x2800;
+const OUTPUT: u32 = 0x3000;
+
+// Command flags
+const A_INIT: u32 = 0x01;
+const A_LEFT: u32 = 0x02;
+const A_VOL: u32 = 0x04;
+const A_AUX: u32 = 0x08;
+
+fn make_sp(hle: bool) -> Mem {
+    let ram = common::make_sp_with_rdram(RAM_SIZE);
+    Sp::get_mut().enable_hle_audio(hle);
+    ram
+}
+
+fn read_reg(addr: u32) -> u32 {
+    R4300::get().bus.read::<u32>(addr)
+}
+
+fn write_reg(addr: u32, val: u32) {
+    R4300::get_mut().bus.write::<u32>(addr, val);
+}
+
+// Write the OSTask of an audio task into DMEM.
+fn write_task(ucode_data_size: u32, alist: &[u32]) {
+    let mut task = [0u32; 16];
+    task[0] = 2; // M_AUDTASK
+    task[6] = UCODE_DATA;
+    task[7] = ucode_data_size;
+    task[12] = ALIST;
+    task[13] = alist.len() as u32 * 4;
+    BigEndian::write_u32_into(&task, &mut Sp::get_mut().dmem[0xFC0..0x1000]);
+}
+
+fn write_words(ram: &mut [u8], addr: u32, words: &[u32]) {
+    let addr = addr as usize;
+    BigEndian::write_u32_into(words, &mut ram[addr..addr + words.len() * 4]);
+}
+
+fn write_samples(ram: &mut [u8], addr: u32, samples: &[i16]) {
+    let addr = addr as usize;
+    BigEndian::write_i16_into(samples, &mut ram[addr..addr + samples.len() * 2]);
+}
+
+fn read_samples(ram: &[u8], addr: u32, n: usize) -> Vec<i16> {
+    let mut samples = vec![0i16; n];
+    let addr = addr as usize;
+    BigEndian::read_i16_into(&ram[addr..addr + n * 2], &mut samples);
+    samples
+}
+
+// A data segment that is recognized as ABI1, with a dummy resample filter.
+fn write_fake_ucode_data(ram: &mut [u8]) -> u32 {
+    write_words(ram, UCODE_DATA, &[0x0000_0001]);
+    write_words(ram, UCODE_DATA + 0x28, &[0x1E24_138C]);
+    write_words(ram, UCODE_DATA + 0x30, &[0xF000_0F00]);
+    let mut lut = [0i16; 256];
+    for row in lut.chunks_exact_mut(4) {
+        row.copy_from_slice(&[0x0C39, 0x66AD, 0x0D46, 0xFFDFu16 as i16]);
+    }
+    write_samples(ram, UCODE_DATA + 0x100, &lut);
+    0x400
+}
+
+fn cmd(op: u32, flags: u32, w1: u32, w2: u32) -> [u32; 2] {
+    [(op << 24) | (flags << 16) | (w1 & 0xFFFF), w2]
+}
+
+#[test]
+fn hle_audio_synthetic() {
+    let mut ram = make_sp(true);
+    let ucode_data_size = write_fake_ucode_data(&mut ram);
+
+    let left = (0..16).map(|i| i * 100).collect::<Vec<i16>>();
+    let right = (0..16).map(|i| -i * 50).collect::<Vec<i16>>();
+    write_samples(&mut ram, INPUT, &left);
+    write_samples(&mut ram, INPUT + 0x40, &right);
+    // One ADPCM frame, with scale 0 and an all-zero codebook: samples are
+    // just the 4-bit residuals.
+    ram[INPUT as usize + 0x100..INPUT as usize + 0x109]
+        .copy_from_slice(&[0x00, 0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF]);
+
+    let alist = [
+        cmd(0x08, 0, 0x000, 0x0000_0020),  // SETBUFF
+        cmd(0x04, 0, 0, INPUT),            // LOADBUFF
+        cmd(0x08, 0, 0x020, 0x0000_0020),  // SETBUFF
+        cmd(0x04, 0, 0, INPUT + 0x40),     // LOADBUFF
+        cmd(0x0C, 0, 0x4000, 0x0000_0040), // MIXER (gain 0.5)
+        cmd(0x08, 0, 0x000, 0x0080_0020),  // SETBUFF
+        cmd(0x0D, 0, 0, 0x0040_0020),      // INTERLEAVE
+        cmd(0x08, 0, 0x000, 0x0080_0040),  // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT),           // SAVEBUFF
+        cmd(0x08, 0, 0x100, 0x0000_0010),  // SETBUFF
+        cmd(0x04, 0, 0, INPUT + 0x100),    // LOADBUFF
+        cmd(0x08, 0, 0x100, 0x0200_0020),  // SETBUFF
+        cmd(0x01, A_INIT, 0, STATE),       // ADPCM
+        cmd(0x0A, 0, 0x200, 0x0300_0040),  // DMEMMOVE
+        cmd(0x02, 0, 0x200, 0x40),         // CLEARBUFF
+        cmd(0x08, 0, 0x000, 0x0300_0040),  // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT + 0x100),   // SAVEBUFF
+        cmd(0x08, 0, 0x000, 0x0200_0040),  // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT + 0x200),   // SAVEBUFF
+    ]
+    .concat();
+    write_words(&mut ram, ALIST, &alist);
+    write_task(ucode_data_size, &alist);
+
+    // The task completes as soon as the RSP is started.
+    write_reg(SP_STATUS, 1 << 0);
+    assert_eq!(
+        read_reg(SP_STATUS) & (STATUS_HALT | STATUS_BROKE | STATUS_SIG2),
+        STATUS_HALT | STATUS_BROKE | STATUS_SIG2
+    );
+
+    let interleaved = read_samples(&ram, OUTPUT, 32);
+    for i in 0..16 {
+        assert_eq!(interleaved[i * 2], i as i16 * 50, "left sample {}", i);
+        assert_eq!(
+            interleaved[i * 2 + 1],
+            -(i as i16) * 50,
+            "right sample {}",
+            i
+        );
+    }
+
+    let adpcm = read_samples(&ram, OUTPUT + 0x100, 32);
+    let expected = (0..16)
+        .map(|i| ((i << 12) as i16) >> 12)
+        .collect::<Vec<i16>>();
+    assert!(adpcm[..16].iter().all(|&s| s == 0));
+    assert_eq!(&adpcm[16..], &expected[..]);
+    assert_eq!(read_samples(&ram, STATE, 16), expected);
+
+    let cleared = read_samples(&ram, OUTPUT + 0x200, 32);
+    assert!(cleared.iter().all(|&s| s == 0));
+}
+
+// Run an alist through HLE; the task completes as soon as the RSP is started.
+fn run_alist(ram: &mut [u8], ucode_data_size: u32, alist: &[u32]) {
+    write_words(ram, ALIST, alist);
+    write_task(ucode_data_size, alist);
+    write_reg(SP_STATUS, 1 << 0);
+    assert_eq!(
+        read_reg(SP_STATUS) & (STATUS_HALT | STATUS_BROKE | STATUS_SIG2),
+        STATUS_HALT | STATUS_BROKE | STATUS_SIG2
+    );
+}
+
+// The expected outputs of the following tests are computed by hand, from
+// the fixed-point formulas of each command. SETVOL has no output of its
+// own: it is checked through the volumes used by ENVMIXER.
+
+#[test]
+fn hle_audio_resample() {
+    let mut ram = make_sp(true);
+    let ucode_data_size = write_fake_ucode_data(&mut ram);
+
+    // Phase 32 (half-way between two input samples) of the filter averages
+    // the two middle taps.
+    write_samples(
+        &mut ram,
+        UCODE_DATA + 0x100 + 32 * 8,
+        &[0, 0x4000, 0x4000, 0],
+    );
+    write_samples(&mut ram, INPUT, &[0x4000; 16]);
+
+    let alist = [
+        cmd(0x08, 0, 0x040, 0x0000_0020), // SETBUFF
+        cmd(0x04, 0, 0, INPUT),           // LOADBUFF
+        cmd(0x08, 0, 0x040, 0x0100_0020), // SETBUFF
+        cmd(0x05, A_INIT, 0x4000, STATE), // RESAMPLE (pitch 0.5)
+        cmd(0x08, 0, 0x040, 0x0120_0020), // SETBUFF
+        cmd(0x05, 0, 0x4000, STATE),      // RESAMPLE (pitch 0.5)
+        cmd(0x08, 0, 0x000, 0x0100_0040), // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT),          // SAVEBUFF
+    ]
+    .concat();
+    run_alist(&mut ram, ucode_data_size, &alist);
+
+    // With pitch 0.5, each input sample produces two output samples, using
+    // phase 0 and phase 32 of the filter. A_INIT clears the history, so
+    // the input ramps up through the 4 taps of phase 0 (0x0C39, 0x66AD,
+    // 0x0D46, 0xFFDF): for instance, 0x4000 * (0x0D46 + 0xFFDF) >> 15 = 1682.
+    let mut expected = vec![0, 0, -17, 0, 1682, 8192, 14825, 16384];
+    for _ in 0..4 {
+        expected.extend_from_slice(&[16389, 16384]);
+    }
+    assert_eq!(read_samples(&ram, OUTPUT, 16), expected);
+
+    // The second command continues from the saved history (the 4 samples
+    // at the final input position, and the pitch accumulator).
+    for _ in 0..8 {
+        expected.extend_from_slice(&[16389, 16384]);
+    }
+    assert_eq!(read_samples(&ram, OUTPUT, 32), expected);
+    assert_eq!(
+        read_samples(&ram, STATE, 5),
+        vec![0x4000, 0x4000, 0x4000, 0x4000, 0]
+    );
+}
+
+#[test]
+fn hle_audio_envmixer() {
+    let mut ram = make_sp(true);
+    let ucode_data_size = write_fake_ucode_data(&mut ram);
+    write_samples(&mut ram, INPUT, &[0x4000; 16]);
+
+    // Constant volumes (each ramp is already at its target): left 0.5,
+    // right 0.25, dry 0.5, wet 0.25. The second command accumulates into
+    // the same buffers, restoring the volumes from the saved state.
+    let alist = [
+        cmd(0x08, 0, 0x000, 0x0000_0020),               // SETBUFF
+        cmd(0x04, 0, 0, INPUT),                         // LOADBUFF
+        cmd(0x09, A_VOL | A_LEFT, 0x4000, 0x4000_2000), // SETVOL
+        cmd(0x09, A_VOL, 0x2000, 0),                    // SETVOL
+        cmd(0x09, A_LEFT, 0x4000, 0x0001_0000),         // SETVOL
+        cmd(0x09, 0, 0x2000, 0x0001_0000),              // SETVOL
+        cmd(0x02, 0, 0x100, 0x100),                     // CLEARBUFF
+        cmd(0x08, A_AUX, 0x140, 0x0180_01C0),           // SETBUFF
+        cmd(0x08, 0, 0x000, 0x0100_0020),               // SETBUFF
+        cmd(0x03, A_INIT | A_AUX, 0, STATE),            // ENVMIXER
+        cmd(0x03, A_AUX, 0, STATE),                     // ENVMIXER
+        cmd(0x08, 0, 0x000, 0x0100_0100),               // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT),                        // SAVEBUFF
+    ]
+    .concat();
+    run_alist(&mut ram, ucode_data_size, &alist);
+
+    // The gain is vol * dry (or wet), rounded: 0.25, 0.125, 0.125, 0.0625
+    // for the four outputs, so each command adds 4096, 2048, 2048, 1024.
+    for &(off, val) in &[(0x00, 8192), (0x40, 4096), (0x80, 4096), (0xC0, 2048)] {
+        assert_eq!(
+            read_samples(&ram, OUTPUT + off, 16),
+            vec![val; 16],
+            "output {:#x}",
+            off
+        );
+    }
+}
+
+#[test]
+fn hle_audio_envmixer_ramp() {
+    let mut ram = make_sp(true);
+    let ucode_data_size = write_fake_ucode_data(&mut ram);
+    write_samples(&mut ram, INPUT, &[0x4000; 16]);
+
+    // The left volume ramps from 0x1000 to 0x3000, with rate 2.0; the
+    // right volume stays at 0x2000. Dry is (almost) 1.0, so the gains are
+    // the volumes themselves.
+    let alist = [
+        cmd(0x08, 0, 0x000, 0x0000_0020),               // SETBUFF
+        cmd(0x04, 0, 0, INPUT),                         // LOADBUFF
+        cmd(0x09, A_VOL | A_LEFT, 0x1000, 0x7FFF_0000), // SETVOL
+        cmd(0x09, A_VOL, 0x2000, 0),                    // SETVOL
+        cmd(0x09, A_LEFT, 0x3000, 0x0002_0000),         // SETVOL
+        cmd(0x09, 0, 0x2000, 0x0001_0000),              // SETVOL
+        cmd(0x02, 0, 0x100, 0x80),                      // CLEARBUFF
+        cmd(0x08, A_AUX, 0x140, 0x0000_0000),           // SETBUFF
+        cmd(0x08, 0, 0x000, 0x0100_0020),               // SETBUFF
+        cmd(0x03, A_INIT, 0, STATE),                    // ENVMIXER
+        cmd(0x08, 0, 0x000, 0x0100_0080),               // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT),                        // SAVEBUFF
+    ]
+    .concat();
+    run_alist(&mut ram, ucode_data_size, &alist);
+
+    // Every 8 samples, the ramp aims at the next term of vol * rate^n
+    // (here: 0x4000 for the first 8 samples), moving by 1/8th of the
+    // distance per sample (0x1600, 0x1C00, ...) and stopping at the target
+    // (0x3000). The input is 0.5, so the outputs are half the volumes.
+    let mut expected = vec![0x0B00, 0x0E00, 0x1100, 0x1400, 0x1700];
+    expected.resize(16, 0x1800);
+    assert_eq!(read_samples(&ram, OUTPUT, 16), expected);
+    assert_eq!(read_samples(&ram, OUTPUT + 0x40, 16), vec![0x1000; 16]);
+}
+
+#[test]
+fn hle_audio_polef() {
+    let mut ram = make_sp(true);
+    let ucode_data_size = write_fake_ucode_data(&mut ram);
+
+    // Coefficients, in Q14: h1 is 0.25 for every sample; h2 is 0.5 for
+    // the first sample and 0 for the others.
+    let mut table = [0x1000i16; 16];
+    for t in table[8..].iter_mut() {
+        *t = 0;
+    }
+    table[8] = 0x2000;
+    write_samples(&mut ram, INPUT + 0x400, &table);
+    write_samples(&mut ram, INPUT, &[0, 0, 0, 0, 0, 0, 4000, 0]);
+
+    let alist = [
+        cmd(0x0B, 0, 0x20, INPUT + 0x400), // LOADADPCM
+        cmd(0x08, 0, 0x000, 0x0000_0020),  // SETBUFF
+        cmd(0x04, 0, 0, INPUT),            // LOADBUFF
+        cmd(0x08, 0, 0x000, 0x0100_0020),  // SETBUFF
+        cmd(0x0E, A_INIT, 0x2000, STATE),  // POLEF (gain 0.5)
+        cmd(0x08, 0, 0x000, 0x0100_0020),  // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT),           // SAVEBUFF
+    ]
+    .concat();
+    run_alist(&mut ram, ucode_data_size, &alist);
+
+    // First frame: the input is scaled by the gain (4000 -> 2000), and
+    // the next sample gets 0.5 * gain of it (1000). Second frame (silent
+    // input): each sample gets 0.25 * 2000 from l1, and the first one
+    // also 0.5 * 1000 from l2.
+    assert_eq!(
+        read_samples(&ram, OUTPUT, 16),
+        vec![0, 0, 0, 0, 0, 0, 2000, 1000, 1000, 500, 500, 500, 500, 500, 500, 500]
+    );
+    assert_eq!(read_samples(&ram, STATE, 4), vec![500; 4]);
+}
+
+#[test]
+fn hle_audio_fallback() {
+    // Unknown microcode: the RSP is started normally.
+    let mut ram = make_sp(true);
+    write_fake_ucode_data(&mut ram);
+    write_words(&mut ram, UCODE_DATA + 0x28, &[0]);
+    write_task(0x400, &[]);
+    write_reg(SP_STATUS, 1 << 0);
+    assert_eq!(read_reg(SP_STATUS) & (STATUS_HALT | STATUS_BROKE), 0);
+}
+
+#[test]
+fn hle_audio_disabled() {
+    // HLE disabled: the RSP is started normally.
+    let mut ram = make_sp(false);
+    write_fake_ucode_data(&mut ram);
+    write_task(0x400, &[]);
+    write_reg(SP_STATUS, 1 << 0);
+    assert_eq!(read_reg(SP_STATUS) & (STATUS_HALT | STATUS_BROKE), 0);
+}
+
+// Run an audio task with the real microcode, either through HLE or on
+// RSPCPU, and return the final RDRAM contents.
+fn run_aspmain(hle: bool, text: &[u8], data: &[u8], alist: &[u32]) -> Vec<u8> {
+    let (text, data, alist) = (text.to_vec(), data.to_vec(), alist.to_vec());
+    common::run_in_thread(move || exec_aspmain(hle, &text, &data, &alist))
+}
+
+fn exec_aspmain(hle: bool, text: &[u8], data: &[u8], alist: &[u32]) -> Vec<u8> {
+    let mut ram = make_sp(hle);
+
+    // Codebook (order 2, 2 predictors), followed by 4 ADPCM frames.
+    let book: [i16; 32] = [
+        -1500, 1200, -800, 400, -200, 100, -50, 25, 2048, 1300, 600, 200, 50, 0, 0, 0, 1800, -900,
+        300, -100, 30, -10, 3, -1, -700, 900, -300, 150, -60, 20, -5, 2,
+    ];
+    write_samples(&mut ram, INPUT + 0x400, &book);
+    for (i, b) in ram[INPUT as usize..INPUT as usize + 36]
+        .iter_mut()
+        .enumerate()
+    {
+        *b = if i % 9 == 0 {
+            ((i / 9) as u8 * 0x31) & 0xB1
+        } else {
+            (i as u8).wrapping_mul(0x5B)
+        };
+    }
+
+    ram[UCODE_DATA as usize..UCODE_DATA as usize + data.len()].copy_from_slice(data);
+    write_words(&mut ram, ALIST, alist);
+    write_task(data.len() as u32, alist);
+
+    // Do what rspboot does: load the microcode and jump to it.
+    {
+        let sp = Sp::get_mut();
+        sp.imem[0x80..0x80 + text.len()].copy_from_slice(text);
+        sp.dmem[..data.len()].copy_from_slice(data);
+    }
+    write_reg(SP_PC, 0x080);
+    write_reg(SP_STATUS, 1 << 0);
+
+    let cpu = RSPCPU::get_mut();
+    let mut cycles = 0;
+    while read_reg(SP_STATUS) & STATUS_HALT == 0 {
+        let clock = cpu.ctx().clock;
+        cpu.run(clock + 1000, &Tracer::null()).unwrap();
+        cycles += 1000;
+        assert!(cycles < 10_000_000, "microcode did not complete");
+    }
+    ram.to_vec()
+}
+
+// Compare HLE with the real microcode running on the RSP. The microcode is
+// copyrighted and cannot be shipped, so this test is ignored by default:
+// extract aspMain text and data from a ROM into roms/ucode/aspMain.text and
+// roms/ucode/aspMain.data, and run it with `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn hle_audio_vs_lle() {
+    let text = fs::read(Path::new(UCODE_PATH).join("aspMain.text"));
+    let data = fs::read(Path::new(UCODE_PATH).join("aspMain.data"));
+    let (text, data) = match (text, data) {
+        (Ok(text), Ok(data)) => (text, data),
+        _ => panic!(
+            "aspMain microcode not found in {} (see the comment above this test)",
+            UCODE_PATH
+        ),
+    };
+
+    let alist = [
+        cmd(0x0B, 0, 0x40, INPUT + 0x400),              // LOADADPCM
+        cmd(0x08, 0, 0x000, 0x0000_0028),               // SETBUFF
+        cmd(0x04, 0, 0, INPUT),                         // LOADBUFF
+        cmd(0x08, 0, 0x000, 0x0100_0080),               // SETBUFF
+        cmd(0x01, A_INIT, 0, STATE),                    // ADPCM
+        cmd(0x08, 0, 0x120, 0x0200_0040),               // SETBUFF
+        cmd(0x05, A_INIT, 0x6000, STATE + 0x40),        // RESAMPLE
+        cmd(0x09, A_VOL | A_LEFT, 0x4000, 0x6000_2000), // SETVOL
+        cmd(0x09, A_VOL, 0x3000, 0),                    // SETVOL
+        cmd(0x09, A_LEFT, 0x7000, 0x0001_0200),         // SETVOL
+        cmd(0x09, 0, 0x1000, 0x0000_F000),              // SETVOL
+        cmd(0x02, 0, 0x300, 0x100),                     // CLEARBUFF
+        cmd(0x08, A_AUX, 0x340, 0x0380_03C0),           // SETBUFF
+        cmd(0x08, 0, 0x200, 0x0300_0040),               // SETBUFF
+        cmd(0x03, A_INIT | A_AUX, 0, STATE + 0x80),     // ENVMIXER
+        cmd(0x0C, 0, 0x2000, 0x0200_0300),              // MIXER
+        cmd(0x08, 0, 0x000, 0x0400_0040),               // SETBUFF
+        cmd(0x0D, 0, 0, 0x0300_0340),                   // INTERLEAVE
+        cmd(0x08, 0, 0x000, 0x0400_0080),               // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT),                        // SAVEBUFF
+        cmd(0x08, 0, 0x000, 0x0380_0080),               // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT + 0x100),                // SAVEBUFF
+        cmd(0x08, 0, 0x100, 0x0480_0040),               // SETBUFF
+        cmd(0x0E, A_INIT, 0x5000, STATE + 0x100),       // POLEF
+        cmd(0x08, 0, 0x000, 0x0480_0040),               // SETBUFF
+        cmd(0x06, 0, 0, OUTPUT + 0x200),                // SAVEBUFF
+    ]
+    .concat();
+
+    let lle = run_aspmain(false, &text, &data, &alist);
+    let hle = run_aspmain(true, &text, &data, &alist);
+
+    // The state saved by ENVMIXER has a private layout, so only compare
+    // sample buffers and the ADPCM/RESAMPLE/POLEF state.
+    for &(addr, len) in &[
+        (STATE, 0x20),
+        (STATE + 0x40, 0x0A),
+        (STATE + 0x100, 0x08),
+        (OUTPUT, 0x300),
+    ] {
+        let (addr, len) = (addr as usize, len as usize);
+        for i in (addr..addr + len).step_by(2) {
+            assert_eq!(
+                BigEndian::read_i16(&hle[i..]),
+                BigEndian::read_i16(&lle[i..]),
+                "HLE and LLE differ at RDRAM {:#x}",
+                i
+            );
+        }
+    }
+}
@@ -0,0 +1,179 @@
+// Tests for the HLE of RSP graphics tasks: display lists are walked and
+// their RDP commands reach the RDP, which draws into RDRAM.
+#![feature(pin)]
+
+#[macro_use]
+extern crate slog;
+
+extern crate byteorder;
+extern crate emu;
+extern crate r64emu;
+
+use byteorder::{BigEndian, ByteOrder};
+use emu::bus::be::{Device, Mem};
+use r64emu::r4300::R4300;
+use r64emu::sp::Sp;
+
+mod common;
+
+const RAM_SIZE: usize = 0x4_0000;
+
+const SP_STATUS: u32 = 0x0404_0010;
+const MI_INTR: u32 = 0x0430_0008;
+
+const STATUS_HALT: u32 = 1 << 0;
+const STATUS_BROKE: u32 = 1 << 1;
+const MI_INTR_DP: u32 = 1 << 5;
+
+// Layout of the test RDRAM
+const UCODE_DATA: u32 = 0x1000;
+const DLIST: u32 = 0x2000;
+const SUBDLIST: u32 = 0x3000;
+const VERTICES: u32 = 0x3800;
+const FRAMEBUFFER: u32 = 0x8000;
+
+fn make_sp() -> Mem {
+    let ram = common::make_sp_with_rdram(RAM_SIZE);
+    Sp::get_mut().enable_hle_gfx(true);
+    ram
+}
+
+fn read_reg(addr: u32) -> u32 {
+    R4300::get().bus.read::<u32>(addr)
+}
+
+fn write_reg(addr: u32, val: u32) {
+    R4300::get_mut().bus.write::<u32>(addr, val);
+}
+
+fn write_words(ram: &mut [u8], addr: u32, words: &[u32]) {
+    let addr = addr as usize;
+    BigEndian::write_u32_into(words, &mut ram[addr..addr + words.len() * 4]);
+}
+
+// Write the OSTask of a graphics task into DMEM.
+fn write_task(ucode_data_size: u32) {
+    let mut task = [0u32; 16];
+    task[0] = 1; // M_GFXTASK
+    task[6] = UCODE_DATA;
+    task[7] = ucode_data_size;
+    task[12] = DLIST;
+    BigEndian::write_u32_into(&task, &mut Sp::get_mut().dmem[0xFC0..0x1000]);
+}
+
+fn run_dlist(version: &[u8], dlist: &[u32], subdlist: &[u32]) -> Mem {
+    let mut ram = make_sp();
+    let off = UCODE_DATA as usize + 0x100;
+    ram[off..off + version.len()].copy_from_slice(version);
+
+    // A triangle that crosses the clipping box (with identity matrices), so
+    // that it gets clipped.
+    let vtx: [u32; 12] = [
+        0x0000_0040,
+        0x0000_0000,
+        0x0000_0000,
+        0xFF00_00FF,
+        0xFFC0_FFC0,
+        0x0000_0000,
+        0x0000_0000,
+        0x00FF_00FF,
+        0x0040_FFC0,
+        0x0000_0000,
+        0x0000_0000,
+        0x0000_FFFF,
+    ];
+    write_words(&mut ram, VERTICES, &vtx);
+    write_words(&mut ram, DLIST, dlist);
+    write_words(&mut ram, SUBDLIST, subdlist);
+    write_task(0x800);
+
+    write_reg(SP_STATUS, 1 << 0);
+    ram
+}
+
+fn pixel(ram: &[u8], x: usize, y: usize) -> u16 {
+    BigEndian::read_u16(&ram[FRAMEBUFFER as usize + (y * 320 + x) * 2..])
+}
+
+#[test]
+fn hle_gfx_f3dex2() {
+    let ram = run_dlist(
+        b"RSP Gfx ucode F3DEX       fifo 2.05  Yoshitake Kimura Nintendo.",
+        &[
+            [0xDB06_0004, SUBDLIST],    // G_MOVEWORD: segment 1
+            [0xDB06_0008, FRAMEBUFFER], // G_MOVEWORD: segment 2
+            [0xDE00_0000, 0x0100_0000], // G_DL (call)
+            [0xE900_0000, 0x0000_0000], // G_RDPFULLSYNC
+            [0xDF00_0000, 0x0000_0000], // G_ENDDL
+        ]
+        .concat(),
+        &[
+            [0xFF10_013F, 0x0200_0000], // G_SETCIMG (segmented)
+            [0xD9FF_FFFF, 0x0000_0005], // G_GEOMETRYMODE: shade, zbuffer
+            [0x0100_3006, VERTICES],    // G_VTX: 3 vertices at 0
+            [0x0500_0204, 0x0000_0000], // G_TRI1
+            [0xE300_0A01, 0x0030_0000], // G_SETOTHERMODE_H: fill mode
+            [0xF700_0000, 0xF801_F801], // G_SETFILLCOLOR
+            [0xF603_C00C, 0x0000_0000], // G_FILLRECT (0,0)-(15,3)
+            [0xDF00_0000, 0x0000_0000], // G_ENDDL
+        ]
+        .concat(),
+    );
+
+    let status = read_reg(SP_STATUS);
+    assert_eq!(
+        status & (STATUS_HALT | STATUS_BROKE),
+        STATUS_HALT | STATUS_BROKE
+    );
+    assert_eq!(read_reg(MI_INTR) & MI_INTR_DP, MI_INTR_DP);
+
+    assert_eq!(pixel(&ram, 0, 0), 0xF801);
+    assert_eq!(pixel(&ram, 15, 0), 0xF801);
+    assert_eq!(pixel(&ram, 16, 0), 0);
+}
+
+#[test]
+fn hle_gfx_f3d() {
+    let ram = run_dlist(
+        b"RSP SW Version: 2.0D, 04-01-96",
+        &[
+            [0xBC00_0406, SUBDLIST],    // G_MOVEWORD: segment 1
+            [0xBC00_0806, FRAMEBUFFER], // G_MOVEWORD: segment 2
+            [0x0600_0000, 0x0100_0000], // G_DL (call)
+            [0xE900_0000, 0x0000_0000], // G_RDPFULLSYNC
+            [0xB800_0000, 0x0000_0000], // G_ENDDL
+        ]
+        .concat(),
+        &[
+            [0xFF10_013F, 0x0200_0000], // G_SETCIMG (segmented)
+            [0xB700_0000, 0x0000_0005], // G_SETGEOMETRYMODE: shade, zbuffer
+            [0x0420_0030, VERTICES],    // G_VTX: 3 vertices at 0
+            [0xBF00_0000, 0x0000_0A14], // G_TRI1
+            [0xBA00_1402, 0x0030_0000], // G_SETOTHERMODE_H: fill mode
+            [0xF700_0000, 0xF801_F801], // G_SETFILLCOLOR
+            [0xF603_C00C, 0x0000_0000], // G_FILLRECT (0,0)-(15,3)
+            [0xB800_0000, 0x0000_0000], // G_ENDDL
+        ]
+        .concat(),
+    );
+
+    let status = read_reg(SP_STATUS);
+    assert_eq!(
+        status & (STATUS_HALT | STATUS_BROKE),
+        STATUS_HALT | STATUS_BROKE
+    );
+    assert_eq!(read_reg(MI_INTR) & MI_INTR_DP, MI_INTR_DP);
+    assert_eq!(pixel(&ram, 0, 0), 0xF801);
+    assert_eq!(pixel(&ram, 16, 0), 0);
+}
+
+#[test]
+fn hle_gfx_unknown_ucode() {
+    // S2DEX is not supported: the task runs on the RSP.
+    run_dlist(
+        b"RSP Gfx ucode S2DEX  fifo 1.06  Yoshitake Kimura Nintendo.",
+        &[0xDF00_0000, 0x0000_0000],
+        &[],
+    );
+    assert_eq!(read_reg(SP_STATUS) & (STATUS_HALT | STATUS_BROKE), 0);
+}
@@ -0,0 +1,182 @@
+// Tests for the RSP cycle timing model: dual issue of SU/VU instructions,
+// and pipeline stalls.
+#![feature(pin)]
+
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate mips64;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use r64emu::r4300::R4300;
+use r64emu::sp::{Sp, RSPCPU};
+
+mod common;
+
+const SP_STATUS: u32 = 0x0404_0010;
+const SP_PC: u32 = 0x0408_0000;
+
+const STATUS_HALT: u32 = 1 << 0;
+
+// Run the specified code (which must end with a BREAK), and return the
+// number of cycles it took.
+fn run_code(code: &str, timing: bool) -> i64 {
+    common::make_sp();
+    let bin = mips64::asm::assemble(code, 0).expect("cannot assemble RSP code");
+    Sp::get_mut().imem[..bin.len()].copy_from_slice(&bin);
+    RSPCPU::get_mut().enable_timing(timing);
+
+    let bus = &mut R4300::get_mut().bus;
+    bus.write::<u32>(SP_PC, 0);
+    bus.write::<u32>(SP_STATUS, 1 << 0); // clear halt
+
+    let cpu = RSPCPU::get_mut();
+    let start = cpu.ctx().clock;
+    while bus.read::<u32>(SP_STATUS) & STATUS_HALT == 0 {
+        let clock = cpu.ctx().clock;
+        cpu.run(clock + 1, &Tracer::null()).unwrap();
+    }
+    cpu.ctx().clock - start
+}
+
+fn cycles(code: &str, timing: bool) -> i64 {
+    let code = code.to_owned();
+    common::run_in_thread(move || run_code(&code, timing))
+}
+
+#[test]
+fn rsp_timing_disabled() {
+    let code = "
+        vadd v1,v2,v3
+        addi t0,t0,1
+        vadd v4,v5,v6
+        addi t1,t1,1
+        break
+    ";
+    assert_eq!(cycles(code, false), 5);
+}
+
+#[test]
+fn rsp_timing_dual_issue() {
+    // Each VU op is paired with the following SU op.
+    let code = "
+        vadd v1,v2,v3
+        addi t0,t0,1
+        vadd v4,v5,v6
+        addi t1,t1,1
+        break
+    ";
+    assert_eq!(cycles(code, true), 3);
+
+    // Two ops of the same unit are never paired.
+    let code = "
+        vadd v1,v2,v3
+        vadd v4,v5,v6
+        addi t0,t0,1
+        addi t1,t1,1
+        break
+    ";
+    assert_eq!(cycles(code, true), 4);
+}
+
+#[test]
+fn rsp_timing_load_use() {
+    let code = "
+        lw t0,0(zero)
+        addi t1,t0,1
+        break
+    ";
+    assert_eq!(cycles(code, true), 4);
+
+    let code = "
+        lw t0,0(zero)
+        addi t1,t2,1
+        break
+    ";
+    assert_eq!(cycles(code, true), 3);
+}
+
+#[test]
+fn rsp_timing_vector_hazard() {
+    // Storing the result of a VU op waits for the end of the VU pipeline.
+    let code = "
+        vadd v1,v2,v3
+        sqv v1[e0],0(zero)
+        break
+    ";
+    assert_eq!(cycles(code, true), 6);
+
+    // An independent store is paired with the VU op.
+    let code = "
+        vadd v1,v2,v3
+        sqv v4[e0],0(zero)
+        break
+    ";
+    assert_eq!(cycles(code, true), 2);
+
+    // A VU op that reads the result of the previous VU op waits for the
+    // end of the VU pipeline as well (no forwarding between VU ops).
+    let code = "
+        vadd v1,v2,v3
+        vadd v4,v1,v5
+        break
+    ";
+    assert_eq!(cycles(code, true), 5);
+
+    let code = "
+        vadd v1,v2,v3
+        vadd v4,v2,v5
+        break
+    ";
+    assert_eq!(cycles(code, true), 2);
+
+    // An SU op executed while waiting is free.
+    let code = "
+        vadd v1,v2,v3
+        addi t0,t0,1
+        vadd v4,v5,v1
+        break
+    ";
+    assert_eq!(cycles(code, true), 5);
+
+    // Two independent VU ops in between leave a single stall cycle.
+    let code = "
+        vadd v1,v2,v3
+        vadd v6,v7,v8
+        vadd v9,v10,v11
+        vadd v4,v1,v5
+        break
+    ";
+    assert_eq!(cycles(code, true), 5);
+
+    // A VU op waits for a vector load of its operands.
+    let code = "
+        lqv v1[e0],0(zero)
+        vadd v2,v1,v3
+        addi t0,t0,1
+        break
+    ";
+    assert_eq!(cycles(code, true), 4);
+}
+
+#[test]
+fn rsp_timing_div() {
+    // Back-to-back DIV-family ops stall.
+    let code = "
+        vrcph v1[e0],v2[e0]
+        vrcpl v3[e0],v4[e0]
+        break
+    ";
+    assert_eq!(cycles(code, true), 4);
+
+    // DIV-family ops are not paired.
+    let code = "
+        vrcph v1[e0],v2[e0]
+        addi t0,t0,1
+        break
+    ";
+    assert_eq!(cycles(code, true), 3);
+}
@@ -0,0 +1,773 @@
+// Differential fuzzing of the RSP vector unit: random register state and
+// random VU opcodes are executed both through SpCop2 and through a
+// straightforward scalar model written from doc/rsp.md, and the first
+// divergence is reported with a minimized reproducer.
+//
+// The run is deterministic. Set RSP_VU_FUZZ_SEED and RSP_VU_FUZZ_CASES to
+// explore further, eg:
+//   RSP_VU_FUZZ_CASES=1000000 cargo test --release --test rsp_vu_fuzz_test
+#![feature(pin)]
+
+#[macro_use]
+extern crate slog;
+
+extern crate emu;
+extern crate mips64;
+extern crate r64emu;
+
+use emu::bus::be::Device;
+use emu::dbg::Tracer;
+use mips64::{Cop, CpuContext};
+use r64emu::sp::{SpCop2, RSPCPU};
+use std::env;
+
+mod common;
+
+const SEED: u64 = 0x5EED_C0DE_F00D_CAFE;
+const CASES: usize = 100_000;
+
+// Architectural state of the vector unit. Accumulator lanes are 48-bit,
+// kept sign-extended.
+#[derive(Copy, Clone, Default, PartialEq)]
+struct Regs {
+    vpr: [[u16; 8]; 32],
+    acc: [i64; 8],
+    vco: u16,
+    vcc: u16,
+    vce: u8,
+}
+
+// A test case: the opcode, and the state of the vector unit before it.
+// DIV_OUT cannot be loaded directly, so it is described by the input of the
+// VRCP that produced it.
+#[derive(Copy, Clone, PartialEq)]
+struct Case {
+    regs: Regs,
+    div_in: Option<u16>,
+    div_seed: u16,
+    op: u32,
+}
+
+// Number of fields that the minimizer tries to clear (see Case::clear).
+const CLEARABLE: usize = 32 * 8 + 8 + 16 + 16 + 8 + 3;
+
+impl Case {
+    // Clear the specified field; return false if it was already clear.
+    fn clear(&mut self, field: usize) -> bool {
+        let old = *self;
+        let r = &mut self.regs;
+        match field {
+            0..=255 => r.vpr[field / 8][field % 8] = 0,
+            256..=263 => r.acc[field - 256] = 0,
+            264..=279 => r.vco &= !(1 << (field - 264)),
+            280..=295 => r.vcc &= !(1 << (field - 280)),
+            296..=303 => r.vce &= !(1 << (field - 296)),
+            304 => self.div_in = None,
+            305 => self.div_seed = 0,
+            _ => self.op &= !(0xF << 21), // element
+        }
+        *self != old
+    }
+}
+
+const fn vu(func: u32, e: u32, vt: u32, vs: u32, vd: u32) -> u32 {
+    0x4A00_0000 | e << 21 | vt << 16 | vs << 11 | vd << 6 | func
+}
+
+// Decode a VU opcode into (func, e, vt, vs, vd).
+fn fields(op: u32) -> (u32, usize, usize, usize, usize) {
+    (
+        op & 0x3F,
+        ((op >> 21) & 0xF) as usize,
+        ((op >> 16) & 0x1F) as usize,
+        ((op >> 11) & 0x1F) as usize,
+        ((op >> 6) & 0x1F) as usize,
+    )
+}
+
+// vrcpl v1[e0],v0[e8] and vrcph v1[e0],v0[e8]. They are used to load the
+// divide unit, and to expose its state into v1 after the op under test.
+const VRCPL_V1: u32 = vu(0x31, 8, 0, 0, 1);
+const VRCPH_V1: u32 = vu(0x32, 8, 0, 0, 1);
+
+// Each case is run as three sequences: the op alone, and the op followed by
+// an op that exposes DIV_OUT or DIV_IN.
+const SEQUENCES: [&str; 3] = ["", " + vrcph v1[e0],v0[e8]", " + vrcpl v1[e0],v0[e8]"];
+
+fn sequence(op: u32, n: usize) -> Vec<u32> {
+    match n {
+        0 => vec![op],
+        1 => vec![op, VRCPH_V1],
+        _ => vec![op, VRCPL_V1],
+    }
+}
+
+const NAMES: [&str; 64] = [
+    "vmulf", "vmulu", "vrndp", "vmulq", "vmudl", "vmudm", "vmudn", "vmudh", "vmacf", "vmacu",
+    "vrndn", "vmacq", "vmadl", "vmadm", "vmadn", "vmadh", "vadd", "vsub", "vsut", "vabs", "vaddc",
+    "vsubc", "vaddb", "vsubb", "vaccb", "vsucb", "vsad", "vsac", "vsum", "vsar", "v1e", "v1f",
+    "vlt", "veq", "vne", "vge", "vcl", "vch", "vcr", "vmrg", "vand", "vnand", "vor", "vnor",
+    "vxor", "vnxor", "v2e", "v2f", "vrcp", "vrcpl", "vrcph", "vmov", "vrsq", "vrsql", "vrsqh",
+    "vnop", "v38", "v39", "v3a", "v3b", "v3c", "v3d", "v3e", "vnull",
+];
+
+fn disasm(op: u32) -> String {
+    let (func, e, vt, vs, vd) = fields(op);
+    let name = NAMES[func as usize];
+    match func {
+        0x30..=0x36 => format!("{} v{}[e{}],v{}[e{}]", name, vd, vs, vt, e),
+        _ => format!("{} v{},v{},v{}[e{}]", name, vd, vs, vt, e),
+    }
+}
+
+struct Rng(u64);
+
+impl Rng {
+    // xorshift64*
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u32) -> u32 {
+        ((self.next() >> 32) % n as u64) as u32
+    }
+
+    // Lane values that are most likely to expose saturation, sign, carry
+    // and rounding bugs.
+    fn lane(&mut self) -> u16 {
+        const EDGES: [u16; 12] = [
+            0x0000, 0x0001, 0x0002, 0x000F, 0x001F, 0x0020, 0x7FFE, 0x7FFF, 0x8000, 0x8001, 0xFFFE,
+            0xFFFF,
+        ];
+        let r = self.next();
+        if r & 1 == 0 {
+            EDGES[(r >> 8) as usize % EDGES.len()]
+        } else {
+            (r >> 16) as u16
+        }
+    }
+
+    // Registers are biased towards a few of them, to exercise aliasing
+    // between VD, VS and VT.
+    fn reg(&mut self) -> u32 {
+        if self.next() & 1 == 0 {
+            self.below(4)
+        } else {
+            self.below(32)
+        }
+    }
+
+    fn case(&mut self) -> Case {
+        let mut regs = Regs::default();
+        for lane in regs.vpr.iter_mut().flat_map(|v| v.iter_mut()) {
+            *lane = self.lane();
+        }
+        for acc in regs.acc.iter_mut() {
+            // ACC_HI is usually the sign extension of ACC_MD, so that
+            // clamping does not always saturate.
+            let (md, lo) = (self.lane(), self.lane());
+            let hi = if self.next() & 1 == 0 {
+                0u16.wrapping_sub(md >> 15)
+            } else {
+                self.lane()
+            };
+            *acc = sext48((hi as i64) << 32 | (md as i64) << 16 | lo as i64);
+        }
+        regs.vco = self.next() as u16;
+        regs.vcc = self.next() as u16;
+        regs.vce = self.next() as u8;
+
+        let div_in = if self.next() & 1 == 0 {
+            Some(self.lane())
+        } else {
+            None
+        };
+        let (func, e) = (self.below(64), self.below(16));
+        let op = vu(func, e, self.reg(), self.reg(), self.reg());
+        Case {
+            regs,
+            div_in,
+            div_seed: self.lane(),
+            op,
+        }
+    }
+}
+
+// RCP_ROM and RSQ_ROM, as listed in doc/rsp.md.
+struct Roms {
+    rcp: Vec<u16>,
+    rsq: Vec<u16>,
+}
+
+impl Roms {
+    fn from_doc() -> Roms {
+        let mut tables = include_str!("../doc/rsp.md")
+            .split("```")
+            .skip(1)
+            .step_by(2)
+            .map(|block| {
+                block
+                    .split_whitespace()
+                    .map(|w| u16::from_str_radix(w, 16))
+                    .collect::<Result<Vec<u16>, _>>()
+                    .unwrap_or_default()
+            })
+            .filter(|t| t.len() == 512);
+        Roms {
+            rcp: tables.next().expect("RCP_ROM not found in doc/rsp.md"),
+            rsq: tables.next().expect("RSQ_ROM not found in doc/rsp.md"),
+        }
+    }
+
+    // rcp(input) and rsq(input), as described in doc/rsp.md.
+    fn compute(&self, input: i32, rsq: bool) -> u32 {
+        if input == 0 {
+            return 0x7FFF_FFFF;
+        }
+        if rsq && input == -32768 {
+            return 0xFFFF_0000;
+        }
+        let x = if input < -32768 { !input } else { input.abs() } as u32;
+        let scale = 31 - x.leading_zeros();
+        let frac = (((x as u64) << (32 - scale)) as u32) >> 23;
+        let res = if rsq {
+            let rom = self.rsq[(((scale & 1) << 8) | (frac >> 1)) as usize] as u32;
+            ((0x10000 | rom) << 14) >> (scale / 2)
+        } else {
+            let rom = self.rcp[frac as usize] as u32;
+            ((0x10000 | rom) << 14) >> scale
+        };
+        if input < 0 {
+            !res
+        } else {
+            res
+        }
+    }
+}
+
+// Element selection: lane i of the VT operand is VT<BROADCAST[e][i]>.
+const BROADCAST: [[usize; 8]; 16] = [
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 0, 2, 2, 4, 4, 6, 6],
+    [1, 1, 3, 3, 5, 5, 7, 7],
+    [0, 0, 0, 0, 4, 4, 4, 4],
+    [1, 1, 1, 1, 5, 5, 5, 5],
+    [2, 2, 2, 2, 6, 6, 6, 6],
+    [3, 3, 3, 3, 7, 7, 7, 7],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [1, 1, 1, 1, 1, 1, 1, 1],
+    [2, 2, 2, 2, 2, 2, 2, 2],
+    [3, 3, 3, 3, 3, 3, 3, 3],
+    [4, 4, 4, 4, 4, 4, 4, 4],
+    [5, 5, 5, 5, 5, 5, 5, 5],
+    [6, 6, 6, 6, 6, 6, 6, 6],
+    [7, 7, 7, 7, 7, 7, 7, 7],
+];
+
+fn sext48(x: i64) -> i64 {
+    (x << 16) >> 16
+}
+
+fn bit(x: u16, i: usize) -> bool {
+    x & (1 << i) != 0
+}
+
+fn set_acc_lo(acc: &mut i64, lo: u16) {
+    *acc = (*acc & !0xFFFF) | lo as i64;
+}
+
+fn clamp_signed(x: i64) -> u16 {
+    x.max(-32768).min(32767) as u16
+}
+
+fn clamp_unsigned(x: i64) -> u16 {
+    if x < 0 {
+        0
+    } else if x > 32767 {
+        0xFFFF
+    } else {
+        x as u16
+    }
+}
+
+// Clamp ACC(31..0) as signed, returning the saturated ACC_LO.
+fn clamp_unsigned_lo(acc: i64) -> u16 {
+    if acc >> 31 == acc >> 47 {
+        acc as u16
+    } else if acc < 0 {
+        0
+    } else {
+        0xFFFF
+    }
+}
+
+// Scalar model of the vector unit, written after doc/rsp.md.
+struct Model<'a> {
+    roms: &'a Roms,
+    regs: Regs,
+    div_in: Option<u16>,
+    div_out: u16,
+}
+
+impl<'a> Model<'a> {
+    fn run(roms: &Roms, case: &Case, ops: &[u32]) -> Regs {
+        let mut m = Model {
+            roms,
+            regs: case.regs,
+            div_in: case.div_in,
+            div_out: (roms.compute(case.div_seed as i16 as i32, false) >> 16) as u16,
+        };
+        for &op in ops {
+            m.exec(op);
+        }
+        m.regs
+    }
+
+    fn exec(&mut self, op: u32) {
+        let (func, e, vt, vs, vd) = fields(op);
+        let r = &mut self.regs;
+        let s = r.vpr[vs];
+        let mut t = [0u16; 8];
+        for i in 0..8 {
+            t[i] = r.vpr[vt][BROADCAST[e][i]];
+        }
+        let mut d = [0u16; 8];
+
+        match func {
+            // Multiply / multiply-accumulate
+            0x00..=0x0F => {
+                for i in 0..8 {
+                    let (us, ut) = (s[i] as i64, t[i] as i64);
+                    let (ss, st) = (s[i] as i16 as i64, t[i] as i16 as i64);
+                    let acc = r.acc[i];
+                    let acc = sext48(match func {
+                        0x00 | 0x01 => ss * st * 2 + 0x8000,
+                        0x02 | 0x0A => {
+                            let val = if vs & 1 != 0 { st << 16 } else { st };
+                            if (acc >= 0) == (func == 0x02) {
+                                acc + val
+                            } else {
+                                acc
+                            }
+                        }
+                        0x03 => {
+                            let prod = ss * st;
+                            (if prod < 0 { prod + 31 } else { prod }) << 16
+                        }
+                        0x04 => (us * ut) >> 16,
+                        0x05 => ss * ut,
+                        0x06 => us * st,
+                        0x07 => (ss * st) << 16,
+                        0x08 | 0x09 => acc + ss * st * 2,
+                        0x0B => {
+                            let mut prod = acc >> 16;
+                            if prod & 32 == 0 {
+                                if prod < 0 {
+                                    prod += 32;
+                                } else if prod >= 32 {
+                                    prod -= 32;
+                                }
+                            }
+                            (prod << 16) | (acc & 0xFFFF)
+                        }
+                        0x0C => acc + ((us * ut) >> 16),
+                        0x0D => acc + ss * ut,
+                        0x0E => acc + us * st,
+                        _ => acc + ((ss * st) << 16),
+                    });
+                    r.acc[i] = acc;
+                    d[i] = match func {
+                        0x01 | 0x09 => clamp_unsigned(acc >> 16),
+                        0x03 | 0x0B => clamp_signed(acc >> 17) & !0xF,
+                        0x04 | 0x06 | 0x0C | 0x0E => clamp_unsigned_lo(acc),
+                        _ => clamp_signed(acc >> 16),
+                    };
+                }
+                r.vpr[vd] = d;
+            }
+
+            // VADD, VSUB, VABS, VADDC, VSUBC
+            0x10 | 0x11 | 0x13 | 0x14 | 0x15 => {
+                let mut vco = 0;
+                for i in 0..8 {
+                    let (us, ut) = (s[i] as i64, t[i] as i64);
+                    let (ss, st) = (s[i] as i16 as i64, t[i] as i16 as i64);
+                    let carry = bit(r.vco, i) as i64;
+                    let (val, res) = match func {
+                        0x10 => (ss + st + carry, clamp_signed(ss + st + carry)),
+                        0x11 => (ss - st - carry, clamp_signed(ss - st - carry)),
+                        0x13 => {
+                            let val = if ss < 0 {
+                                -st
+                            } else if ss == 0 {
+                                0
+                            } else {
+                                st
+                            };
+                            (val, clamp_signed(val))
+                        }
+                        0x14 => {
+                            let val = us + ut;
+                            vco |= ((val > 0xFFFF) as u16) << i;
+                            (val, val as u16)
+                        }
+                        _ => {
+                            let val = us - ut;
+                            vco |= ((val < 0) as u16) << i | ((val != 0) as u16) << (i + 8);
+                            (val, val as u16)
+                        }
+                    };
+                    set_acc_lo(&mut r.acc[i], val as u16);
+                    d[i] = res;
+                }
+                if func != 0x13 {
+                    r.vco = vco;
+                }
+                r.vpr[vd] = d;
+            }
+
+            // VSAR
+            0x1D => {
+                for i in 0..8 {
+                    d[i] = match e {
+                        8 => (r.acc[i] >> 32) as u16,
+                        9 => (r.acc[i] >> 16) as u16,
+                        10 => r.acc[i] as u16,
+                        _ => 0,
+                    };
+                }
+                r.vpr[vd] = d;
+            }
+
+            // Select: VLT, VEQ, VNE, VGE, VCL, VCH, VCR, VMRG
+            0x20..=0x27 => {
+                let (mut vco, mut vcc, mut vce) = (0u16, 0u16, 0u8);
+                for i in 0..8 {
+                    let (a, b) = (s[i], t[i]);
+                    let (sa, sb) = (a as i16 as i32, b as i16 as i32);
+                    let (carry, ne) = (bit(r.vco, i), bit(r.vco, i + 8));
+                    let (le, ge) = (bit(r.vcc, i), bit(r.vcc, i + 8));
+                    let ce = r.vce & (1 << i) != 0;
+
+                    let (res, carry, ne, le, ge, ce) = match func {
+                        0x20..=0x23 => {
+                            let eq = sa == sb;
+                            let cond = match func {
+                                0x20 => sa < sb || (carry && ne && eq),
+                                0x21 => !ne && eq,
+                                0x22 => ne || !eq,
+                                _ => sa > sb || (!(carry && ne) && eq),
+                            };
+                            (if cond { a } else { b }, false, false, cond, false, ce)
+                        }
+                        0x24 => {
+                            let mut le = le;
+                            let mut ge = ge;
+                            if !carry && !ne {
+                                ge = a >= b;
+                            }
+                            if carry && !ne {
+                                let sum = a as u32 + b as u32;
+                                le = if ce { sum <= 0x10000 } else { sum == 0 };
+                            }
+                            let vt_abs = if carry { b.wrapping_neg() } else { b };
+                            let clip = if carry { le } else { ge };
+                            (if clip { vt_abs } else { a }, false, false, le, ge, false)
+                        }
+                        0x25 => {
+                            let sign = (sa < 0) != (sb < 0);
+                            let vt_abs = if sign { b.wrapping_neg() } else { b };
+                            let ce = sign && sa + sb == -1;
+                            let ne = !ce && a != vt_abs;
+                            let le = if sign { sa + sb <= 0 } else { sb < 0 };
+                            let ge = if sign { sb < 0 } else { sa >= sb };
+                            let clip = if sign { le } else { ge };
+                            (if clip { vt_abs } else { a }, sign, ne, le, ge, ce)
+                        }
+                        0x26 => {
+                            let sign = (sa < 0) != (sb < 0);
+                            let vt_abs = if sign { !b } else { b };
+                            let le = if sign { sa + sb + 1 <= 0 } else { sb < 0 };
+                            let ge = if sign { sb < 0 } else { sa >= sb };
+                            let clip = if sign { le } else { ge };
+                            (if clip { vt_abs } else { a }, false, false, le, ge, false)
+                        }
+                        _ => (if le { a } else { b }, false, false, le, ge, ce),
+                    };
+                    vco |= (carry as u16) << i | (ne as u16) << (i + 8);
+                    vcc |= (le as u16) << i | (ge as u16) << (i + 8);
+                    vce |= (ce as u8) << i;
+                    set_acc_lo(&mut r.acc[i], res);
+                    d[i] = res;
+                }
+                r.vco = vco;
+                r.vcc = vcc;
+                r.vce = vce;
+                r.vpr[vd] = d;
+            }
+
+            // Logical: VAND, VNAND, VOR, VNOR, VXOR, VNXOR
+            0x28..=0x2D => {
+                for i in 0..8 {
+                    let (a, b) = (s[i], t[i]);
+                    d[i] = match func {
+                        0x28 => a & b,
+                        0x29 => !(a & b),
+                        0x2A => a | b,
+                        0x2B => !(a | b),
+                        0x2C => a ^ b,
+                        _ => !(a ^ b),
+                    };
+                    set_acc_lo(&mut r.acc[i], d[i]);
+                }
+                r.vpr[vd] = d;
+            }
+
+            // VMOV
+            0x33 => {
+                let de = vs & 7;
+                r.vpr[vd][de] = t[de];
+                for i in 0..8 {
+                    set_acc_lo(&mut r.acc[i], t[i]);
+                }
+            }
+
+            // Divide: VRCP, VRCPL, VRCPH, VRSQ, VRSQL, VRSQH
+            0x30..=0x36 => {
+                let raw = r.vpr[vt];
+                let x = raw[e & 7];
+                let rsq = func >= 0x34;
+                let res = match func & 3 {
+                    0 => Some(self.roms.compute(x as i16 as i32, rsq)),
+                    1 => {
+                        let input = match self.div_in.take() {
+                            Some(hi) => ((hi as u32) << 16 | x as u32) as i32,
+                            None => x as i16 as i32,
+                        };
+                        Some(self.roms.compute(input, rsq))
+                    }
+                    _ => None,
+                };
+                let de = vs & 7;
+                match res {
+                    Some(res) => {
+                        r.vpr[vd][de] = res as u16;
+                        self.div_out = (res >> 16) as u16;
+                    }
+                    None => {
+                        r.vpr[vd][de] = self.div_out;
+                        self.div_in = Some(x);
+                    }
+                }
+                for i in 0..8 {
+                    set_acc_lo(&mut r.acc[i], raw[i]);
+                }
+            }
+
+            // VNOP, VNULL
+            0x37 | 0x3F => {}
+
+            // Reserved
+            _ => {
+                for i in 0..8 {
+                    set_acc_lo(&mut r.acc[i], s[i].wrapping_add(t[i]));
+                }
+                r.vpr[vd] = d;
+            }
+        }
+    }
+}
+
+const ACC_REGS: [usize; 3] = [
+    SpCop2::REG_ACCUM_LO,
+    SpCop2::REG_ACCUM_MD,
+    SpCop2::REG_ACCUM_HI,
+];
+
+fn to_u128(v: &[u16; 8]) -> u128 {
+    v.iter().fold(0, |acc, &lane| acc << 16 | lane as u128)
+}
+
+fn from_u128(x: u128) -> [u16; 8] {
+    let mut v = [0u16; 8];
+    for i in 0..8 {
+        v[i] = (x >> ((7 - i) * 16)) as u16;
+    }
+    v
+}
+
+fn exec(cop2: &mut SpCop2, ctx: &mut CpuContext, op: u32) {
+    cop2.op(ctx, op, &Tracer::null()).unwrap();
+}
+
+// Run a sequence of ops through SpCop2, starting from the state of the case.
+fn emulate(case: &Case, ops: &[u32]) -> Regs {
+    let cpu = RSPCPU::get_mut();
+    let mut ctx = *cpu.ctx();
+    let cop2 = &mut cpu.cop2;
+
+    // Compute DIV_OUT with VRCPL (twice, to drop any pending DIV_IN), and
+    // then load DIV_IN with VRCPH.
+    cop2.set_reg(&mut ctx, 0, to_u128(&[case.div_seed, 0, 0, 0, 0, 0, 0, 0]));
+    exec(cop2, &mut ctx, VRCPL_V1);
+    exec(cop2, &mut ctx, VRCPL_V1);
+    if let Some(div_in) = case.div_in {
+        cop2.set_reg(&mut ctx, 0, to_u128(&[div_in, 0, 0, 0, 0, 0, 0, 0]));
+        exec(cop2, &mut ctx, VRCPH_V1);
+    }
+
+    let r = &case.regs;
+    for (idx, v) in r.vpr.iter().enumerate() {
+        cop2.set_reg(&mut ctx, idx, to_u128(v));
+    }
+    for (n, &idx) in ACC_REGS.iter().enumerate() {
+        let mut v = [0u16; 8];
+        for i in 0..8 {
+            v[i] = (r.acc[i] >> (n * 16)) as u16;
+        }
+        cop2.set_reg(&mut ctx, idx, to_u128(&v));
+    }
+    cop2.set_reg(&mut ctx, SpCop2::REG_VCO, r.vco as u128);
+    cop2.set_reg(&mut ctx, SpCop2::REG_VCC, r.vcc as u128);
+    cop2.set_reg(&mut ctx, SpCop2::REG_VCE, r.vce as u128);
+
+    for &op in ops {
+        exec(cop2, &mut ctx, op);
+    }
+
+    let mut out = Regs::default();
+    for (idx, v) in out.vpr.iter_mut().enumerate() {
+        *v = from_u128(cop2.reg(&ctx, idx));
+    }
+    for (n, &idx) in ACC_REGS.iter().enumerate() {
+        let v = from_u128(cop2.reg(&ctx, idx));
+        for i in 0..8 {
+            out.acc[i] |= (v[i] as i64) << (n * 16);
+        }
+    }
+    for acc in out.acc.iter_mut() {
+        *acc = sext48(*acc);
+    }
+    out.vco = cop2.reg(&ctx, SpCop2::REG_VCO) as u16;
+    out.vcc = cop2.reg(&ctx, SpCop2::REG_VCC) as u16;
+    out.vce = cop2.reg(&ctx, SpCop2::REG_VCE) as u8;
+    out
+}
+
+// Run a case through the model and SpCop2, and return the first sequence
+// whose final state differs, along with the expected and actual states.
+fn check(roms: &Roms, case: &Case) -> Option<(usize, Regs, Regs)> {
+    (0..SEQUENCES.len()).find_map(|n| {
+        let ops = sequence(case.op, n);
+        let (exp, got) = (Model::run(roms, case, &ops), emulate(case, &ops));
+        if exp != got {
+            Some((n, exp, got))
+        } else {
+            None
+        }
+    })
+}
+
+// Greedily clear the fields of a diverging case, as long as it keeps
+// diverging.
+fn minimize(roms: &Roms, case: &mut Case) {
+    let mut progress = true;
+    while progress {
+        progress = false;
+        for field in 0..CLEARABLE {
+            let mut c = *case;
+            if c.clear(field) && check(roms, &c).is_some() {
+                *case = c;
+                progress = true;
+            }
+        }
+    }
+}
+
+fn fmt_lanes(v: &[u16; 8]) -> String {
+    let lanes: Vec<String> = v.iter().map(|l| format!("{:04x}", l)).collect();
+    lanes.join(" ")
+}
+
+fn fmt_acc(acc: &[i64; 8]) -> String {
+    let lanes: Vec<String> = acc
+        .iter()
+        .map(|&a| format!("{:012x}", a & 0xFFFF_FFFF_FFFF))
+        .collect();
+    lanes.join(" ")
+}
+
+fn report(case: &Case, seq: usize, exp: &Regs, got: &Regs) -> String {
+    let r = &case.regs;
+    let mut s = format!("{:08x}  {}\n", case.op, disasm(case.op));
+    s += "input (zero registers omitted):\n";
+    for (i, v) in r.vpr.iter().enumerate().filter(|(_, v)| **v != [0; 8]) {
+        s += &format!("  v{:<4} {}\n", i, fmt_lanes(v));
+    }
+    s += &format!("  acc   {}\n", fmt_acc(&r.acc));
+    s += &format!(
+        "  vco={:04x} vcc={:04x} vce={:02x} div_in={} div_out=vrcp({:04x})\n",
+        r.vco,
+        r.vcc,
+        r.vce,
+        case.div_in
+            .map_or("none".to_owned(), |x| format!("{:04x}", x)),
+        case.div_seed,
+    );
+    s += &format!(
+        "after {}{}:\n",
+        NAMES[(case.op & 0x3F) as usize],
+        SEQUENCES[seq]
+    );
+    for i in 0..32 {
+        if exp.vpr[i] != got.vpr[i] {
+            s += &format!("  v{:<4} expected {}\n", i, fmt_lanes(&exp.vpr[i]));
+            s += &format!("        got      {}\n", fmt_lanes(&got.vpr[i]));
+        }
+    }
+    if exp.acc != got.acc {
+        s += &format!("  acc   expected {}\n", fmt_acc(&exp.acc));
+        s += &format!("        got      {}\n", fmt_acc(&got.acc));
+    }
+    if (exp.vco, exp.vcc, exp.vce) != (got.vco, got.vcc, got.vce) {
+        s += &format!(
+            "  flags expected vco={:04x} vcc={:04x} vce={:02x}\n",
+            exp.vco, exp.vcc, exp.vce
+        );
+        s += &format!(
+            "        got      vco={:04x} vcc={:04x} vce={:02x}\n",
+            got.vco, got.vcc, got.vce
+        );
+    }
+    s
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[test]
+fn rsp_vu_fuzz() {
+    common::make_sp();
+    let roms = Roms::from_doc();
+    let mut rng = Rng(env_or("RSP_VU_FUZZ_SEED", SEED));
+    for n in 0..env_or("RSP_VU_FUZZ_CASES", CASES) {
+        let mut case = rng.case();
+        if check(&roms, &case).is_some() {
+            minimize(&roms, &mut case);
+            let (seq, exp, got) = check(&roms, &case).unwrap();
+            panic!(
+                "divergence from the model in case #{}, minimized:\n{}",
+                n,
+                report(&case, seq, &exp, &got)
+            );
+        }
+    }
+}